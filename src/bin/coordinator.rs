@@ -3,6 +3,7 @@ extern crate tokio;
 extern crate undermoon;
 #[macro_use]
 extern crate log;
+extern crate actix_web;
 extern crate config;
 extern crate env_logger;
 
@@ -12,12 +13,20 @@ use std::env;
 use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
+use undermoon::coordinator::accrual::PhiAccrualConfig;
+use undermoon::coordinator::admin::{run_admin_server, CoordinatorAdmin};
+use undermoon::coordinator::config::{
+    TunableConfig, DEFAULT_CLIENT_POOL_SIZE, DEFAULT_CLIENT_TIMEOUT, TUNABLE_FIELDS,
+};
 use undermoon::coordinator::http_mani_broker::HttpMetaManipulationBroker;
 use undermoon::coordinator::http_meta_broker::HttpMetaBroker;
-use undermoon::coordinator::service::{CoordinatorConfig, CoordinatorService};
+use undermoon::coordinator::service::{
+    CoordinatorConfig, CoordinatorService, FailureDetectorConfig,
+};
 use undermoon::protocol::PooledRedisClientFactory;
 
-fn gen_conf() -> Vec<CoordinatorConfig> {
+// Returns the address of the admin http api and the configs of the services.
+fn gen_conf() -> (String, Vec<CoordinatorConfig>) {
     let mut s = config::Config::new();
     // If config file is specified, load it.
     if let Some(conf_file_path) = env::args().nth(1) {
@@ -42,19 +51,92 @@ fn gen_conf() -> Vec<CoordinatorConfig> {
         )
     }
 
-    // Currently this address it not used. Later we should open a http
-    // api to expose some inner states.
     let reporter_id = s
         .get::<String>("reporter_id")
         .unwrap_or_else(|_| "127.0.0.1:6699".to_string());
+    // The admin http api only listens on the localhost with the port of reporter_id by default.
+    let admin_address = s.get::<String>("admin_address").unwrap_or_else(|_| {
+        let port = reporter_id.rsplit(':').next().unwrap_or("6699");
+        format!("127.0.0.1:{}", port)
+    });
+    let broker_token = s
+        .get::<String>("broker_token")
+        .unwrap_or_else(|_| "".to_string());
 
-    broker_address_list
+    let failure_detector = gen_failure_detector_conf(&s);
+    let tunable = Arc::new(gen_tunable_conf(&s));
+
+    let configs = broker_address_list
         .into_iter()
         .map(|broker_address| CoordinatorConfig {
             broker_address,
             reporter_id: reporter_id.clone(),
+            broker_token: broker_token.clone(),
+            failure_detector: failure_detector.clone(),
+            tunable: tunable.clone(),
         })
-        .collect()
+        .collect();
+    (admin_address, configs)
+}
+
+fn gen_tunable_conf(s: &config::Config) -> TunableConfig {
+    let client_timeout = s
+        .get::<u64>("client_timeout_ms")
+        .map(Duration::from_millis)
+        .unwrap_or_else(|_| DEFAULT_CLIENT_TIMEOUT);
+    let client_pool_size = s
+        .get::<usize>("client_pool_size")
+        .unwrap_or_else(|_| DEFAULT_CLIENT_POOL_SIZE);
+    let tunable = TunableConfig::new(client_timeout, client_pool_size);
+    for field in TUNABLE_FIELDS.iter() {
+        if let Ok(value) = s.get::<String>(field) {
+            if let Err(err) = tunable.set_value(field, &value) {
+                warn!("invalid config {} {}: {:?}", field, value, err);
+            }
+        }
+    }
+    tunable
+}
+
+fn gen_failure_detector_conf(s: &config::Config) -> FailureDetectorConfig {
+    let detector = s
+        .get::<String>("failure_detector")
+        .unwrap_or_else(|_| "ping".to_string());
+    match detector.to_lowercase().as_str() {
+        "phi_accrual" => {
+            let default_config = PhiAccrualConfig::default();
+            let get_millis = |key: &str, default: Duration| {
+                s.get::<u64>(key)
+                    .map(Duration::from_millis)
+                    .unwrap_or_else(|_| default)
+            };
+            FailureDetectorConfig::PhiAccrual(PhiAccrualConfig {
+                threshold: s
+                    .get::<f64>("phi_threshold")
+                    .unwrap_or_else(|_| default_config.threshold),
+                max_sample_size: s
+                    .get::<usize>("phi_max_sample_size")
+                    .unwrap_or_else(|_| default_config.max_sample_size),
+                min_std_deviation: get_millis(
+                    "phi_min_std_deviation_ms",
+                    default_config.min_std_deviation,
+                ),
+                acceptable_heartbeat_pause: get_millis(
+                    "phi_acceptable_heartbeat_pause_ms",
+                    default_config.acceptable_heartbeat_pause,
+                ),
+                first_heartbeat_estimate: get_millis(
+                    "phi_first_heartbeat_estimate_ms",
+                    default_config.first_heartbeat_estimate,
+                ),
+            })
+        }
+        "ping" => FailureDetectorConfig::Ping,
+        other => {
+            warn!("unknown failure_detector {}, use ping instead", other);
+            FailureDetectorConfig::Ping
+        }
+    }
 }
 
 fn gen_service(
@@ -64,15 +146,18 @@ fn gen_service(
     let data_broker = Arc::new(HttpMetaBroker::new(
         config.broker_address.clone(),
         http_client.clone(),
+        config.broker_token.clone(),
     ));
     let mani_broker = Arc::new(HttpMetaManipulationBroker::new(
         config.broker_address.clone(),
         http_client,
+        config.broker_token.clone(),
     ));
 
-    let timeout = Duration::new(2, 0);
-    let pool_size = 2;
-    let client_factory = PooledRedisClientFactory::new(pool_size, timeout);
+    let client_factory = PooledRedisClientFactory::new(
+        config.tunable.get_client_pool_size(),
+        config.tunable.get_client_timeout(),
+    );
 
     CoordinatorService::new(config, data_broker, mani_broker, client_factory)
 }
@@ -80,10 +165,24 @@ fn gen_service(
 fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
 
-    let configs = gen_conf();
+    let (admin_address, configs) = gen_conf();
     let service_num = configs.len();
-    let services = configs.into_iter().map(gen_service);
-    let futs = select_all(services.map(|service| {
+
+    let admin_conf = configs
+        .first()
+        .map(|config| (config.tunable.clone(), config.broker_token.clone()));
+    let services: Vec<_> = configs.into_iter().map(gen_service).collect();
+
+    if let Some((tunable, broker_token)) = admin_conf {
+        let statuses = services
+            .iter()
+            .map(|service| service.get_status())
+            .collect();
+        let admin = CoordinatorAdmin::new(tunable, statuses).with_token(broker_token);
+        run_admin_server(Arc::new(admin), &admin_address)?;
+    }
+
+    let futs = select_all(services.into_iter().map(|service| {
         Box::pin(async move {
             if let Err(err) = service.run().await {
                 error!("coordinator error {:?}", err);
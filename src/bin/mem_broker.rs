@@ -7,8 +7,26 @@ extern crate env_logger;
 use actix_web::server;
 use std::env;
 use std::sync::Arc;
+use std::thread;
 use undermoon::broker::service::{gen_app, MemBrokerConfig, MemBrokerService};
 
+// The tokens could be either an array in the config file
+// or a comma separated list in the env vars.
+fn get_tokens(s: &config::Config, key: &str) -> Vec<String> {
+    if let Ok(tokens) = s.get::<Vec<String>>(key) {
+        return tokens;
+    }
+    s.get::<String>(key)
+        .map(|tokens| {
+            tokens
+                .split(',')
+                .map(|token| token.trim().to_string())
+                .filter(|token| !token.is_empty())
+                .collect()
+        })
+        .unwrap_or_else(|_| vec![])
+}
+
 fn gen_conf() -> MemBrokerConfig {
     let conf_file_path = env::args()
         .nth(1)
@@ -28,6 +46,24 @@ fn gen_conf() -> MemBrokerConfig {
             .get::<String>("address")
             .unwrap_or_else(|_| "127.0.0.1:7799".to_string()),
         failure_ttl: s.get::<u64>("failure_ttl").unwrap_or_else(|_| 60),
+        snapshot_dir: s
+            .get::<String>("snapshot_dir")
+            .unwrap_or_else(|_| "".to_string()),
+        snapshot_interval: s.get::<u64>("snapshot_interval").unwrap_or_else(|_| 300),
+        snapshot_retention: s.get::<usize>("snapshot_retention").unwrap_or_else(|_| 24),
+        audit_log_capacity: s
+            .get::<usize>("audit_log_capacity")
+            .unwrap_or_else(|_| 10000),
+        audit_log_file: s
+            .get::<String>("audit_log_file")
+            .unwrap_or_else(|_| "".to_string()),
+        readonly_tokens: get_tokens(&s, "readonly_tokens"),
+        operator_tokens: get_tokens(&s, "operator_tokens"),
+        admin_tokens: get_tokens(&s, "admin_tokens"),
+        validation_interval: s.get::<u64>("validation_interval").unwrap_or_else(|_| 60),
+        auto_repair: s.get::<bool>("auto_repair").unwrap_or_else(|_| false),
+        failover_max_lag: s.get::<u64>("failover_max_lag").ok(),
+        failover_max_wait: s.get::<u64>("failover_max_wait").unwrap_or_else(|_| 60),
     }
 }
 
@@ -38,6 +74,12 @@ fn main() {
     let address = config.address.clone();
 
     let service = Arc::new(MemBrokerService::new(config));
+    let snapshot_service = service.clone();
+    thread::spawn(move || snapshot_service.run_snapshot_loop());
+    let validation_service = service.clone();
+    thread::spawn(move || validation_service.run_validation_loop());
+    let plan_service = service.clone();
+    thread::spawn(move || plan_service.run_planned_migration_loop());
     server::new(move || gen_app(service.clone()))
         .keep_alive(300)
         .bind(&address)
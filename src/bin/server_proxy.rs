@@ -113,6 +113,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         meta_map,
         future_registry.clone(),
     );
+    let backend_health_check = forward_handler.run_backend_health_check();
     let server = ServerProxyService::new(
         config.clone(),
         forward_handler,
@@ -126,6 +127,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         .enable_all()
         .build()?;
 
+    runtime.spawn(backend_health_check);
     if let Err(err) = runtime.block_on(server.run()) {
         error!("tokio runtime failed: {}", err);
         return Err(err);
@@ -0,0 +1,177 @@
+use actix_web::http::Method;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+// Avoid the large payload such as the whole metadata filling up the memory.
+const MAX_PAYLOAD_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuditRecord {
+    pub id: u64,
+    pub timestamp: i64, // unix timestamp in seconds
+    pub method: String,
+    pub route: String,
+    pub caller: String,
+    // The role of the bearer token. None if the authentication is disabled.
+    #[serde(default)]
+    pub role: Option<String>,
+    pub payload: Option<String>,
+    // The global epoch after the operation.
+    pub epoch: u64,
+    pub status: u16,
+    pub error: Option<String>,
+}
+
+// Only the requests which could change the metadata are recorded.
+// The failure reports are sent by the coordinators in every round
+// and would flood the audit log.
+pub fn is_audited(method: &Method, path: &str) -> bool {
+    match *method {
+        Method::GET | Method::HEAD => false,
+        Method::POST if path.starts_with("/api/failures/") => false,
+        _ => true,
+    }
+}
+
+// Handlers with request bodies put this into the request extensions
+// for the audit middleware to record.
+pub struct AuditPayload(pub String);
+
+impl AuditPayload {
+    pub fn new(mut payload: String) -> Self {
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            let mut end = MAX_PAYLOAD_SIZE;
+            while !payload.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.truncate(end);
+            payload.push_str("...");
+        }
+        AuditPayload(payload)
+    }
+}
+
+struct AuditLogInner {
+    records: VecDeque<AuditRecord>,
+    next_id: u64,
+    file: Option<File>,
+}
+
+// Keeps the latest records in memory and optionally appends all of them to a file.
+pub struct AuditLog {
+    capacity: usize,
+    inner: Mutex<AuditLogInner>,
+}
+
+impl AuditLog {
+    pub fn new(capacity: usize, file_path: &str) -> io::Result<Self> {
+        let file = if file_path.is_empty() {
+            None
+        } else {
+            Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(file_path)?,
+            )
+        };
+        let inner = AuditLogInner {
+            records: VecDeque::with_capacity(capacity),
+            next_id: 1,
+            file,
+        };
+        Ok(Self {
+            capacity,
+            inner: Mutex::new(inner),
+        })
+    }
+
+    // The `id` of the record will be overwritten.
+    pub fn append(&self, mut record: AuditRecord) {
+        let mut inner = self.inner.lock().expect("AuditLog::append");
+        record.id = inner.next_id;
+        inner.next_id += 1;
+
+        if let Some(file) = inner.file.as_mut() {
+            let res = serde_json::to_string(&record)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+                .and_then(|line| writeln!(file, "{}", line));
+            if let Err(err) = res {
+                error!("failed to write audit log: {:?}", err);
+            }
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+        if inner.records.len() >= self.capacity {
+            inner.records.pop_front();
+        }
+        inner.records.push_back(record);
+    }
+
+    pub fn get_records_since(&self, since_timestamp: i64) -> Vec<AuditRecord> {
+        self.inner
+            .lock()
+            .expect("AuditLog::get_records_since")
+            .records
+            .iter()
+            .filter(|record| record.timestamp >= since_timestamp)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_record(timestamp: i64) -> AuditRecord {
+        AuditRecord {
+            id: 0,
+            timestamp,
+            method: "POST".to_string(),
+            route: "/api/clusters/mydb".to_string(),
+            caller: "127.0.0.1:5299".to_string(),
+            role: None,
+            payload: None,
+            epoch: 1,
+            status: 200,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_audit_log_capacity() {
+        let log = AuditLog::new(2, "").unwrap();
+        for timestamp in 0..3 {
+            log.append(gen_record(timestamp));
+        }
+        let records = log.get_records_since(0);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].id, 2);
+        assert_eq!(records[1].id, 3);
+        assert_eq!(log.get_records_since(2).len(), 1);
+    }
+
+    #[test]
+    fn test_is_audited() {
+        assert!(!is_audited(&Method::GET, "/api/clusters/names"));
+        assert!(!is_audited(
+            &Method::POST,
+            "/api/failures/127.0.0.1:6000/127.0.0.1:6699"
+        ));
+        assert!(is_audited(&Method::POST, "/api/clusters/mydb"));
+        assert!(is_audited(&Method::PUT, "/api/clusters/migrations"));
+        assert!(is_audited(&Method::DELETE, "/api/clusters/mydb"));
+    }
+
+    #[test]
+    fn test_truncate_payload() {
+        let payload = AuditPayload::new("a".repeat(MAX_PAYLOAD_SIZE + 1));
+        assert_eq!(payload.0.len(), MAX_PAYLOAD_SIZE + 3);
+        assert!(payload.0.ends_with("..."));
+    }
+}
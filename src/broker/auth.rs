@@ -0,0 +1,215 @@
+use crate::common::utils::{constant_time_eq, strip_prefix};
+use actix_web::http::Method;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    ReadOnly,
+    Operator,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Self::ReadOnly => "read-only",
+            Self::Operator => "operator",
+            Self::Admin => "admin",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    Unauthorized,
+    Forbidden { required: Role, role: Role },
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unauthorized => write!(f, "UNAUTHORIZED: missing or invalid bearer token"),
+            Self::Forbidden { required, role } => {
+                write!(f, "FORBIDDEN: {} role required but got {}", required, role)
+            }
+        }
+    }
+}
+
+pub struct Authenticator {
+    tokens: HashMap<String, Role>,
+}
+
+impl Authenticator {
+    // The higher role wins if the same token is configured multiple times.
+    pub fn new(
+        readonly_tokens: &[String],
+        operator_tokens: &[String],
+        admin_tokens: &[String],
+    ) -> Self {
+        let mut tokens = HashMap::new();
+        let groups = [
+            (Role::ReadOnly, readonly_tokens),
+            (Role::Operator, operator_tokens),
+            (Role::Admin, admin_tokens),
+        ];
+        for (role, group) in groups.iter() {
+            for token in group.iter().filter(|t| !t.is_empty()) {
+                tokens.insert(token.clone(), *role);
+            }
+        }
+        Self { tokens }
+    }
+
+    // All the requests are allowed if no token is configured.
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    pub fn authorize(
+        &self,
+        method: &Method,
+        path: &str,
+        authorization: Option<&str>,
+    ) -> Result<Role, AuthError> {
+        let token = authorization
+            .and_then(|value| strip_prefix(value.trim(), "Bearer "))
+            .map(str::trim)
+            .ok_or(AuthError::Unauthorized)?;
+        let role = self
+            .tokens
+            .iter()
+            .find(|(t, _)| constant_time_eq(t.as_bytes(), token.as_bytes()))
+            .map(|(_, role)| *role)
+            .ok_or(AuthError::Unauthorized)?;
+
+        let required = get_required_role(method, path);
+        if role < required {
+            return Err(AuthError::Forbidden { required, role });
+        }
+        Ok(role)
+    }
+}
+
+// The coordinator only needs the operator role:
+// it reads the metadata, reports failures and commits migrations.
+// Removing resources, overwriting the whole metadata
+// and applying the repairs of the validation require the admin role.
+pub fn get_required_role(method: &Method, path: &str) -> Role {
+    let path = path.trim_end_matches('/');
+    match *method {
+        // The audit records contain the request payloads.
+        Method::GET if path == "/api/audit" => Role::Admin,
+        Method::GET | Method::HEAD => Role::ReadOnly,
+        // These only check or plan without changing anything.
+        Method::POST if path == "/api/validation" || path.ends_with("/rebalance/plan") => {
+            Role::ReadOnly
+        }
+        Method::POST
+            if path == "/api/metadata"
+                || path == "/api/validation/repair"
+                || path.starts_with("/api/snapshots/") =>
+        {
+            Role::Admin
+        }
+        Method::DELETE => Role::Admin,
+        _ => Role::Operator,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_authenticator() -> Authenticator {
+        Authenticator::new(
+            &["reader".to_string()],
+            &["operator".to_string(), "".to_string()],
+            &["admin".to_string()],
+        )
+    }
+
+    #[test]
+    fn test_required_role() {
+        assert_eq!(
+            get_required_role(&Method::GET, "/api/clusters/names"),
+            Role::ReadOnly
+        );
+        assert_eq!(get_required_role(&Method::GET, "/api/audit"), Role::Admin);
+        assert_eq!(
+            get_required_role(&Method::POST, "/api/clusters/mydb/rebalance/plan"),
+            Role::ReadOnly
+        );
+        assert_eq!(
+            get_required_role(&Method::POST, "/api/failures/127.0.0.1:6000/coord"),
+            Role::Operator
+        );
+        assert_eq!(
+            get_required_role(&Method::PUT, "/api/clusters/migrations"),
+            Role::Operator
+        );
+        assert_eq!(
+            get_required_role(&Method::POST, "/api/snapshots"),
+            Role::Operator
+        );
+        assert_eq!(
+            get_required_role(&Method::POST, "/api/snapshots/metadata_1_1.json/restore"),
+            Role::Admin
+        );
+        assert_eq!(
+            get_required_role(&Method::POST, "/api/metadata"),
+            Role::Admin
+        );
+        assert_eq!(
+            get_required_role(&Method::POST, "/api/validation"),
+            Role::ReadOnly
+        );
+        assert_eq!(
+            get_required_role(&Method::POST, "/api/validation/repair/"),
+            Role::Admin
+        );
+        assert_eq!(
+            get_required_role(&Method::DELETE, "/api/clusters/mydb"),
+            Role::Admin
+        );
+    }
+
+    #[test]
+    fn test_authorize() {
+        let auth = gen_authenticator();
+        assert!(auth.is_enabled());
+        assert!(!Authenticator::new(&[], &[], &[]).is_enabled());
+
+        let path = "/api/clusters/mydb";
+        assert_eq!(
+            auth.authorize(&Method::POST, path, None),
+            Err(AuthError::Unauthorized)
+        );
+        assert_eq!(
+            auth.authorize(&Method::POST, path, Some("Bearer unknown")),
+            Err(AuthError::Unauthorized)
+        );
+        // Empty tokens are ignored.
+        assert_eq!(
+            auth.authorize(&Method::GET, path, Some("Bearer ")),
+            Err(AuthError::Unauthorized)
+        );
+        assert_eq!(
+            auth.authorize(&Method::POST, path, Some("Bearer reader")),
+            Err(AuthError::Forbidden {
+                required: Role::Operator,
+                role: Role::ReadOnly,
+            })
+        );
+        assert_eq!(
+            auth.authorize(&Method::POST, path, Some("Bearer operator")),
+            Ok(Role::Operator)
+        );
+        assert_eq!(
+            auth.authorize(&Method::DELETE, path, Some("Bearer admin")),
+            Ok(Role::Admin)
+        );
+    }
+}
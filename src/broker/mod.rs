@@ -1,2 +1,8 @@
+mod audit;
+mod auth;
+pub(crate) mod placement;
+mod planner;
 pub mod service;
-mod store;
+mod snapshot;
+pub(crate) mod store;
+mod switchover;
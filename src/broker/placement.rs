@@ -0,0 +1,462 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+
+// The anti-affinity rule for the host part of the proxy address.
+// All the other rules are treated as label keys of the proxies.
+pub const HOST_RULE: &str = "host";
+// The label used to avoid placing the replication peers in the same failure domain on failover.
+pub const ZONE_LABEL: &str = "zone";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PlacementOptions {
+    pub master_num: usize,
+    #[serde(default)]
+    pub replicas_per_master: usize,
+    // A master and its replicas should never share the same value of any of these rules.
+    #[serde(default)]
+    pub anti_affinity: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProxyCandidate {
+    pub proxy_address: String,
+    pub node_addresses: Vec<String>,
+    pub labels: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacedNode {
+    pub proxy_address: String,
+    pub node_address: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeGroup {
+    pub master: PlacedNode,
+    pub replicas: Vec<PlacedNode>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub groups: Vec<NodeGroup>,
+    // The remaining nodes of the chosen proxies,
+    // which could be used as replicas later.
+    pub spare_nodes: Vec<PlacedNode>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PlacementError {
+    NotEnoughNodes {
+        required: usize,
+        available: usize,
+        unlabeled_proxies: usize,
+    },
+    AntiAffinity {
+        rules: Vec<String>,
+        master_index: usize,
+    },
+}
+
+impl fmt::Display for PlacementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotEnoughNodes {
+                required,
+                available,
+                unlabeled_proxies,
+            } => write!(
+                f,
+                "{} free nodes required but only {} available, {} free proxies excluded for missing labels",
+                required, available, unlabeled_proxies
+            ),
+            Self::AntiAffinity {
+                rules,
+                master_index,
+            } => write!(
+                f,
+                "cannot place master {} and its replicas on different proxies by anti-affinity rules {:?}",
+                master_index, rules
+            ),
+        }
+    }
+}
+
+struct PlacingProxy {
+    proxy_address: String,
+    node_addresses: Vec<String>,
+    // The values of the anti-affinity rules in the same order.
+    domains: Vec<String>,
+}
+
+impl PlacingProxy {
+    fn conflicts_with(&self, other: &Self) -> bool {
+        self.proxy_address == other.proxy_address
+            || self
+                .domains
+                .iter()
+                .zip(other.domains.iter())
+                .any(|(d1, d2)| d1 == d2)
+    }
+}
+
+// Since every proxy could only be owned by one cluster,
+// use as few proxies as possible by trying the largest ones first.
+pub fn plan_placement(
+    candidates: Vec<ProxyCandidate>,
+    options: &PlacementOptions,
+) -> Result<Placement, PlacementError> {
+    let required = options.master_num * (options.replicas_per_master + 1);
+
+    let mut proxies = vec![];
+    let mut unlabeled_proxies = 0;
+    for candidate in candidates.into_iter() {
+        match gen_placing_proxy(candidate, &options.anti_affinity) {
+            Some(proxy) => proxies.push(proxy),
+            None => unlabeled_proxies += 1,
+        }
+    }
+
+    let available: usize = proxies.iter().map(|p| p.node_addresses.len()).sum();
+    if available < required {
+        return Err(PlacementError::NotEnoughNodes {
+            required,
+            available,
+            unlabeled_proxies,
+        });
+    }
+
+    proxies.sort_by(|a, b| {
+        b.node_addresses
+            .len()
+            .cmp(&a.node_addresses.len())
+            .then_with(|| a.proxy_address.cmp(&b.proxy_address))
+    });
+
+    let mut master_index = 0;
+    let mut capacity = 0;
+    for proxy_num in 1..=proxies.len() {
+        capacity += proxies[proxy_num - 1].node_addresses.len();
+        if capacity < required {
+            continue;
+        }
+        match assign_nodes(&proxies[..proxy_num], options) {
+            Ok(placement) => return Ok(placement),
+            Err(index) => master_index = index,
+        }
+    }
+
+    Err(PlacementError::AntiAffinity {
+        rules: options.anti_affinity.clone(),
+        master_index,
+    })
+}
+
+fn gen_placing_proxy(candidate: ProxyCandidate, rules: &[String]) -> Option<PlacingProxy> {
+    let ProxyCandidate {
+        proxy_address,
+        mut node_addresses,
+        labels,
+    } = candidate;
+
+    let mut domains = vec![];
+    for rule in rules.iter() {
+        let domain = if rule == HOST_RULE {
+            proxy_address
+                .rsplitn(2, ':')
+                .last()
+                .unwrap_or(&proxy_address)
+                .to_string()
+        } else {
+            labels.get(rule)?.clone()
+        };
+        domains.push(domain);
+    }
+
+    node_addresses.sort();
+    Some(PlacingProxy {
+        proxy_address,
+        node_addresses,
+        domains,
+    })
+}
+
+// Bound the backtracking so that a hopeless layout fails fast instead of blocking the broker.
+const MAX_SEARCH_STEPS: usize = 100_000;
+
+struct PlacementSearch<'a> {
+    proxies: &'a [PlacingProxy],
+    group_size: usize,
+    position_num: usize,
+    used: Vec<usize>,
+    // The proxy index of every placed node. Every `group_size` of them form a group
+    // with the master at the start.
+    chosen: Vec<usize>,
+    steps: usize,
+    failed_master: usize,
+}
+
+impl<'a> PlacementSearch<'a> {
+    // Prefer the proxy with the most free nodes left which does not conflict
+    // with the proxies already chosen by this group,
+    // and backtrack to the other candidates when the rest could not be placed.
+    fn search(&mut self) -> bool {
+        let position = self.chosen.len();
+        if position == self.position_num {
+            return true;
+        }
+        self.steps += 1;
+        if self.steps > MAX_SEARCH_STEPS {
+            return false;
+        }
+
+        let group_start = position - position % self.group_size;
+        let group = &self.chosen[group_start..];
+        // The replicas are interchangeable so only try them in ascending proxy order.
+        let min_index = if group.len() >= 2 {
+            group[group.len() - 1]
+        } else {
+            0
+        };
+        let mut candidates: Vec<usize> = (min_index..self.proxies.len())
+            .filter(|i| self.used[*i] < self.proxies[*i].node_addresses.len())
+            .filter(|i| {
+                group
+                    .iter()
+                    .all(|c| !self.proxies[*c].conflicts_with(&self.proxies[*i]))
+            })
+            .collect();
+        candidates.sort_by_key(|i| {
+            (
+                Reverse(self.proxies[*i].node_addresses.len() - self.used[*i]),
+                *i,
+            )
+        });
+
+        for index in candidates.into_iter() {
+            self.used[index] += 1;
+            self.chosen.push(index);
+            if self.search() {
+                return true;
+            }
+            self.chosen.pop();
+            self.used[index] -= 1;
+            if self.steps > MAX_SEARCH_STEPS {
+                break;
+            }
+        }
+
+        self.failed_master = std::cmp::max(self.failed_master, position / self.group_size);
+        false
+    }
+}
+
+// Returns the index of the master which could not be placed on failure.
+fn assign_nodes(proxies: &[PlacingProxy], options: &PlacementOptions) -> Result<Placement, usize> {
+    let group_size = options.replicas_per_master + 1;
+    let mut search = PlacementSearch {
+        proxies,
+        group_size,
+        position_num: options.master_num * group_size,
+        used: vec![0; proxies.len()],
+        chosen: vec![],
+        steps: 0,
+        failed_master: 0,
+    };
+    if !search.search() {
+        return Err(search.failed_master);
+    }
+
+    let mut used = vec![0; proxies.len()];
+    let mut groups = vec![];
+    for group_indices in search.chosen.chunks(group_size) {
+        let mut group_nodes = vec![];
+        for index in group_indices.iter() {
+            let proxy = &proxies[*index];
+            group_nodes.push(PlacedNode {
+                proxy_address: proxy.proxy_address.clone(),
+                node_address: proxy.node_addresses[used[*index]].clone(),
+            });
+            used[*index] += 1;
+        }
+        let master = group_nodes.remove(0);
+        groups.push(NodeGroup {
+            master,
+            replicas: group_nodes,
+        });
+    }
+
+    let mut spare_nodes = vec![];
+    for (proxy, used_num) in proxies.iter().zip(used) {
+        if used_num == 0 {
+            continue;
+        }
+        for node_address in proxy.node_addresses[used_num..].iter() {
+            spare_nodes.push(PlacedNode {
+                proxy_address: proxy.proxy_address.clone(),
+                node_address: node_address.clone(),
+            });
+        }
+    }
+
+    Ok(Placement {
+        groups,
+        spare_nodes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn gen_candidate(proxy_address: &str, node_num: usize, zone: Option<&str>) -> ProxyCandidate {
+        let node_addresses = (0..node_num)
+            .map(|i| format!("{}-node{}", proxy_address, i))
+            .collect();
+        let mut labels = HashMap::new();
+        if let Some(zone) = zone {
+            labels.insert("zone".to_string(), zone.to_string());
+        }
+        ProxyCandidate {
+            proxy_address: proxy_address.to_string(),
+            node_addresses,
+            labels,
+        }
+    }
+
+    fn gen_options(master_num: usize, replicas: usize, rules: &[&str]) -> PlacementOptions {
+        PlacementOptions {
+            master_num,
+            replicas_per_master: replicas,
+            anti_affinity: rules.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+
+    fn get_proxies(placement: &Placement) -> HashSet<String> {
+        placement
+            .groups
+            .iter()
+            .flat_map(|g| g.replicas.iter().chain(std::iter::once(&g.master)))
+            .chain(placement.spare_nodes.iter())
+            .map(|n| n.proxy_address.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_masters_only() {
+        let candidates = vec![
+            gen_candidate("host1:6000", 2, None),
+            gen_candidate("host2:6000", 4, None),
+        ];
+        let placement = plan_placement(candidates, &gen_options(3, 0, &[])).unwrap();
+        assert_eq!(placement.groups.len(), 3);
+        assert!(placement.groups.iter().all(|g| g.replicas.is_empty()));
+        // Only the largest proxy is consumed.
+        assert_eq!(placement.spare_nodes.len(), 1);
+        assert_eq!(get_proxies(&placement).len(), 1);
+    }
+
+    #[test]
+    fn test_replicas_on_different_proxies() {
+        let candidates = vec![
+            gen_candidate("host1:6000", 2, None),
+            gen_candidate("host1:6001", 2, None),
+        ];
+        let placement = plan_placement(candidates, &gen_options(2, 1, &[])).unwrap();
+        for group in placement.groups.iter() {
+            assert_eq!(group.replicas.len(), 1);
+            assert_ne!(group.master.proxy_address, group.replicas[0].proxy_address);
+        }
+        assert!(placement.spare_nodes.is_empty());
+    }
+
+    #[test]
+    fn test_host_anti_affinity() {
+        let candidates = vec![
+            gen_candidate("host1:6000", 2, None),
+            gen_candidate("host1:6001", 2, None),
+        ];
+        let err = plan_placement(candidates.clone(), &gen_options(2, 1, &[HOST_RULE])).unwrap_err();
+        assert_eq!(
+            err,
+            PlacementError::AntiAffinity {
+                rules: vec![HOST_RULE.to_string()],
+                master_index: 0,
+            }
+        );
+
+        let mut candidates = candidates;
+        candidates.push(gen_candidate("host2:6000", 2, None));
+        let placement = plan_placement(candidates, &gen_options(2, 1, &[HOST_RULE])).unwrap();
+        for group in placement.groups.iter() {
+            let master_host = group.master.proxy_address.split(':').next();
+            let replica_host = group.replicas[0].proxy_address.split(':').next();
+            assert_ne!(master_host, replica_host);
+        }
+    }
+
+    #[test]
+    fn test_label_anti_affinity() {
+        let candidates = vec![
+            gen_candidate("host1:6000", 4, Some("zone1")),
+            gen_candidate("host2:6000", 4, Some("zone1")),
+            gen_candidate("host3:6000", 4, None),
+            gen_candidate("host4:6000", 2, Some("zone2")),
+        ];
+        let placement = plan_placement(candidates, &gen_options(2, 1, &["zone"])).unwrap();
+        for group in placement.groups.iter() {
+            let proxies = [
+                group.master.proxy_address.as_str(),
+                group.replicas[0].proxy_address.as_str(),
+            ];
+            assert!(proxies.contains(&"host4:6000"));
+        }
+        assert!(!get_proxies(&placement).contains("host3:6000"));
+    }
+
+    #[test]
+    fn test_tight_anti_affinity() {
+        // Pairing the two largest proxies first would leave only zone3 nodes for the last master.
+        let candidates = vec![
+            gen_candidate("host1:6000", 3, Some("zone1")),
+            gen_candidate("host2:6000", 2, Some("zone2")),
+            gen_candidate("host3:6000", 1, Some("zone3")),
+            gen_candidate("host4:6000", 1, Some("zone3")),
+            gen_candidate("host5:6000", 1, Some("zone3")),
+        ];
+        let zones: HashMap<String, String> = candidates
+            .iter()
+            .map(|c| (c.proxy_address.clone(), c.labels["zone"].clone()))
+            .collect();
+        let placement = plan_placement(candidates, &gen_options(4, 1, &["zone"])).unwrap();
+        assert_eq!(placement.groups.len(), 4);
+        assert!(placement.spare_nodes.is_empty());
+        let mut node_addresses = HashSet::new();
+        for group in placement.groups.iter() {
+            assert_ne!(
+                zones[&group.master.proxy_address],
+                zones[&group.replicas[0].proxy_address]
+            );
+            assert!(node_addresses.insert(group.master.node_address.clone()));
+            assert!(node_addresses.insert(group.replicas[0].node_address.clone()));
+        }
+    }
+
+    #[test]
+    fn test_not_enough_nodes() {
+        let candidates = vec![
+            gen_candidate("host1:6000", 2, Some("zone1")),
+            gen_candidate("host2:6000", 2, None),
+        ];
+        let err = plan_placement(candidates, &gen_options(2, 1, &["zone"])).unwrap_err();
+        assert_eq!(
+            err,
+            PlacementError::NotEnoughNodes {
+                required: 4,
+                available: 2,
+                unlabeled_proxies: 1,
+            }
+        );
+    }
+}
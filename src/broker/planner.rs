@@ -0,0 +1,452 @@
+use crate::common::cluster::Range;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RebalanceOptions {
+    // node_address => capacity weight, default to 1.0
+    #[serde(default)]
+    pub weights: HashMap<String, f64>,
+    // node_address => measured key number
+    #[serde(default)]
+    pub key_counts: HashMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SlotRangeMove {
+    pub src_node_address: String,
+    pub dst_node_address: String,
+    pub ranges: Vec<Range>,
+}
+
+impl SlotRangeMove {
+    pub fn get_slot_num(&self) -> usize {
+        self.ranges.iter().map(|r| r.end - r.start + 1).sum()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MigrationPlan {
+    // The cluster epoch the plan is generated from.
+    pub epoch: u64,
+    pub moves: Vec<SlotRangeMove>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MasterSlots {
+    pub node_address: String,
+    pub proxy_address: String,
+    // Only the slot ranges without migration tags.
+    pub ranges: Vec<Range>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PlanError {
+    InvalidWeight,
+    NoReceiver,
+}
+
+struct PlanningNode {
+    node_address: String,
+    proxy_address: String,
+    ranges: Vec<Range>,
+    // Estimated load of a single slot.
+    density: f64,
+    excess: f64,
+}
+
+// Greedily move slots from the most overloaded master to the most underloaded one
+// on another proxy until no more whole slot could be moved.
+// The load of a master is its slot number, or its key number if `key_counts` is specified.
+pub fn plan_rebalance(
+    masters: Vec<MasterSlots>,
+    options: &RebalanceOptions,
+) -> Result<Vec<SlotRangeMove>, PlanError> {
+    let mut weights = Vec::with_capacity(masters.len());
+    for master in masters.iter() {
+        let weight = options
+            .weights
+            .get(&master.node_address)
+            .cloned()
+            .unwrap_or(1.0);
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(PlanError::InvalidWeight);
+        }
+        weights.push(weight);
+    }
+
+    let mut nodes = gen_planning_nodes(masters, &weights, &options.key_counts)?;
+    Ok(gen_moves(&mut nodes, |_| true))
+}
+
+// Move all the slots of the masters inside `proxy_address` to the other masters.
+pub fn plan_drain(
+    masters: Vec<MasterSlots>,
+    proxy_address: &str,
+) -> Result<Vec<SlotRangeMove>, PlanError> {
+    let weights: Vec<f64> = masters
+        .iter()
+        .map(|master| {
+            if master.proxy_address == proxy_address {
+                0.0
+            } else {
+                1.0
+            }
+        })
+        .collect();
+    let mut nodes = match gen_planning_nodes(masters, &weights, &HashMap::new()) {
+        Ok(nodes) => nodes,
+        Err(PlanError::InvalidWeight) => return Err(PlanError::NoReceiver),
+        Err(err) => return Err(err),
+    };
+    let mut moves = gen_moves(&mut nodes, |node| node.proxy_address == proxy_address);
+
+    // The rounding could leave a few slots behind.
+    let leftovers: Vec<usize> = (0..nodes.len())
+        .filter(|i| nodes[*i].proxy_address == proxy_address && !nodes[*i].ranges.is_empty())
+        .collect();
+    for donor in leftovers.into_iter() {
+        let receiver = find_receiver(&nodes, donor).ok_or(PlanError::NoReceiver)?;
+        let slot_num = get_slot_num(&nodes[donor].ranges);
+        add_move(&mut moves, &mut nodes, donor, receiver, slot_num);
+    }
+
+    Ok(moves)
+}
+
+fn gen_planning_nodes(
+    masters: Vec<MasterSlots>,
+    weights: &[f64],
+    key_counts: &HashMap<String, u64>,
+) -> Result<Vec<PlanningNode>, PlanError> {
+    let total_weight: f64 = weights.iter().sum();
+    if masters.is_empty() || total_weight <= 0.0 {
+        return Err(PlanError::InvalidWeight);
+    }
+
+    let slot_nums: Vec<usize> = masters
+        .iter()
+        .map(|master| get_slot_num(&master.ranges))
+        .collect();
+    let densities = gen_densities(&masters, &slot_nums, key_counts);
+
+    let total_load: f64 = slot_nums
+        .iter()
+        .zip(densities.iter())
+        .map(|(n, d)| *n as f64 * d)
+        .sum();
+
+    let nodes = masters
+        .into_iter()
+        .enumerate()
+        .map(|(i, master)| {
+            let load = slot_nums[i] as f64 * densities[i];
+            let target = total_load * weights[i] / total_weight;
+            let mut ranges = master.ranges;
+            ranges.sort_by_key(|r| r.start);
+            PlanningNode {
+                node_address: master.node_address,
+                proxy_address: master.proxy_address,
+                ranges,
+                density: densities[i],
+                excess: load - target,
+            }
+        })
+        .collect();
+    Ok(nodes)
+}
+
+fn gen_moves<F>(nodes: &mut [PlanningNode], is_donor: F) -> Vec<SlotRangeMove>
+where
+    F: Fn(&PlanningNode) -> bool,
+{
+    let mut moves: Vec<SlotRangeMove> = vec![];
+    let max_iteration = 2 * nodes.len() * nodes.len() + 1;
+    for _ in 0..max_iteration {
+        let (donor, receiver) = match find_move_pair(nodes, &is_donor) {
+            Some(pair) => pair,
+            None => break,
+        };
+
+        let density = nodes[donor].density;
+        let amount = nodes[donor].excess.min(-nodes[receiver].excess);
+        // Never overshoot, or slots could be moved back and forth.
+        let slot_num =
+            ((amount / density + 1e-6).floor() as usize).min(get_slot_num(&nodes[donor].ranges));
+        if slot_num == 0 {
+            break;
+        }
+        add_move(&mut moves, nodes, donor, receiver, slot_num);
+    }
+    moves
+}
+
+fn add_move(
+    moves: &mut Vec<SlotRangeMove>,
+    nodes: &mut [PlanningNode],
+    donor: usize,
+    receiver: usize,
+    slot_num: usize,
+) {
+    let ranges = take_slots(&mut nodes[donor].ranges, slot_num);
+    let moved_load = slot_num as f64 * nodes[donor].density;
+    nodes[donor].excess -= moved_load;
+    nodes[receiver].excess += moved_load;
+
+    let src_node_address = nodes[donor].node_address.clone();
+    let dst_node_address = nodes[receiver].node_address.clone();
+    match moves
+        .iter_mut()
+        .find(|m| m.src_node_address == src_node_address && m.dst_node_address == dst_node_address)
+    {
+        Some(m) => {
+            m.ranges.extend(ranges);
+            m.ranges.sort_by_key(|r| r.start);
+        }
+        None => moves.push(SlotRangeMove {
+            src_node_address,
+            dst_node_address,
+            ranges,
+        }),
+    }
+}
+
+fn get_slot_num(ranges: &[Range]) -> usize {
+    ranges.iter().map(|r| r.end - r.start + 1).sum()
+}
+
+fn gen_densities(
+    masters: &[MasterSlots],
+    slot_nums: &[usize],
+    key_counts: &HashMap<String, u64>,
+) -> Vec<f64> {
+    if key_counts.is_empty() {
+        return vec![1.0; masters.len()];
+    }
+
+    let total_keys: u64 = masters
+        .iter()
+        .map(|master| key_counts.get(&master.node_address).cloned().unwrap_or(0))
+        .sum();
+    let total_slots: usize = slot_nums.iter().sum();
+    if total_keys == 0 || total_slots == 0 {
+        return vec![1.0; masters.len()];
+    }
+    let avg_density = total_keys as f64 / total_slots as f64;
+
+    masters
+        .iter()
+        .zip(slot_nums.iter())
+        .map(|(master, slot_num)| {
+            match (key_counts.get(&master.node_address), *slot_num) {
+                (Some(keys), n) if n > 0 && *keys > 0 => *keys as f64 / n as f64,
+                // Avoid moving slots without any cost.
+                _ => avg_density,
+            }
+        })
+        .collect()
+}
+
+fn find_move_pair<F>(nodes: &[PlanningNode], is_donor: F) -> Option<(usize, usize)>
+where
+    F: Fn(&PlanningNode) -> bool,
+{
+    let mut donors: Vec<usize> = (0..nodes.len())
+        .filter(|i| {
+            let node = &nodes[*i];
+            node.excess > 0.0 && !node.ranges.is_empty() && is_donor(node)
+        })
+        .collect();
+    donors.sort_by(|a, b| cmp_excess(&nodes[*b], &nodes[*a]));
+
+    for donor in donors.into_iter() {
+        let receiver = (0..nodes.len())
+            .filter(|i| nodes[*i].excess < 0.0)
+            .filter(|i| nodes[*i].proxy_address != nodes[donor].proxy_address)
+            .min_by(|a, b| cmp_excess(&nodes[*a], &nodes[*b]));
+        if let Some(receiver) = receiver {
+            return Some((donor, receiver));
+        }
+    }
+    None
+}
+
+fn find_receiver(nodes: &[PlanningNode], donor: usize) -> Option<usize> {
+    (0..nodes.len())
+        .filter(|i| nodes[*i].proxy_address != nodes[donor].proxy_address)
+        .min_by(|a, b| cmp_excess(&nodes[*a], &nodes[*b]))
+}
+
+fn cmp_excess(lhs: &PlanningNode, rhs: &PlanningNode) -> std::cmp::Ordering {
+    lhs.excess
+        .partial_cmp(&rhs.excess)
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+// Take `slot_num` slots from the tail of the sorted ranges.
+fn take_slots(ranges: &mut Vec<Range>, slot_num: usize) -> Vec<Range> {
+    let mut taken = vec![];
+    let mut left = slot_num;
+    while left > 0 {
+        let range = match ranges.pop() {
+            Some(range) => range,
+            None => break,
+        };
+        let num = range.end - range.start + 1;
+        if num <= left {
+            left -= num;
+            taken.push(range);
+        } else {
+            let split = range.end + 1 - left;
+            taken.push(Range {
+                start: split,
+                end: range.end,
+            });
+            ranges.push(Range {
+                start: range.start,
+                end: split - 1,
+            });
+            left = 0;
+        }
+    }
+    taken.reverse();
+    taken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_master(node: &str, proxy: &str, ranges: Vec<(usize, usize)>) -> MasterSlots {
+        MasterSlots {
+            node_address: node.to_string(),
+            proxy_address: proxy.to_string(),
+            ranges: ranges
+                .into_iter()
+                .map(|(start, end)| Range { start, end })
+                .collect(),
+        }
+    }
+
+    fn apply_moves(masters: &[MasterSlots], moves: &[SlotRangeMove]) -> HashMap<String, usize> {
+        let mut slots: HashMap<String, usize> = masters
+            .iter()
+            .map(|m| (m.node_address.clone(), get_slot_num(&m.ranges)))
+            .collect();
+        for m in moves.iter() {
+            *slots.get_mut(&m.src_node_address).unwrap() -= m.get_slot_num();
+            *slots.get_mut(&m.dst_node_address).unwrap() += m.get_slot_num();
+        }
+        slots
+    }
+
+    #[test]
+    fn test_rebalance_new_nodes() {
+        let masters = vec![
+            gen_master("redis1", "proxy1", vec![(0, 8191)]),
+            gen_master("redis2", "proxy2", vec![(8192, 16383)]),
+            gen_master("redis3", "proxy3", vec![]),
+            gen_master("redis4", "proxy4", vec![]),
+        ];
+        let moves = plan_rebalance(masters.clone(), &RebalanceOptions::default()).unwrap();
+        assert_eq!(moves.len(), 2);
+        let slots = apply_moves(&masters, &moves);
+        for n in slots.values() {
+            assert_eq!(*n, 4096);
+        }
+        for m in moves.iter() {
+            assert_eq!(m.ranges.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_rebalance_balanced() {
+        let masters = vec![
+            gen_master("redis1", "proxy1", vec![(0, 8191)]),
+            gen_master("redis2", "proxy2", vec![(8192, 16383)]),
+        ];
+        let moves = plan_rebalance(masters, &RebalanceOptions::default()).unwrap();
+        assert!(moves.is_empty());
+    }
+
+    #[test]
+    fn test_rebalance_skip_same_proxy() {
+        let masters = vec![
+            gen_master("redis1", "proxy1", vec![(0, 16383)]),
+            gen_master("redis2", "proxy1", vec![]),
+        ];
+        let moves = plan_rebalance(masters, &RebalanceOptions::default()).unwrap();
+        assert!(moves.is_empty());
+    }
+
+    #[test]
+    fn test_rebalance_with_weights() {
+        let masters = vec![
+            gen_master("redis1", "proxy1", vec![(0, 8191)]),
+            gen_master("redis2", "proxy2", vec![(8192, 16383)]),
+        ];
+        let mut options = RebalanceOptions::default();
+        options.weights.insert("redis1".to_string(), 3.0);
+        let moves = plan_rebalance(masters.clone(), &options).unwrap();
+        let slots = apply_moves(&masters, &moves);
+        assert_eq!(slots["redis1"], 12288);
+        assert_eq!(slots["redis2"], 4096);
+
+        options.weights.insert("redis2".to_string(), -1.0);
+        assert_eq!(
+            plan_rebalance(masters, &options).unwrap_err(),
+            PlanError::InvalidWeight
+        );
+    }
+
+    #[test]
+    fn test_rebalance_with_key_counts() {
+        let masters = vec![
+            gen_master("redis1", "proxy1", vec![(0, 8191)]),
+            gen_master("redis2", "proxy2", vec![(8192, 16383)]),
+        ];
+        let mut options = RebalanceOptions::default();
+        options.key_counts.insert("redis1".to_string(), 30000);
+        options.key_counts.insert("redis2".to_string(), 10000);
+        let moves = plan_rebalance(masters.clone(), &options).unwrap();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].src_node_address, "redis1");
+        let slots = apply_moves(&masters, &moves);
+        assert_eq!(slots["redis1"], 5462);
+    }
+
+    #[test]
+    fn test_drain() {
+        let masters = vec![
+            gen_master("redis1", "proxy1", vec![(0, 5460)]),
+            gen_master("redis2", "proxy1", vec![(5461, 10922)]),
+            gen_master("redis3", "proxy2", vec![(10923, 16383)]),
+            gen_master("redis4", "proxy3", vec![]),
+        ];
+        let moves = plan_drain(masters.clone(), "proxy1").unwrap();
+        for m in moves.iter() {
+            assert!(m.src_node_address == "redis1" || m.src_node_address == "redis2");
+        }
+        let slots = apply_moves(&masters, &moves);
+        assert_eq!(slots["redis1"], 0);
+        assert_eq!(slots["redis2"], 0);
+        assert_eq!(slots["redis3"] + slots["redis4"], 16384);
+        assert_eq!(slots["redis3"], 8192);
+
+        let masters = vec![gen_master("redis1", "proxy1", vec![(0, 16383)])];
+        assert_eq!(
+            plan_drain(masters, "proxy1").unwrap_err(),
+            PlanError::NoReceiver
+        );
+    }
+
+    #[test]
+    fn test_take_slots() {
+        let mut ranges = vec![Range { start: 0, end: 9 }, Range { start: 20, end: 24 }];
+        let taken = take_slots(&mut ranges, 7);
+        assert_eq!(
+            taken,
+            vec![Range { start: 8, end: 9 }, Range { start: 20, end: 24 }]
+        );
+        assert_eq!(ranges, vec![Range { start: 0, end: 7 }]);
+    }
+}
@@ -1,31 +1,83 @@
-use super::store::{MetaStore, MetaStoreError, MigrationType};
+use super::audit::{is_audited, AuditLog, AuditPayload, AuditRecord};
+use super::auth::{AuthError, Authenticator, Role};
+use super::placement::PlacementOptions;
+use super::planner::{MigrationPlan, RebalanceOptions};
+use super::snapshot::{list_snapshots, load_snapshot, save_snapshot, SnapshotError};
+use super::store::{
+    Inconsistency, MetaStore, MetaStoreError, MigrationType, PendingMigrationPlan,
+    ProxyResourceInfo, RepairOperation,
+};
+use super::switchover::{
+    choose_failover_replica, pause_writes, select_fallback_replica, wait_for_catch_up,
+    wait_for_final_catch_up, ReplOffsetError,
+};
 use crate::broker::store::InconsistentError;
-use crate::common::cluster::{Cluster, DBName, MigrationTaskMeta, Node, Proxy};
+use crate::common::cluster::{
+    Cluster, DBName, MetaChanges, MigrationTaskMeta, Node, NodeFailover, Proxy,
+};
+use crate::common::config::ClusterConfig;
 use crate::common::version::UNDERMOON_VERSION;
 use crate::coordinator::http_meta_broker::{
     ClusterNamesPayload, ClusterPayload, FailuresPayload, ProxyAddressesPayload, ProxyPayload,
 };
+use crate::protocol::PooledRedisClientFactory;
 use actix_web::{
-    error, http, middleware, App, HttpRequest, HttpResponse, Json, Path, Responder, State,
+    error, http, middleware, App, Body, FromRequest, FutureResponse, HttpRequest, HttpResponse,
+    Json, Path, Query, Responder, State,
 };
 use chrono;
+use futures::{Future, FutureExt, TryFutureExt};
+use futures_timer::Delay;
+use serde_json;
+use std::cmp;
+use std::collections::HashMap;
 use std::error::Error;
-use std::sync::{Arc, RwLock};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub fn gen_app(service: Arc<MemBrokerService>) -> App<Arc<MemBrokerService>> {
     App::with_state(service)
         .middleware(middleware::Logger::default())
+        .middleware(AuditMiddleware)
+        .middleware(AuthMiddleware)
+        .middleware(ValidationMiddleware)
+        .middleware(EpochMiddleware)
         .prefix("/api")
         .resource("/version", |r| r.method(http::Method::GET).f(get_version))
         .resource("/metadata", |r| {
-            r.method(http::Method::GET).f(get_all_metadata)
+            r.method(http::Method::GET).f(get_all_metadata);
+            r.method(http::Method::POST).with(import_metadata);
+        })
+        .resource("/snapshots/{name}/restore", |r| {
+            r.method(http::Method::POST).with(restore_snapshot)
+        })
+        .resource("/snapshots", |r| {
+            r.method(http::Method::GET).f(get_snapshots);
+            r.method(http::Method::POST).f(take_snapshot);
+        })
+        .resource("/validation/repair", |r| {
+            r.method(http::Method::POST).with(repair_meta)
         })
         .resource("/validation", |r| {
-            r.method(http::Method::POST).f(validate_meta)
+            r.method(http::Method::GET).f(get_validation_status);
+            r.method(http::Method::POST).f(validate_meta);
+        })
+        .resource("/metrics", |r| r.method(http::Method::GET).f(get_metrics))
+        .resource("/changes", |r| {
+            r.method(http::Method::GET).with(get_changes)
+        })
+        .resource("/audit", |r| {
+            r.method(http::Method::GET).with(get_audit_records)
         })
         .resource("/proxies/addresses", |r| {
             r.method(http::Method::GET).f(get_host_addresses)
         })
+        .resource("/proxies/resources", |r| {
+            r.method(http::Method::GET).with(get_proxy_resources)
+        })
         .resource("/proxies/nodes/{proxy_address}", |r| {
             r.method(http::Method::DELETE).with(remove_proxy)
         })
@@ -35,18 +87,28 @@ pub fn gen_app(service: Arc<MemBrokerService>) -> App<Arc<MemBrokerService>> {
         .resource("/proxies/failover/{address}", |r| {
             r.method(http::Method::POST).with(replace_failed_node)
         })
+        .resource("/nodes/failover/{node_address}", |r| {
+            r.method(http::Method::POST).with(failover_node)
+        })
         .resource("/proxies/meta/{address}", |r| {
             r.method(http::Method::GET).with(get_host_by_address)
         })
         .resource("/clusters/migrations", |r| {
             r.method(http::Method::PUT).with(commit_migration)
         })
+        .resource("/clusters/migrations/rollback/{proxy_address}", |r| {
+            r.method(http::Method::PUT).with(ack_migration_rollback)
+        })
         .resource("/clusters/meta/{cluster_name}", |r| {
             r.method(http::Method::GET).with(get_cluster_by_name)
         })
         .resource("/clusters/names", |r| {
             r.method(http::Method::GET).f(get_cluster_names)
         })
+        .resource(
+            "/clusters/{cluster_name}/nodes/{proxy_address}/drain",
+            |r| r.method(http::Method::POST).with(drain_proxy),
+        )
         .resource("/clusters/{cluster_name}/nodes/{proxy_address}", |r| {
             r.method(http::Method::DELETE)
                 .with(remove_proxy_from_cluster);
@@ -54,6 +116,21 @@ pub fn gen_app(service: Arc<MemBrokerService>) -> App<Arc<MemBrokerService>> {
         .resource("/clusters/{cluster_name}/nodes", |r| {
             r.method(http::Method::POST).with(auto_add_nodes)
         })
+        .resource("/clusters/{cluster_name}/rebalance/plan", |r| {
+            r.method(http::Method::POST).with(plan_rebalance)
+        })
+        .resource("/clusters/{cluster_name}/rebalance", |r| {
+            r.method(http::Method::POST).with(apply_migration_plan);
+            r.method(http::Method::GET).with(get_migration_plan);
+            r.method(http::Method::DELETE).with(remove_migration_plan);
+        })
+        .resource("/clusters/{cluster_name}/switchover/{node_address}", |r| {
+            r.method(http::Method::POST).with(switchover)
+        })
+        .resource("/clusters/{cluster_name}/config", |r| {
+            r.method(http::Method::GET).with(get_cluster_config);
+            r.method(http::Method::PATCH).with(change_cluster_config);
+        })
         .resource("/clusters/{cluster_name}", |r| {
             r.method(http::Method::POST).with(add_cluster);
             r.method(http::Method::DELETE).with(remove_cluster);
@@ -70,6 +147,10 @@ pub fn gen_app(service: Arc<MemBrokerService>) -> App<Arc<MemBrokerService>> {
             "/clusters/{cluster_name}/migrations/all/{src_node}/{dst_node}",
             |r| r.method(http::Method::POST).with(migrate_all_slots),
         )
+        .resource(
+            "/clusters/{cluster_name}/migrations/cancel/{src_node}/{dst_node}",
+            |r| r.method(http::Method::POST).with(cancel_migrations),
+        )
         .resource(
             "/clusters/{cluster_name}/migrations/{src_node}/{dst_node}",
             |r| r.method(http::Method::DELETE).with(stop_migrations),
@@ -78,24 +159,324 @@ pub fn gen_app(service: Arc<MemBrokerService>) -> App<Arc<MemBrokerService>> {
             "/clusters/{cluster_name}/replications/{master_node}/{replica_node}",
             |r| r.method(http::Method::POST).with(assign_replica),
         )
+        .resource("/clusters/{cluster_name}/replications/{master_node}", |r| {
+            r.method(http::Method::POST).with(add_replica)
+        })
 }
 
+const CHANGES_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_CHANGES_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_SWITCHOVER_TIMEOUT_SECS: u64 = 10;
+const MAX_SWITCHOVER_TIMEOUT_SECS: u64 = 60;
+const PLANNED_MIGRATION_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Clone)]
 pub struct MemBrokerConfig {
     pub address: String,
     pub failure_ttl: u64, // in seconds
+    // Disable the snapshots if it's empty.
+    pub snapshot_dir: String,
+    pub snapshot_interval: u64, // in seconds
+    pub snapshot_retention: usize,
+    pub audit_log_capacity: usize,
+    // Only keep the audit log in memory if it's empty.
+    pub audit_log_file: String,
+    // The authentication is disabled if all of them are empty.
+    pub readonly_tokens: Vec<String>,
+    pub operator_tokens: Vec<String>,
+    pub admin_tokens: Vec<String>,
+    pub validation_interval: u64, // in seconds
+    // Apply the proposed repair operations in the periodic validation.
+    pub auto_repair: bool,
+    // Promote only the replicas within this replication lag in failover.
+    // Disabled if it's None.
+    pub failover_max_lag: Option<u64>,
+    // Give up the lag check if the failover keeps getting refused for this long.
+    pub failover_max_wait: u64, // in seconds
+}
+
+struct FailoverWait {
+    start: Instant,
+    last_retry: Instant,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ValidationIssue {
+    error: String,
+    detail: String,
+    repair: Option<RepairOperation>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ValidationStatus {
+    // The global epoch of the validated metadata.
+    epoch: Option<u64>,
+    timestamp: i64, // unix timestamp in seconds
+    issues: Vec<ValidationIssue>,
 }
 
 pub struct MemBrokerService {
     config: MemBrokerConfig,
     store: Arc<RwLock<MetaStore>>,
+    last_snapshot_epoch: Mutex<Option<u64>>,
+    audit_log: AuditLog,
+    authenticator: Authenticator,
+    validation_status: Mutex<ValidationStatus>,
+    // Makes checking the epoch precondition and the mutation atomic.
+    mutation_lock: Mutex<()>,
+    // Runs the requests waiting for the proxies or Redis
+    // so that they won't block the http workers.
+    runtime: tokio::runtime::Runtime,
+    failover_max_wait: Duration,
+    // master node address => the refused failover
+    failover_waits: Mutex<HashMap<String, FailoverWait>>,
 }
 
 impl MemBrokerService {
     pub fn new(config: MemBrokerConfig) -> Self {
+        let audit_log = AuditLog::new(config.audit_log_capacity, &config.audit_log_file)
+            .unwrap_or_else(|err| {
+                error!(
+                    "failed to open audit log file {}: {:?}",
+                    config.audit_log_file, err
+                );
+                AuditLog::new(config.audit_log_capacity, "")
+                    .expect("MemBrokerService::new: memory audit log")
+            });
+        let authenticator = Authenticator::new(
+            &config.readonly_tokens,
+            &config.operator_tokens,
+            &config.admin_tokens,
+        );
+        let failover_max_wait = Duration::from_secs(config.failover_max_wait);
         Self {
             config,
             store: Arc::new(RwLock::new(MetaStore::default())),
+            last_snapshot_epoch: Mutex::new(None),
+            audit_log,
+            authenticator,
+            validation_status: Mutex::new(ValidationStatus::default()),
+            mutation_lock: Mutex::new(()),
+            runtime: tokio::runtime::Builder::new()
+                .threaded_scheduler()
+                .enable_all()
+                .build()
+                .expect("MemBrokerService::new: runtime"),
+            failover_max_wait,
+            failover_waits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn spawn_response<T, E, Fut>(&self, fut: Fut) -> FutureResponse<T>
+    where
+        T: Send + 'static,
+        E: Into<error::Error> + Send + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        let fut = self.runtime.spawn(fut).map(|res| match res {
+            Ok(res) => res.map_err(Into::into),
+            Err(err) => Err(error::ErrorInternalServerError(err)),
+        });
+        Box::new(fut.boxed().compat())
+    }
+
+    // The request is only used outside the runtime since it's not `Send`.
+    fn spawn_mutation_response<T, E, Fut>(&self, req: ServiceRequest, fut: Fut) -> FutureResponse<T>
+    where
+        T: Send + 'static,
+        E: Into<error::Error> + Send + 'static,
+        Fut: Future<Output = Result<(T, MutationEpochs), E>> + Send + 'static,
+    {
+        let fut = self.runtime.spawn(fut).map(move |res| match res {
+            Ok(Ok((res, epochs))) => {
+                req.extensions_mut().insert(epochs);
+                Ok(res)
+            }
+            Ok(Err(err)) => Err(err.into()),
+            Err(err) => Err(error::ErrorInternalServerError(err)),
+        });
+        Box::new(fut.boxed_local().compat())
+    }
+
+    pub fn get_global_epoch(&self) -> u64 {
+        self.store
+            .read()
+            .expect("MemBrokerService::get_global_epoch")
+            .get_global_epoch()
+    }
+
+    pub fn get_cluster_epoch(&self, cluster_name: &str) -> Option<u64> {
+        self.store
+            .read()
+            .expect("MemBrokerService::get_cluster_epoch")
+            .get_cluster_by_name(cluster_name)
+            .map(|cluster| cluster.get_epoch())
+    }
+
+    pub fn check_precondition(
+        &self,
+        precondition: &EpochPrecondition,
+        cluster_name: Option<&str>,
+    ) -> Result<(), MetaStoreError> {
+        if let Some(global_epoch) = precondition.global_epoch {
+            if global_epoch != self.get_global_epoch() {
+                return Err(MetaStoreError::MismatchEpoch);
+            }
+        }
+        match (precondition.cluster_epoch, cluster_name) {
+            (Some(cluster_epoch), Some(cluster_name)) => {
+                let epoch = self
+                    .get_cluster_epoch(cluster_name)
+                    .ok_or(MetaStoreError::ClusterNotFound)?;
+                if epoch != cluster_epoch {
+                    return Err(MetaStoreError::MismatchEpoch);
+                }
+                Ok(())
+            }
+            // The cluster epoch only works for the apis of a specific cluster.
+            (Some(_), None) => Err(MetaStoreError::InvalidRequest),
+            _ => Ok(()),
+        }
+    }
+
+    // All the mutations from the apis should go through this
+    // so that no other mutation could happen between the check and the mutation.
+    // The epochs are also read before releasing the lock
+    // so that they're exactly the ones resulting from this mutation.
+    pub fn update_with_precondition<T, E, F>(
+        &self,
+        precondition: &EpochPrecondition,
+        cluster_name: Option<&str>,
+        update: F,
+    ) -> Result<(T, MutationEpochs), E>
+    where
+        E: From<MetaStoreError>,
+        F: FnOnce() -> Result<T, E>,
+    {
+        let _guard = self
+            .mutation_lock
+            .lock()
+            .expect("MemBrokerService::update_with_precondition");
+        self.check_precondition(precondition, cluster_name)?;
+        let res = update()?;
+        let store = self
+            .store
+            .read()
+            .expect("MemBrokerService::update_with_precondition");
+        let epochs = MutationEpochs {
+            global_epoch: store.get_global_epoch(),
+            cluster_epoch: cluster_name
+                .and_then(|cluster_name| store.get_cluster_by_name(cluster_name))
+                .map(|cluster| cluster.get_epoch()),
+        };
+        Ok((res, epochs))
+    }
+
+    pub fn add_audit_record(&self, record: AuditRecord) {
+        self.audit_log.append(record)
+    }
+
+    pub fn get_audit_records(&self, since_timestamp: i64) -> Vec<AuditRecord> {
+        self.audit_log.get_records_since(since_timestamp)
+    }
+
+    // Returns None if the authentication is disabled.
+    pub fn authorize(
+        &self,
+        method: &http::Method,
+        path: &str,
+        authorization: Option<&str>,
+    ) -> Result<Option<Role>, AuthError> {
+        if !self.authenticator.is_enabled() {
+            return Ok(None);
+        }
+        self.authenticator
+            .authorize(method, path, authorization)
+            .map(Some)
+    }
+
+    // Only the empty store could be overwritten unless `force` is specified.
+    pub fn import_metadata(
+        &self,
+        mut metadata: MetaStore,
+        force: bool,
+        min_epoch: u64,
+    ) -> Result<(), MetaImportError> {
+        metadata.validate().map_err(MetaImportError::Inconsistent)?;
+
+        let mut store = self
+            .store
+            .write()
+            .expect("MemBrokerService::import_metadata");
+        if !force && !store.is_empty() {
+            return Err(MetaImportError::NotEmpty);
+        }
+        let min_epoch = cmp::max(min_epoch, store.get_global_epoch());
+        metadata.raise_epoch(min_epoch);
+        info!(
+            "metadata imported with epoch {}",
+            metadata.get_global_epoch()
+        );
+        *store = metadata;
+        Ok(())
+    }
+
+    pub fn restore_snapshot(
+        &self,
+        name: &str,
+        force: bool,
+        min_epoch: u64,
+    ) -> Result<(), MetaImportError> {
+        let metadata = load_snapshot(&self.config.snapshot_dir, name).map_err(|err| {
+            error!("failed to load snapshot {}: {:?}", name, err);
+            MetaImportError::Snapshot(err)
+        })?;
+        self.import_metadata(metadata, force, min_epoch)
+    }
+
+    pub fn get_snapshots(&self) -> Result<Vec<String>, MetaImportError> {
+        list_snapshots(&self.config.snapshot_dir).map_err(MetaImportError::Snapshot)
+    }
+
+    // Returns None if nothing changes since the last snapshot.
+    pub fn save_snapshot(&self) -> Result<Option<String>, MetaImportError> {
+        if self.config.snapshot_dir.is_empty() {
+            return Err(MetaImportError::SnapshotDisabled);
+        }
+
+        let mut last_snapshot_epoch = self
+            .last_snapshot_epoch
+            .lock()
+            .expect("MemBrokerService::save_snapshot");
+        let metadata = self.get_all_data();
+        let epoch = metadata.get_global_epoch();
+        if *last_snapshot_epoch == Some(epoch) {
+            return Ok(None);
+        }
+
+        let name = save_snapshot(
+            &self.config.snapshot_dir,
+            &metadata,
+            self.config.snapshot_retention,
+        )
+        .map_err(MetaImportError::Snapshot)?;
+        *last_snapshot_epoch = Some(epoch);
+        Ok(Some(name))
+    }
+
+    pub fn run_snapshot_loop(&self) {
+        if self.config.snapshot_dir.is_empty() || self.config.snapshot_interval == 0 {
+            info!("snapshot is disabled");
+            return;
+        }
+        let interval = Duration::from_secs(self.config.snapshot_interval);
+        loop {
+            thread::sleep(interval);
+            match self.save_snapshot() {
+                Ok(Some(name)) => info!("saved snapshot {}", name),
+                Ok(None) => debug!("metadata not changed, skip snapshot"),
+                Err(err) => error!("failed to save snapshot: {:?}", err),
+            }
         }
     }
 
@@ -113,6 +494,13 @@ impl MemBrokerService {
             .get_hosts()
     }
 
+    pub fn get_proxy_resources(&self, labels: &HashMap<String, String>) -> Vec<ProxyResourceInfo> {
+        self.store
+            .read()
+            .expect("MemBrokerService::get_proxy_resources")
+            .get_proxy_resources(labels)
+    }
+
     pub fn get_host_by_address(&self, address: &str) -> Option<Proxy> {
         self.store
             .read()
@@ -120,6 +508,22 @@ impl MemBrokerService {
             .get_host_by_address(address)
     }
 
+    // Long polling until the global epoch is larger than `since_epoch` or timeout.
+    pub async fn get_changes(self: Arc<Self>, since_epoch: u64, timeout: Duration) -> MetaChanges {
+        let start = Instant::now();
+        loop {
+            let changes = self
+                .store
+                .read()
+                .expect("MemBrokerService::get_changes")
+                .get_changes(since_epoch);
+            if changes.epoch > since_epoch || start.elapsed() >= timeout {
+                return changes;
+            }
+            Delay::new(CHANGES_POLL_INTERVAL).await;
+        }
+    }
+
     pub fn get_cluster_names(&self) -> Vec<DBName> {
         self.store
             .read()
@@ -138,18 +542,57 @@ impl MemBrokerService {
         let ProxyResource {
             proxy_address,
             nodes,
+            labels,
         } = host_resource;
         self.store
             .write()
             .expect("MemBrokerService::add_hosts")
-            .add_hosts(proxy_address, nodes)
+            .add_hosts(proxy_address, nodes, labels)
     }
 
-    pub fn add_cluster(&self, cluster_name: String) -> Result<(), MetaStoreError> {
+    pub fn add_cluster(
+        &self,
+        cluster_name: String,
+        config_fields: HashMap<String, String>,
+        placement_options: Option<PlacementOptions>,
+    ) -> Result<(), MetaStoreError> {
+        // Searching for the placement could take a while,
+        // so it's planned on the candidates without holding the lock.
+        let placement = match placement_options {
+            Some(options) => {
+                let candidates = self
+                    .store
+                    .read()
+                    .expect("MemBrokerService::add_cluster")
+                    .get_placement_candidates();
+                let placement =
+                    MetaStore::plan_cluster_placement(&cluster_name, candidates, &options)?;
+                Some((options, placement))
+            }
+            None => None,
+        };
         self.store
             .write()
             .expect("MemBrokerService::add_cluster")
-            .add_cluster(cluster_name)
+            .add_placed_cluster(cluster_name, config_fields, placement)
+    }
+
+    pub fn get_cluster_config(&self, cluster_name: &str) -> Result<ClusterConfig, MetaStoreError> {
+        self.store
+            .read()
+            .expect("MemBrokerService::get_cluster_config")
+            .get_cluster_config(cluster_name)
+    }
+
+    pub fn change_cluster_config(
+        &self,
+        cluster_name: String,
+        config_fields: HashMap<String, String>,
+    ) -> Result<(), MetaStoreError> {
+        self.store
+            .write()
+            .expect("MemBrokerService::change_cluster_config")
+            .change_cluster_config(cluster_name, config_fields)
     }
 
     pub fn remove_cluster(&self, cluster_name: String) -> Result<(), MetaStoreError> {
@@ -177,6 +620,18 @@ impl MemBrokerService {
             .remove_proxy_from_cluster(cluster_name, proxy_address)
     }
 
+    pub fn drain_proxy(
+        &self,
+        cluster_name: String,
+        proxy_address: String,
+        max_concurrent_migrations: usize,
+    ) -> Result<(), MetaStoreError> {
+        self.store
+            .write()
+            .expect("MemBrokerService::drain_proxy")
+            .drain_proxy(cluster_name, proxy_address, max_concurrent_migrations)
+    }
+
     pub fn remove_proxy(&self, proxy_address: String) -> Result<(), MetaStoreError> {
         self.store
             .write()
@@ -214,6 +669,29 @@ impl MemBrokerService {
             .stop_migrations(cluster_name, src_node_address, dst_node_address)
     }
 
+    pub fn cancel_migrations(
+        &self,
+        cluster_name: String,
+        src_node_address: String,
+        dst_node_address: String,
+    ) -> Result<(), MetaStoreError> {
+        self.store
+            .write()
+            .expect("MemBrokerService::cancel_migrations")
+            .cancel_migrations(cluster_name, src_node_address, dst_node_address)
+    }
+
+    pub fn ack_migration_rollback(
+        &self,
+        proxy_address: String,
+        task: MigrationTaskMeta,
+    ) -> Result<(), MetaStoreError> {
+        self.store
+            .write()
+            .expect("MemBrokerService::ack_migration_rollback")
+            .ack_migration_rollback(proxy_address, task)
+    }
+
     pub fn assign_replica(
         &self,
         cluster_name: String,
@@ -226,6 +704,123 @@ impl MemBrokerService {
             .assign_replica(cluster_name, master_node_address, replica_node_address)
     }
 
+    pub fn add_replica(
+        &self,
+        cluster_name: String,
+        master_node_address: String,
+    ) -> Result<Node, MetaStoreError> {
+        self.store
+            .write()
+            .expect("MemBrokerService::add_replica")
+            .add_replica(cluster_name, master_node_address)
+    }
+
+    // Promote the replica after it catches up with its master.
+    // The writes of the master are paused before the final check
+    // so that nothing written after the check gets lost.
+    // The precondition is only checked before waiting for the replication.
+    // Any later change of the cluster will fail the switchover.
+    pub async fn switchover(
+        self: Arc<Self>,
+        cluster_name: String,
+        replica_address: String,
+        max_lag: u64,
+        timeout: Duration,
+        precondition: EpochPrecondition,
+    ) -> Result<(Node, MutationEpochs), PromotionError> {
+        let ((master, replica, cluster_epoch), _) =
+            self.update_with_precondition(&precondition, Some(&cluster_name), || {
+                let cluster_name =
+                    DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
+                self.store
+                    .read()
+                    .expect("MemBrokerService::switchover")
+                    .get_switchover_master(&cluster_name, &replica_address)
+                    .map_err(PromotionError::Meta)
+            })?;
+        let db_name =
+            DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
+
+        let client_factory = PooledRedisClientFactory::new(1, Duration::from_secs(2));
+        wait_for_catch_up(&client_factory, &master, &replica, max_lag, timeout)
+            .await
+            .map_err(PromotionError::Replication)?;
+        // The pause expires by itself after the new metadata takes effect.
+        pause_writes(&client_factory, &master, timeout)
+            .await
+            .map_err(PromotionError::Replication)?;
+        wait_for_final_catch_up(&client_factory, &master, &replica, max_lag, timeout)
+            .await
+            .map_err(PromotionError::Replication)?;
+
+        let (node, epochs) = self.update_with_precondition(
+            &EpochPrecondition::default(),
+            Some(&cluster_name),
+            || {
+                self.store
+                    .write()
+                    .expect("MemBrokerService::switchover")
+                    .switchover(&db_name, &replica_address, cluster_epoch)
+                    .map_err(PromotionError::Meta)
+            },
+        )?;
+        info!(
+            "switched over master {} to {}",
+            master.get_address(),
+            replica_address
+        );
+        Ok((node, epochs))
+    }
+
+    pub fn plan_rebalance(
+        &self,
+        cluster_name: String,
+        options: &RebalanceOptions,
+    ) -> Result<MigrationPlan, MetaStoreError> {
+        self.store
+            .read()
+            .expect("MemBrokerService::plan_rebalance")
+            .plan_rebalance(cluster_name, options)
+    }
+
+    pub fn apply_migration_plan(
+        &self,
+        cluster_name: String,
+        plan: MigrationPlan,
+        max_concurrent_migrations: usize,
+    ) -> Result<(), MetaStoreError> {
+        self.store
+            .write()
+            .expect("MemBrokerService::apply_migration_plan")
+            .apply_migration_plan(cluster_name, plan, max_concurrent_migrations)
+    }
+
+    pub fn remove_migration_plan(&self, cluster_name: String) -> Result<(), MetaStoreError> {
+        self.store
+            .write()
+            .expect("MemBrokerService::remove_migration_plan")
+            .remove_migration_plan(cluster_name)
+    }
+
+    // The moves failed to start are kept in the plans.
+    pub fn retry_planned_migrations(&self) {
+        let _guard = self
+            .mutation_lock
+            .lock()
+            .expect("MemBrokerService::retry_planned_migrations");
+        self.store
+            .write()
+            .expect("MemBrokerService::retry_planned_migrations")
+            .schedule_all_planned_migrations()
+    }
+
+    pub fn get_migration_plan(&self, cluster_name: &str) -> Option<PendingMigrationPlan> {
+        self.store
+            .read()
+            .expect("MemBrokerService::get_migration_plan")
+            .get_migration_plan(cluster_name)
+    }
+
     pub fn get_failures(&self) -> Vec<String> {
         let failure_ttl = chrono::Duration::seconds(self.config.failure_ttl as i64);
         self.store
@@ -248,21 +843,316 @@ impl MemBrokerService {
             .commit_migration(task)
     }
 
-    pub fn replace_failed_node(
+    // Choose the replicas to take over the masters by the replication lags.
+    // master node address => replica node address
+    // This queries the proxies without holding the lock.
+    async fn choose_failover_replicas(
         &self,
+        candidates: Vec<(String, Vec<Node>)>,
+        force: bool,
+    ) -> Result<HashMap<String, String>, PromotionError> {
+        let max_lag = match self.config.failover_max_lag {
+            Some(max_lag) if !force => max_lag,
+            _ => return Ok(HashMap::new()),
+        };
+        if candidates.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let client_factory = PooledRedisClientFactory::new(1, Duration::from_secs(2));
+        let mut replicas = HashMap::new();
+        for (master_address, nodes) in candidates.into_iter() {
+            let res =
+                choose_failover_replica(&client_factory, &master_address, &nodes, max_lag).await;
+            let replica_address = match res {
+                Ok(replica_address) => replica_address,
+                Err(ReplOffsetError::LagTooLarge { lags, .. })
+                    if self.failover_waited_too_long(&master_address) =>
+                {
+                    let replica_address = select_fallback_replica(&lags)
+                        .unwrap_or_else(|| nodes[0].get_address().to_string());
+                    warn!(
+                        "failover {} to {} without a known lag within {} after {:?}, replica lags {:?}",
+                        master_address, replica_address, max_lag, self.failover_max_wait, lags
+                    );
+                    replica_address
+                }
+                Err(err) => return Err(PromotionError::Replication(err)),
+            };
+            self.failover_waits
+                .lock()
+                .expect("MemBrokerService::choose_failover_replicas")
+                .remove(&master_address);
+            replicas.insert(master_address, replica_address);
+        }
+        Ok(replicas)
+    }
+
+    // The lags could stay unknown if the proxies of the replicas are not reachable.
+    // Gives up the lag check after the failover of the master keeps getting refused
+    // for `failover_max_wait`. A long gap between the retries starts a new wait.
+    fn failover_waited_too_long(&self, master_address: &str) -> bool {
+        let now = Instant::now();
+        let mut waits = self
+            .failover_waits
+            .lock()
+            .expect("MemBrokerService::failover_waited_too_long");
+        let wait = waits
+            .entry(master_address.to_string())
+            .or_insert_with(|| FailoverWait {
+                start: now,
+                last_retry: now,
+            });
+        if now.duration_since(wait.last_retry) > self.failover_max_wait {
+            wait.start = now;
+        }
+        wait.last_retry = now;
+        now.duration_since(wait.start) >= self.failover_max_wait
+    }
+
+    pub async fn replace_failed_node(
+        self: Arc<Self>,
         failed_proxy_address: String,
-    ) -> Result<Proxy, MetaStoreError> {
-        self.store
-            .write()
-            .expect("MemBrokerService::replace_failed_node")
-            .replace_failed_proxy(failed_proxy_address)
+        force: bool,
+        precondition: EpochPrecondition,
+    ) -> Result<(Proxy, MutationEpochs), PromotionError> {
+        let (candidates, _) = self.update_with_precondition(&precondition, None, || {
+            self.store
+                .read()
+                .expect("MemBrokerService::replace_failed_node")
+                .get_proxy_failover_candidates(&failed_proxy_address)
+                .map_err(PromotionError::Meta)
+        })?;
+        let replicas = self.choose_failover_replicas(candidates, force).await?;
+        self.replace_failed_proxy(failed_proxy_address, &replicas, &precondition)
+    }
+
+    // The precondition is checked again since the lock is released
+    // while choosing the replicas.
+    // The replicas are still checked by the store
+    // in case the metadata changed without a precondition.
+    fn replace_failed_proxy(
+        &self,
+        failed_proxy_address: String,
+        replicas: &HashMap<String, String>,
+        precondition: &EpochPrecondition,
+    ) -> Result<(Proxy, MutationEpochs), PromotionError> {
+        self.update_with_precondition(precondition, None, || {
+            self.store
+                .write()
+                .expect("MemBrokerService::replace_failed_proxy")
+                .replace_failed_proxy(failed_proxy_address, replicas)
+                .map_err(PromotionError::Meta)
+        })
     }
 
+    pub async fn failover_node(
+        self: Arc<Self>,
+        failed_node_address: String,
+        force: bool,
+        precondition: EpochPrecondition,
+    ) -> Result<(NodeFailover, MutationEpochs), PromotionError> {
+        let (candidates, _) = self.update_with_precondition(&precondition, None, || {
+            self.store
+                .read()
+                .expect("MemBrokerService::failover_node")
+                .get_failover_candidates(&failed_node_address)
+                .map_err(PromotionError::Meta)
+        })?;
+        let candidates = if candidates.is_empty() {
+            vec![]
+        } else {
+            vec![(failed_node_address.clone(), candidates)]
+        };
+        let replicas = self.choose_failover_replicas(candidates, force).await?;
+
+        // Same as `replace_failed_proxy`.
+        self.update_with_precondition(&precondition, None, || {
+            self.store
+                .write()
+                .expect("MemBrokerService::failover_node")
+                .failover_node(
+                    &failed_node_address,
+                    replicas.get(&failed_node_address).map(String::as_str),
+                )
+                .map_err(PromotionError::Meta)
+        })
+    }
+
+    // Also refreshes the validation status so that it won't get validated again.
     pub fn validate_meta(&self) -> Result<(), InconsistentError> {
-        self.store
+        let (epoch, inconsistencies) = self.collect_inconsistencies();
+        self.record_validation_status(epoch, &inconsistencies);
+        match inconsistencies.into_iter().next() {
+            Some(inconsistency) => Err(inconsistency.error),
+            None => Ok(()),
+        }
+    }
+
+    fn collect_inconsistencies(&self) -> (u64, Vec<Inconsistency>) {
+        let store = self
+            .store
             .read()
-            .expect("MemBrokerService::validate_meta")
-            .validate()
+            .expect("MemBrokerService::collect_inconsistencies");
+        (store.get_global_epoch(), store.collect_inconsistencies())
+    }
+
+    pub fn refresh_validation_status(&self) -> ValidationStatus {
+        let (epoch, inconsistencies) = self.collect_inconsistencies();
+        self.record_validation_status(epoch, &inconsistencies)
+    }
+
+    fn record_validation_status(
+        &self,
+        epoch: u64,
+        inconsistencies: &[Inconsistency],
+    ) -> ValidationStatus {
+        let issues: Vec<ValidationIssue> = inconsistencies
+            .iter()
+            .map(|inconsistency| ValidationIssue {
+                error: inconsistency.error.error_code().to_string(),
+                detail: inconsistency.error.to_string(),
+                repair: inconsistency.repair.clone(),
+            })
+            .collect();
+        if !issues.is_empty() {
+            warn!(
+                "found {} inconsistencies in metadata of epoch {}",
+                issues.len(),
+                epoch
+            );
+        }
+
+        let status = ValidationStatus {
+            epoch: Some(epoch),
+            timestamp: chrono::Utc::now().timestamp(),
+            issues,
+        };
+        *self
+            .validation_status
+            .lock()
+            .expect("MemBrokerService::record_validation_status") = status.clone();
+        status
+    }
+
+    pub fn get_validation_status(&self) -> ValidationStatus {
+        let status = self
+            .validation_status
+            .lock()
+            .expect("MemBrokerService::get_validation_status")
+            .clone();
+        if status.epoch == Some(self.get_global_epoch()) {
+            return status;
+        }
+        self.refresh_validation_status()
+    }
+
+    // Only validate the metadata after it's changed.
+    pub fn validate_if_changed(&self) {
+        self.get_validation_status();
+    }
+
+    // Returns the applied repair operations.
+    // The metadata only needs to be validated again if anything gets repaired.
+    pub fn repair_meta(&self) -> Vec<RepairOperation> {
+        let mut applied = vec![];
+        let mut store = self.store.write().expect("MemBrokerService::repair_meta");
+        let inconsistencies = store.collect_inconsistencies();
+        for inconsistency in inconsistencies.iter() {
+            let repair = match inconsistency.repair.as_ref() {
+                Some(repair) => repair,
+                None => continue,
+            };
+            match store.apply_repair(repair) {
+                Ok(()) => {
+                    info!("repaired metadata: {:?}", repair);
+                    applied.push(repair.clone());
+                }
+                Err(err) => warn!("failed to repair metadata {:?}: {:?}", repair, err),
+            }
+        }
+        let (epoch, inconsistencies) = if applied.is_empty() {
+            (store.get_global_epoch(), inconsistencies)
+        } else {
+            (store.get_global_epoch(), store.collect_inconsistencies())
+        };
+        drop(store);
+        self.record_validation_status(epoch, &inconsistencies);
+        applied
+    }
+
+    pub fn run_planned_migration_loop(&self) {
+        loop {
+            thread::sleep(PLANNED_MIGRATION_RETRY_INTERVAL);
+            self.retry_planned_migrations();
+        }
+    }
+
+    pub fn run_validation_loop(&self) {
+        if self.config.validation_interval == 0 {
+            info!("periodic validation is disabled");
+            return;
+        }
+        let interval = Duration::from_secs(self.config.validation_interval);
+        loop {
+            thread::sleep(interval);
+            let status = self.get_validation_status();
+            let repairable = status.issues.iter().any(|issue| issue.repair.is_some());
+            if self.config.auto_repair && repairable {
+                let applied = self
+                    .update_with_precondition(&EpochPrecondition::default(), None, || {
+                        Ok::<_, MetaStoreError>(self.repair_meta())
+                    })
+                    .map(|(applied, _)| applied)
+                    .unwrap_or_default();
+                info!("applied {} repair operations", applied.len());
+            }
+        }
+    }
+
+    pub fn get_metrics(&self) -> String {
+        let status = self.get_validation_status();
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for issue in status.issues.iter() {
+            *counts.entry(issue.error.clone()).or_insert(0) += 1;
+        }
+        let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+        counts.sort();
+
+        let mut lines = vec![
+            "# HELP undermoon_broker_metadata_inconsistencies The number of inconsistencies found in the latest validation.".to_string(),
+            "# TYPE undermoon_broker_metadata_inconsistencies gauge".to_string(),
+            format!(
+                "undermoon_broker_metadata_inconsistencies {}",
+                status.issues.len()
+            ),
+            "# HELP undermoon_broker_metadata_inconsistencies_by_error The number of inconsistencies of each error in the latest validation.".to_string(),
+            "# TYPE undermoon_broker_metadata_inconsistencies_by_error gauge".to_string(),
+        ];
+        for (error, count) in counts.into_iter() {
+            lines.push(format!(
+                "undermoon_broker_metadata_inconsistencies_by_error{{error=\"{}\"}} {}",
+                error, count
+            ));
+        }
+        lines.push("# HELP undermoon_broker_metadata_validation_timestamp_seconds The time of the latest validation.".to_string());
+        lines.push(
+            "# TYPE undermoon_broker_metadata_validation_timestamp_seconds gauge".to_string(),
+        );
+        lines.push(format!(
+            "undermoon_broker_metadata_validation_timestamp_seconds {}",
+            status.timestamp
+        ));
+        lines.push(
+            "# HELP undermoon_broker_global_epoch The global epoch of the metadata.".to_string(),
+        );
+        lines.push("# TYPE undermoon_broker_global_epoch gauge".to_string());
+        lines.push(format!(
+            "undermoon_broker_global_epoch {}",
+            self.get_global_epoch()
+        ));
+        lines.push("".to_string());
+        lines.join("\n")
     }
 }
 
@@ -275,11 +1165,106 @@ fn get_all_metadata(request: &HttpRequest<Arc<MemBrokerService>>) -> impl Respon
     Json(metadata)
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct ImportOptions {
+    #[serde(default)]
+    force: bool,
+    // The epoch of the imported metadata will be larger than this.
+    #[serde(default)]
+    min_epoch: u64,
+}
+
+fn import_metadata(
+    (req, metadata, options, precondition): (
+        ServiceRequest,
+        Json<MetaStore>,
+        Query<ImportOptions>,
+        EpochPrecondition,
+    ),
+) -> Result<&'static str, MetaImportError> {
+    record_payload(&req, &*metadata);
+    let ImportOptions { force, min_epoch } = options.into_inner();
+    let state = req.state();
+    update_with_request(&req, &precondition, None, || {
+        state
+            .import_metadata(metadata.into_inner(), force, min_epoch)
+            .map(|()| "")
+    })
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SnapshotsPayload {
+    names: Vec<String>,
+}
+
+fn get_snapshots(
+    request: &HttpRequest<Arc<MemBrokerService>>,
+) -> Result<Json<SnapshotsPayload>, MetaImportError> {
+    let names = request.state().get_snapshots()?;
+    Ok(Json(SnapshotsPayload { names }))
+}
+
+fn take_snapshot(
+    request: &HttpRequest<Arc<MemBrokerService>>,
+) -> Result<Json<SnapshotsPayload>, MetaImportError> {
+    let names = request.state().save_snapshot()?.into_iter().collect();
+    Ok(Json(SnapshotsPayload { names }))
+}
+
+fn restore_snapshot(
+    (path, options, precondition, req): (
+        Path<(String,)>,
+        Query<ImportOptions>,
+        EpochPrecondition,
+        ServiceRequest,
+    ),
+) -> Result<&'static str, MetaImportError> {
+    let state = req.state();
+    let name = path.into_inner().0;
+    let ImportOptions { force, min_epoch } = options.into_inner();
+    update_with_request(&req, &precondition, None, || {
+        state.restore_snapshot(&name, force, min_epoch).map(|()| "")
+    })
+}
+
 fn get_host_addresses(request: &HttpRequest<Arc<MemBrokerService>>) -> impl Responder {
     let addresses = request.state().get_host_addresses();
     Json(ProxyAddressesPayload { addresses })
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct ProxyResourcesPayload {
+    proxies: Vec<ProxyResourceInfo>,
+}
+
+// The query parameters are used as the label filters.
+fn get_proxy_resources(
+    (labels, state): (Query<HashMap<String, String>>, ServiceState),
+) -> impl Responder {
+    let proxies = state.get_proxy_resources(&labels.into_inner());
+    Json(ProxyResourcesPayload { proxies })
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ChangesQuery {
+    #[serde(default)]
+    since: u64,
+    // In seconds. Return immediately by default.
+    #[serde(default)]
+    timeout: u64,
+}
+
+// The long polling runs on the runtime of the service instead of the http workers.
+fn get_changes(
+    (query, state): (Query<ChangesQuery>, ServiceState),
+) -> FutureResponse<Json<MetaChanges>> {
+    let ChangesQuery { since, timeout } = query.into_inner();
+    let timeout = Duration::from_secs(cmp::min(timeout, MAX_CHANGES_TIMEOUT_SECS));
+    let service = (*state).clone();
+    let fut = service.clone().get_changes(since, timeout);
+    service.spawn_response(fut.map(|changes| Ok::<_, error::Error>(Json(changes))))
+}
+
 fn get_host_by_address((path, state): (Path<(String,)>, ServiceState)) -> impl Responder {
     let name = path.into_inner().0;
     let host = state.get_host_by_address(&name);
@@ -306,130 +1291,722 @@ fn get_failures(request: &HttpRequest<Arc<MemBrokerService>>) -> impl Responder
 pub struct ProxyResource {
     proxy_address: String,
     nodes: Vec<String>,
+    // Replaces all the existing labels of the proxy.
+    #[serde(default)]
+    labels: HashMap<String, String>,
 }
 
 type ServiceState = State<Arc<MemBrokerService>>;
+type ServiceRequest = HttpRequest<Arc<MemBrokerService>>;
+
+fn record_payload<T: serde::Serialize>(req: &ServiceRequest, payload: &T) {
+    match serde_json::to_string(payload) {
+        Ok(payload) => {
+            req.extensions_mut().insert(AuditPayload::new(payload));
+        }
+        Err(err) => error!("failed to serialize audit payload: {:?}", err),
+    }
+}
+
+// Records the requests which could change the metadata.
+struct AuditMiddleware;
+
+impl middleware::Middleware<Arc<MemBrokerService>> for AuditMiddleware {
+    fn response(
+        &self,
+        req: &ServiceRequest,
+        resp: HttpResponse,
+    ) -> actix_web::Result<middleware::Response> {
+        if !is_audited(req.method(), req.path()) {
+            return Ok(middleware::Response::Done(resp));
+        }
+
+        let payload = req
+            .extensions()
+            .get::<AuditPayload>()
+            .map(|payload| payload.0.clone());
+        let error = if resp.status().is_success() {
+            None
+        } else {
+            match resp.body() {
+                Body::Binary(body) => Some(String::from_utf8_lossy(body.as_ref()).to_string()),
+                _ => Some("".to_string()),
+            }
+        };
+        let caller = req
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let role = req.extensions().get::<Role>().map(|role| role.to_string());
+        // Other mutations could have happened after this one.
+        let epoch = req
+            .extensions()
+            .get::<MutationEpochs>()
+            .map(|epochs| epochs.global_epoch)
+            .unwrap_or_else(|| req.state().get_global_epoch());
+        let record = AuditRecord {
+            id: 0,
+            timestamp: chrono::Utc::now().timestamp(),
+            method: req.method().to_string(),
+            route: req.uri().to_string(),
+            caller,
+            role,
+            payload,
+            epoch,
+            status: resp.status().as_u16(),
+            error,
+        };
+        req.state().add_audit_record(record);
+        Ok(middleware::Response::Done(resp))
+    }
+}
+
+// Checks the bearer token against the role required by the route.
+struct AuthMiddleware;
+
+impl middleware::Middleware<Arc<MemBrokerService>> for AuthMiddleware {
+    fn start(&self, req: &ServiceRequest) -> actix_web::Result<middleware::Started> {
+        let authorization = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        match req
+            .state()
+            .authorize(req.method(), req.path(), authorization)
+        {
+            Ok(Some(role)) => {
+                req.extensions_mut().insert(role);
+                Ok(middleware::Started::Done)
+            }
+            Ok(None) => Ok(middleware::Started::Done),
+            Err(err) => {
+                let mut resp = match err {
+                    AuthError::Unauthorized => HttpResponse::Unauthorized(),
+                    AuthError::Forbidden { .. } => HttpResponse::Forbidden(),
+                };
+                if let AuthError::Unauthorized = err {
+                    resp.header(http::header::WWW_AUTHENTICATE, "Bearer");
+                }
+                Ok(middleware::Started::Response(resp.body(err.to_string())))
+            }
+        }
+    }
+}
+
+pub const CLUSTER_EPOCH_HEADER: &str = "X-Cluster-Epoch";
+
+// The optional precondition of the mutating requests for the optimistic concurrency control.
+// `If-Match` carries the expected global epoch which is returned in `ETag`,
+// and `X-Cluster-Epoch` carries the expected epoch of the cluster in the path.
+#[derive(Debug, Clone, Default)]
+pub struct EpochPrecondition {
+    global_epoch: Option<u64>,
+    cluster_epoch: Option<u64>,
+}
+
+impl<S> FromRequest<S> for EpochPrecondition {
+    type Config = ();
+    type Result = Result<Self, actix_web::Error>;
+
+    fn from_request(req: &HttpRequest<S>, _cfg: &Self::Config) -> Self::Result {
+        let parse_epoch = |name: &str| -> Result<Option<u64>, actix_web::Error> {
+            let value = match req.headers().get(name) {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+            value
+                .to_str()
+                .ok()
+                .map(|value| value.trim().trim_matches('"'))
+                .and_then(|value| value.parse::<u64>().ok())
+                .map(Some)
+                .ok_or_else(|| error::ErrorBadRequest(format!("INVALID_EPOCH: {}", name)))
+        };
+        Ok(Self {
+            global_epoch: parse_epoch(http::header::IF_MATCH.as_str())?,
+            cluster_epoch: parse_epoch(CLUSTER_EPOCH_HEADER)?,
+        })
+    }
+}
+
+// The epochs right after a mutation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MutationEpochs {
+    pub global_epoch: u64,
+    pub cluster_epoch: Option<u64>,
+}
+
+// Records the epochs of the mutation for the `EpochMiddleware`.
+fn update_with_request<T, E, F>(
+    req: &ServiceRequest,
+    precondition: &EpochPrecondition,
+    cluster_name: Option<&str>,
+    update: F,
+) -> Result<T, E>
+where
+    E: From<MetaStoreError>,
+    F: FnOnce() -> Result<T, E>,
+{
+    let (res, epochs) = req
+        .state()
+        .update_with_precondition(precondition, cluster_name, update)?;
+    req.extensions_mut().insert(epochs);
+    Ok(res)
+}
+
+// Returns the resulting epochs so that the clients could do read-modify-write.
+// The successful mutations return the epochs they result in,
+// while the others return the current ones.
+struct EpochMiddleware;
+
+impl middleware::Middleware<Arc<MemBrokerService>> for EpochMiddleware {
+    fn response(
+        &self,
+        req: &ServiceRequest,
+        mut resp: HttpResponse,
+    ) -> actix_web::Result<middleware::Response> {
+        let mutation_epochs = req.extensions().get::<MutationEpochs>().cloned();
+        let state = req.state();
+        let cluster_name = req.match_info().get("cluster_name");
+        let (global_epoch, cluster_epoch) = match mutation_epochs {
+            Some(epochs) => (epochs.global_epoch, epochs.cluster_epoch),
+            None => (
+                state.get_global_epoch(),
+                cluster_name.and_then(|cluster_name| state.get_cluster_epoch(cluster_name)),
+            ),
+        };
+        let global_epoch = format!("\"{}\"", global_epoch);
+        if let Ok(value) = http::header::HeaderValue::from_str(&global_epoch) {
+            resp.headers_mut().insert(http::header::ETAG, value);
+        }
+        if let Some(epoch) = cluster_epoch {
+            resp.headers_mut()
+                .insert(CLUSTER_EPOCH_HEADER, http::header::HeaderValue::from(epoch));
+        }
+        Ok(middleware::Response::Done(resp))
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AuditQuery {
+    // Unix timestamp in seconds.
+    #[serde(default)]
+    since: i64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AuditRecordsPayload {
+    records: Vec<AuditRecord>,
+}
+
+fn get_audit_records((query, state): (Query<AuditQuery>, ServiceState)) -> impl Responder {
+    let records = state.get_audit_records(query.since);
+    Json(AuditRecordsPayload { records })
+}
 
 fn add_host(
-    (host_resource, state): (Json<ProxyResource>, ServiceState),
+    (req, host_resource, precondition): (ServiceRequest, Json<ProxyResource>, EpochPrecondition),
 ) -> Result<&'static str, MetaStoreError> {
-    state.add_hosts(host_resource.into_inner()).map(|()| "")
+    record_payload(&req, &*host_resource);
+    let state = req.state();
+    update_with_request(&req, &precondition, None, || {
+        state.add_hosts(host_resource.into_inner()).map(|()| "")
+    })
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CreateClusterPayload {
+    #[serde(default)]
+    config: HashMap<String, String>,
+    // Only choose the nodes by the placement constraints when `master_num` is specified.
+    #[serde(default)]
+    master_num: Option<usize>,
+    #[serde(default)]
+    replicas_per_master: usize,
+    #[serde(default)]
+    anti_affinity: Vec<String>,
 }
 
+// The body is optional so that the clusters could still be created without any payload.
 fn add_cluster(
+    (req, path, body, precondition): (ServiceRequest, Path<(String,)>, String, EpochPrecondition),
+) -> Result<&'static str, MetaStoreError> {
+    req.extensions_mut().insert(AuditPayload::new(body.clone()));
+    let cluster_name = path.into_inner().0;
+    let payload = if body.trim().is_empty() {
+        CreateClusterPayload {
+            config: HashMap::new(),
+            master_num: None,
+            replicas_per_master: 0,
+            anti_affinity: vec![],
+        }
+    } else {
+        serde_json::from_str::<CreateClusterPayload>(&body).map_err(|err| {
+            warn!("invalid create cluster payload: {:?}", err);
+            MetaStoreError::InvalidRequest
+        })?
+    };
+    let CreateClusterPayload {
+        config,
+        master_num,
+        replicas_per_master,
+        anti_affinity,
+    } = payload;
+    let placement_options = master_num.map(|master_num| PlacementOptions {
+        master_num,
+        replicas_per_master,
+        anti_affinity,
+    });
+    // The cluster does not exist yet so only the global epoch could be checked.
+    if precondition.cluster_epoch.is_some() {
+        return Err(MetaStoreError::InvalidRequest);
+    }
+    let state = req.state();
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .add_cluster(cluster_name.clone(), config, placement_options)
+            .map(|()| "")
+    })
+}
+
+fn get_cluster_config(
     (path, state): (Path<(String,)>, ServiceState),
+) -> Result<Json<HashMap<String, String>>, MetaStoreError> {
+    let cluster_name = path.into_inner().0;
+    state
+        .get_cluster_config(&cluster_name)
+        .map(|config| Json(config.to_str_map()))
+}
+
+type ConfigFieldsPayload = Json<HashMap<String, String>>;
+
+fn change_cluster_config(
+    (req, path, config_fields, precondition): (
+        ServiceRequest,
+        Path<(String,)>,
+        ConfigFieldsPayload,
+        EpochPrecondition,
+    ),
 ) -> Result<&'static str, MetaStoreError> {
+    record_payload(&req, &*config_fields);
     let cluster_name = path.into_inner().0;
-    state.add_cluster(cluster_name).map(|()| "")
+    let state = req.state();
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .change_cluster_config(cluster_name.clone(), config_fields.into_inner())
+            .map(|()| "")
+    })
 }
 
 fn remove_cluster(
-    (path, state): (Path<(String,)>, ServiceState),
+    (path, precondition, req): (Path<(String,)>, EpochPrecondition, ServiceRequest),
 ) -> Result<&'static str, MetaStoreError> {
+    let state = req.state();
     let cluster_name = path.into_inner().0;
-    state.remove_cluster(cluster_name).map(|()| "")
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state.remove_cluster(cluster_name.clone()).map(|()| "")
+    })
 }
 
 fn auto_add_nodes(
-    (path, state): (Path<(String,)>, ServiceState),
+    (path, precondition, req): (Path<(String,)>, EpochPrecondition, ServiceRequest),
 ) -> Result<Json<Vec<Node>>, MetaStoreError> {
+    let state = req.state();
     let cluster_name = path.into_inner().0;
-    state.auto_add_node(cluster_name).map(Json)
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state.auto_add_node(cluster_name.clone()).map(Json)
+    })
 }
 
 fn remove_proxy_from_cluster(
-    (path, state): (Path<(String, String)>, ServiceState),
+    (path, precondition, req): (Path<(String, String)>, EpochPrecondition, ServiceRequest),
 ) -> Result<&'static str, MetaStoreError> {
+    let state = req.state();
     let (cluster_name, proxy_address) = path.into_inner();
-    state
-        .remove_proxy_from_cluster(cluster_name, proxy_address)
-        .map(|()| "")
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .remove_proxy_from_cluster(cluster_name.clone(), proxy_address)
+            .map(|()| "")
+    })
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct DrainOptions {
+    max_concurrent_migrations: Option<usize>,
+}
+
+const DEFAULT_DRAIN_CONCURRENCY: usize = 1;
+
+fn drain_proxy(
+    (path, options, precondition, req): (
+        Path<(String, String)>,
+        Query<DrainOptions>,
+        EpochPrecondition,
+        ServiceRequest,
+    ),
+) -> Result<&'static str, MetaStoreError> {
+    let state = req.state();
+    let (cluster_name, proxy_address) = path.into_inner();
+    let max_concurrent_migrations = options
+        .max_concurrent_migrations
+        .unwrap_or(DEFAULT_DRAIN_CONCURRENCY);
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .drain_proxy(
+                cluster_name.clone(),
+                proxy_address,
+                max_concurrent_migrations,
+            )
+            .map(|()| "")
+    })
 }
 
 fn remove_proxy(
-    (path, state): (Path<(String,)>, ServiceState),
+    (path, precondition, req): (Path<(String,)>, EpochPrecondition, ServiceRequest),
 ) -> Result<&'static str, MetaStoreError> {
+    let state = req.state();
     let (proxy_address,) = path.into_inner();
-    state.remove_proxy(proxy_address).map(|()| "")
+    update_with_request(&req, &precondition, None, || {
+        state.remove_proxy(proxy_address).map(|()| "")
+    })
 }
 
-fn migrate_half_slots(
-    (path, state): (Path<(String, String, String)>, ServiceState),
+fn migrate_slots(
+    path: Path<(String, String, String)>,
+    precondition: EpochPrecondition,
+    req: ServiceRequest,
+    migration_type: MigrationType,
 ) -> Result<&'static str, MetaStoreError> {
+    let state = req.state();
     let (cluster_name, src_node_address, dst_node_address) = path.into_inner();
-    state
-        .migrate_slots(
-            cluster_name,
-            src_node_address,
-            dst_node_address,
-            MigrationType::Half,
-        )
-        .map(|()| "")
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .migrate_slots(
+                cluster_name.clone(),
+                src_node_address,
+                dst_node_address,
+                migration_type,
+            )
+            .map(|()| "")
+    })
+}
+
+fn migrate_half_slots(
+    (path, precondition, req): (
+        Path<(String, String, String)>,
+        EpochPrecondition,
+        ServiceRequest,
+    ),
+) -> Result<&'static str, MetaStoreError> {
+    migrate_slots(path, precondition, req, MigrationType::Half)
 }
 
 fn migrate_all_slots(
-    (path, state): (Path<(String, String, String)>, ServiceState),
+    (path, precondition, req): (
+        Path<(String, String, String)>,
+        EpochPrecondition,
+        ServiceRequest,
+    ),
 ) -> Result<&'static str, MetaStoreError> {
-    let (cluster_name, src_node_address, dst_node_address) = path.into_inner();
-    state
-        .migrate_slots(
-            cluster_name,
-            src_node_address,
-            dst_node_address,
-            MigrationType::All,
-        )
-        .map(|()| "")
+    migrate_slots(path, precondition, req, MigrationType::All)
 }
 
 fn stop_migrations(
-    (path, state): (Path<(String, String, String)>, ServiceState),
+    (path, precondition, req): (
+        Path<(String, String, String)>,
+        EpochPrecondition,
+        ServiceRequest,
+    ),
 ) -> Result<&'static str, MetaStoreError> {
+    let state = req.state();
     let (cluster_name, src_node_address, dst_node_address) = path.into_inner();
-    state
-        .stop_migrations(cluster_name, src_node_address, dst_node_address)
-        .map(|()| "")
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .stop_migrations(cluster_name.clone(), src_node_address, dst_node_address)
+            .map(|()| "")
+    })
+}
+
+fn cancel_migrations(
+    (path, precondition, req): (
+        Path<(String, String, String)>,
+        EpochPrecondition,
+        ServiceRequest,
+    ),
+) -> Result<&'static str, MetaStoreError> {
+    let state = req.state();
+    let (cluster_name, src_node_address, dst_node_address) = path.into_inner();
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .cancel_migrations(cluster_name.clone(), src_node_address, dst_node_address)
+            .map(|()| "")
+    })
 }
 
 fn assign_replica(
-    (path, state): (Path<(String, String, String)>, ServiceState),
+    (path, precondition, req): (
+        Path<(String, String, String)>,
+        EpochPrecondition,
+        ServiceRequest,
+    ),
 ) -> Result<&'static str, MetaStoreError> {
+    let state = req.state();
     let (cluster_name, master_node_address, replica_node_address) = path.into_inner();
-    state
-        .assign_replica(cluster_name, master_node_address, replica_node_address)
-        .map(|()| "")
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .assign_replica(
+                cluster_name.clone(),
+                master_node_address,
+                replica_node_address,
+            )
+            .map(|()| "")
+    })
+}
+
+fn add_replica(
+    (path, precondition, req): (Path<(String, String)>, EpochPrecondition, ServiceRequest),
+) -> Result<Json<Node>, MetaStoreError> {
+    let state = req.state();
+    let (cluster_name, master_node_address) = path.into_inner();
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .add_replica(cluster_name.clone(), master_node_address)
+            .map(Json)
+    })
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SwitchoverOptions {
+    // The replica could be promoted if its replication offset is within this lag.
+    #[serde(default)]
+    max_lag: u64,
+    // In seconds.
+    #[serde(default)]
+    timeout: Option<u64>,
+}
+
+fn switchover(
+    (path, options, precondition, req): (
+        Path<(String, String)>,
+        Query<SwitchoverOptions>,
+        EpochPrecondition,
+        ServiceRequest,
+    ),
+) -> FutureResponse<Json<Node>> {
+    let (cluster_name, node_address) = path.into_inner();
+    let timeout = options.timeout.unwrap_or(DEFAULT_SWITCHOVER_TIMEOUT_SECS);
+    let timeout = Duration::from_secs(cmp::min(timeout, MAX_SWITCHOVER_TIMEOUT_SECS));
+    let service = (*req.state()).clone();
+    let fut = service.clone().switchover(
+        cluster_name,
+        node_address,
+        options.max_lag,
+        timeout,
+        precondition,
+    );
+    service.spawn_mutation_response(req, fut.map_ok(|(res, epochs)| (Json(res), epochs)))
+}
+
+fn plan_rebalance(
+    (path, options, state): (Path<(String,)>, Json<RebalanceOptions>, ServiceState),
+) -> Result<Json<MigrationPlan>, MetaStoreError> {
+    let (cluster_name,) = path.into_inner();
+    state.plan_rebalance(cluster_name, &options).map(Json)
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ApplyMigrationPlanPayload {
+    plan: MigrationPlan,
+    max_concurrent_migrations: usize,
+}
+
+fn apply_migration_plan(
+    (req, path, payload, precondition): (
+        ServiceRequest,
+        Path<(String,)>,
+        Json<ApplyMigrationPlanPayload>,
+        EpochPrecondition,
+    ),
+) -> Result<&'static str, MetaStoreError> {
+    record_payload(&req, &*payload);
+    let (cluster_name,) = path.into_inner();
+    let ApplyMigrationPlanPayload {
+        plan,
+        max_concurrent_migrations,
+    } = payload.into_inner();
+    let state = req.state();
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .apply_migration_plan(cluster_name.clone(), plan, max_concurrent_migrations)
+            .map(|()| "")
+    })
 }
 
-fn add_failure((path, state): (Path<(String, String)>, ServiceState)) -> &'static str {
+#[derive(Deserialize, Serialize)]
+pub struct MigrationPlanPayload {
+    plan: Option<PendingMigrationPlan>,
+}
+
+fn get_migration_plan((path, state): (Path<(String,)>, ServiceState)) -> impl Responder {
+    let (cluster_name,) = path.into_inner();
+    let plan = state.get_migration_plan(&cluster_name);
+    Json(MigrationPlanPayload { plan })
+}
+
+fn remove_migration_plan(
+    (path, precondition, req): (Path<(String,)>, EpochPrecondition, ServiceRequest),
+) -> Result<&'static str, MetaStoreError> {
+    let state = req.state();
+    let (cluster_name,) = path.into_inner();
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .remove_migration_plan(cluster_name.clone())
+            .map(|()| "")
+    })
+}
+
+// Reporting a failure bumps the global epoch like the other mutations,
+// so it also invalidates the `If-Match` preconditions based on the previous epoch.
+fn add_failure(
+    (path, req): (Path<(String, String)>, ServiceRequest),
+) -> Result<&'static str, MetaStoreError> {
+    let state = req.state();
     let (server_proxy_address, reporter_id) = path.into_inner();
-    state.add_failure(server_proxy_address, reporter_id);
-    ""
+    update_with_request(&req, &EpochPrecondition::default(), None, || {
+        state.add_failure(server_proxy_address, reporter_id);
+        Ok("")
+    })
 }
 
 fn commit_migration(
-    (task, state): (Json<MigrationTaskMeta>, ServiceState),
+    (req, task, precondition): (ServiceRequest, Json<MigrationTaskMeta>, EpochPrecondition),
 ) -> Result<&'static str, MetaStoreError> {
-    state.commit_migration(task.into_inner()).map(|()| "")
+    record_payload(&req, &*task);
+    let state = req.state();
+    let cluster_name = task.db_name.to_string();
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state.commit_migration(task.into_inner()).map(|()| "")
+    })
+}
+
+fn ack_migration_rollback(
+    (req, path, task, precondition): (
+        ServiceRequest,
+        Path<(String,)>,
+        Json<MigrationTaskMeta>,
+        EpochPrecondition,
+    ),
+) -> Result<&'static str, MetaStoreError> {
+    record_payload(&req, &*task);
+    let (proxy_address,) = path.into_inner();
+    let state = req.state();
+    let cluster_name = task.db_name.to_string();
+    update_with_request(&req, &precondition, Some(&cluster_name), || {
+        state
+            .ack_migration_rollback(proxy_address, task.into_inner())
+            .map(|()| "")
+    })
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FailoverOptions {
+    // Skip the replication lag check.
+    #[serde(default)]
+    force: bool,
 }
 
 fn replace_failed_node(
-    (path, state): (Path<(String,)>, ServiceState),
-) -> Result<Json<Proxy>, MetaStoreError> {
+    (path, options, precondition, req): (
+        Path<(String,)>,
+        Query<FailoverOptions>,
+        EpochPrecondition,
+        ServiceRequest,
+    ),
+) -> FutureResponse<Json<Proxy>> {
     let (proxy_address,) = path.into_inner();
-    state.replace_failed_node(proxy_address).map(Json)
+    let service = (*req.state()).clone();
+    let fut = service
+        .clone()
+        .replace_failed_node(proxy_address, options.force, precondition);
+    service.spawn_mutation_response(req, fut.map_ok(|(res, epochs)| (Json(res), epochs)))
+}
+
+fn failover_node(
+    (path, options, precondition, req): (
+        Path<(String,)>,
+        Query<FailoverOptions>,
+        EpochPrecondition,
+        ServiceRequest,
+    ),
+) -> FutureResponse<Json<NodeFailover>> {
+    let (node_address,) = path.into_inner();
+    let service = (*req.state()).clone();
+    let fut = service
+        .clone()
+        .failover_node(node_address, options.force, precondition);
+    service.spawn_mutation_response(req, fut.map_ok(|(res, epochs)| (Json(res), epochs)))
 }
 
 fn validate_meta(req: &HttpRequest<Arc<MemBrokerService>>) -> Result<String, InconsistentError> {
     req.state().validate_meta().map(|()| "".to_string())
 }
 
+fn get_validation_status(req: &HttpRequest<Arc<MemBrokerService>>) -> impl Responder {
+    Json(req.state().get_validation_status())
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RepairPayload {
+    repairs: Vec<RepairOperation>,
+    status: ValidationStatus,
+}
+
+fn repair_meta(
+    (precondition, req): (EpochPrecondition, ServiceRequest),
+) -> Result<Json<RepairPayload>, MetaStoreError> {
+    let state = req.state();
+    let repairs = update_with_request(&req, &precondition, None, || {
+        Ok::<_, MetaStoreError>(state.repair_meta())
+    })?;
+    let status = state.get_validation_status();
+    Ok(Json(RepairPayload { repairs, status }))
+}
+
+fn get_metrics(req: &HttpRequest<Arc<MemBrokerService>>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(req.state().get_metrics())
+}
+
+// Validates the metadata after every mutation.
+struct ValidationMiddleware;
+
+impl middleware::Middleware<Arc<MemBrokerService>> for ValidationMiddleware {
+    fn response(
+        &self,
+        req: &ServiceRequest,
+        resp: HttpResponse,
+    ) -> actix_web::Result<middleware::Response> {
+        if req.method() != http::Method::GET && req.method() != http::Method::HEAD {
+            req.state().validate_if_changed();
+        }
+        Ok(middleware::Response::Done(resp))
+    }
+}
+
 impl error::ResponseError for MetaStoreError {
     fn error_response(&self) -> HttpResponse {
         let status_code = match self {
-            MetaStoreError::NoAvailableResource => http::StatusCode::CONFLICT,
+            MetaStoreError::NoAvailableResource(_) => http::StatusCode::CONFLICT,
+            MetaStoreError::MismatchEpoch => http::StatusCode::PRECONDITION_FAILED,
             _ => http::StatusCode::BAD_REQUEST,
         };
         let mut response = HttpResponse::new(status_code);
-        response.set_body(self.description().to_string());
+        let mut body = self.description().to_string();
+        if let MetaStoreError::NoAvailableResource(reason) = self {
+            body = format!("{}: {}", body, reason);
+        }
+        response.set_body(body);
         response
     }
 }
@@ -441,3 +2018,300 @@ impl error::ResponseError for InconsistentError {
         response
     }
 }
+
+#[derive(Debug)]
+pub enum PromotionError {
+    Meta(MetaStoreError),
+    Replication(ReplOffsetError),
+    Io(io::Error),
+}
+
+impl fmt::Display for PromotionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Meta(err) => write!(f, "{}", err),
+            Self::Replication(err) => write!(f, "{}", err),
+            Self::Io(err) => write!(f, "IO_ERROR: {}", err),
+        }
+    }
+}
+
+impl Error for PromotionError {}
+
+impl From<MetaStoreError> for PromotionError {
+    fn from(err: MetaStoreError) -> Self {
+        Self::Meta(err)
+    }
+}
+
+impl error::ResponseError for PromotionError {
+    fn error_response(&self) -> HttpResponse {
+        let status_code = match self {
+            Self::Meta(err) => return err.error_response(),
+            Self::Replication(ReplOffsetError::NotCaughtUp { .. })
+            | Self::Replication(ReplOffsetError::LagTooLarge { .. }) => http::StatusCode::CONFLICT,
+            Self::Replication(_) => http::StatusCode::BAD_GATEWAY,
+            Self::Io(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let mut response = HttpResponse::new(status_code);
+        response.set_body(self.to_string());
+        response
+    }
+}
+
+#[derive(Debug)]
+pub enum MetaImportError {
+    NotEmpty,
+    SnapshotDisabled,
+    Inconsistent(InconsistentError),
+    Snapshot(SnapshotError),
+    // The epoch precondition is not satisfied.
+    Precondition(MetaStoreError),
+}
+
+impl From<MetaStoreError> for MetaImportError {
+    fn from(err: MetaStoreError) -> Self {
+        Self::Precondition(err)
+    }
+}
+
+impl fmt::Display for MetaImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for MetaImportError {
+    fn description(&self) -> &str {
+        match self {
+            MetaImportError::NotEmpty => "NOT_EMPTY",
+            MetaImportError::SnapshotDisabled => "SNAPSHOT_DISABLED",
+            MetaImportError::Inconsistent(_) => "INCONSISTENT_METADATA",
+            MetaImportError::Snapshot(SnapshotError::InvalidName) => "INVALID_SNAPSHOT_NAME",
+            MetaImportError::Snapshot(SnapshotError::InvalidData(_)) => "INVALID_SNAPSHOT_DATA",
+            MetaImportError::Snapshot(SnapshotError::Io(_)) => "SNAPSHOT_IO_ERROR",
+            MetaImportError::Precondition(_) => "PRECONDITION_FAILED",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn Error> {
+        match self {
+            MetaImportError::Inconsistent(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl error::ResponseError for MetaImportError {
+    fn error_response(&self) -> HttpResponse {
+        let status_code = match self {
+            MetaImportError::NotEmpty => http::StatusCode::CONFLICT,
+            MetaImportError::Precondition(MetaStoreError::MismatchEpoch) => {
+                http::StatusCode::PRECONDITION_FAILED
+            }
+            MetaImportError::Snapshot(SnapshotError::Io(err))
+                if err.kind() == io::ErrorKind::NotFound =>
+            {
+                http::StatusCode::NOT_FOUND
+            }
+            MetaImportError::Snapshot(SnapshotError::Io(_)) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR
+            }
+            _ => http::StatusCode::BAD_REQUEST,
+        };
+        let mut response = HttpResponse::new(status_code);
+        response.set_body(self.to_string());
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::Middleware;
+    use actix_web::test::TestRequest;
+
+    fn gen_service() -> MemBrokerService {
+        MemBrokerService::new(MemBrokerConfig {
+            address: "127.0.0.1:7799".to_string(),
+            failure_ttl: 60,
+            snapshot_dir: "".to_string(),
+            snapshot_interval: 0,
+            snapshot_retention: 0,
+            audit_log_capacity: 10,
+            audit_log_file: "".to_string(),
+            readonly_tokens: vec![],
+            operator_tokens: vec![],
+            admin_tokens: vec![],
+            validation_interval: 0,
+            auto_repair: false,
+            failover_max_lag: None,
+            failover_max_wait: 0,
+        })
+    }
+
+    fn gen_host(index: usize) -> ProxyResource {
+        ProxyResource {
+            proxy_address: format!("127.0.0.1:{}", 6000 + index),
+            nodes: vec![
+                format!("127.0.0.1:{}", 7000 + 2 * index),
+                format!("127.0.0.1:{}", 7001 + 2 * index),
+            ],
+            labels: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_update_with_mismatched_epoch() {
+        let service = gen_service();
+        let epoch = service.get_global_epoch();
+        let precondition = EpochPrecondition {
+            global_epoch: Some(epoch + 1),
+            cluster_epoch: None,
+        };
+        let res = service
+            .update_with_precondition(&precondition, None, || service.add_hosts(gen_host(1)));
+        match res {
+            Err(MetaStoreError::MismatchEpoch) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(service.get_global_epoch(), epoch);
+        assert!(service.get_host_addresses().is_empty());
+    }
+
+    #[test]
+    fn test_update_with_matching_epoch() {
+        let service = gen_service();
+        let epoch = service.get_global_epoch();
+        let precondition = EpochPrecondition {
+            global_epoch: Some(epoch),
+            cluster_epoch: None,
+        };
+        let ((), epochs) = service
+            .update_with_precondition(&precondition, None, || service.add_hosts(gen_host(1)))
+            .unwrap();
+        assert!(epochs.global_epoch > epoch);
+        assert_eq!(epochs.global_epoch, service.get_global_epoch());
+        assert_eq!(epochs.cluster_epoch, None);
+    }
+
+    #[test]
+    fn test_replace_failed_proxy_with_changed_epoch() {
+        let service = gen_service();
+        service.add_hosts(gen_host(1)).unwrap();
+        let epoch = service.get_global_epoch();
+        let precondition = EpochPrecondition {
+            global_epoch: Some(epoch),
+            cluster_epoch: None,
+        };
+        // The metadata changes while the replicas are being chosen.
+        service.add_hosts(gen_host(2)).unwrap();
+        let epoch = service.get_global_epoch();
+
+        let res = service.replace_failed_proxy(
+            "127.0.0.1:6001".to_string(),
+            &HashMap::new(),
+            &precondition,
+        );
+        match res {
+            Err(PromotionError::Meta(MetaStoreError::MismatchEpoch)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(service.get_global_epoch(), epoch);
+    }
+
+    #[test]
+    fn test_epoch_middleware() {
+        let service = Arc::new(gen_service());
+        let current_epoch = service.get_global_epoch();
+
+        let req = TestRequest::with_state(service.clone()).finish();
+        let resp = HttpResponse::Ok().finish();
+        let resp = match EpochMiddleware.response(&req, resp).unwrap() {
+            middleware::Response::Done(resp) => resp,
+            _ => panic!("unexpected response"),
+        };
+        let etag = resp.headers().get(http::header::ETAG).unwrap();
+        assert_eq!(etag.to_str().unwrap(), format!("\"{}\"", current_epoch));
+
+        // The epochs of the mutation are used even if the store has changed after that.
+        let req = TestRequest::with_state(service).finish();
+        req.extensions_mut().insert(MutationEpochs {
+            global_epoch: current_epoch + 233,
+            cluster_epoch: None,
+        });
+        let resp = HttpResponse::Ok().finish();
+        let resp = match EpochMiddleware.response(&req, resp).unwrap() {
+            middleware::Response::Done(resp) => resp,
+            _ => panic!("unexpected response"),
+        };
+        let etag = resp.headers().get(http::header::ETAG).unwrap();
+        assert_eq!(
+            etag.to_str().unwrap(),
+            format!("\"{}\"", current_epoch + 233)
+        );
+    }
+
+    #[test]
+    fn test_audit_mutation_epoch() {
+        let service = Arc::new(gen_service());
+        service.add_hosts(gen_host(1)).unwrap();
+        let mutation_epoch = service.get_global_epoch();
+        service.add_hosts(gen_host(2)).unwrap();
+
+        let req = TestRequest::with_state(service.clone())
+            .method(http::Method::POST)
+            .uri("/api/proxies/meta")
+            .finish();
+        req.extensions_mut().insert(MutationEpochs {
+            global_epoch: mutation_epoch,
+            cluster_epoch: None,
+        });
+        AuditMiddleware
+            .response(&req, HttpResponse::Ok().finish())
+            .unwrap();
+        let records = service.get_audit_records(0);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].epoch, mutation_epoch);
+    }
+
+    #[test]
+    fn test_validate_and_repair_meta() {
+        let service = gen_service();
+        for i in 0..2 {
+            service.add_hosts(gen_host(i)).unwrap();
+        }
+        service
+            .add_cluster("mydb".to_string(), HashMap::new(), None)
+            .unwrap();
+        service.auto_add_node("mydb".to_string()).unwrap();
+        assert!(service.validate_meta().is_ok());
+        assert!(service.repair_meta().is_empty());
+
+        // Remove a node of the cluster from the proxy resource, which could be repaired.
+        {
+            let mut store = service.store.write().unwrap();
+            let mut value = serde_json::to_value(&*store).unwrap();
+            let resource = value["all_nodes"]
+                .as_object_mut()
+                .unwrap()
+                .values_mut()
+                .find(|resource| !resource["cluster_name"].is_null())
+                .unwrap();
+            resource["node_addresses"].as_array_mut().unwrap().pop();
+            *store = serde_json::from_value(value).unwrap();
+        }
+        assert!(service.validate_meta().is_err());
+        // The status is refreshed by the validation above.
+        let status = service.get_validation_status();
+        assert_eq!(status.epoch, Some(service.get_global_epoch()));
+        assert_eq!(status.issues.len(), 1);
+        assert!(status.issues[0].repair.is_some());
+
+        let applied = service.repair_meta();
+        assert_eq!(applied.len(), 1);
+        let status = service.get_validation_status();
+        assert!(status.issues.is_empty());
+        assert!(service.validate_meta().is_ok());
+    }
+}
@@ -0,0 +1,181 @@
+use super::store::MetaStore;
+use chrono::Utc;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const SNAPSHOT_PREFIX: &str = "metadata_";
+const SNAPSHOT_SUFFIX: &str = ".json";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    InvalidName,
+    InvalidData(serde_json::Error),
+}
+
+// The file name contains the time and the epoch for sorting the snapshots.
+pub fn gen_snapshot_name(epoch: u64) -> String {
+    format!(
+        "{}{}_{}{}",
+        SNAPSHOT_PREFIX,
+        Utc::now().format("%Y%m%d%H%M%S"),
+        epoch,
+        SNAPSHOT_SUFFIX
+    )
+}
+
+pub fn save_snapshot(
+    dir: &str,
+    store: &MetaStore,
+    retention: usize,
+) -> Result<String, SnapshotError> {
+    fs::create_dir_all(dir).map_err(SnapshotError::Io)?;
+
+    let name = gen_snapshot_name(store.get_global_epoch());
+    let data = serde_json::to_vec(store).map_err(SnapshotError::InvalidData)?;
+    // Write to a temporary file first to avoid leaving a broken snapshot behind.
+    let tmp_path = Path::new(dir).join(format!("{}.tmp", name));
+    fs::write(&tmp_path, data).map_err(SnapshotError::Io)?;
+    fs::rename(&tmp_path, Path::new(dir).join(&name)).map_err(SnapshotError::Io)?;
+
+    let snapshots = list_snapshots(dir)?;
+    if snapshots.len() > retention {
+        for expired in snapshots[..snapshots.len() - retention].iter() {
+            if let Err(err) = fs::remove_file(Path::new(dir).join(expired)) {
+                warn!("failed to remove expired snapshot {}: {:?}", expired, err);
+            }
+        }
+    }
+    Ok(name)
+}
+
+// Returns the snapshot names from the oldest to the latest.
+pub fn list_snapshots(dir: &str) -> Result<Vec<String>, SnapshotError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(SnapshotError::Io(err)),
+    };
+    let mut names = vec![];
+    for entry in entries {
+        let entry = entry.map_err(SnapshotError::Io)?;
+        if let Some(name) = entry.file_name().to_str() {
+            if is_valid_snapshot_name(name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort_by_cached_key(|name| get_sort_key(name));
+    Ok(names)
+}
+
+// The epochs should be compared as numbers
+// since the snapshots taken within the same second only differ in them.
+fn get_sort_key(name: &str) -> (String, u64, String) {
+    let stem = name
+        .trim_start_matches(SNAPSHOT_PREFIX)
+        .trim_end_matches(SNAPSHOT_SUFFIX);
+    let (time, epoch) = stem
+        .rfind('_')
+        .and_then(|i| {
+            let epoch = stem[i + 1..].parse::<u64>().ok()?;
+            Some((&stem[..i], epoch))
+        })
+        .unwrap_or((stem, 0));
+    (time.to_string(), epoch, name.to_string())
+}
+
+pub fn load_snapshot(dir: &str, name: &str) -> Result<MetaStore, SnapshotError> {
+    // Avoid reading any file outside the snapshot directory.
+    if !is_valid_snapshot_name(name) {
+        return Err(SnapshotError::InvalidName);
+    }
+    let data = fs::read(Path::new(dir).join(name)).map_err(SnapshotError::Io)?;
+    serde_json::from_slice(&data).map_err(SnapshotError::InvalidData)
+}
+
+fn is_valid_snapshot_name(name: &str) -> bool {
+    name.starts_with(SNAPSHOT_PREFIX)
+        && name.ends_with(SNAPSHOT_SUFFIX)
+        && !name.contains('/')
+        && !name.contains('\\')
+        && !name.contains("..")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_snapshot_name() {
+        let name = gen_snapshot_name(233);
+        assert!(is_valid_snapshot_name(&name));
+        assert!(name.ends_with("_233.json"));
+        assert!(!is_valid_snapshot_name("../metadata_1_1.json"));
+        assert!(!is_valid_snapshot_name("other.json"));
+    }
+
+    #[test]
+    fn test_snapshot_order() {
+        let dir = env::temp_dir().join(format!(
+            "undermoon_snapshot_order_test_{}",
+            std::process::id()
+        ));
+        let dir = dir.to_str().unwrap().to_string();
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let names = [
+            "metadata_20200101000000_9.json",
+            "metadata_20200101000000_10.json",
+            "metadata_20200101000001_2.json",
+            "metadata_20200101000000_100.json",
+        ];
+        for name in names.iter() {
+            fs::write(Path::new(&dir).join(name), "{}").unwrap();
+        }
+        let snapshots = list_snapshots(&dir).unwrap();
+        assert_eq!(
+            snapshots,
+            vec![
+                "metadata_20200101000000_9.json",
+                "metadata_20200101000000_10.json",
+                "metadata_20200101000000_100.json",
+                "metadata_20200101000001_2.json",
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_retention() {
+        let dir = env::temp_dir().join(format!("undermoon_snapshot_test_{}", std::process::id()));
+        let dir = dir.to_str().unwrap().to_string();
+        let _ = fs::remove_dir_all(&dir);
+
+        let store = MetaStore::default();
+        // The names generated within the same second only differ in epochs,
+        // so create the files directly.
+        for i in 0..3 {
+            let name = format!("{}2020010100000{}_0{}", SNAPSHOT_PREFIX, i, SNAPSHOT_SUFFIX);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(
+                Path::new(&dir).join(name),
+                serde_json::to_vec(&store).unwrap(),
+            )
+            .unwrap();
+        }
+        let name = save_snapshot(&dir, &store, 2).unwrap();
+        let snapshots = list_snapshots(&dir).unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[1], name);
+
+        let loaded = load_snapshot(&dir, &name).unwrap();
+        assert_eq!(loaded.get_global_epoch(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,18 @@
+use super::placement::{plan_placement, Placement, PlacementOptions, ProxyCandidate, ZONE_LABEL};
+use super::planner::{
+    plan_drain, plan_rebalance, MasterSlots, MigrationPlan, RebalanceOptions, SlotRangeMove,
+};
 use crate::common::cluster::{
-    Cluster, MigrationTaskMeta, Node, PeerProxy, Proxy, ReplMeta, ReplPeer, SlotRange, SlotRangeTag,
+    Cluster, MigrationTaskMeta, Node, PeerProxy, Proxy, Range, ReplMeta, ReplPeer, SlotRange,
+    SlotRangeTag,
 };
-use crate::common::cluster::{DBName, MigrationMeta, Role};
+use crate::common::cluster::{DBName, MetaChanges, MigrationMeta, NodeFailover, Role};
 use crate::common::config::ClusterConfig;
 use crate::common::utils::SLOT_NUM;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use itertools::Itertools;
 use std::cmp;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 use std::fmt;
 
@@ -21,12 +26,44 @@ pub struct NodeSlot {
 pub struct NodeResource {
     pub node_addresses: HashSet<String>,
     pub cluster_name: Option<DBName>,
+    // Could be used to describe the failure domains such as zone and rack.
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProxyResourceInfo {
+    pub proxy_address: String,
+    pub node_addresses: Vec<String>,
+    pub cluster_name: Option<DBName>,
+    pub labels: HashMap<String, String>,
+    pub failed: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum MigrationType {
     All,
     Half,
+    Ranges(Vec<Range>),
+}
+
+// A planned move is dropped after failing to start for this many times in a row.
+const MAX_PLANNED_MIGRATION_FAILURES: usize = 3;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PendingMigrationPlan {
+    pub max_concurrent_migrations: usize,
+    pub moves: VecDeque<SlotRangeMove>,
+    // The number of times the first move has failed to start.
+    #[serde(default)]
+    pub failures: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CancelingMigration {
+    pub meta: MigrationMeta,
+    pub src_acked: bool,
+    pub dst_acked: bool,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -37,6 +74,17 @@ pub struct MetaStore {
     all_nodes: HashMap<String, NodeResource>,
     failed_proxies: HashMap<String, HashSet<String>>,
     failures: HashMap<String, i64>,
+    #[serde(default)]
+    migration_plans: HashMap<DBName, PendingMigrationPlan>,
+    // proxy_address => cluster_name
+    #[serde(default)]
+    draining_proxies: HashMap<String, DBName>,
+    #[serde(default)]
+    canceling_migrations: HashMap<DBName, Vec<CancelingMigration>>,
+    // The failed nodes whose proxies are still in use.
+    // They are kept in the cluster but never used as the free nodes.
+    #[serde(default)]
+    failed_nodes: HashSet<String>,
 }
 
 impl Default for MetaStore {
@@ -47,6 +95,10 @@ impl Default for MetaStore {
             all_nodes: HashMap::new(),
             failed_proxies: HashMap::new(),
             failures: HashMap::new(),
+            migration_plans: HashMap::new(),
+            draining_proxies: HashMap::new(),
+            canceling_migrations: HashMap::new(),
+            failed_nodes: HashSet::new(),
         }
     }
 }
@@ -73,6 +125,31 @@ impl MetaStore {
         self.all_nodes.keys().cloned().collect()
     }
 
+    // Only returns the proxies with all the specified labels.
+    pub fn get_proxy_resources(&self, labels: &HashMap<String, String>) -> Vec<ProxyResourceInfo> {
+        self.all_nodes
+            .iter()
+            .filter(|(_, node_resource)| {
+                labels
+                    .iter()
+                    .all(|(k, v)| node_resource.labels.get(k) == Some(v))
+            })
+            .map(|(proxy_address, node_resource)| {
+                let mut node_addresses: Vec<String> =
+                    node_resource.node_addresses.iter().cloned().collect();
+                node_addresses.sort();
+                ProxyResourceInfo {
+                    proxy_address: proxy_address.clone(),
+                    node_addresses,
+                    cluster_name: node_resource.cluster_name.clone(),
+                    labels: node_resource.labels.clone(),
+                    failed: self.failed_proxies.contains_key(proxy_address)
+                        || self.failures.contains_key(proxy_address),
+                }
+            })
+            .collect()
+    }
+
     pub fn get_host_by_address(&self, address: &str) -> Option<Proxy> {
         let all_nodes = &self.all_nodes;
         let clusters = &self.clusters;
@@ -84,11 +161,22 @@ impl MetaStore {
                 .map(|cluster| {
                     let cluster_name = cluster.get_name().clone();
                     let epoch = cluster.get_epoch();
+                    let canceling = self
+                        .canceling_migrations
+                        .get(&cluster_name)
+                        .map(|canceling| canceling.as_slice())
+                        .unwrap_or(&[]);
+                    let reverted = Self::get_reverted_migrations(canceling, address);
+                    let rollback_node = |node: &Node| {
+                        let mut node = node.clone();
+                        Self::rollback_slot_ranges(node.get_mut_slots(), &reverted);
+                        node
+                    };
                     let nodes = cluster
                         .get_nodes()
                         .iter()
                         .filter(|node| node.get_proxy_address() == address)
-                        .cloned()
+                        .map(rollback_node)
                         .collect();
                     let peers = cluster
                         .get_nodes()
@@ -96,7 +184,7 @@ impl MetaStore {
                         .filter(|n| {
                             n.get_role() == Role::Master && n.get_proxy_address() != address
                         })
-                        .cloned()
+                        .map(rollback_node)
                         .group_by(|node| node.get_proxy_address().to_string())
                         .into_iter()
                         .map(|(proxy_address, nodes)| {
@@ -109,14 +197,20 @@ impl MetaStore {
                             }
                         })
                         .collect();
-                    Proxy::new(
+                    let mut clusters_config = HashMap::new();
+                    clusters_config.insert(cluster_name.clone(), cluster.get_config().clone());
+                    let mut proxy = Proxy::new(
                         address.to_string(),
                         epoch,
                         nodes,
                         Vec::new(),
                         peers,
-                        HashMap::new(),
-                    )
+                        clusters_config,
+                    );
+                    proxy.set_canceling_migrations(Self::get_pending_rollbacks(
+                        cluster, canceling, address,
+                    ));
+                    proxy
                 })
                 .or_else(|| {
                     Some(Proxy::new(
@@ -135,10 +229,126 @@ impl MetaStore {
         })
     }
 
+    // The source proxy should stop migrating before the destination drops the importing slots,
+    // so the destination could only see the rollback after the source acknowledges it.
+    fn get_reverted_migrations(
+        canceling: &[CancelingMigration],
+        proxy_address: &str,
+    ) -> Vec<MigrationMeta> {
+        canceling
+            .iter()
+            .filter(|c| c.src_acked || c.meta.src_proxy_address == proxy_address)
+            .map(|c| c.meta.clone())
+            .collect()
+    }
+
+    fn get_pending_rollbacks(
+        cluster: &Cluster,
+        canceling: &[CancelingMigration],
+        proxy_address: &str,
+    ) -> Vec<MigrationTaskMeta> {
+        let mut tasks = vec![];
+        for c in canceling.iter() {
+            let pending = (c.meta.src_proxy_address == proxy_address && !c.src_acked)
+                || (c.meta.dst_proxy_address == proxy_address && c.src_acked && !c.dst_acked);
+            if !pending {
+                continue;
+            }
+            let src_node = match cluster.get_node(&c.meta.src_node_address) {
+                Some(node) => node,
+                None => continue,
+            };
+            for slot_range in src_node.get_slots().iter() {
+                match slot_range.tag {
+                    SlotRangeTag::Migrating(ref meta) if *meta == c.meta => (),
+                    _ => continue,
+                }
+                tasks.push(MigrationTaskMeta {
+                    db_name: cluster.get_name().clone(),
+                    slot_range: slot_range.clone(),
+                });
+            }
+        }
+        tasks
+    }
+
+    fn rollback_slot_ranges(slot_ranges: &mut Vec<SlotRange>, reverted: &[MigrationMeta]) {
+        if reverted.is_empty() {
+            return;
+        }
+        slot_ranges.retain(|slot_range| match slot_range.tag {
+            SlotRangeTag::Importing(ref meta) => !reverted.contains(meta),
+            _ => true,
+        });
+        for slot_range in slot_ranges.iter_mut() {
+            let revert = match slot_range.tag {
+                SlotRangeTag::Migrating(ref meta) => reverted.contains(meta),
+                _ => false,
+            };
+            if revert {
+                slot_range.tag = SlotRangeTag::None;
+            }
+        }
+    }
+
+    pub fn get_global_epoch(&self) -> u64 {
+        self.global_epoch
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clusters.is_empty() && self.all_nodes.is_empty()
+    }
+
+    // The proxies only accept the metadata with larger epochs,
+    // so the imported metadata needs to have an epoch larger than all the epochs they have seen.
+    pub fn raise_epoch(&mut self, min_epoch: u64) {
+        self.global_epoch = cmp::max(self.global_epoch, min_epoch);
+        let new_epoch = self.bump_global_epoch();
+        for cluster in self.clusters.values_mut() {
+            cluster.set_epoch(new_epoch);
+        }
+    }
+
     pub fn get_cluster_names(&self) -> Vec<DBName> {
         self.clusters.keys().cloned().collect()
     }
 
+    // The epoch of a cluster is bumped on every change of it,
+    // while the free proxies are using the global epoch.
+    pub fn get_changes(&self, since_epoch: u64) -> MetaChanges {
+        let mut clusters: Vec<DBName> = self
+            .clusters
+            .iter()
+            .filter(|(_, cluster)| cluster.get_epoch() > since_epoch)
+            .map(|(cluster_name, _)| cluster_name.clone())
+            .collect();
+        clusters.sort_by_key(|cluster_name| cluster_name.to_string());
+
+        let global_changed = self.global_epoch > since_epoch;
+        let mut proxies: Vec<String> = self
+            .all_nodes
+            .iter()
+            .filter(
+                |(_, node_resource)| match node_resource.cluster_name.as_ref() {
+                    Some(cluster_name) => self
+                        .clusters
+                        .get(cluster_name)
+                        .map(|cluster| cluster.get_epoch() > since_epoch)
+                        .unwrap_or(global_changed),
+                    None => global_changed,
+                },
+            )
+            .map(|(proxy_address, _)| proxy_address.clone())
+            .collect();
+        proxies.sort();
+
+        MetaChanges {
+            epoch: self.global_epoch,
+            clusters,
+            proxies,
+        }
+    }
+
     pub fn get_cluster_by_name(&self, name: &str) -> Option<Cluster> {
         let name = DBName::from(name).ok()?;
         self.clusters.get(&name).cloned()
@@ -164,6 +374,7 @@ impl MetaStore {
         &mut self,
         proxy_address: String,
         nodes: Vec<String>,
+        labels: HashMap<String, String>,
     ) -> Result<(), MetaStoreError> {
         self.bump_global_epoch();
 
@@ -173,6 +384,7 @@ impl MetaStore {
             .or_insert_with(|| NodeResource {
                 node_addresses: HashSet::new(),
                 cluster_name: None,
+                labels: HashMap::new(),
             });
 
         if node_resource.cluster_name.is_some() {
@@ -182,6 +394,9 @@ impl MetaStore {
         for node in nodes.into_iter() {
             node_resource.node_addresses.insert(node);
         }
+        // The labels of the registered proxy are replaced as a whole
+        // so that the stale ones could be removed by registering it again.
+        node_resource.labels = labels;
 
         self.failed_proxies
             .remove(&proxy_address)
@@ -191,28 +406,69 @@ impl MetaStore {
         Ok(())
     }
 
-    pub fn add_cluster(&mut self, cluster_name: String) -> Result<(), MetaStoreError> {
+    pub fn add_cluster(
+        &mut self,
+        cluster_name: String,
+        config_fields: HashMap<String, String>,
+        placement_options: Option<PlacementOptions>,
+    ) -> Result<(), MetaStoreError> {
+        let placement = match placement_options {
+            Some(options) => {
+                let candidates = self.get_placement_candidates();
+                let placement = Self::plan_cluster_placement(&cluster_name, candidates, &options)?;
+                Some((options, placement))
+            }
+            None => None,
+        };
+        self.add_placed_cluster(cluster_name, config_fields, placement)
+    }
+
+    // The placement could be planned without holding the lock of the store
+    // and it will be checked again before adding the cluster.
+    pub fn add_placed_cluster(
+        &mut self,
+        cluster_name: String,
+        config_fields: HashMap<String, String>,
+        placement: Option<(PlacementOptions, Placement)>,
+    ) -> Result<(), MetaStoreError> {
         let cluster_name =
             DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
         if self.clusters.contains_key(&cluster_name) {
             return Err(MetaStoreError::AlreadyExisted);
         }
 
-        let node_slots = self.consume_node_slot(&cluster_name)?;
+        let mut default_config = ClusterConfig::default();
+        if let Some((options, _)) = placement.as_ref() {
+            default_config.replicas_per_master = options.replicas_per_master;
+        }
+        let config = Self::gen_cluster_config(default_config, &config_fields)?;
+
+        let nodes = match placement {
+            Some((_, placement)) => self.place_cluster_nodes(&cluster_name, placement)?,
+            None => self.gen_cluster_nodes(&cluster_name)?,
+        };
+
+        let cluster = Cluster::new(
+            cluster_name.clone(),
+            self.bump_global_epoch(),
+            nodes,
+            config,
+        );
+        self.clusters.insert(cluster_name, cluster);
+        Ok(())
+    }
+
+    fn gen_cluster_nodes(&mut self, cluster_name: &DBName) -> Result<Vec<Node>, MetaStoreError> {
+        let node_slots = self.consume_node_slot(cluster_name, &HashSet::new())?;
 
         let node_num = node_slots.len();
-        let range_per_node = (SLOT_NUM + node_num - 1) / node_num;
         let mut nodes = vec![];
         for (i, node_slot) in node_slots.into_iter().enumerate() {
             let NodeSlot {
                 proxy_address,
                 node_address,
             } = node_slot;
-            let slots = SlotRange {
-                start: i * range_per_node,
-                end: cmp::min((i + 1) * range_per_node - 1, SLOT_NUM - 1),
-                tag: SlotRangeTag::None,
-            };
+            let slots = Self::gen_even_slot_range(i, node_num);
             let repl = ReplMeta::new(Role::Master, vec![]);
             let node = Node::new(
                 node_address,
@@ -223,17 +479,186 @@ impl MetaStore {
             );
             nodes.push(node);
         }
+        Ok(nodes)
+    }
 
-        let cluster = Cluster::new(
-            cluster_name.clone(),
-            self.bump_global_epoch(),
-            nodes,
-            ClusterConfig::default(),
-        );
-        self.clusters.insert(cluster_name, cluster);
+    fn gen_even_slot_range(index: usize, node_num: usize) -> SlotRange {
+        let range_per_node = (SLOT_NUM + node_num - 1) / node_num;
+        SlotRange {
+            start: index * range_per_node,
+            end: cmp::min((index + 1) * range_per_node - 1, SLOT_NUM - 1),
+            tag: SlotRangeTag::None,
+        }
+    }
+
+    pub fn get_placement_candidates(&self) -> Vec<ProxyCandidate> {
+        self.all_nodes
+            .iter()
+            .filter(|(proxy_address, _)| !self.failures.contains_key(*proxy_address))
+            .filter(|(_, node_resource)| node_resource.cluster_name.is_none())
+            .map(|(proxy_address, node_resource)| ProxyCandidate {
+                proxy_address: proxy_address.clone(),
+                node_addresses: node_resource.node_addresses.iter().cloned().collect(),
+                labels: node_resource.labels.clone(),
+            })
+            .collect()
+    }
+
+    pub fn plan_cluster_placement(
+        cluster_name: &str,
+        candidates: Vec<ProxyCandidate>,
+        options: &PlacementOptions,
+    ) -> Result<Placement, MetaStoreError> {
+        if options.master_num == 0 {
+            return Err(MetaStoreError::InvalidNodeNum);
+        }
+        plan_placement(candidates, options).map_err(|err| {
+            warn!(
+                "failed to place nodes for cluster {}: {}",
+                cluster_name, err
+            );
+            MetaStoreError::NoAvailableResource(err.to_string())
+        })
+    }
+
+    fn place_cluster_nodes(
+        &mut self,
+        cluster_name: &DBName,
+        placement: Placement,
+    ) -> Result<Vec<Node>, MetaStoreError> {
+        let Placement {
+            groups,
+            spare_nodes,
+        } = placement;
+
+        // The proxies could have been taken or failed after the placement was planned.
+        let placed_nodes = groups
+            .iter()
+            .flat_map(|group| std::iter::once(&group.master).chain(group.replicas.iter()))
+            .chain(spare_nodes.iter());
+        for placed_node in placed_nodes {
+            let available = !self.failures.contains_key(&placed_node.proxy_address)
+                && self
+                    .all_nodes
+                    .get(&placed_node.proxy_address)
+                    .map(|node_resource| {
+                        node_resource.cluster_name.is_none()
+                            && node_resource
+                                .node_addresses
+                                .contains(&placed_node.node_address)
+                    })
+                    .unwrap_or(false);
+            if !available {
+                return Err(MetaStoreError::NoAvailableResource(format!(
+                    "proxy {} is no longer free",
+                    placed_node.proxy_address
+                )));
+            }
+        }
+
+        let master_num = groups.len();
+        let mut nodes = vec![];
+        for (i, group) in groups.into_iter().enumerate() {
+            let slots = Self::gen_even_slot_range(i, master_num);
+            let master_peer = ReplPeer {
+                node_address: group.master.node_address.clone(),
+                proxy_address: group.master.proxy_address.clone(),
+            };
+            let mut replica_peers = vec![];
+            for replica in group.replicas.into_iter() {
+                replica_peers.push(ReplPeer {
+                    node_address: replica.node_address.clone(),
+                    proxy_address: replica.proxy_address.clone(),
+                });
+                let repl = ReplMeta::new(Role::Replica, vec![master_peer.clone()]);
+                nodes.push(Node::new(
+                    replica.node_address,
+                    replica.proxy_address,
+                    cluster_name.clone(),
+                    vec![],
+                    repl,
+                ));
+            }
+            let repl = ReplMeta::new(Role::Master, replica_peers);
+            nodes.push(Node::new(
+                group.master.node_address,
+                group.master.proxy_address,
+                cluster_name.clone(),
+                vec![slots],
+                repl,
+            ));
+        }
+
+        // The whole proxy is owned by the cluster so the rest of its nodes
+        // are added as masters without slots.
+        for spare in spare_nodes.into_iter() {
+            let repl = ReplMeta::new(Role::Master, vec![]);
+            nodes.push(Node::new(
+                spare.node_address,
+                spare.proxy_address,
+                cluster_name.clone(),
+                vec![],
+                repl,
+            ));
+        }
+
+        for node in nodes.iter() {
+            if let Some(node_resource) = self.all_nodes.get_mut(node.get_proxy_address()) {
+                node_resource.cluster_name = Some(cluster_name.clone());
+            }
+        }
+        Ok(nodes)
+    }
+
+    pub fn get_cluster_config(&self, cluster_name: &str) -> Result<ClusterConfig, MetaStoreError> {
+        let cluster_name =
+            DBName::from(cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
+        self.clusters
+            .get(&cluster_name)
+            .map(|cluster| cluster.get_config().clone())
+            .ok_or(MetaStoreError::ClusterNotFound)
+    }
+
+    pub fn change_cluster_config(
+        &mut self,
+        cluster_name: String,
+        config_fields: HashMap<String, String>,
+    ) -> Result<(), MetaStoreError> {
+        let cluster_name =
+            DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
+        let config = match self.clusters.get(&cluster_name) {
+            Some(cluster) => cluster.get_config().clone(),
+            None => return Err(MetaStoreError::ClusterNotFound),
+        };
+        let config = Self::gen_cluster_config(config, &config_fields)?;
+
+        let new_epoch = self.bump_global_epoch();
+        let cluster = self
+            .clusters
+            .get_mut(&cluster_name)
+            .ok_or(MetaStoreError::ClusterNotFound)?;
+        cluster.set_config(config);
+        cluster.set_epoch(new_epoch);
         Ok(())
     }
 
+    // All the fields are validated before any of them takes effect.
+    fn gen_cluster_config(
+        mut config: ClusterConfig,
+        config_fields: &HashMap<String, String>,
+    ) -> Result<ClusterConfig, MetaStoreError> {
+        for (field, value) in config_fields.iter() {
+            config.set_field(field, value).map_err(|err| {
+                warn!(
+                    "failed to set cluster config {} {}: {:?}",
+                    field, value, err
+                );
+                MetaStoreError::InvalidConfig
+            })?;
+        }
+        Ok(config)
+    }
+
     pub fn remove_cluster(&mut self, cluster_name: String) -> Result<(), MetaStoreError> {
         let cluster_name =
             DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
@@ -241,8 +666,13 @@ impl MetaStore {
             .clusters
             .remove(&cluster_name)
             .ok_or(MetaStoreError::ClusterNotFound)?;
+        self.migration_plans.remove(&cluster_name);
+        self.canceling_migrations.remove(&cluster_name);
+        self.draining_proxies
+            .retain(|_, draining_cluster| *draining_cluster != cluster_name);
 
         for node in cluster.into_nodes().iter() {
+            self.failed_nodes.remove(node.get_address());
             match self.all_nodes.get_mut(node.get_proxy_address()) {
                 Some(node_resource) => node_resource.cluster_name = None,
                 None => error!(
@@ -260,16 +690,24 @@ impl MetaStore {
     pub fn auto_add_nodes(&mut self, cluster_name: String) -> Result<Vec<Node>, MetaStoreError> {
         let cluster_name =
             DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
-        if self.clusters.get(&cluster_name).is_none() {
+        self.add_free_proxy_nodes(&cluster_name, &HashSet::new())
+    }
+
+    fn add_free_proxy_nodes(
+        &mut self,
+        cluster_name: &DBName,
+        avoided_zones: &HashSet<String>,
+    ) -> Result<Vec<Node>, MetaStoreError> {
+        if !self.clusters.contains_key(cluster_name) {
             return Err(MetaStoreError::ClusterNotFound);
         }
 
-        let node_slots = self.consume_node_slot(&cluster_name)?;
+        let node_slots = self.consume_node_slot(cluster_name, avoided_zones)?;
         let new_epoch = self.bump_global_epoch();
 
         let cluster = self
             .clusters
-            .get_mut(&cluster_name)
+            .get_mut(cluster_name)
             .ok_or_else(|| MetaStoreError::ClusterNotFound)?;
 
         let mut nodes = vec![];
@@ -347,6 +785,11 @@ impl MetaStore {
 
         cluster.set_epoch(new_epoch);
 
+        for node_address in node_addresses.iter() {
+            self.failed_nodes.remove(node_address);
+        }
+
+        self.draining_proxies.remove(proxy_address);
         try_state!(Self::set_node_free(&mut self.all_nodes, &proxy_address,));
         Ok(())
     }
@@ -452,10 +895,8 @@ impl MetaStore {
             return Err(MetaStoreError::SameHost);
         }
 
-        cluster.set_epoch(new_epoch);
-
         let migration_meta = MigrationMeta {
-            epoch: cluster.get_epoch(),
+            epoch: new_epoch,
             src_proxy_address,
             src_node_address: src_node_address.clone(),
             dst_proxy_address,
@@ -491,8 +932,24 @@ impl MetaStore {
                 src_slot_ranges.extend_from_slice(&migrating_slot_ranges);
                 (src_slot_ranges, dst_slot_ranges)
             }
+            MigrationType::Ranges(ranges) => {
+                let (mut src_slot_ranges, mut dst_slot_ranges) =
+                    Self::pick_slot_ranges(slot_ranges, ranges)?;
+
+                let mut src_new_migrating_slots = dst_slot_ranges.clone();
+                for slot_range in src_new_migrating_slots.iter_mut() {
+                    slot_range.tag = SlotRangeTag::Migrating(migration_meta.clone());
+                }
+                for slot_range in dst_slot_ranges.iter_mut() {
+                    slot_range.tag = SlotRangeTag::Importing(migration_meta.clone());
+                }
+                src_slot_ranges.extend_from_slice(&src_new_migrating_slots);
+                (src_slot_ranges, dst_slot_ranges)
+            }
         };
 
+        cluster.set_epoch(new_epoch);
+
         {
             let src_node = try_state!(cluster
                 .get_mut_node(&src_node_address)
@@ -529,6 +986,18 @@ impl MetaStore {
             .cloned()
             .ok_or_else(|| MetaStoreError::InvalidRequest)?;
 
+        // The source proxy refuses to abort the migration after switching the slots
+        // and keeps running it until this commit.
+        let canceling_index = match self.canceling_migrations.get(&cluster_name) {
+            Some(canceling) => match canceling.iter().position(|c| c.meta == migration_meta) {
+                Some(index) if canceling[index].src_acked => {
+                    return Err(MetaStoreError::MigrationCanceling)
+                }
+                index => index,
+            },
+            None => None,
+        };
+
         {
             let src = cluster
                 .get_node(&migration_meta.src_node_address)
@@ -570,46 +1039,430 @@ impl MetaStore {
 
         cluster.set_epoch(new_epoch);
 
+        if let Some(index) = canceling_index {
+            warn!("the canceled migration is committed {:?}", migration_meta);
+            if let Some(canceling) = self.canceling_migrations.get_mut(&cluster_name) {
+                canceling.remove(index);
+                if canceling.is_empty() {
+                    self.canceling_migrations.remove(&cluster_name);
+                }
+            }
+        }
+
+        // The failed moves are kept in the plan and retried later.
+        if let Err(err) = self.schedule_planned_migrations(&cluster_name) {
+            warn!(
+                "failed to schedule the planned migrations of {}: {:?}",
+                cluster_name, err
+            );
+        }
+        self.finish_draining(&cluster_name);
+
         Ok(())
     }
 
-    pub fn stop_migrations(
+    pub fn plan_rebalance(
+        &self,
+        cluster_name: String,
+        options: &RebalanceOptions,
+    ) -> Result<MigrationPlan, MetaStoreError> {
+        let cluster_name =
+            DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
+        let cluster = self
+            .clusters
+            .get(&cluster_name)
+            .ok_or(MetaStoreError::ClusterNotFound)?;
+
+        let masters = Self::get_master_slots(cluster)?;
+        let moves = plan_rebalance(masters, options).map_err(|err| {
+            warn!("failed to plan rebalance {:?}", err);
+            MetaStoreError::InvalidRequest
+        })?;
+        Ok(MigrationPlan {
+            epoch: cluster.get_epoch(),
+            moves,
+        })
+    }
+
+    pub fn apply_migration_plan(
         &mut self,
         cluster_name: String,
-        src_node_address: String,
-        dst_node_address: String,
+        plan: MigrationPlan,
+        max_concurrent_migrations: usize,
     ) -> Result<(), MetaStoreError> {
         let cluster_name =
             DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
-        let new_epoch = self.bump_global_epoch();
-
+        if max_concurrent_migrations == 0 {
+            return Err(MetaStoreError::InvalidRequest);
+        }
         let cluster = self
             .clusters
-            .get_mut(&cluster_name)
+            .get(&cluster_name)
             .ok_or(MetaStoreError::ClusterNotFound)?;
+        // The plan could be outdated.
+        if cluster.get_epoch() != plan.epoch {
+            return Err(MetaStoreError::MismatchEpoch);
+        }
+        if self.migration_plans.contains_key(&cluster_name) {
+            return Err(MetaStoreError::AlreadyExisted);
+        }
 
-        cluster
-            .get_mut_node(&src_node_address)
-            .ok_or(MetaStoreError::NodeNotFound)
-            .and_then(
-                |node| match (node.get_role(), node.get_slots().is_empty()) {
-                    (Role::Master, false) => {
-                        for slot_range in node.get_mut_slots().iter_mut() {
-                            match slot_range.tag {
-                                SlotRangeTag::Migrating(ref meta)
-                                    if meta.dst_node_address == dst_node_address => {}
-                                _ => continue,
-                            }
-                            slot_range.tag = SlotRangeTag::None;
-                        }
-                        Ok(())
-                    }
-                    (Role::Replica, _) => Err(MetaStoreError::InvalidRole),
-                    (_, true) => Err(MetaStoreError::SlotEmpty),
-                },
-            )?;
+        let MigrationPlan { moves, .. } = plan;
+        self.migration_plans.insert(
+            cluster_name.clone(),
+            PendingMigrationPlan {
+                max_concurrent_migrations,
+                moves: moves.into_iter().collect(),
+                failures: 0,
+            },
+        );
+        if let Err(err) = self.schedule_planned_migrations(&cluster_name) {
+            // Once some moves have started, the plan is kept and the failed move will be retried.
+            let started = self
+                .clusters
+                .get(&cluster_name)
+                .map(|cluster| Self::get_running_migration_num(cluster) > 0)
+                .unwrap_or(false);
+            if !started {
+                self.migration_plans.remove(&cluster_name);
+                return Err(err);
+            }
+            warn!(
+                "planned migrations of {} partially started: {:?}",
+                cluster_name, err
+            );
+        }
+        Ok(())
+    }
 
-        cluster
+    // The migrations already started still go on.
+    pub fn remove_migration_plan(&mut self, cluster_name: String) -> Result<(), MetaStoreError> {
+        let cluster_name =
+            DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
+        if self.migration_plans.remove(&cluster_name).is_none() {
+            return Err(MetaStoreError::NotMigrating);
+        }
+        // The proxies being drained by the plan are kept in the cluster.
+        self.draining_proxies
+            .retain(|_, draining_cluster| *draining_cluster != cluster_name);
+        Ok(())
+    }
+
+    pub fn drain_proxy(
+        &mut self,
+        cluster_name: String,
+        proxy_address: String,
+        max_concurrent_migrations: usize,
+    ) -> Result<(), MetaStoreError> {
+        let cluster_name =
+            DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
+        if max_concurrent_migrations == 0 {
+            return Err(MetaStoreError::InvalidRequest);
+        }
+        if self.migration_plans.contains_key(&cluster_name) {
+            return Err(MetaStoreError::MigrationRunning);
+        }
+        let cluster = self
+            .clusters
+            .get(&cluster_name)
+            .ok_or(MetaStoreError::ClusterNotFound)?;
+
+        let nodes: Vec<&Node> = cluster
+            .get_nodes()
+            .iter()
+            .filter(|node| node.get_proxy_address() == proxy_address)
+            .collect();
+        if nodes.is_empty() {
+            return Err(MetaStoreError::HostNotFound);
+        }
+
+        let masters = Self::get_master_slots(cluster)?;
+        let moves = plan_drain(masters, &proxy_address).map_err(|err| {
+            warn!("failed to plan draining {} {:?}", proxy_address, err);
+            MetaStoreError::NoAvailableResource(
+                "no other master could receive the slots".to_string(),
+            )
+        })?;
+
+        self.draining_proxies
+            .insert(proxy_address.clone(), cluster_name.clone());
+        if !moves.is_empty() {
+            self.migration_plans.insert(
+                cluster_name.clone(),
+                PendingMigrationPlan {
+                    max_concurrent_migrations,
+                    moves: moves.into_iter().collect(),
+                    failures: 0,
+                },
+            );
+            if let Err(err) = self.schedule_planned_migrations(&cluster_name) {
+                self.migration_plans.remove(&cluster_name);
+                self.draining_proxies.remove(&proxy_address);
+                return Err(err);
+            }
+        }
+        self.finish_draining(&cluster_name);
+        Ok(())
+    }
+
+    // Free the draining proxies whose slots have all been migrated out.
+    // The replication of their nodes is moved to the other proxies.
+    fn finish_draining(&mut self, cluster_name: &DBName) {
+        let cluster = match self.clusters.get(cluster_name) {
+            Some(cluster) => cluster,
+            None => return,
+        };
+        let drained: Vec<String> = self
+            .draining_proxies
+            .iter()
+            .filter(|(_, draining_cluster)| *draining_cluster == cluster_name)
+            .map(|(proxy_address, _)| proxy_address)
+            .filter(|proxy_address| {
+                cluster.get_nodes().iter().all(|node| {
+                    node.get_proxy_address() != proxy_address.as_str()
+                        || node.get_slots().is_empty()
+                })
+            })
+            .cloned()
+            .collect();
+
+        for proxy_address in drained.into_iter() {
+            let masters = self.detach_drained_replication(cluster_name, &proxy_address);
+            // The draining proxy is still in the cluster so that the new replicas won't be put on it.
+            for master_node_address in masters.into_iter() {
+                if let Err(err) =
+                    self.add_replica(cluster_name.to_string(), master_node_address.clone())
+                {
+                    error!(
+                        "failed to move the replica of {} {} out of drained proxy {}: {:?}",
+                        cluster_name, master_node_address, proxy_address, err
+                    );
+                }
+            }
+            if let Err(err) =
+                self.remove_proxy_from_cluster_helper(cluster_name.as_str(), &proxy_address, false)
+            {
+                error!(
+                    "failed to remove drained proxy {} {} {:?}",
+                    cluster_name, proxy_address, err
+                );
+            }
+        }
+    }
+
+    // The replicas of the drained masters become free nodes,
+    // and the masters on the other proxies lose their replicas on the drained proxy.
+    // Returns these masters so that they can get new replicas.
+    fn detach_drained_replication(
+        &mut self,
+        cluster_name: &DBName,
+        proxy_address: &str,
+    ) -> Vec<String> {
+        let cluster = match self.clusters.get_mut(cluster_name) {
+            Some(cluster) => cluster,
+            None => return vec![],
+        };
+        let drained_peers: Vec<(ReplPeer, Role, Vec<ReplPeer>)> = cluster
+            .get_nodes()
+            .iter()
+            .filter(|node| node.get_proxy_address() == proxy_address)
+            .map(|node| {
+                let peer = ReplPeer {
+                    node_address: node.get_address().to_string(),
+                    proxy_address: node.get_proxy_address().to_string(),
+                };
+                let repl = node.get_repl_meta();
+                (peer, repl.get_role(), repl.get_peers().to_vec())
+            })
+            .collect();
+
+        let mut masters = vec![];
+        for (drained_peer, role, peers) in drained_peers.into_iter() {
+            if let Some(node) = cluster.get_mut_node(&drained_peer.node_address) {
+                *node.get_mut_repl() = ReplMeta::new(Role::Master, vec![]);
+            }
+            for peer in peers.into_iter() {
+                let node = match cluster.get_mut_node(&peer.node_address) {
+                    Some(node) => node,
+                    None => continue,
+                };
+                if role == Role::Master {
+                    *node.get_mut_repl() = ReplMeta::new(Role::Master, vec![]);
+                } else {
+                    node.get_mut_repl().remove_peer(&drained_peer);
+                    if peer.proxy_address != proxy_address {
+                        masters.push(peer.node_address);
+                    }
+                }
+            }
+        }
+        masters
+    }
+
+    fn get_master_slots(cluster: &Cluster) -> Result<Vec<MasterSlots>, MetaStoreError> {
+        let mut masters = vec![];
+        for node in cluster.get_nodes().iter() {
+            if node.get_role() != Role::Master {
+                continue;
+            }
+            if node
+                .get_slots()
+                .iter()
+                .any(|slot_range| slot_range.tag != SlotRangeTag::None)
+            {
+                return Err(MetaStoreError::MigrationRunning);
+            }
+            masters.push(MasterSlots {
+                node_address: node.get_address().to_string(),
+                proxy_address: node.get_proxy_address().to_string(),
+                ranges: node.get_slots().iter().map(SlotRange::to_range).collect(),
+            });
+        }
+        Ok(masters)
+    }
+
+    pub fn get_migration_plan(&self, cluster_name: &str) -> Option<PendingMigrationPlan> {
+        let cluster_name = DBName::from(cluster_name).ok()?;
+        self.migration_plans.get(&cluster_name).cloned()
+    }
+
+    fn get_running_migration_num(cluster: &Cluster) -> usize {
+        cluster
+            .get_nodes()
+            .iter()
+            .flat_map(|node| node.get_slots().iter())
+            .filter_map(|slot_range| match slot_range.tag {
+                SlotRangeTag::Migrating(ref meta) => Some(meta.clone()),
+                _ => None,
+            })
+            .collect::<HashSet<MigrationMeta>>()
+            .len()
+    }
+
+    // Start the next moves of the plan within `max_concurrent_migrations`.
+    // The move failed to start is put back to the front of the plan for the next retry,
+    // and gets dropped after failing for `MAX_PLANNED_MIGRATION_FAILURES` times.
+    fn schedule_planned_migrations(&mut self, cluster_name: &DBName) -> Result<(), MetaStoreError> {
+        let running = match self.clusters.get(cluster_name) {
+            Some(cluster) => Self::get_running_migration_num(cluster),
+            None => {
+                self.migration_plans.remove(cluster_name);
+                return Ok(());
+            }
+        };
+
+        let available = match self.migration_plans.get(cluster_name) {
+            Some(plan) => plan.max_concurrent_migrations.saturating_sub(running),
+            None => return Ok(()),
+        };
+        for _ in 0..available {
+            let slot_range_move = match self
+                .migration_plans
+                .get_mut(cluster_name)
+                .and_then(|plan| plan.moves.pop_front())
+            {
+                Some(slot_range_move) => slot_range_move,
+                None => break,
+            };
+            let res = self.migrate_slots(
+                cluster_name.to_string(),
+                slot_range_move.src_node_address.clone(),
+                slot_range_move.dst_node_address.clone(),
+                MigrationType::Ranges(slot_range_move.ranges.clone()),
+            );
+            if let Err(err) = res {
+                error!(
+                    "failed to start planned migration {} {} {} {:?}",
+                    cluster_name,
+                    slot_range_move.src_node_address,
+                    slot_range_move.dst_node_address,
+                    err
+                );
+                if let Some(plan) = self.migration_plans.get_mut(cluster_name) {
+                    plan.failures += 1;
+                    if plan.failures >= MAX_PLANNED_MIGRATION_FAILURES {
+                        error!(
+                            "drop planned migration {} {:?} after {} failures",
+                            cluster_name, slot_range_move, plan.failures
+                        );
+                        plan.failures = 0;
+                    } else {
+                        plan.moves.push_front(slot_range_move);
+                    }
+                }
+                self.remove_finished_plan(cluster_name);
+                return Err(err);
+            }
+            if let Some(plan) = self.migration_plans.get_mut(cluster_name) {
+                plan.failures = 0;
+            }
+        }
+
+        self.remove_finished_plan(cluster_name);
+        Ok(())
+    }
+
+    fn remove_finished_plan(&mut self, cluster_name: &DBName) {
+        let finished = self
+            .migration_plans
+            .get(cluster_name)
+            .map(|plan| plan.moves.is_empty())
+            .unwrap_or(false);
+        if finished {
+            self.migration_plans.remove(cluster_name);
+        }
+    }
+
+    // Retry the plans which failed to start their moves.
+    pub fn schedule_all_planned_migrations(&mut self) {
+        let cluster_names: Vec<DBName> = self.migration_plans.keys().cloned().collect();
+        for cluster_name in cluster_names.into_iter() {
+            if let Err(err) = self.schedule_planned_migrations(&cluster_name) {
+                warn!(
+                    "failed to schedule the planned migrations of {}: {:?}",
+                    cluster_name, err
+                );
+            }
+        }
+    }
+
+    pub fn stop_migrations(
+        &mut self,
+        cluster_name: String,
+        src_node_address: String,
+        dst_node_address: String,
+    ) -> Result<(), MetaStoreError> {
+        let cluster_name =
+            DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
+        let new_epoch = self.bump_global_epoch();
+
+        let cluster = self
+            .clusters
+            .get_mut(&cluster_name)
+            .ok_or(MetaStoreError::ClusterNotFound)?;
+
+        cluster
+            .get_mut_node(&src_node_address)
+            .ok_or(MetaStoreError::NodeNotFound)
+            .and_then(
+                |node| match (node.get_role(), node.get_slots().is_empty()) {
+                    (Role::Master, false) => {
+                        for slot_range in node.get_mut_slots().iter_mut() {
+                            match slot_range.tag {
+                                SlotRangeTag::Migrating(ref meta)
+                                    if meta.dst_node_address == dst_node_address => {}
+                                _ => continue,
+                            }
+                            slot_range.tag = SlotRangeTag::None;
+                        }
+                        Ok(())
+                    }
+                    (Role::Replica, _) => Err(MetaStoreError::InvalidRole),
+                    (_, true) => Err(MetaStoreError::SlotEmpty),
+                },
+            )?;
+
+        cluster
             .get_mut_node(&dst_node_address)
             .ok_or(MetaStoreError::NodeNotFound)
             .and_then(
@@ -639,6 +1492,142 @@ impl MetaStore {
         Ok(())
     }
 
+    // Unlike `stop_migrations`, the migration tags are kept until both proxies
+    // have aborted their migration tasks, so that these slots can't be migrated again
+    // before the partially migrated keys get deleted.
+    pub fn cancel_migrations(
+        &mut self,
+        cluster_name: String,
+        src_node_address: String,
+        dst_node_address: String,
+    ) -> Result<(), MetaStoreError> {
+        let cluster_name =
+            DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
+        let cluster = self
+            .clusters
+            .get(&cluster_name)
+            .ok_or(MetaStoreError::ClusterNotFound)?;
+        let src_node = cluster
+            .get_node(&src_node_address)
+            .ok_or(MetaStoreError::NodeNotFound)?;
+        if src_node.get_role() != Role::Master {
+            return Err(MetaStoreError::InvalidRole);
+        }
+
+        let canceling = self
+            .canceling_migrations
+            .entry(cluster_name.clone())
+            .or_default();
+        let metas: HashSet<MigrationMeta> = src_node
+            .get_slots()
+            .iter()
+            .filter_map(|slot_range| match slot_range.tag {
+                SlotRangeTag::Migrating(ref meta) if meta.dst_node_address == dst_node_address => {
+                    Some(meta.clone())
+                }
+                _ => None,
+            })
+            .filter(|meta| canceling.iter().all(|c| c.meta != *meta))
+            .collect();
+        if metas.is_empty() {
+            return Err(MetaStoreError::NotMigrating);
+        }
+
+        for meta in metas.into_iter() {
+            canceling.push(CancelingMigration {
+                meta,
+                src_acked: false,
+                dst_acked: false,
+            });
+        }
+        // The remaining planned migrations are canceled too,
+        // so are the proxies being drained by the plan.
+        self.migration_plans.remove(&cluster_name);
+        self.draining_proxies
+            .retain(|_, draining_cluster| *draining_cluster != cluster_name);
+
+        let new_epoch = self.bump_global_epoch();
+        let cluster = try_state!(self
+            .clusters
+            .get_mut(&cluster_name)
+            .ok_or(MetaStoreError::ClusterNotFound));
+        cluster.set_epoch(new_epoch);
+        Ok(())
+    }
+
+    // Called after the proxy has aborted the migration tasks.
+    pub fn ack_migration_rollback(
+        &mut self,
+        proxy_address: String,
+        task: MigrationTaskMeta,
+    ) -> Result<(), MetaStoreError> {
+        let MigrationTaskMeta {
+            db_name: cluster_name,
+            slot_range,
+        } = task;
+        let migration_meta = slot_range
+            .tag
+            .get_migration_meta()
+            .cloned()
+            .ok_or(MetaStoreError::InvalidRequest)?;
+
+        let canceling = self
+            .canceling_migrations
+            .get_mut(&cluster_name)
+            .ok_or(MetaStoreError::NotMigrating)?;
+        let index = canceling
+            .iter()
+            .position(|c| c.meta == migration_meta)
+            .ok_or(MetaStoreError::NotMigrating)?;
+
+        let finished = {
+            let c = &mut canceling[index];
+            if c.meta.src_proxy_address == proxy_address {
+                if c.src_acked {
+                    return Ok(());
+                }
+                c.src_acked = true;
+                false
+            } else if c.meta.dst_proxy_address == proxy_address {
+                if !c.src_acked {
+                    return Err(MetaStoreError::InvalidState);
+                }
+                c.dst_acked = true;
+                true
+            } else {
+                return Err(MetaStoreError::InvalidRequest);
+            }
+        };
+
+        if finished {
+            canceling.remove(index);
+            if canceling.is_empty() {
+                self.canceling_migrations.remove(&cluster_name);
+            }
+        }
+
+        let new_epoch = self.bump_global_epoch();
+        let cluster = self
+            .clusters
+            .get_mut(&cluster_name)
+            .ok_or(MetaStoreError::ClusterNotFound)?;
+        if finished {
+            let node_addresses = [
+                migration_meta.src_node_address.clone(),
+                migration_meta.dst_node_address.clone(),
+            ];
+            let reverted = vec![migration_meta];
+            for node_address in node_addresses.iter() {
+                let node = try_state!(cluster
+                    .get_mut_node(node_address)
+                    .ok_or(MetaStoreError::NodeNotFound));
+                Self::rollback_slot_ranges(node.get_mut_slots(), &reverted);
+            }
+        }
+        cluster.set_epoch(new_epoch);
+        Ok(())
+    }
+
     pub fn assign_replica(
         &mut self,
         cluster_name: String,
@@ -711,168 +1700,271 @@ impl MetaStore {
         Ok(())
     }
 
+    // Returns the first inconsistency.
     pub fn validate(&self) -> Result<(), InconsistentError> {
-        self.validate_all_nodes()?;
-        self.validate_cluster_nodes()?;
-        self.validate_peers()?;
-        self.validate_slots()?;
-        Ok(())
+        match self.collect_inconsistencies().into_iter().next() {
+            Some(issue) => Err(issue.error),
+            None => Ok(()),
+        }
     }
 
-    pub fn validate_all_nodes(&self) -> Result<(), InconsistentError> {
-        let all_nodes = &self.all_nodes;
-        let clusters = &self.clusters;
-        for (proxy_address, node_resource) in all_nodes.iter() {
+    // Unlike `validate`, this does not stop at the first inconsistency
+    // and also proposes the repair operations for some of them.
+    pub fn collect_inconsistencies(&self) -> Vec<Inconsistency> {
+        let mut issues = vec![];
+        self.validate_all_nodes(&mut issues);
+        self.validate_cluster_nodes(&mut issues);
+        self.validate_peers(&mut issues);
+        self.validate_slots(&mut issues);
+        issues
+    }
+
+    fn validate_all_nodes(&self, issues: &mut Vec<Inconsistency>) {
+        for (proxy_address, node_resource) in self.all_nodes.iter() {
             let cluster_name = match &node_resource.cluster_name {
                 Some(cluster_name) => cluster_name,
                 None => continue,
             };
-            let cluster =
-                clusters
-                    .get(&cluster_name)
-                    .ok_or_else(|| InconsistentError::ClusterNotFound {
+            let cluster = match self.clusters.get(cluster_name) {
+                Some(cluster) => cluster,
+                None => {
+                    issues.push(Inconsistency::new(InconsistentError::ClusterNotFound {
                         cluster_name: cluster_name.to_string(),
-                    })?;
+                    }));
+                    continue;
+                }
+            };
             for node_address in node_resource.node_addresses.iter() {
-                let node = cluster.get_node(node_address).ok_or_else(|| {
-                    InconsistentError::NodeNotFoundInCluster {
-                        proxy_address: proxy_address.clone(),
-                        node_address: node_address.clone(),
+                let node = match cluster.get_node(node_address) {
+                    Some(node) => node,
+                    None => {
+                        issues.push(Inconsistency::new(
+                            InconsistentError::NodeNotFoundInCluster {
+                                proxy_address: proxy_address.clone(),
+                                node_address: node_address.clone(),
+                            },
+                        ));
+                        continue;
                     }
-                })?;
+                };
                 if node.get_proxy_address() != proxy_address {
-                    return Err(InconsistentError::InvalidProxyAddress {
+                    issues.push(Inconsistency::new(InconsistentError::InvalidProxyAddress {
                         node_address: node_address.clone(),
                         expected_proxy_address: proxy_address.clone(),
                         unexpected_proxy_address: node.get_proxy_address().to_string(),
-                    });
+                    }));
                 }
             }
         }
-        Ok(())
     }
 
-    pub fn validate_cluster_nodes(&self) -> Result<(), InconsistentError> {
-        let all_nodes = &self.all_nodes;
-        let clusters = &self.clusters;
-        for (cluster_name, cluster) in clusters.iter() {
+    fn validate_cluster_nodes(&self, issues: &mut Vec<Inconsistency>) {
+        for (cluster_name, cluster) in self.clusters.iter() {
             if cluster_name != cluster.get_name() {
-                return Err(InconsistentError::InvalidClusterName {
+                issues.push(Inconsistency::new(InconsistentError::InvalidClusterName {
                     expected_name: cluster_name.to_string(),
                     unexpected_name: cluster.get_name().to_string(),
-                });
+                }));
             }
             for node in cluster.get_nodes().iter() {
                 if node.get_cluster_name() != cluster_name {
-                    return Err(InconsistentError::InvalidClusterName {
+                    issues.push(Inconsistency::new(InconsistentError::InvalidClusterName {
                         expected_name: cluster_name.to_string(),
                         unexpected_name: node.get_cluster_name().to_string(),
-                    });
+                    }));
                 }
                 let proxy_address = node.get_proxy_address();
-                let node_address = node.get_address().to_string();
-                let node_resource = all_nodes.get(proxy_address).ok_or_else(|| {
-                    InconsistentError::NodeNotFoundInAllNodes {
+                let node_address = node.get_address();
+                let node_resource = self.all_nodes.get(proxy_address);
+                let registered = node_resource
+                    .map(|resource| resource.node_addresses.contains(node_address))
+                    .unwrap_or(false);
+                if registered {
+                    continue;
+                }
+                // The node could only be registered back if the proxy is not used by other clusters.
+                let repair = match node_resource.and_then(|resource| resource.cluster_name.as_ref())
+                {
+                    Some(owner) if owner != cluster_name => None,
+                    _ => Some(RepairOperation::RegisterNode {
+                        cluster_name: cluster_name.clone(),
                         proxy_address: proxy_address.to_string(),
-                        node_address: node_address.clone(),
-                    }
-                })?;
-                node_resource
-                    .node_addresses
-                    .iter()
-                    .find(|node| **node == node_address)
-                    .ok_or_else(|| InconsistentError::NodeNotFoundInAllNodes {
+                        node_address: node_address.to_string(),
+                    }),
+                };
+                issues.push(Inconsistency {
+                    error: InconsistentError::NodeNotFoundInAllNodes {
                         proxy_address: proxy_address.to_string(),
-                        node_address: node_address.clone(),
-                    })?;
+                        node_address: node_address.to_string(),
+                    },
+                    repair,
+                });
             }
         }
-        Ok(())
     }
 
-    pub fn validate_peers(&self) -> Result<(), InconsistentError> {
-        for cluster in self.clusters.values() {
+    fn validate_peers(&self, issues: &mut Vec<Inconsistency>) {
+        for (cluster_name, cluster) in self.clusters.iter() {
             for node in cluster.get_nodes().iter() {
                 let peers = node.get_repl_meta().get_peers();
                 for peer in peers.iter() {
-                    let peer_node = cluster.get_node(&peer.node_address).ok_or_else(|| {
-                        InconsistentError::PeerNotFound {
-                            proxy_address: peer.proxy_address.clone(),
-                            node_address: peer.node_address.clone(),
+                    let peer_node = match cluster.get_node(&peer.node_address) {
+                        Some(peer_node) => peer_node,
+                        None => {
+                            issues.push(Inconsistency {
+                                error: InconsistentError::PeerNotFound {
+                                    proxy_address: peer.proxy_address.clone(),
+                                    node_address: peer.node_address.clone(),
+                                },
+                                repair: Some(RepairOperation::RemovePeer {
+                                    cluster_name: cluster_name.clone(),
+                                    node_address: node.get_address().to_string(),
+                                    peer: peer.clone(),
+                                }),
+                            });
+                            continue;
                         }
-                    })?;
+                    };
                     if *peer_node.get_proxy_address() != peer.proxy_address {
-                        return Err(InconsistentError::InvalidProxyAddress {
+                        issues.push(Inconsistency::new(InconsistentError::InvalidProxyAddress {
                             node_address: peer.node_address.clone(),
                             expected_proxy_address: peer.proxy_address.clone(),
                             unexpected_proxy_address: peer_node.get_proxy_address().to_string(),
-                        });
+                        }));
                     }
                     if node.get_role() == peer_node.get_role() {
-                        return Err(InconsistentError::SameRole {
+                        issues.push(Inconsistency::new(InconsistentError::SameRole {
                             node_address: node.get_address().to_string(),
                             node_address_peer: peer_node.get_address().to_string(),
-                        });
+                        }));
                     }
                 }
                 if node.get_role() == Role::Replica && !node.get_slots().is_empty() {
-                    return Err(InconsistentError::ReplicaHasSlots {
+                    issues.push(Inconsistency::new(InconsistentError::ReplicaHasSlots {
                         proxy_address: node.get_proxy_address().to_string(),
                         node_address: node.get_address().to_string(),
-                    });
+                    }));
                 }
             }
         }
-        Ok(())
     }
 
-    pub fn validate_slots(&self) -> Result<(), InconsistentError> {
-        for cluster in self.clusters.values() {
+    fn validate_slots(&self, issues: &mut Vec<Inconsistency>) {
+        for (cluster_name, cluster) in self.clusters.iter() {
             for node in cluster.get_nodes().iter() {
-                let slots = node.get_slots();
-                for slot_range in slots.iter() {
-                    match &slot_range.tag {
+                for slot_range in node.get_slots().iter() {
+                    let (peer_node_address, peer_proxy_address) = match &slot_range.tag {
                         SlotRangeTag::None => continue,
                         SlotRangeTag::Migrating(meta) => {
-                            let peer_node =
-                                cluster.get_node(&meta.dst_node_address).ok_or_else(|| {
-                                    InconsistentError::MigrationPeerNotFound {
-                                        node_address: meta.dst_node_address.clone(),
-                                        proxy_address: meta.dst_proxy_address.clone(),
-                                    }
-                                })?;
-                            if *peer_node.get_proxy_address() != meta.dst_proxy_address {
-                                return Err(InconsistentError::MigrationPeerNotFound {
-                                    node_address: meta.dst_node_address.clone(),
-                                    proxy_address: meta.dst_proxy_address.clone(),
-                                });
-                            }
+                            (&meta.dst_node_address, &meta.dst_proxy_address)
                         }
                         SlotRangeTag::Importing(meta) => {
-                            let peer_node =
-                                cluster.get_node(&meta.src_node_address).ok_or_else(|| {
-                                    InconsistentError::MigrationPeerNotFound {
-                                        node_address: meta.src_node_address.clone(),
-                                        proxy_address: meta.src_proxy_address.clone(),
-                                    }
-                                })?;
-                            if *peer_node.get_proxy_address() != meta.src_proxy_address {
-                                return Err(InconsistentError::MigrationPeerNotFound {
-                                    node_address: meta.src_node_address.clone(),
-                                    proxy_address: meta.src_proxy_address.clone(),
-                                });
-                            }
+                            (&meta.src_node_address, &meta.src_proxy_address)
                         }
+                    };
+                    let peer_found = cluster
+                        .get_node(peer_node_address)
+                        .map(|peer_node| peer_node.get_proxy_address() == peer_proxy_address)
+                        .unwrap_or(false);
+                    if peer_found {
+                        continue;
                     }
+                    // Let the node keep the slots since the other side is gone.
+                    issues.push(Inconsistency {
+                        error: InconsistentError::MigrationPeerNotFound {
+                            node_address: peer_node_address.clone(),
+                            proxy_address: peer_proxy_address.clone(),
+                        },
+                        repair: Some(RepairOperation::ClearMigrationTag {
+                            cluster_name: cluster_name.clone(),
+                            node_address: node.get_address().to_string(),
+                            start: slot_range.start,
+                            end: slot_range.end,
+                        }),
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn apply_repair(&mut self, repair: &RepairOperation) -> Result<(), MetaStoreError> {
+        let cluster_name = match repair {
+            RepairOperation::RegisterNode { cluster_name, .. }
+            | RepairOperation::RemovePeer { cluster_name, .. }
+            | RepairOperation::ClearMigrationTag { cluster_name, .. } => cluster_name.clone(),
+        };
+        if !self.clusters.contains_key(&cluster_name) {
+            return Err(MetaStoreError::ClusterNotFound);
+        }
+
+        match repair {
+            RepairOperation::RegisterNode {
+                proxy_address,
+                node_address,
+                ..
+            } => {
+                let resource = self
+                    .all_nodes
+                    .entry(proxy_address.clone())
+                    .or_insert_with(|| NodeResource {
+                        node_addresses: HashSet::new(),
+                        cluster_name: None,
+                        labels: HashMap::new(),
+                    });
+                match &resource.cluster_name {
+                    Some(owner) if *owner != cluster_name => return Err(MetaStoreError::InUse),
+                    _ => (),
                 }
+                resource.cluster_name = Some(cluster_name.clone());
+                resource.node_addresses.insert(node_address.clone());
+            }
+            RepairOperation::RemovePeer {
+                node_address, peer, ..
+            } => {
+                let cluster = self
+                    .clusters
+                    .get_mut(&cluster_name)
+                    .ok_or(MetaStoreError::ClusterNotFound)?;
+                let node = cluster
+                    .get_mut_node(node_address)
+                    .ok_or(MetaStoreError::NodeNotFound)?;
+                node.get_mut_repl().remove_peer(peer);
+            }
+            RepairOperation::ClearMigrationTag {
+                node_address,
+                start,
+                end,
+                ..
+            } => {
+                let cluster = self
+                    .clusters
+                    .get_mut(&cluster_name)
+                    .ok_or(MetaStoreError::ClusterNotFound)?;
+                let node = cluster
+                    .get_mut_node(node_address)
+                    .ok_or(MetaStoreError::NodeNotFound)?;
+                let slot_range = node
+                    .get_mut_slots()
+                    .iter_mut()
+                    .find(|range| range.start == *start && range.end == *end)
+                    .ok_or(MetaStoreError::SlotRangeNotFound)?;
+                slot_range.tag = SlotRangeTag::None;
             }
         }
+
+        let new_epoch = self.bump_global_epoch();
+        self.clusters
+            .get_mut(&cluster_name)
+            .ok_or(MetaStoreError::ClusterNotFound)?
+            .set_epoch(new_epoch);
         Ok(())
     }
 
+    // The proxies outside the `avoided_zones` are preferred.
     fn consume_node_slot(
         &mut self,
         cluster_name: &DBName,
+        avoided_zones: &HashSet<String>,
     ) -> Result<Vec<NodeSlot>, MetaStoreError> {
         let failures = self.failures.clone();
 
@@ -882,18 +1974,15 @@ impl MetaStore {
             .filter(|(proxy_address, node_resource)| {
                 !failures.contains_key(*proxy_address) && node_resource.cluster_name.is_none()
             })
-            .fold1(
-                |(max_proxy_address, max_node_resource), (proxy_address, node_resource)| {
-                    let max_free_num = max_node_resource.node_addresses.len();
-                    let curr_free_num = node_resource.node_addresses.len();
-                    if curr_free_num > max_free_num {
-                        (proxy_address, node_resource)
-                    } else {
-                        (max_proxy_address, max_node_resource)
-                    }
-                },
-            )
-            .ok_or_else(|| MetaStoreError::NoAvailableResource)?;
+            .max_by_key(|(_, node_resource)| {
+                let preferred = node_resource
+                    .labels
+                    .get(ZONE_LABEL)
+                    .map(|zone| !avoided_zones.contains(zone))
+                    .unwrap_or(true);
+                (preferred, node_resource.node_addresses.len())
+            })
+            .ok_or_else(|| MetaStoreError::NoAvailableResource("no free proxy".to_string()))?;
 
         node_resource.cluster_name = Some(cluster_name.clone());
 
@@ -920,6 +2009,50 @@ impl MetaStore {
         (migrating_slot_ranges, free_slot_ranges)
     }
 
+    // Split out the specified ranges from the free slot ranges.
+    fn pick_slot_ranges(
+        slot_ranges: Vec<SlotRange>,
+        ranges: Vec<Range>,
+    ) -> Result<(Vec<SlotRange>, Vec<SlotRange>), MetaStoreError> {
+        if ranges.is_empty() {
+            return Err(MetaStoreError::InvalidRequest);
+        }
+
+        let (mut rest_slot_ranges, mut free_slot_ranges) = Self::move_slot_ranges(slot_ranges);
+        let mut picked_slot_ranges = vec![];
+        for range in ranges.into_iter() {
+            if range.start > range.end {
+                return Err(MetaStoreError::InvalidRequest);
+            }
+            let index = free_slot_ranges
+                .iter()
+                .position(|sr| sr.start <= range.start && range.end <= sr.end)
+                .ok_or(MetaStoreError::SlotRangeNotFound)?;
+            let slot_range = free_slot_ranges.remove(index);
+            if slot_range.start < range.start {
+                free_slot_ranges.push(SlotRange {
+                    start: slot_range.start,
+                    end: range.start - 1,
+                    tag: SlotRangeTag::None,
+                });
+            }
+            if range.end < slot_range.end {
+                free_slot_ranges.push(SlotRange {
+                    start: range.end + 1,
+                    end: slot_range.end,
+                    tag: SlotRangeTag::None,
+                });
+            }
+            picked_slot_ranges.push(SlotRange {
+                start: range.start,
+                end: range.end,
+                tag: SlotRangeTag::None,
+            });
+        }
+        rest_slot_ranges.extend(free_slot_ranges);
+        Ok((rest_slot_ranges, picked_slot_ranges))
+    }
+
     fn split_slot_ranges(
         slot_ranges: Vec<SlotRange>,
     ) -> (Vec<SlotRange>, Vec<SlotRange>, Vec<SlotRange>) {
@@ -972,9 +2105,12 @@ impl MetaStore {
         (src_slot_ranges, dst_slot_ranges, migrating_slot_ranges)
     }
 
+    // The masters in `preferred_replicas` are taken over by the specified replicas.
+    // The other masters are taken over by their first replicas.
     pub fn replace_failed_proxy(
         &mut self,
         failed_proxy_address: String,
+        preferred_replicas: &HashMap<String, String>,
     ) -> Result<Proxy, MetaStoreError> {
         let (cluster_name, node_addresses) = {
             let node_resource = self
@@ -994,10 +2130,16 @@ impl MetaStore {
             )
         };
 
+        // The zones should be collected before the takeover changes the peers.
+        let peer_zones = self.get_peer_zones(&cluster_name, &node_addresses);
+
         for node_address in node_addresses.iter() {
-            match self.takeover_master(&cluster_name, &node_address) {
+            let replica_address = preferred_replicas.get(node_address).map(String::as_str);
+            match self.takeover_master(&cluster_name, &node_address, replica_address) {
                 Err(MetaStoreError::NotMaster) => (),
-                Err(MetaStoreError::NoPeer) => (),
+                // The master without any replica will be replaced by an empty node.
+                // But the chosen replica which is no longer a peer should not be skipped.
+                Err(MetaStoreError::NoPeer) if replica_address.is_none() => (),
                 Err(unexpected_errors) => {
                     error!(
                         "unexpected errors when replacing failed nodes {:?}",
@@ -1009,54 +2151,414 @@ impl MetaStore {
             }
         }
 
-        let node_slots = self.consume_new_proxy(&cluster_name)?;
+        let node_slots = self.consume_new_proxy(&cluster_name, &peer_zones)?;
         if node_slots.len() != node_addresses.len() {
             if let Some(node_resource) = self.all_nodes.get_mut(&failed_proxy_address) {
                 node_resource.cluster_name = None;
             }
             let cluster = try_state!(self
                 .clusters
-                .get_mut(&cluster_name)
-                .ok_or_else(|| MetaStoreError::ClusterNotFound));
-            for node_slot in node_slots.iter() {
-                try_state!(cluster
-                    .remove_node(&node_slot.node_address)
-                    .ok_or_else(|| MetaStoreError::NodeNotFound));
+                .get_mut(&cluster_name)
+                .ok_or_else(|| MetaStoreError::ClusterNotFound));
+            for node_slot in node_slots.iter() {
+                try_state!(cluster
+                    .remove_node(&node_slot.node_address)
+                    .ok_or_else(|| MetaStoreError::NodeNotFound));
+            }
+            error!(
+                "invalid node number, slots: {} nodes {}",
+                node_slots.len(),
+                node_addresses.len()
+            );
+            return Err(MetaStoreError::InvalidNodeNum);
+        }
+
+        let new_proxy_address = node_slots
+            .iter()
+            .next()
+            .map(|node_slot| node_slot.proxy_address.clone())
+            .ok_or_else(|| MetaStoreError::InvalidNodeNum)?;
+
+        for (node_address, node_slot) in node_addresses.iter().zip(node_slots.into_iter()) {
+            try_state!(self.replace_node(&cluster_name, node_address, node_slot));
+        }
+
+        try_state!(self.remove_proxy_from_cluster_helper(
+            cluster_name.as_str(),
+            &failed_proxy_address,
+            false
+        ));
+        try_state!(self.move_failed_proxy(&failed_proxy_address));
+        let host = try_state!(self
+            .get_host_by_address(&new_proxy_address)
+            .ok_or_else(|| MetaStoreError::HostNotFound));
+        Ok(host)
+    }
+
+    fn get_node_cluster(&self, node_address: &str) -> Option<&Cluster> {
+        self.clusters
+            .values()
+            .find(|cluster| cluster.get_node(node_address).is_some())
+    }
+
+    // The replicas on the healthy proxies which could take over the master.
+    // Empty if the node is not a master.
+    pub fn get_failover_candidates(&self, node_address: &str) -> Result<Vec<Node>, MetaStoreError> {
+        let cluster = self
+            .get_node_cluster(node_address)
+            .ok_or(MetaStoreError::NodeNotFound)?;
+        let node = cluster
+            .get_node(node_address)
+            .ok_or(MetaStoreError::NodeNotFound)?;
+        if node.get_role() != Role::Master {
+            return Ok(vec![]);
+        }
+        Ok(node
+            .get_repl_meta()
+            .get_peers()
+            .iter()
+            .filter(|peer| !self.is_proxy_failed(&peer.proxy_address))
+            .filter_map(|peer| cluster.get_node(&peer.node_address))
+            .cloned()
+            .collect())
+    }
+
+    // master node address => failover candidates, for the masters in the proxy.
+    pub fn get_proxy_failover_candidates(
+        &self,
+        proxy_address: &str,
+    ) -> Result<Vec<(String, Vec<Node>)>, MetaStoreError> {
+        let node_resource = self
+            .all_nodes
+            .get(proxy_address)
+            .ok_or(MetaStoreError::HostNotFound)?;
+        let mut candidates = vec![];
+        for node_address in node_resource.node_addresses.iter() {
+            if self.get_node_cluster(node_address).is_none() {
+                continue;
+            }
+            let replicas = self.get_failover_candidates(node_address)?;
+            if !replicas.is_empty() {
+                candidates.push((node_address.clone(), replicas));
+            }
+        }
+        Ok(candidates)
+    }
+
+    // Handles a single failed node while its proxy is still alive.
+    // The failed master is taken over by `replica_address` or
+    // the first replica on the healthy proxies if it's not specified.
+    // Then the failed node is replaced by a free node as the new replica.
+    // The other nodes in the same proxy are not changed.
+    pub fn failover_node(
+        &mut self,
+        failed_node_address: &str,
+        replica_address: Option<&str>,
+    ) -> Result<NodeFailover, MetaStoreError> {
+        let (cluster_name, is_master) = {
+            let cluster = self
+                .get_node_cluster(failed_node_address)
+                .ok_or(MetaStoreError::NodeNotFound)?;
+            let node = cluster
+                .get_node(failed_node_address)
+                .ok_or(MetaStoreError::NodeNotFound)?;
+            (cluster.get_name().clone(), node.get_role() == Role::Master)
+        };
+
+        let replica_address = if is_master {
+            let replica_address = match replica_address {
+                Some(replica_address) => replica_address.to_string(),
+                None => self
+                    .get_failover_candidates(failed_node_address)?
+                    .first()
+                    .map(|node| node.get_address().to_string())
+                    .ok_or(MetaStoreError::NoPeer)?,
+            };
+            Some(replica_address)
+        } else {
+            None
+        };
+
+        let promoted = match replica_address {
+            Some(replica_address) => Some(self.takeover_master(
+                &cluster_name,
+                failed_node_address,
+                Some(&replica_address),
+            )?),
+            None => None,
+        };
+
+        // The takeover has been done even if there's no free node for the new replica.
+        let new_replica = match self.replace_failed_replica(&cluster_name, failed_node_address) {
+            Ok(node) => Some(node),
+            Err(err) if promoted.is_some() => {
+                warn!(
+                    "failed to replace the failed node {}: {:?}",
+                    failed_node_address, err
+                );
+                None
+            }
+            Err(err) => return Err(err),
+        };
+
+        // The reported failure has been handled.
+        self.failures.remove(failed_node_address);
+
+        Ok(NodeFailover {
+            promoted,
+            new_replica,
+        })
+    }
+
+    fn is_proxy_failed(&self, proxy_address: &str) -> bool {
+        self.failed_proxies.contains_key(proxy_address) || self.failures.contains_key(proxy_address)
+    }
+
+    // Detach the failed replica from its master and assign a free node as the new replica.
+    // The failed node stays in the cluster since its proxy is still in use,
+    // but it's marked as failed so that it won't be chosen as a free node again.
+    fn replace_failed_replica(
+        &mut self,
+        cluster_name: &DBName,
+        failed_node_address: &str,
+    ) -> Result<Node, MetaStoreError> {
+        let master_peer = {
+            let cluster = self
+                .clusters
+                .get(cluster_name)
+                .ok_or(MetaStoreError::ClusterNotFound)?;
+            let node = cluster
+                .get_node(failed_node_address)
+                .ok_or(MetaStoreError::NodeNotFound)?;
+            if node.get_role() != Role::Replica {
+                return Err(MetaStoreError::InvalidRole);
+            }
+            node.get_repl_meta()
+                .get_peers()
+                .first()
+                .cloned()
+                .ok_or(MetaStoreError::NoPeer)?
+        };
+
+        // Avoid placing the new replica in the same zone as the master.
+        let master_zones = self.get_peer_zones(cluster_name, &[failed_node_address.to_string()]);
+        let new_replica_address =
+            match self.find_free_node(cluster_name, &master_peer.proxy_address, &master_zones) {
+                Some(node_address) => node_address,
+                None => self
+                    .add_free_proxy_nodes(cluster_name, &master_zones)?
+                    .first()
+                    .map(|node| node.get_address().to_string())
+                    .ok_or_else(|| {
+                        MetaStoreError::NoAvailableResource("no free node".to_string())
+                    })?,
+            };
+
+        let new_epoch = self.bump_global_epoch();
+        let cluster = self
+            .clusters
+            .get_mut(cluster_name)
+            .ok_or(MetaStoreError::ClusterNotFound)?;
+        cluster.set_epoch(new_epoch);
+
+        info!(
+            "start to replace the failed replica {} with {}",
+            failed_node_address, new_replica_address
+        );
+
+        let failed_peer = {
+            let failed_node = try_state!(cluster
+                .get_mut_node(failed_node_address)
+                .ok_or(MetaStoreError::NodeNotFound));
+            *failed_node.get_mut_repl() = ReplMeta::new(Role::Master, vec![]);
+            ReplPeer {
+                node_address: failed_node.get_address().to_string(),
+                proxy_address: failed_node.get_proxy_address().to_string(),
+            }
+        };
+        self.failed_nodes.insert(failed_node_address.to_string());
+
+        let new_replica = {
+            let new_replica = try_state!(cluster
+                .get_mut_node(&new_replica_address)
+                .ok_or(MetaStoreError::NodeNotFound));
+            *new_replica.get_mut_repl() = ReplMeta::new(Role::Replica, vec![master_peer.clone()]);
+            new_replica.clone()
+        };
+
+        let master = try_state!(cluster
+            .get_mut_node(&master_peer.node_address)
+            .ok_or(MetaStoreError::NodeNotFound));
+        master.get_mut_repl().remove_peer(&failed_peer);
+        master.get_mut_repl().add_peer(ReplPeer {
+            node_address: new_replica.get_address().to_string(),
+            proxy_address: new_replica.get_proxy_address().to_string(),
+        });
+
+        Ok(new_replica)
+    }
+
+    // Assign a free node on another proxy as a new replica of the master,
+    // or a node of a new free proxy if there's no free node in the cluster.
+    // Refuses it if the master already has `replicas_per_master` replicas
+    // so that the retries of the coordinators won't add more.
+    pub fn add_replica(
+        &mut self,
+        cluster_name: String,
+        master_node_address: String,
+    ) -> Result<Node, MetaStoreError> {
+        let db_name =
+            DBName::from(&cluster_name).map_err(|_| MetaStoreError::InvalidClusterName)?;
+        let master_proxy_address = {
+            let cluster = self
+                .clusters
+                .get(&db_name)
+                .ok_or(MetaStoreError::ClusterNotFound)?;
+            let master = cluster
+                .get_node(&master_node_address)
+                .ok_or(MetaStoreError::NodeNotFound)?;
+            if master.get_role() != Role::Master {
+                return Err(MetaStoreError::InvalidRole);
             }
-            error!(
-                "invalid node number, slots: {} nodes {}",
-                node_slots.len(),
-                node_addresses.len()
-            );
-            return Err(MetaStoreError::InvalidNodeNum);
+            let peers = master.get_repl_meta().get_peers();
+            if peers.len() >= cluster.get_config().replicas_per_master {
+                return Err(MetaStoreError::AlreadyExisted);
+            }
+            master.get_proxy_address().to_string()
+        };
+
+        // Avoid the zones of the master and its existing replicas.
+        let mut avoided_zones =
+            self.get_peer_zones(&db_name, std::slice::from_ref(&master_node_address));
+        if let Some(zone) = self
+            .all_nodes
+            .get(&master_proxy_address)
+            .and_then(|node_resource| node_resource.labels.get(ZONE_LABEL))
+        {
+            avoided_zones.insert(zone.clone());
         }
+        let replica_address =
+            match self.find_free_node(&db_name, &master_proxy_address, &avoided_zones) {
+                Some(node_address) => node_address,
+                None => self
+                    .add_free_proxy_nodes(&db_name, &avoided_zones)?
+                    .first()
+                    .map(|node| node.get_address().to_string())
+                    .ok_or_else(|| {
+                        MetaStoreError::NoAvailableResource("no free node".to_string())
+                    })?,
+            };
 
-        let new_proxy_address = node_slots
+        self.assign_replica(cluster_name, master_node_address, replica_address.clone())?;
+        self.clusters
+            .get(&db_name)
+            .and_then(|cluster| cluster.get_node(&replica_address))
+            .cloned()
+            .ok_or(MetaStoreError::NodeNotFound)
+    }
+
+    // Free nodes are the masters without any slot or replica
+    // and not marked as failed or being drained.
+    // Prefers the nodes outside `avoided_zones` and the proxies with more free nodes.
+    fn find_free_node(
+        &self,
+        cluster_name: &DBName,
+        master_proxy_address: &str,
+        avoided_zones: &HashSet<String>,
+    ) -> Option<String> {
+        let cluster = self.clusters.get(cluster_name)?;
+        let free_nodes: Vec<&Node> = cluster
+            .get_nodes()
             .iter()
-            .next()
-            .map(|node_slot| node_slot.proxy_address.clone())
-            .ok_or_else(|| MetaStoreError::InvalidNodeNum)?;
+            .filter(|node| {
+                node.get_role() == Role::Master
+                    && node.get_slots().is_empty()
+                    && node.get_repl_meta().get_peers().is_empty()
+                    && !self.failed_nodes.contains(node.get_address())
+                    && node.get_proxy_address() != master_proxy_address
+                    && !self.is_proxy_failed(node.get_proxy_address())
+                    && !self.draining_proxies.contains_key(node.get_proxy_address())
+            })
+            .collect();
+        free_nodes
+            .iter()
+            .max_by_key(|node| {
+                let preferred = self
+                    .all_nodes
+                    .get(node.get_proxy_address())
+                    .and_then(|node_resource| node_resource.labels.get(ZONE_LABEL))
+                    .map(|zone| !avoided_zones.contains(zone))
+                    .unwrap_or(true);
+                let free_num = free_nodes
+                    .iter()
+                    .filter(|n| n.get_proxy_address() == node.get_proxy_address())
+                    .count();
+                (preferred, free_num, cmp::Reverse(node.get_address()))
+            })
+            .map(|node| node.get_address().to_string())
+    }
 
-        for (node_address, node_slot) in node_addresses.iter().zip(node_slots.into_iter()) {
-            try_state!(self.replace_node(&cluster_name, node_address, node_slot));
+    // Returns the master of the replica and the current cluster epoch.
+    pub fn get_switchover_master(
+        &self,
+        cluster_name: &DBName,
+        replica_address: &str,
+    ) -> Result<(Node, Node, u64), MetaStoreError> {
+        let cluster = self
+            .clusters
+            .get(cluster_name)
+            .ok_or(MetaStoreError::ClusterNotFound)?;
+        let replica = cluster
+            .get_node(replica_address)
+            .ok_or(MetaStoreError::NodeNotFound)?;
+        if replica.get_role() != Role::Replica {
+            return Err(MetaStoreError::InvalidRole);
+        }
+        let master_peer = replica
+            .get_repl_meta()
+            .get_peers()
+            .first()
+            .ok_or(MetaStoreError::NoPeer)?;
+        let master = cluster
+            .get_node(&master_peer.node_address)
+            .ok_or(MetaStoreError::NodeNotFound)?;
+        if master.get_role() != Role::Master {
+            return Err(MetaStoreError::NotMaster);
         }
 
-        try_state!(self.remove_proxy_from_cluster_helper(
-            cluster_name.as_str(),
-            &failed_proxy_address,
-            false
-        ));
-        try_state!(self.move_failed_proxy(&failed_proxy_address));
-        let host = try_state!(self
-            .get_host_by_address(&new_proxy_address)
-            .ok_or_else(|| MetaStoreError::HostNotFound));
-        Ok(host)
+        // Use failover instead if any of them has failed.
+        let failed = [master.get_proxy_address(), replica.get_proxy_address()]
+            .iter()
+            .any(|address| {
+                self.failed_proxies.contains_key(*address) || self.failures.contains_key(*address)
+            });
+        if failed {
+            return Err(MetaStoreError::InvalidState);
+        }
+        Ok((master.clone(), replica.clone(), cluster.get_epoch()))
+    }
+
+    // The `cluster_epoch` should be the one returned by `get_switchover_master`
+    // to make sure nothing changed while waiting for the replication.
+    pub fn switchover(
+        &mut self,
+        cluster_name: &DBName,
+        replica_address: &str,
+        cluster_epoch: u64,
+    ) -> Result<Node, MetaStoreError> {
+        let (master, _, epoch) = self.get_switchover_master(cluster_name, replica_address)?;
+        if epoch != cluster_epoch {
+            return Err(MetaStoreError::MismatchEpoch);
+        }
+        self.takeover_master(cluster_name, master.get_address(), Some(replica_address))
     }
 
+    // Promote the `replica_address` or the first replica if it's not specified.
     fn takeover_master(
         &mut self,
         cluster_name: &DBName,
         node_address: &str,
+        replica_address: Option<&str>,
     ) -> Result<Node, MetaStoreError> {
         let new_epoch = self.bump_global_epoch();
 
@@ -1078,7 +2580,10 @@ impl MetaStore {
             .get_repl_meta()
             .get_peers()
             .iter()
-            .next()
+            .find(|peer| match replica_address {
+                Some(address) => peer.node_address == address,
+                None => true,
+            })
             .cloned()
             .ok_or_else(|| MetaStoreError::NoPeer)?;
         info!("start to takeover {:?}", master_node);
@@ -1110,6 +2615,23 @@ impl MetaStore {
 
         try_state!(Self::update_peer(cluster, &master_node, &replica_node));
 
+        // The other replicas now replicate from the new master.
+        let old_master_peer = ReplPeer {
+            node_address: master_node.get_address().to_string(),
+            proxy_address: master_node.get_proxy_address().to_string(),
+        };
+        let mut new_master_peers = vec![old_master_peer];
+        new_master_peers.extend(
+            master_node
+                .get_repl_meta()
+                .get_peers()
+                .iter()
+                .filter(|p| p.node_address != peer_node_address)
+                .cloned(),
+        );
+        *master_node.get_mut_repl() = ReplMeta::new(Role::Replica, vec![peer.clone()]);
+        *replica_node.get_mut_repl() = ReplMeta::new(Role::Master, new_master_peers);
+
         *try_state!(cluster
             .get_mut_node(&node_address)
             .ok_or_else(|| MetaStoreError::NodeNotFound)) = master_node;
@@ -1310,11 +2832,27 @@ impl MetaStore {
         Ok(new_node)
     }
 
+    fn get_peer_zones(&self, cluster_name: &DBName, node_addresses: &[String]) -> HashSet<String> {
+        let cluster = match self.clusters.get(cluster_name) {
+            Some(cluster) => cluster,
+            None => return HashSet::new(),
+        };
+        node_addresses
+            .iter()
+            .filter_map(|node_address| cluster.get_node(node_address))
+            .flat_map(|node| node.get_repl_meta().get_peers().iter())
+            .filter_map(|peer| self.all_nodes.get(&peer.proxy_address))
+            .filter_map(|node_resource| node_resource.labels.get(ZONE_LABEL))
+            .cloned()
+            .collect()
+    }
+
     fn consume_new_proxy(
         &mut self,
         cluster_name: &DBName,
+        avoided_zones: &HashSet<String>,
     ) -> Result<Vec<NodeSlot>, MetaStoreError> {
-        let nodes = self.auto_add_nodes(cluster_name.to_string())?;
+        let nodes = self.add_free_proxy_nodes(cluster_name, avoided_zones)?;
         Ok(nodes
             .into_iter()
             .map(|node| NodeSlot {
@@ -1365,14 +2903,55 @@ pub enum InconsistentError {
     },
 }
 
+// The repair operations for the known inconsistencies.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum RepairOperation {
+    // Add the node back to the proxy resource.
+    RegisterNode {
+        cluster_name: DBName,
+        proxy_address: String,
+        node_address: String,
+    },
+    // Remove the peer which no longer exists from the node.
+    RemovePeer {
+        cluster_name: DBName,
+        node_address: String,
+        peer: ReplPeer,
+    },
+    // Stop the migration whose peer no longer exists and keep the slots in this node.
+    ClearMigrationTag {
+        cluster_name: DBName,
+        node_address: String,
+        start: usize,
+        end: usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct Inconsistency {
+    pub error: InconsistentError,
+    // None if it could not be repaired automatically.
+    pub repair: Option<RepairOperation>,
+}
+
+impl Inconsistency {
+    fn new(error: InconsistentError) -> Self {
+        Self {
+            error,
+            repair: None,
+        }
+    }
+}
+
 impl fmt::Display for InconsistentError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
-impl Error for InconsistentError {
-    fn description(&self) -> &str {
+impl InconsistentError {
+    pub fn error_code(&self) -> &'static str {
         match self {
             InconsistentError::ClusterNotFound { .. } => "CLUSTER_NOT_FOUND",
             InconsistentError::NodeNotFoundInCluster { .. } => "NODE_NOT_FOUND_IN_CLUSTER",
@@ -1385,6 +2964,12 @@ impl Error for InconsistentError {
             InconsistentError::MigrationPeerNotFound { .. } => "MIGRATION_PEER_NOT_FOUND",
         }
     }
+}
+
+impl Error for InconsistentError {
+    fn description(&self) -> &str {
+        self.error_code()
+    }
 
     fn cause(&self) -> Option<&dyn Error> {
         None
@@ -1395,7 +2980,8 @@ impl Error for InconsistentError {
 pub enum MetaStoreError {
     InUse,
     NotInUse,
-    NoAvailableResource,
+    // The reason why the resource is not enough.
+    NoAvailableResource(String),
     InvalidState,
     InvalidRole,
     SlotNotEmpty,
@@ -1415,6 +3001,9 @@ pub enum MetaStoreError {
     MismatchEpoch,
     InvalidNodeNum,
     InvalidClusterName,
+    MigrationRunning,
+    MigrationCanceling,
+    InvalidConfig,
 }
 
 impl fmt::Display for MetaStoreError {
@@ -1428,7 +3017,7 @@ impl Error for MetaStoreError {
         match self {
             MetaStoreError::InUse => "IN_USE",
             MetaStoreError::NotInUse => "NOT_IN_USE",
-            MetaStoreError::NoAvailableResource => "NO_AVAILABLE_RESOURCE",
+            MetaStoreError::NoAvailableResource(_) => "NO_AVAILABLE_RESOURCE",
             MetaStoreError::InvalidState => "INVALID_STATE",
             MetaStoreError::InvalidRole => "INVALID_ROLE",
             MetaStoreError::SlotNotEmpty => "SLOT_NOT_EMPTY",
@@ -1448,6 +3037,9 @@ impl Error for MetaStoreError {
             MetaStoreError::MismatchEpoch => "MISMATCH_EPOCH",
             MetaStoreError::InvalidNodeNum => "INVALID_NODE_NUM",
             MetaStoreError::InvalidClusterName => "INVALID_CLUSTER_NAME",
+            MetaStoreError::MigrationRunning => "MIGRATION_RUNNING",
+            MetaStoreError::MigrationCanceling => "MIGRATION_CANCELING",
+            MetaStoreError::InvalidConfig => "INVALID_CONFIG",
         }
     }
 
@@ -1455,3 +3047,721 @@ impl Error for MetaStoreError {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLUSTER_NAME: &str = "mydb";
+
+    // Every proxy has 2 nodes.
+    fn gen_store(proxy_num: usize) -> MetaStore {
+        let mut store = MetaStore::default();
+        for i in 0..proxy_num {
+            let nodes = vec![
+                format!("127.0.0.1:{}", 7000 + 2 * i),
+                format!("127.0.0.1:{}", 7001 + 2 * i),
+            ];
+            store
+                .add_hosts(format!("127.0.0.1:{}", 6000 + i), nodes, HashMap::new())
+                .unwrap();
+        }
+        store
+    }
+
+    fn get_cluster(store: &MetaStore) -> Cluster {
+        store.get_cluster_by_name(CLUSTER_NAME).unwrap()
+    }
+
+    // Returns the migrating task in the source node.
+    fn start_migration(store: &mut MetaStore) -> MigrationTaskMeta {
+        store
+            .add_cluster(CLUSTER_NAME.to_string(), HashMap::new(), None)
+            .unwrap();
+        let new_nodes = store.auto_add_nodes(CLUSTER_NAME.to_string()).unwrap();
+        let src_node = get_cluster(store)
+            .get_nodes()
+            .iter()
+            .find(|node| !node.get_slots().is_empty())
+            .cloned()
+            .unwrap();
+        store
+            .migrate_slots(
+                CLUSTER_NAME.to_string(),
+                src_node.get_address().to_string(),
+                new_nodes[0].get_address().to_string(),
+                MigrationType::Half,
+            )
+            .unwrap();
+        get_migrating_task(store, src_node.get_address())
+    }
+
+    fn get_migrating_task(store: &MetaStore, src_node_address: &str) -> MigrationTaskMeta {
+        let cluster = get_cluster(store);
+        let slot_range = cluster
+            .get_node(src_node_address)
+            .unwrap()
+            .get_slots()
+            .iter()
+            .find(|slot_range| slot_range.tag != SlotRangeTag::None)
+            .cloned()
+            .unwrap();
+        MigrationTaskMeta {
+            db_name: cluster.get_name().clone(),
+            slot_range,
+        }
+    }
+
+    fn get_migration_meta(task: &MigrationTaskMeta) -> MigrationMeta {
+        task.slot_range.tag.get_migration_meta().cloned().unwrap()
+    }
+
+    #[test]
+    fn test_replace_proxy_labels() {
+        let mut store = gen_store(1);
+        let proxy_address = "127.0.0.1:6000";
+        let get_labels = |store: &MetaStore| {
+            store
+                .get_proxy_resources(&HashMap::new())
+                .pop()
+                .unwrap()
+                .labels
+        };
+        let mut labels = HashMap::new();
+        labels.insert("zone".to_string(), "zone1".to_string());
+        labels.insert("rack".to_string(), "rack1".to_string());
+        store
+            .add_hosts(proxy_address.to_string(), vec![], labels.clone())
+            .unwrap();
+        assert_eq!(get_labels(&store), labels);
+
+        labels.remove("rack");
+        labels.insert("zone".to_string(), "zone2".to_string());
+        store
+            .add_hosts(proxy_address.to_string(), vec![], labels.clone())
+            .unwrap();
+        assert_eq!(get_labels(&store), labels);
+        let mut filter = HashMap::new();
+        filter.insert("rack".to_string(), "rack1".to_string());
+        assert!(store.get_proxy_resources(&filter).is_empty());
+
+        store
+            .add_hosts(proxy_address.to_string(), vec![], HashMap::new())
+            .unwrap();
+        assert!(get_labels(&store).is_empty());
+        // The nodes are kept.
+        let resources = store.get_proxy_resources(&HashMap::new());
+        assert_eq!(resources[0].node_addresses.len(), 2);
+    }
+
+    #[test]
+    fn test_commit_canceling_migration_before_source_ack() {
+        let mut store = gen_store(2);
+        let task = start_migration(&mut store);
+        let meta = get_migration_meta(&task);
+        store
+            .cancel_migrations(
+                CLUSTER_NAME.to_string(),
+                meta.src_node_address.clone(),
+                meta.dst_node_address.clone(),
+            )
+            .unwrap();
+        let src_proxy = store.get_host_by_address(&meta.src_proxy_address).unwrap();
+        assert_eq!(src_proxy.get_canceling_migrations().len(), 1);
+
+        // The source proxy refused to abort the task so it gets committed.
+        store.commit_migration(task).unwrap();
+        let src_proxy = store.get_host_by_address(&meta.src_proxy_address).unwrap();
+        assert!(src_proxy.get_canceling_migrations().is_empty());
+        assert!(store.canceling_migrations.is_empty());
+        assert!(get_cluster(&store).get_nodes().iter().all(|node| node
+            .get_slots()
+            .iter()
+            .all(|sr| sr.tag == SlotRangeTag::None)));
+        assert!(store.validate().is_ok());
+    }
+
+    #[test]
+    fn test_commit_canceling_migration_after_source_ack() {
+        let mut store = gen_store(2);
+        let task = start_migration(&mut store);
+        let meta = get_migration_meta(&task);
+        store
+            .cancel_migrations(
+                CLUSTER_NAME.to_string(),
+                meta.src_node_address.clone(),
+                meta.dst_node_address.clone(),
+            )
+            .unwrap();
+        store
+            .ack_migration_rollback(meta.src_proxy_address.clone(), task.clone())
+            .unwrap();
+
+        match store.commit_migration(task.clone()) {
+            Err(MetaStoreError::MigrationCanceling) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        store
+            .ack_migration_rollback(meta.dst_proxy_address.clone(), task)
+            .unwrap();
+        assert!(store.canceling_migrations.is_empty());
+        assert!(get_cluster(&store).get_nodes().iter().all(|node| node
+            .get_slots()
+            .iter()
+            .all(|sr| sr.tag == SlotRangeTag::None)));
+    }
+
+    #[test]
+    fn test_add_placed_cluster() {
+        let mut store = gen_store(2);
+        let options = PlacementOptions {
+            master_num: 1,
+            replicas_per_master: 1,
+            anti_affinity: vec![],
+        };
+        let candidates = store.get_placement_candidates();
+        let placement =
+            MetaStore::plan_cluster_placement(CLUSTER_NAME, candidates, &options).unwrap();
+        let proxy_address = placement.groups[0].master.proxy_address.clone();
+
+        // The proxy fails after the placement is planned.
+        store.add_failure(proxy_address.clone(), "reporter".to_string());
+        let res = store.add_placed_cluster(
+            CLUSTER_NAME.to_string(),
+            HashMap::new(),
+            Some((options.clone(), placement.clone())),
+        );
+        match res {
+            Err(MetaStoreError::NoAvailableResource(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(store.get_cluster_by_name(CLUSTER_NAME).is_none());
+        assert!(store.all_nodes[&proxy_address].cluster_name.is_none());
+
+        store.failures.clear();
+        store
+            .add_placed_cluster(
+                CLUSTER_NAME.to_string(),
+                HashMap::new(),
+                Some((options, placement)),
+            )
+            .unwrap();
+        assert_eq!(get_cluster(&store).get_nodes().len(), 4);
+        assert!(store.validate().is_ok());
+    }
+
+    // Adds a replica on another proxy to the first master.
+    fn add_cluster_with_replica(store: &mut MetaStore) -> (Node, Node) {
+        let mut config = HashMap::new();
+        config.insert("replicas_per_master".to_string(), "1".to_string());
+        store
+            .add_cluster(CLUSTER_NAME.to_string(), config, None)
+            .unwrap();
+        let master = get_cluster(store)
+            .get_nodes()
+            .iter()
+            .find(|node| !node.get_slots().is_empty())
+            .cloned()
+            .unwrap();
+        let replica = store
+            .add_replica(CLUSTER_NAME.to_string(), master.get_address().to_string())
+            .unwrap();
+        (master, replica)
+    }
+
+    #[test]
+    fn test_failover_master_node() {
+        let mut store = gen_store(3);
+        let (master, replica) = add_cluster_with_replica(&mut store);
+        store.add_failure(master.get_address().to_string(), "coord".to_string());
+
+        let failover = store.failover_node(master.get_address(), None).unwrap();
+        assert_eq!(
+            failover.promoted.unwrap().get_address(),
+            replica.get_address()
+        );
+        let new_replica = failover.new_replica.unwrap();
+        assert_ne!(new_replica.get_address(), master.get_address());
+        assert_ne!(new_replica.get_proxy_address(), replica.get_proxy_address());
+
+        let cluster = get_cluster(&store);
+        let promoted = cluster.get_node(replica.get_address()).unwrap();
+        assert_eq!(promoted.get_role(), Role::Master);
+        assert_eq!(promoted.get_slots(), master.get_slots());
+        assert_eq!(
+            promoted.get_repl_meta().get_peers()[0].node_address,
+            new_replica.get_address()
+        );
+        // The failed node is kept in the cluster without slots or peers.
+        let failed = cluster.get_node(master.get_address()).unwrap();
+        assert_eq!(failed.get_role(), Role::Master);
+        assert!(failed.get_slots().is_empty());
+        assert!(failed.get_repl_meta().get_peers().is_empty());
+        assert!(store.failed_nodes.contains(master.get_address()));
+        // The reported failure is cleared.
+        assert!(!store
+            .get_failures(chrono::Duration::seconds(60))
+            .contains(&master.get_address().to_string()));
+        assert!(store.validate().is_ok());
+    }
+
+    #[test]
+    fn test_failover_node_skips_failed_nodes() {
+        let mut store = gen_store(3);
+        let (master, _) = add_cluster_with_replica(&mut store);
+        let failover = store.failover_node(master.get_address(), None).unwrap();
+        let mut replica = failover.new_replica.unwrap();
+        let mut failed_nodes = vec![master.get_address().to_string()];
+
+        // Keep failing the new replica until the free nodes run out.
+        // None of the failed nodes should be picked again.
+        loop {
+            match store.failover_node(replica.get_address(), None) {
+                Ok(failover) => {
+                    assert!(failover.promoted.is_none());
+                    failed_nodes.push(replica.get_address().to_string());
+                    replica = failover.new_replica.unwrap();
+                    assert!(!failed_nodes.contains(&replica.get_address().to_string()));
+                }
+                Err(MetaStoreError::NoAvailableResource(_)) => break,
+                Err(err) => panic!("unexpected error {:?}", err),
+            }
+        }
+        assert!(failed_nodes.len() > 1);
+        for node_address in failed_nodes.iter() {
+            assert!(store.failed_nodes.contains(node_address));
+        }
+        // The replica is kept if there's no free node to replace it.
+        assert!(!store.failed_nodes.contains(replica.get_address()));
+
+        // The new proxy could still be used.
+        store
+            .add_hosts(
+                "127.0.0.1:6003".to_string(),
+                vec!["127.0.0.1:7006".to_string(), "127.0.0.1:7007".to_string()],
+                HashMap::new(),
+            )
+            .unwrap();
+        let failover = store.failover_node(replica.get_address(), None).unwrap();
+        let replacement = failover.new_replica.unwrap();
+        assert_eq!(replacement.get_proxy_address(), "127.0.0.1:6003");
+    }
+
+    #[test]
+    fn test_failover_node_not_found() {
+        let mut store = gen_store(2);
+        store
+            .add_cluster(CLUSTER_NAME.to_string(), HashMap::new(), None)
+            .unwrap();
+        match store.failover_node("127.0.0.1:9999", None) {
+            Err(MetaStoreError::NodeNotFound) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_remove_cluster_clears_failed_nodes() {
+        let mut store = gen_store(3);
+        let (master, _) = add_cluster_with_replica(&mut store);
+        store.failover_node(master.get_address(), None).unwrap();
+        assert!(!store.failed_nodes.is_empty());
+        store.remove_cluster(CLUSTER_NAME.to_string()).unwrap();
+        assert!(store.failed_nodes.is_empty());
+    }
+
+    #[test]
+    fn test_switchover() {
+        let mut store = gen_store(3);
+        let (master, replica) = add_cluster_with_replica(&mut store);
+        let db_name = DBName::from(CLUSTER_NAME).unwrap();
+
+        let (switchover_master, switchover_replica, epoch) = store
+            .get_switchover_master(&db_name, replica.get_address())
+            .unwrap();
+        assert_eq!(switchover_master.get_address(), master.get_address());
+        assert_eq!(switchover_replica.get_address(), replica.get_address());
+        match store.get_switchover_master(&db_name, master.get_address()) {
+            Err(MetaStoreError::InvalidRole) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let promoted = store
+            .switchover(&db_name, replica.get_address(), epoch)
+            .unwrap();
+        assert_eq!(promoted.get_address(), replica.get_address());
+
+        let cluster = get_cluster(&store);
+        assert!(cluster.get_epoch() > epoch);
+        let new_master = cluster.get_node(replica.get_address()).unwrap();
+        assert_eq!(new_master.get_role(), Role::Master);
+        assert_eq!(
+            new_master
+                .get_slots()
+                .iter()
+                .map(|sr| (sr.start, sr.end))
+                .collect::<Vec<_>>(),
+            master
+                .get_slots()
+                .iter()
+                .map(|sr| (sr.start, sr.end))
+                .collect::<Vec<_>>()
+        );
+        let old_master = cluster.get_node(master.get_address()).unwrap();
+        assert_eq!(old_master.get_role(), Role::Replica);
+        assert!(old_master.get_slots().is_empty());
+        assert_eq!(
+            old_master.get_repl_meta().get_peers()[0].node_address,
+            replica.get_address()
+        );
+        assert!(store.validate().is_ok());
+    }
+
+    #[test]
+    fn test_switchover_with_changed_epoch() {
+        let mut store = gen_store(3);
+        let (master, replica) = add_cluster_with_replica(&mut store);
+        let db_name = DBName::from(CLUSTER_NAME).unwrap();
+        let (_, _, epoch) = store
+            .get_switchover_master(&db_name, replica.get_address())
+            .unwrap();
+
+        // The cluster changes while waiting for the replication.
+        store
+            .change_cluster_config(
+                CLUSTER_NAME.to_string(),
+                vec![(
+                    "compression_strategy".to_string(),
+                    "set_get_only".to_string(),
+                )]
+                .into_iter()
+                .collect(),
+            )
+            .unwrap();
+        match store.switchover(&db_name, replica.get_address(), epoch) {
+            Err(MetaStoreError::MismatchEpoch) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let cluster = get_cluster(&store);
+        assert_eq!(
+            cluster.get_node(master.get_address()).unwrap().get_role(),
+            Role::Master
+        );
+        assert_eq!(
+            cluster.get_node(replica.get_address()).unwrap().get_role(),
+            Role::Replica
+        );
+    }
+
+    #[test]
+    fn test_replace_failed_proxy_with_missing_replica() {
+        let mut store = gen_store(3);
+        let (master, replica) = add_cluster_with_replica(&mut store);
+
+        // The chosen replica is no longer a peer of the master.
+        let mut preferred_replicas = HashMap::new();
+        preferred_replicas.insert(
+            master.get_address().to_string(),
+            "127.0.0.1:9999".to_string(),
+        );
+        match store
+            .replace_failed_proxy(master.get_proxy_address().to_string(), &preferred_replicas)
+        {
+            Err(MetaStoreError::NoPeer) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let cluster = get_cluster(&store);
+        assert_eq!(
+            cluster.get_node(master.get_address()).unwrap().get_role(),
+            Role::Master
+        );
+
+        preferred_replicas.insert(
+            master.get_address().to_string(),
+            replica.get_address().to_string(),
+        );
+        store
+            .replace_failed_proxy(master.get_proxy_address().to_string(), &preferred_replicas)
+            .unwrap();
+        let cluster = get_cluster(&store);
+        let promoted = cluster.get_node(replica.get_address()).unwrap();
+        assert_eq!(promoted.get_role(), Role::Master);
+        assert!(cluster.get_node(master.get_address()).is_none());
+    }
+
+    // Returns a master with slots and an empty master on another proxy.
+    fn add_cluster_with_empty_node(store: &mut MetaStore) -> (Node, Node) {
+        store
+            .add_cluster(CLUSTER_NAME.to_string(), HashMap::new(), None)
+            .unwrap();
+        let new_nodes = store.auto_add_nodes(CLUSTER_NAME.to_string()).unwrap();
+        let src_node = get_cluster(store)
+            .get_nodes()
+            .iter()
+            .find(|node| !node.get_slots().is_empty())
+            .cloned()
+            .unwrap();
+        (src_node, new_nodes[0].clone())
+    }
+
+    fn gen_move(src_node: &Node, dst_node_address: &str, offset: usize) -> SlotRangeMove {
+        let start = src_node.get_slots()[0].start + offset;
+        SlotRangeMove {
+            src_node_address: src_node.get_address().to_string(),
+            dst_node_address: dst_node_address.to_string(),
+            ranges: vec![Range {
+                start,
+                end: start + 9,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_apply_migration_plan_failed_to_start() {
+        let mut store = gen_store(2);
+        let (src_node, _) = add_cluster_with_empty_node(&mut store);
+        let plan = MigrationPlan {
+            epoch: get_cluster(&store).get_epoch(),
+            moves: vec![gen_move(&src_node, "127.0.0.1:9999", 0)],
+        };
+        match store.apply_migration_plan(CLUSTER_NAME.to_string(), plan, 1) {
+            Err(MetaStoreError::NodeNotFound) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(store.get_migration_plan(CLUSTER_NAME).is_none());
+    }
+
+    #[test]
+    fn test_planned_migration_retry() {
+        let mut store = gen_store(2);
+        let (src_node, dst_node) = add_cluster_with_empty_node(&mut store);
+        let failed_move = gen_move(&src_node, "127.0.0.1:9999", 10);
+        let plan = MigrationPlan {
+            epoch: get_cluster(&store).get_epoch(),
+            moves: vec![
+                gen_move(&src_node, dst_node.get_address(), 0),
+                failed_move.clone(),
+            ],
+        };
+        store
+            .apply_migration_plan(CLUSTER_NAME.to_string(), plan, 1)
+            .unwrap();
+        let plan = store.get_migration_plan(CLUSTER_NAME).unwrap();
+        assert_eq!(plan.moves.len(), 1);
+
+        // The next move fails to start after the commit but it's kept in the plan.
+        let task = get_migrating_task(&store, src_node.get_address());
+        store.commit_migration(task).unwrap();
+        let plan = store.get_migration_plan(CLUSTER_NAME).unwrap();
+        assert_eq!(
+            plan.moves.iter().cloned().collect::<Vec<_>>(),
+            vec![failed_move.clone()]
+        );
+
+        store.schedule_all_planned_migrations();
+        let plan = store.get_migration_plan(CLUSTER_NAME).unwrap();
+        assert_eq!(
+            plan.moves.iter().cloned().collect::<Vec<_>>(),
+            vec![failed_move]
+        );
+        assert_eq!(plan.failures, MAX_PLANNED_MIGRATION_FAILURES - 1);
+
+        // Dropped after too many failures.
+        store.schedule_all_planned_migrations();
+        assert!(store.get_migration_plan(CLUSTER_NAME).is_none());
+    }
+
+    #[test]
+    fn test_apply_migration_plan_partially_started() {
+        let mut store = gen_store(2);
+        let (src_node, dst_node) = add_cluster_with_empty_node(&mut store);
+        let failed_move = gen_move(&src_node, "127.0.0.1:9999", 10);
+        let plan = MigrationPlan {
+            epoch: get_cluster(&store).get_epoch(),
+            moves: vec![
+                gen_move(&src_node, dst_node.get_address(), 0),
+                failed_move.clone(),
+            ],
+        };
+        store
+            .apply_migration_plan(CLUSTER_NAME.to_string(), plan, 2)
+            .unwrap();
+        assert_eq!(get_all_migrating_tasks(&store).len(), 1);
+        let plan = store.get_migration_plan(CLUSTER_NAME).unwrap();
+        assert_eq!(
+            plan.moves.iter().cloned().collect::<Vec<_>>(),
+            vec![failed_move]
+        );
+        assert_eq!(plan.failures, 1);
+
+        store
+            .remove_migration_plan(CLUSTER_NAME.to_string())
+            .unwrap();
+        assert!(store.get_migration_plan(CLUSTER_NAME).is_none());
+        // The started migration still goes on.
+        assert_eq!(get_all_migrating_tasks(&store).len(), 1);
+        match store.remove_migration_plan(CLUSTER_NAME.to_string()) {
+            Err(MetaStoreError::NotMigrating) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    fn get_all_migrating_tasks(store: &MetaStore) -> Vec<MigrationTaskMeta> {
+        let cluster = get_cluster(store);
+        cluster
+            .get_nodes()
+            .iter()
+            .flat_map(|node| node.get_slots().iter())
+            .filter(|slot_range| match slot_range.tag {
+                SlotRangeTag::Migrating(_) => true,
+                _ => false,
+            })
+            .map(|slot_range| MigrationTaskMeta {
+                db_name: cluster.get_name().clone(),
+                slot_range: slot_range.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_drain_proxy() {
+        let mut store = gen_store(3);
+        store
+            .add_cluster(CLUSTER_NAME.to_string(), HashMap::new(), None)
+            .unwrap();
+        store.auto_add_nodes(CLUSTER_NAME.to_string()).unwrap();
+        let proxy_address = get_cluster(&store)
+            .get_nodes()
+            .iter()
+            .find(|node| !node.get_slots().is_empty())
+            .map(|node| node.get_proxy_address().to_string())
+            .unwrap();
+
+        store
+            .drain_proxy(CLUSTER_NAME.to_string(), proxy_address.clone(), 1)
+            .unwrap();
+        assert!(store.draining_proxies.contains_key(&proxy_address));
+
+        commit_all_migrations(&mut store);
+        assert!(store.get_migration_plan(CLUSTER_NAME).is_none());
+        assert!(store.draining_proxies.is_empty());
+        assert!(get_cluster(&store)
+            .get_nodes()
+            .iter()
+            .all(|node| node.get_proxy_address() != proxy_address));
+        assert!(store.all_nodes[&proxy_address].cluster_name.is_none());
+        assert!(store.validate().is_ok());
+    }
+
+    fn commit_all_migrations(store: &mut MetaStore) {
+        loop {
+            let tasks = get_all_migrating_tasks(store);
+            if tasks.is_empty() {
+                break;
+            }
+            for task in tasks.into_iter() {
+                store.commit_migration(task).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_drain_proxy_with_replica() {
+        let mut store = gen_store(4);
+        let (master, replica) = add_cluster_with_replica(&mut store);
+        let drained_proxy = replica.get_proxy_address().to_string();
+        store
+            .drain_proxy(CLUSTER_NAME.to_string(), drained_proxy.clone(), 1)
+            .unwrap();
+        commit_all_migrations(&mut store);
+
+        // The replica is moved to a new proxy.
+        let cluster = get_cluster(&store);
+        assert!(cluster
+            .get_nodes()
+            .iter()
+            .all(|node| node.get_proxy_address() != drained_proxy));
+        let peers = cluster
+            .get_node(master.get_address())
+            .unwrap()
+            .get_repl_meta()
+            .get_peers()
+            .to_vec();
+        assert_eq!(peers.len(), 1);
+        assert_ne!(peers[0].proxy_address, drained_proxy);
+        assert!(store.validate().is_ok());
+    }
+
+    #[test]
+    fn test_drain_proxy_with_replicated_master() {
+        let mut store = gen_store(3);
+        let (master, replica) = add_cluster_with_replica(&mut store);
+
+        let drained_proxy = master.get_proxy_address().to_string();
+        store
+            .drain_proxy(CLUSTER_NAME.to_string(), drained_proxy.clone(), 1)
+            .unwrap();
+        commit_all_migrations(&mut store);
+
+        // The replica of the drained master becomes a free node.
+        let cluster = get_cluster(&store);
+        assert!(cluster
+            .get_nodes()
+            .iter()
+            .all(|node| node.get_proxy_address() != drained_proxy));
+        let node = cluster.get_node(replica.get_address()).unwrap();
+        assert_eq!(node.get_role(), Role::Master);
+        assert!(node.get_repl_meta().get_peers().is_empty());
+        assert!(store.all_nodes[&drained_proxy].cluster_name.is_none());
+        assert!(store.validate().is_ok());
+    }
+
+    #[test]
+    fn test_cancel_draining_migrations() {
+        let mut store = gen_store(3);
+        store
+            .add_cluster(CLUSTER_NAME.to_string(), HashMap::new(), None)
+            .unwrap();
+        store.auto_add_nodes(CLUSTER_NAME.to_string()).unwrap();
+        let proxy_address = get_cluster(&store)
+            .get_nodes()
+            .iter()
+            .find(|node| !node.get_slots().is_empty())
+            .map(|node| node.get_proxy_address().to_string())
+            .unwrap();
+        store
+            .drain_proxy(CLUSTER_NAME.to_string(), proxy_address.clone(), 1)
+            .unwrap();
+        assert!(store.get_migration_plan(CLUSTER_NAME).is_some());
+
+        let task = get_all_migrating_tasks(&store).pop().unwrap();
+        let meta = get_migration_meta(&task);
+        store
+            .cancel_migrations(
+                CLUSTER_NAME.to_string(),
+                meta.src_node_address.clone(),
+                meta.dst_node_address.clone(),
+            )
+            .unwrap();
+        assert!(store.get_migration_plan(CLUSTER_NAME).is_none());
+        assert!(store.draining_proxies.is_empty());
+
+        store
+            .ack_migration_rollback(meta.src_proxy_address.clone(), task.clone())
+            .unwrap();
+        store
+            .ack_migration_rollback(meta.dst_proxy_address.clone(), task)
+            .unwrap();
+        assert!(get_all_migrating_tasks(&store).is_empty());
+        // The proxy stays in the cluster with its slots.
+        assert!(get_cluster(&store)
+            .get_nodes()
+            .iter()
+            .any(|node| node.get_proxy_address() == proxy_address && !node.get_slots().is_empty()));
+        // It could be drained again.
+        store
+            .drain_proxy(CLUSTER_NAME.to_string(), proxy_address.clone(), 1)
+            .unwrap();
+        assert!(store.draining_proxies.contains_key(&proxy_address));
+    }
+}
@@ -0,0 +1,331 @@
+use crate::common::cluster::Node;
+use crate::common::utils::strip_prefix;
+use crate::protocol::{BulkStr, RedisClient, RedisClientFactory, Resp};
+use crate::replication::redis_replicator::parse_repl_offset;
+use futures_timer::Delay;
+use std::fmt;
+use std::str;
+use std::time::{Duration, Instant};
+
+const CATCH_UP_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+const FINAL_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+pub enum ReplOffsetError {
+    ProxyError(String),
+    NodeError(String),
+    OffsetNotFound {
+        proxy_address: String,
+        node_address: String,
+    },
+    NotCaughtUp {
+        master_offset: i64,
+        replica_offset: i64,
+    },
+    // None of the replicas has a known lag within `max_lag`.
+    LagTooLarge {
+        master_address: String,
+        lags: Vec<(String, Option<i64>)>,
+        max_lag: u64,
+    },
+}
+
+impl fmt::Display for ReplOffsetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ProxyError(reason) => write!(f, "REPL_OFFSET_UNAVAILABLE: {}", reason),
+            Self::NodeError(reason) => write!(f, "NODE_ERROR: {}", reason),
+            Self::OffsetNotFound {
+                proxy_address,
+                node_address,
+            } => write!(
+                f,
+                "REPL_OFFSET_UNAVAILABLE: no offset of {} in proxy {}",
+                node_address, proxy_address
+            ),
+            Self::NotCaughtUp {
+                master_offset,
+                replica_offset,
+            } => write!(
+                f,
+                "REPLICA_NOT_CAUGHT_UP: master offset {} replica offset {}",
+                master_offset, replica_offset
+            ),
+            Self::LagTooLarge {
+                master_address,
+                lags,
+                max_lag,
+            } => write!(
+                f,
+                "REPLICA_LAG_TOO_LARGE: no replica of {} within lag {}, replica lags {:?}",
+                master_address, max_lag, lags
+            ),
+        }
+    }
+}
+
+fn parse_repl_report_field(report: &str, node_address: &str, field: &str) -> Option<i64> {
+    let node_line = format!("node_address:{}", node_address);
+    let prefix = format!("{}:", field);
+    report
+        .split("\n\n")
+        .find(|section| section.lines().any(|line| line.trim() == node_line))?
+        .lines()
+        .find_map(|line| strip_prefix(line.trim(), prefix.as_str()))
+        .and_then(|value| value.parse::<i64>().ok())
+}
+
+// Parse the `repl_offset` of the node from the report of `UMCTL INFOREPL`.
+pub fn parse_repl_offset_report(report: &str, node_address: &str) -> Option<i64> {
+    parse_repl_report_field(report, node_address, "repl_offset")
+}
+
+// Parse the `repl_lag` of the replica from the report of `UMCTL INFOREPL`.
+pub fn parse_repl_lag_report(report: &str, node_address: &str) -> Option<i64> {
+    parse_repl_report_field(report, node_address, "repl_lag")
+}
+
+async fn get_repl_report<F: RedisClientFactory>(
+    client_factory: &F,
+    proxy_address: &str,
+) -> Result<String, ReplOffsetError> {
+    let mut client = client_factory
+        .create_client(proxy_address.to_string())
+        .await
+        .map_err(|err| ReplOffsetError::ProxyError(format!("{:?}", err)))?;
+    let cmd = vec![b"UMCTL".to_vec(), b"INFOREPL".to_vec()];
+    let resp = client
+        .execute_single(cmd)
+        .await
+        .map_err(|err| ReplOffsetError::ProxyError(format!("{:?}", err)))?;
+    match resp {
+        Resp::Bulk(BulkStr::Str(report)) => Ok(String::from_utf8_lossy(&report).to_string()),
+        other => Err(ReplOffsetError::ProxyError(format!(
+            "unexpected reply {:?}",
+            other
+        ))),
+    }
+}
+
+pub async fn get_repl_offset<F: RedisClientFactory>(
+    client_factory: &F,
+    node: &Node,
+) -> Result<i64, ReplOffsetError> {
+    let proxy_address = node.get_proxy_address().to_string();
+    let report = get_repl_report(client_factory, &proxy_address).await?;
+    parse_repl_offset_report(&report, node.get_address()).ok_or_else(|| {
+        ReplOffsetError::OffsetNotFound {
+            proxy_address,
+            node_address: node.get_address().to_string(),
+        }
+    })
+}
+
+// The proxy of the replica keeps the last known lag even if the master has failed.
+// Returns None if the lag is unknown.
+async fn get_repl_lag<F: RedisClientFactory>(client_factory: &F, replica: &Node) -> Option<i64> {
+    match get_repl_report(client_factory, replica.get_proxy_address()).await {
+        Ok(report) => parse_repl_lag_report(&report, replica.get_address()),
+        Err(err) => {
+            warn!(
+                "failed to get replication lag of {}: {}",
+                replica.get_address(),
+                err
+            );
+            None
+        }
+    }
+}
+
+// Chooses the replica with the least known lag within `max_lag`
+// to take over the failed master.
+pub async fn choose_failover_replica<F: RedisClientFactory>(
+    client_factory: &F,
+    master_address: &str,
+    candidates: &[Node],
+    max_lag: u64,
+) -> Result<String, ReplOffsetError> {
+    let mut lags = vec![];
+    for replica in candidates.iter() {
+        let lag = get_repl_lag(client_factory, replica).await;
+        lags.push((replica.get_address().to_string(), lag));
+    }
+    select_failover_replica(master_address, lags, max_lag)
+}
+
+fn select_failover_replica(
+    master_address: &str,
+    lags: Vec<(String, Option<i64>)>,
+    max_lag: u64,
+) -> Result<String, ReplOffsetError> {
+    let chosen = lags
+        .iter()
+        .filter_map(|(address, lag)| lag.map(|lag| (address, lag)))
+        .filter(|(_, lag)| *lag <= max_lag as i64)
+        .min_by_key(|(_, lag)| *lag)
+        .map(|(address, _)| address.clone());
+    chosen.ok_or_else(|| ReplOffsetError::LagTooLarge {
+        master_address: master_address.to_string(),
+        lags,
+        max_lag,
+    })
+}
+
+// Used after waiting too long for a replica within the max lag.
+// Prefers the replica with the least known lag.
+pub fn select_fallback_replica(lags: &[(String, Option<i64>)]) -> Option<String> {
+    lags.iter()
+        .filter_map(|(address, lag)| lag.map(|lag| (address, lag)))
+        .min_by_key(|(_, lag)| *lag)
+        .map(|(address, _)| address.clone())
+}
+
+// The proxies refresh the offsets periodically,
+// so the offsets could be a little bit stale.
+pub async fn wait_for_catch_up<F: RedisClientFactory>(
+    client_factory: &F,
+    master: &Node,
+    replica: &Node,
+    max_lag: u64,
+    timeout: Duration,
+) -> Result<(), ReplOffsetError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let master_offset = get_repl_offset(client_factory, master).await?;
+        let replica_offset = get_repl_offset(client_factory, replica).await?;
+        if replica_offset.saturating_add(max_lag as i64) >= master_offset {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(ReplOffsetError::NotCaughtUp {
+                master_offset,
+                replica_offset,
+            });
+        }
+        Delay::new(CATCH_UP_CHECK_INTERVAL).await;
+    }
+}
+
+// Blocks the clients of the master for `timeout` so that no more writes
+// are accepted before the replica takes over. The replication still works.
+pub async fn pause_writes<F: RedisClientFactory>(
+    client_factory: &F,
+    master: &Node,
+    timeout: Duration,
+) -> Result<(), ReplOffsetError> {
+    let mut client = client_factory
+        .create_client(master.get_address().to_string())
+        .await
+        .map_err(|err| ReplOffsetError::NodeError(format!("{:?}", err)))?;
+    let cmd = vec![
+        b"CLIENT".to_vec(),
+        b"PAUSE".to_vec(),
+        timeout.as_millis().to_string().into_bytes(),
+    ];
+    match client
+        .execute_single(cmd)
+        .await
+        .map_err(|err| ReplOffsetError::NodeError(format!("{:?}", err)))?
+    {
+        Resp::Simple(_) => Ok(()),
+        other => Err(ReplOffsetError::NodeError(format!(
+            "failed to pause {}: {:?}",
+            master.get_address(),
+            other
+        ))),
+    }
+}
+
+// Unlike the offsets cached in the proxies, this queries the node itself.
+async fn get_node_repl_offset<F: RedisClientFactory>(
+    client_factory: &F,
+    node: &Node,
+) -> Result<i64, ReplOffsetError> {
+    let mut client = client_factory
+        .create_client(node.get_address().to_string())
+        .await
+        .map_err(|err| ReplOffsetError::NodeError(format!("{:?}", err)))?;
+    let cmd = vec![b"INFO".to_vec(), b"replication".to_vec()];
+    let resp = client
+        .execute_single(cmd)
+        .await
+        .map_err(|err| ReplOffsetError::NodeError(format!("{:?}", err)))?;
+    parse_repl_offset(&resp).ok_or_else(|| ReplOffsetError::OffsetNotFound {
+        proxy_address: node.get_proxy_address().to_string(),
+        node_address: node.get_address().to_string(),
+    })
+}
+
+// The final check after the writes of the master are paused.
+pub async fn wait_for_final_catch_up<F: RedisClientFactory>(
+    client_factory: &F,
+    master: &Node,
+    replica: &Node,
+    max_lag: u64,
+    timeout: Duration,
+) -> Result<(), ReplOffsetError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let master_offset = get_node_repl_offset(client_factory, master).await?;
+        let replica_offset = get_node_repl_offset(client_factory, replica).await?;
+        if replica_offset.saturating_add(max_lag as i64) >= master_offset {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(ReplOffsetError::NotCaughtUp {
+                master_offset,
+                replica_offset,
+            });
+        }
+        Delay::new(FINAL_CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_repl_offset_report() {
+        let report = "db:mydb\nrole:master\nnode_address:redis1:6379\nrepl_offset:100\nreplica:redis2:6379@proxy2:6000\n\n\
+            db:mydb\nrole:replica\nnode_address:redis3:6379\nrepl_offset:99\nmaster:redis4:6379@proxy2:6000\n\n";
+        assert_eq!(parse_repl_offset_report(report, "redis1:6379"), Some(100));
+        assert_eq!(parse_repl_offset_report(report, "redis3:6379"), Some(99));
+        assert_eq!(parse_repl_offset_report(report, "redis2:6379"), None);
+    }
+
+    #[test]
+    fn test_select_failover_replica() {
+        let report = "db:mydb\nrole:replica\nnode_address:redis3:6379\nrepl_offset:99\nrepl_lag:1\nmaster:redis4:6379@proxy2:6000\n\n";
+        assert_eq!(parse_repl_lag_report(report, "redis3:6379"), Some(1));
+
+        let lags = vec![
+            ("redis1:6379".to_string(), None),
+            ("redis2:6379".to_string(), Some(200)),
+            ("redis3:6379".to_string(), Some(100)),
+        ];
+        assert_eq!(
+            select_failover_replica("redis0:6379", lags.clone(), 100).unwrap(),
+            "redis3:6379"
+        );
+        match select_failover_replica("redis0:6379", lags, 99) {
+            Err(ReplOffsetError::LagTooLarge { max_lag, .. }) => assert_eq!(max_lag, 99),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_select_fallback_replica() {
+        let lags = vec![
+            ("redis1:6379".to_string(), None),
+            ("redis2:6379".to_string(), Some(200)),
+            ("redis3:6379".to_string(), Some(300)),
+        ];
+        assert_eq!(
+            select_fallback_replica(&lags),
+            Some("redis2:6379".to_string())
+        );
+        let unknown_lags = vec![("redis1:6379".to_string(), None)];
+        assert_eq!(select_fallback_replica(&unknown_lags), None);
+    }
+}
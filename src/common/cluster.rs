@@ -80,7 +80,7 @@ impl SlotRangeTag {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Range {
     pub start: usize,
     pub end: usize,
@@ -452,6 +452,12 @@ impl Cluster {
     pub fn set_epoch(&mut self, epoch: u64) {
         self.epoch = epoch
     }
+    pub fn get_config(&self) -> &ClusterConfig {
+        &self.config
+    }
+    pub fn set_config(&mut self, config: ClusterConfig) {
+        self.config = config
+    }
     pub fn remove_node(&mut self, node_address: &str) -> Option<Node> {
         let node = match self
             .nodes
@@ -492,6 +498,9 @@ pub struct Proxy {
     peers: Vec<PeerProxy>,
     #[serde(default)]
     clusters_config: HashMap<DBName, ClusterConfig>,
+    // The migrations being rolled back that this proxy needs to abort.
+    #[serde(default)]
+    canceling_migrations: Vec<MigrationTaskMeta>,
 }
 
 impl Proxy {
@@ -510,6 +519,7 @@ impl Proxy {
             free_nodes,
             peers,
             clusters_config,
+            canceling_migrations: Vec::new(),
         }
     }
     pub fn get_address(&self) -> &str {
@@ -550,6 +560,30 @@ impl Proxy {
     pub fn get_clusters_config(&self) -> &HashMap<DBName, ClusterConfig> {
         &self.clusters_config
     }
+
+    pub fn get_canceling_migrations(&self) -> &[MigrationTaskMeta] {
+        &self.canceling_migrations
+    }
+    pub fn set_canceling_migrations(&mut self, canceling_migrations: Vec<MigrationTaskMeta>) {
+        self.canceling_migrations = canceling_migrations;
+    }
+}
+
+// The clusters and proxies whose metadata could have changed after the `since` epoch.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MetaChanges {
+    pub epoch: u64,
+    pub clusters: Vec<DBName>,
+    pub proxies: Vec<String>,
+}
+
+// The result of handling a single failed node.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct NodeFailover {
+    // The replica promoted to take over the failed master.
+    pub promoted: Option<Node>,
+    // The free node assigned to replace the failed node as a replica.
+    pub new_replica: Option<Node>,
 }
 
 #[cfg(test)]
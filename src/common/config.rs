@@ -2,7 +2,7 @@ use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct ClusterConfig {
@@ -10,6 +10,9 @@ pub struct ClusterConfig {
     pub compression_strategy: CompressionStrategy,
     #[serde(default)]
     pub migration_config: MigrationConfig,
+    // The coordinator will add replicas for the masters with fewer replicas.
+    #[serde(default)]
+    pub replicas_per_master: usize,
 }
 
 impl Default for ClusterConfig {
@@ -17,10 +20,14 @@ impl Default for ClusterConfig {
         Self {
             compression_strategy: CompressionStrategy::default(),
             migration_config: MigrationConfig::default(),
+            replicas_per_master: 0,
         }
     }
 }
 
+// Only used by the broker and the coordinator.
+const BROKER_ONLY_FIELDS: &[&str] = &["replicas_per_master"];
+
 impl ClusterConfig {
     pub fn set_field(&mut self, field: &str, value: &str) -> Result<(), ConfigError> {
         let field = field.to_lowercase();
@@ -30,6 +37,11 @@ impl ClusterConfig {
                     CompressionStrategy::from_str(&value).map_err(|_| ConfigError::InvalidValue)?;
                 self.compression_strategy = strategy;
             }
+            "replicas_per_master" => {
+                self.replicas_per_master = value
+                    .parse::<usize>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+            }
             _ => {
                 if field.starts_with("migration_") {
                     let f = field
@@ -45,12 +57,20 @@ impl ClusterConfig {
         Ok(())
     }
 
+    // The proxies only need the fields for serving the commands and the migration.
+    pub fn to_proxy_str_map(&self) -> HashMap<String, String> {
+        let mut map = self.to_str_map();
+        map.retain(|field, _| !BROKER_ONLY_FIELDS.contains(&field.as_str()));
+        map
+    }
+
     pub fn to_str_map(&self) -> HashMap<String, String> {
         vec![
             (
                 "compression_strategy",
                 self.compression_strategy.to_str().to_string(),
             ),
+            ("replicas_per_master", self.replicas_per_master.to_string()),
             (
                 "migration_max_migration_time",
                 self.migration_config.max_migration_time.to_string(),
@@ -59,6 +79,10 @@ impl ClusterConfig {
                 "migration_max_blocking_time",
                 self.migration_config.max_blocking_time.to_string(),
             ),
+            (
+                "migration_max_blocking_queue_size",
+                self.migration_config.max_blocking_queue_size.to_string(),
+            ),
             (
                 "migration_delete_interval",
                 self.migration_config.delete_interval.to_string(),
@@ -75,6 +99,14 @@ impl ClusterConfig {
                 "migration_scan_count",
                 self.migration_config.scan_count.to_string(),
             ),
+            (
+                "migration_verification",
+                self.migration_config.verification.to_str().to_string(),
+            ),
+            (
+                "migration_verification_sample_count",
+                self.migration_config.verification_sample_count.to_string(),
+            ),
         ]
         .into_iter()
         .map(|(k, v)| (k.to_string(), v))
@@ -145,14 +177,99 @@ impl<'de> Deserialize<'de> for CompressionStrategy {
     }
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum VerificationMode {
+    Disabled = 0,
+    // Only check the first `verification_sample_count` keys found by SCAN.
+    Sample = 1,
+    // Check all the keys inside the migrated slot range.
+    Full = 2,
+}
+
+impl Default for VerificationMode {
+    fn default() -> Self {
+        VerificationMode::Disabled
+    }
+}
+
+pub struct InvalidVerificationModeStr;
+
+impl FromStr for VerificationMode {
+    type Err = InvalidVerificationModeStr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lowercase = s.to_lowercase();
+        match lowercase.as_str() {
+            "disabled" => Ok(Self::Disabled),
+            "sample" => Ok(Self::Sample),
+            "full" => Ok(Self::Full),
+            _ => Err(InvalidVerificationModeStr),
+        }
+    }
+}
+
+impl VerificationMode {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Disabled => "disabled",
+            Self::Sample => "sample",
+            Self::Full => "full",
+        }
+    }
+
+    fn from_u8(n: u8) -> Self {
+        match n {
+            1 => Self::Sample,
+            2 => Self::Full,
+            _ => Self::Disabled,
+        }
+    }
+}
+
+impl Serialize for VerificationMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.to_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for VerificationMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(|_| D::Error::custom(format!("invalid verification mode {}", s)))
+    }
+}
+
+const DEFAULT_MAX_BLOCKING_QUEUE_SIZE: u64 = 100_000;
+const DEFAULT_VERIFICATION_SAMPLE_COUNT: u64 = 1024;
+
+fn default_max_blocking_queue_size() -> u64 {
+    DEFAULT_MAX_BLOCKING_QUEUE_SIZE
+}
+
+fn default_verification_sample_count() -> u64 {
+    DEFAULT_VERIFICATION_SAMPLE_COUNT
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct MigrationConfig {
     pub max_migration_time: u64,
     pub max_blocking_time: u64,
+    #[serde(default = "default_max_blocking_queue_size")]
+    pub max_blocking_queue_size: u64,
     pub delete_interval: u64,
     pub delete_count: u64,
     pub scan_interval: u64,
     pub scan_count: u64,
+    #[serde(default)]
+    pub verification: VerificationMode,
+    #[serde(default = "default_verification_sample_count")]
+    pub verification_sample_count: u64,
 }
 
 impl MigrationConfig {
@@ -171,6 +288,12 @@ impl MigrationConfig {
                     .map_err(|_| ConfigError::InvalidValue)?;
                 self.max_blocking_time = v;
             }
+            "max_blocking_queue_size" => {
+                let v = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.max_blocking_queue_size = v;
+            }
             "delete_interval" => {
                 let v = value
                     .parse::<u64>()
@@ -195,6 +318,17 @@ impl MigrationConfig {
                     .map_err(|_| ConfigError::InvalidValue)?;
                 self.scan_count = v;
             }
+            "verification" => {
+                let mode =
+                    VerificationMode::from_str(value).map_err(|_| ConfigError::InvalidValue)?;
+                self.verification = mode;
+            }
+            "verification_sample_count" => {
+                let v = value
+                    .parse::<u64>()
+                    .map_err(|_| ConfigError::InvalidValue)?;
+                self.verification_sample_count = v;
+            }
             _ => return Err(ConfigError::FieldNotFound),
         }
         Ok(())
@@ -206,10 +340,13 @@ impl Default for MigrationConfig {
         Self {
             max_migration_time: 3 * 60 * 60, // 3 hours
             max_blocking_time: 10_000,       // 10 seconds waiting for switch
-            delete_interval: 500,            // 500 microseconds
+            max_blocking_queue_size: DEFAULT_MAX_BLOCKING_QUEUE_SIZE,
+            delete_interval: 500, // 500 microseconds
             delete_count: 16,
             scan_interval: 500, // 500 microseconds
             scan_count: 16,
+            verification: VerificationMode::default(),
+            verification_sample_count: DEFAULT_VERIFICATION_SAMPLE_COUNT,
         }
     }
 }
@@ -217,10 +354,13 @@ impl Default for MigrationConfig {
 pub struct AtomicMigrationConfig {
     max_migration_time: AtomicU64,
     max_blocking_time: AtomicU64,
+    max_blocking_queue_size: AtomicU64,
     delete_interval: AtomicU64,
     delete_count: AtomicU64,
     scan_interval: AtomicU64,
     scan_count: AtomicU64,
+    verification: AtomicU8,
+    verification_sample_count: AtomicU64,
 }
 
 impl Default for AtomicMigrationConfig {
@@ -234,10 +374,13 @@ impl AtomicMigrationConfig {
         Self {
             max_migration_time: AtomicU64::new(config.max_migration_time),
             max_blocking_time: AtomicU64::new(config.max_blocking_time),
+            max_blocking_queue_size: AtomicU64::new(config.max_blocking_queue_size),
             delete_interval: AtomicU64::new(config.delete_interval),
             delete_count: AtomicU64::new(config.delete_count),
             scan_interval: AtomicU64::new(config.scan_interval),
             scan_count: AtomicU64::new(config.scan_count),
+            verification: AtomicU8::new(config.verification as u8),
+            verification_sample_count: AtomicU64::new(config.verification_sample_count),
         }
     }
 
@@ -249,6 +392,10 @@ impl AtomicMigrationConfig {
         self.max_blocking_time.load(Ordering::SeqCst)
     }
 
+    pub fn get_max_blocking_queue_size(&self) -> u64 {
+        self.max_blocking_queue_size.load(Ordering::SeqCst)
+    }
+
     pub fn get_delete_interval(&self) -> u64 {
         self.delete_interval.load(Ordering::SeqCst)
     }
@@ -264,6 +411,14 @@ impl AtomicMigrationConfig {
     pub fn get_scan_count(&self) -> u64 {
         self.scan_count.load(Ordering::SeqCst)
     }
+
+    pub fn get_verification(&self) -> VerificationMode {
+        VerificationMode::from_u8(self.verification.load(Ordering::SeqCst))
+    }
+
+    pub fn get_verification_sample_count(&self) -> u64 {
+        self.verification_sample_count.load(Ordering::SeqCst)
+    }
 }
 
 #[derive(Debug)]
@@ -277,6 +432,20 @@ pub enum ConfigError {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_proxy_str_map() {
+        let cluster_config = ClusterConfig {
+            replicas_per_master: 1,
+            ..Default::default()
+        };
+        assert!(cluster_config
+            .to_str_map()
+            .contains_key("replicas_per_master"));
+        let proxy_map = cluster_config.to_proxy_str_map();
+        assert!(!proxy_map.contains_key("replicas_per_master"));
+        assert!(proxy_map.contains_key("compression_strategy"));
+    }
+
     #[test]
     fn test_config_set_field() {
         let mut migration_config = MigrationConfig::default();
@@ -290,5 +459,21 @@ mod tests {
             .set_field("migration_delete_count", "666")
             .expect("test_config_set_field");
         assert_eq!(cluster_config.migration_config.delete_count, 666);
+
+        cluster_config
+            .set_field("migration_verification", "Sample")
+            .expect("test_config_set_field");
+        assert_eq!(
+            cluster_config.migration_config.verification,
+            VerificationMode::Sample
+        );
+        assert!(cluster_config
+            .set_field("migration_verification", "partial")
+            .is_err());
+
+        cluster_config
+            .set_field("replicas_per_master", "1")
+            .expect("test_config_set_field");
+        assert_eq!(cluster_config.replicas_per_master, 1);
     }
 }
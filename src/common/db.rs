@@ -3,7 +3,7 @@ use super::utils::{has_flags, CmdParseError};
 use crate::common::cluster::DBName;
 use crate::common::config::ClusterConfig;
 use crate::protocol::{Array, BulkStr, Resp};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::iter::Peekable;
 use std::str;
 
@@ -178,6 +178,152 @@ impl ProxyDBMeta {
     }
 }
 
+// The delta form of `UMCTL SETDBDELTA` which only carries the changed databases.
+// The local, peer and config parts of the changed databases in `meta`
+// replace the ones in the metadata of `base_epoch`,
+// and the changed databases missing in `meta` are removed.
+#[derive(Debug, Clone)]
+pub struct ProxyDBMetaDelta {
+    base_epoch: u64,
+    changed_dbs: Vec<DBName>,
+    meta: ProxyDBMeta,
+}
+
+impl ProxyDBMetaDelta {
+    pub fn diff(base: &ProxyDBMeta, meta: ProxyDBMeta) -> Self {
+        let changed_dbs: HashSet<DBName> = base
+            .local
+            .db_map
+            .keys()
+            .chain(base.peer.db_map.keys())
+            .chain(base.clusters_config.config_map.keys())
+            .chain(meta.local.db_map.keys())
+            .chain(meta.peer.db_map.keys())
+            .chain(meta.clusters_config.config_map.keys())
+            .filter(|db_name| {
+                base.local.db_map.get(*db_name) != meta.local.db_map.get(*db_name)
+                    || base.peer.db_map.get(*db_name) != meta.peer.db_map.get(*db_name)
+                    || base.clusters_config.config_map.get(*db_name)
+                        != meta.clusters_config.config_map.get(*db_name)
+            })
+            .cloned()
+            .collect();
+
+        let ProxyDBMeta {
+            epoch,
+            flags,
+            mut local,
+            mut peer,
+            mut clusters_config,
+        } = meta;
+        local
+            .db_map
+            .retain(|db_name, _| changed_dbs.contains(db_name));
+        peer.db_map
+            .retain(|db_name, _| changed_dbs.contains(db_name));
+        clusters_config
+            .config_map
+            .retain(|db_name, _| changed_dbs.contains(db_name));
+
+        Self {
+            base_epoch: base.epoch,
+            changed_dbs: changed_dbs.into_iter().collect(),
+            meta: ProxyDBMeta::new(epoch, flags, local, peer, clusters_config),
+        }
+    }
+
+    pub fn get_base_epoch(&self) -> u64 {
+        self.base_epoch
+    }
+
+    pub fn get_meta(&self) -> &ProxyDBMeta {
+        &self.meta
+    }
+
+    pub fn get_changed_dbs(&self) -> &[DBName] {
+        &self.changed_dbs
+    }
+
+    pub fn apply(&self, base: &ProxyDBMeta) -> ProxyDBMeta {
+        let mut local = base.local.db_map.clone();
+        let mut peer = base.peer.db_map.clone();
+        let mut config_map = base.clusters_config.config_map.clone();
+        for db_name in self.changed_dbs.iter() {
+            local.remove(db_name);
+            peer.remove(db_name);
+            config_map.remove(db_name);
+        }
+
+        let delta = &self.meta;
+        local.extend(delta.local.db_map.clone());
+        peer.extend(delta.peer.db_map.clone());
+        config_map.extend(delta.clusters_config.config_map.clone());
+
+        ProxyDBMeta::new(
+            delta.epoch,
+            delta.flags.clone(),
+            ProxyDBMap::new(local),
+            ProxyDBMap::new(peer),
+            ClusterConfigMap::new(config_map),
+        )
+    }
+
+    pub fn from_resp<T: AsRef<[u8]>>(
+        resp: &Resp<T>,
+    ) -> Result<(Self, Result<(), ParseExtendedMetaError>), CmdParseError> {
+        let arr = match resp {
+            Resp::Arr(Array::Arr(ref arr)) => arr,
+            _ => return Err(CmdParseError {}),
+        };
+
+        // Skip the "UMCTL SETDBDELTA"
+        let it = arr.iter().skip(2).flat_map(|resp| match resp {
+            Resp::Bulk(BulkStr::Str(safe_str)) => match str::from_utf8(safe_str.as_ref()) {
+                Ok(s) => Some(s.to_string()),
+                _ => None,
+            },
+            _ => None,
+        });
+        let mut it = it.peekable();
+
+        Self::parse(&mut it)
+    }
+
+    pub fn parse<It>(
+        it: &mut Peekable<It>,
+    ) -> Result<(Self, Result<(), ParseExtendedMetaError>), CmdParseError>
+    where
+        It: Iterator<Item = String>,
+    {
+        let base_epoch = try_parse!(try_get!(it.next()).parse::<u64>());
+        let db_num = try_parse!(try_get!(it.next()).parse::<usize>());
+        let mut changed_dbs = Vec::with_capacity(db_num);
+        for _ in 0..db_num {
+            let db_name = try_get!(it.next());
+            changed_dbs.push(DBName::from(&db_name).map_err(|_| CmdParseError {})?);
+        }
+        let (meta, extended_res) = ProxyDBMeta::parse(it)?;
+        Ok((
+            Self {
+                base_epoch,
+                changed_dbs,
+                meta,
+            },
+            extended_res,
+        ))
+    }
+
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![
+            self.base_epoch.to_string(),
+            self.changed_dbs.len().to_string(),
+        ];
+        args.extend(self.changed_dbs.iter().map(|db_name| db_name.to_string()));
+        args.extend(self.meta.to_args());
+        args
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ProxyDBMap {
     db_map: HashMap<DBName, HashMap<String, Vec<SlotRange>>>,
@@ -350,7 +496,7 @@ impl ClusterConfigMap {
     pub fn to_args(&self) -> Vec<String> {
         let mut args = vec![];
         for (db_name, config) in &self.config_map {
-            for (k, v) in config.to_str_map().into_iter() {
+            for (k, v) in config.to_proxy_str_map().into_iter() {
                 args.push(db_name.to_string());
                 args.push(k);
                 args.push(v);
@@ -597,6 +743,9 @@ mod tests {
             "migration_max_blocking_time",
             "10000",
             "mydb",
+            "migration_max_blocking_queue_size",
+            "100000",
+            "mydb",
             "migration_delete_interval",
             "500",
             "mydb",
@@ -605,6 +754,12 @@ mod tests {
             "mydb",
             "migration_scan_count",
             "16",
+            "mydb",
+            "migration_verification",
+            "disabled",
+            "mydb",
+            "migration_verification_sample_count",
+            "1024",
             "otherdb",
             "compression_strategy",
             "disabled",
@@ -618,6 +773,9 @@ mod tests {
             "migration_max_blocking_time",
             "10000",
             "otherdb",
+            "migration_max_blocking_queue_size",
+            "100000",
+            "otherdb",
             "migration_delete_interval",
             "500",
             "otherdb",
@@ -626,6 +784,12 @@ mod tests {
             "otherdb",
             "migration_scan_count",
             "16",
+            "otherdb",
+            "migration_verification",
+            "disabled",
+            "otherdb",
+            "migration_verification_sample_count",
+            "1024",
         ];
         result_args.sort();
         full_args.sort();
@@ -785,6 +949,9 @@ mod tests {
             "migration_max_blocking_time",
             "10000",
             "dbname",
+            "migration_max_blocking_queue_size",
+            "100000",
+            "dbname",
             "migration_delete_interval",
             "500",
             "dbname",
@@ -793,6 +960,12 @@ mod tests {
             "dbname",
             "migration_scan_count",
             "16",
+            "dbname",
+            "migration_verification",
+            "disabled",
+            "dbname",
+            "migration_verification_sample_count",
+            "1024",
         ]
         .into_iter()
         .map(|s| s.to_string());
@@ -915,4 +1088,63 @@ mod tests {
 
         assert!(ProxyDBMeta::parse(&mut it).is_err());
     }
+
+    #[test]
+    fn test_proxy_db_meta_delta() {
+        let parse_meta = |arguments: Vec<&str>| {
+            let mut it = arguments.into_iter().map(|s| s.to_string()).peekable();
+            ProxyDBMeta::parse(&mut it)
+                .expect("test_proxy_db_meta_delta")
+                .0
+        };
+        let base = parse_meta(vec![
+            "233",
+            "NOFLAG",
+            "db1",
+            "127.0.0.1:7000",
+            "0-8000",
+            "db2",
+            "127.0.0.1:7001",
+            "0-16383",
+            "PEER",
+            "db1",
+            "127.0.0.1:6001",
+            "8001-16383",
+        ]);
+        let meta = parse_meta(vec![
+            "234",
+            "NOFLAG",
+            "db1",
+            "127.0.0.1:7000",
+            "0-8000",
+            "db3",
+            "127.0.0.1:7001",
+            "0-16383",
+            "PEER",
+            "db1",
+            "127.0.0.1:6002",
+            "8001-16383",
+            "CONFIG",
+            "db3",
+            "compression_strategy",
+            "set_get_only",
+        ]);
+
+        let delta = ProxyDBMetaDelta::diff(&base, meta.clone());
+        assert_eq!(delta.get_base_epoch(), 233);
+        assert_eq!(delta.changed_dbs.len(), 3);
+
+        let mut it = delta.to_args().into_iter().peekable();
+        let (delta, extended_res) =
+            ProxyDBMetaDelta::parse(&mut it).expect("test_proxy_db_meta_delta");
+        assert!(extended_res.is_ok());
+        let applied = delta.apply(&base);
+        assert_eq!(applied.get_epoch(), 234);
+        assert_eq!(applied.get_local().get_map(), meta.get_local().get_map());
+        assert_eq!(applied.get_peer().get_map(), meta.get_peer().get_map());
+        assert_eq!(
+            applied.get_configs().get_map(),
+            meta.get_configs().get_map()
+        );
+    }
 }
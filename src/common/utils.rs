@@ -133,6 +133,7 @@ pub fn pretty_print_bytes(data: &[u8]) -> String {
 pub const OK_REPLY: &str = "OK";
 pub const OLD_EPOCH_REPLY: &str = "OLD_EPOCH";
 pub const TRY_AGAIN_REPLY: &str = "TRY_AGAIN";
+pub const BASE_EPOCH_MISMATCH_REPLY: &str = "BASE_EPOCH_MISMATCH";
 pub const NOT_READY_FOR_SWITCHING_REPLY: &str = "NOT_READY_FOR_SWITCHING";
 pub const SLOT_NUM: usize = 16384;
 
@@ -191,6 +192,23 @@ pub fn bytes_ascii_case_insensitive_eq(lhs: &[u8], rhs: &[u8]) -> bool {
 }
 
 #[inline]
+// Compare the tokens without leaking the length of the matched prefix.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// `str::strip_prefix` is not available in our minimum toolchain.
+pub fn strip_prefix<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.starts_with(prefix) {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
 pub fn byte_to_uppercase(b: u8) -> u8 {
     const DELTA: u8 = b'a' - b'A';
     if b'a' <= b && b <= b'z' {
@@ -236,6 +254,13 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_strip_prefix() {
+        assert_eq!(strip_prefix("Bearer token", "Bearer "), Some("token"));
+        assert_eq!(strip_prefix("Bearer ", "Bearer "), Some(""));
+        assert_eq!(strip_prefix("token", "Bearer "), None);
+    }
+
     #[test]
     fn test_byte_to_uppercase() {
         assert_eq!(byte_to_uppercase(b'@'), b'@');
@@ -0,0 +1,187 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct PhiAccrualConfig {
+    // The proxy is treated as failed when phi exceeds this value.
+    pub threshold: f64,
+    // The number of the latest heartbeat intervals kept for each proxy.
+    pub max_sample_size: usize,
+    // Avoid treating a slightly late heartbeat as failure when the intervals are too steady.
+    pub min_std_deviation: Duration,
+    // Tolerate the pauses such as GC or network blips.
+    pub acceptable_heartbeat_pause: Duration,
+    // Used before there're enough heartbeats.
+    pub first_heartbeat_estimate: Duration,
+}
+
+impl Default for PhiAccrualConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 8.0,
+            max_sample_size: 200,
+            min_std_deviation: Duration::from_millis(500),
+            acceptable_heartbeat_pause: Duration::from_secs(3),
+            first_heartbeat_estimate: Duration::from_secs(1),
+        }
+    }
+}
+
+struct HeartbeatHistory {
+    // In milliseconds
+    intervals: VecDeque<f64>,
+    last_heartbeat: Instant,
+    // Whether it's checked since the last `remove_unchecked`.
+    checked: bool,
+}
+
+impl HeartbeatHistory {
+    fn new(config: &PhiAccrualConfig, now: Instant) -> Self {
+        // Two samples around the estimate give a reasonable initial deviation.
+        let estimate = config.first_heartbeat_estimate.as_millis() as f64;
+        let std_deviation = estimate / 4.0;
+        let mut intervals = VecDeque::new();
+        intervals.push_back(estimate - std_deviation);
+        intervals.push_back(estimate + std_deviation);
+        Self {
+            intervals,
+            last_heartbeat: now,
+            checked: true,
+        }
+    }
+
+    fn add_interval(&mut self, interval: f64, max_sample_size: usize) {
+        self.intervals.push_back(interval);
+        while self.intervals.len() > max_sample_size {
+            self.intervals.pop_front();
+        }
+    }
+
+    fn mean(&self) -> f64 {
+        self.intervals.iter().sum::<f64>() / self.intervals.len() as f64
+    }
+
+    fn std_deviation(&self) -> f64 {
+        let mean = self.mean();
+        let variance = self
+            .intervals
+            .iter()
+            .map(|interval| (interval - mean).powi(2))
+            .sum::<f64>()
+            / self.intervals.len() as f64;
+        variance.sqrt()
+    }
+}
+
+// The phi accrual failure detector keeps the heartbeat history of each proxy
+// across the detection rounds and outputs the suspicion level phi
+// instead of judging by the failures of a single round.
+pub struct PhiAccrualDetector {
+    config: PhiAccrualConfig,
+    histories: Mutex<HashMap<String, HeartbeatHistory>>,
+}
+
+impl PhiAccrualDetector {
+    pub fn new(config: PhiAccrualConfig) -> Self {
+        Self {
+            config,
+            histories: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn heartbeat(&self, address: &str, now: Instant) {
+        let mut histories = self
+            .histories
+            .lock()
+            .expect("PhiAccrualDetector::heartbeat");
+        match histories.get_mut(address) {
+            Some(history) => {
+                let interval = now.duration_since(history.last_heartbeat).as_millis() as f64;
+                history.add_interval(interval, self.config.max_sample_size);
+                history.last_heartbeat = now;
+                history.checked = true;
+            }
+            None => {
+                histories.insert(
+                    address.to_string(),
+                    HeartbeatHistory::new(&self.config, now),
+                );
+            }
+        }
+    }
+
+    // The proxies never seen before start their history at `now`.
+    pub fn phi(&self, address: &str, now: Instant) -> f64 {
+        let mut histories = self.histories.lock().expect("PhiAccrualDetector::phi");
+        let config = &self.config;
+        let history = histories
+            .entry(address.to_string())
+            .or_insert_with(|| HeartbeatHistory::new(config, now));
+        history.checked = true;
+        let elapsed = now.duration_since(history.last_heartbeat).as_millis() as f64;
+        let mean = history.mean() + config.acceptable_heartbeat_pause.as_millis() as f64;
+        let std_deviation = history
+            .std_deviation()
+            .max(config.min_std_deviation.as_millis() as f64);
+        calculate_phi(elapsed, mean, std_deviation)
+    }
+
+    pub fn is_available(&self, address: &str, now: Instant) -> bool {
+        self.phi(address, now) < self.config.threshold
+    }
+
+    // Called after every detection round to remove the proxies
+    // no longer in the metadata, which are not checked in this round.
+    pub fn remove_unchecked(&self) {
+        let mut histories = self
+            .histories
+            .lock()
+            .expect("PhiAccrualDetector::remove_unchecked");
+        histories.retain(|_, history| history.checked);
+        for history in histories.values_mut() {
+            history.checked = false;
+        }
+    }
+}
+
+// Uses the logistic approximation of the cumulative normal distribution.
+fn calculate_phi(elapsed: f64, mean: f64, std_deviation: f64) -> f64 {
+    let y = (elapsed - mean) / std_deviation;
+    let e = (-y * (1.5976 + 0.070_566 * y * y)).exp();
+    if elapsed > mean {
+        -(e / (1.0 + e)).log10()
+    } else {
+        -(1.0 - 1.0 / (1.0 + e)).log10()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phi_accrual_detector() {
+        let detector = PhiAccrualDetector::new(PhiAccrualConfig::default());
+        let address = "127.0.0.1:5299";
+        let mut now = Instant::now();
+        for _ in 0..10 {
+            detector.heartbeat(address, now);
+            now += Duration::from_secs(1);
+        }
+        // A pause within the acceptable pause is tolerated.
+        assert!(detector.is_available(address, now + Duration::from_secs(2)));
+        assert!(!detector.is_available(address, now + Duration::from_secs(30)));
+        assert!(
+            detector.phi(address, now + Duration::from_secs(5))
+                < detector.phi(address, now + Duration::from_secs(10))
+        );
+
+        // The unknown proxies start with a fresh history.
+        assert!(detector.is_available("127.0.0.1:6000", now));
+
+        detector.remove_unchecked();
+        detector.remove_unchecked();
+        assert!(detector.is_available(address, now + Duration::from_secs(30)));
+    }
+}
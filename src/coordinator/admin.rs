@@ -0,0 +1,186 @@
+use super::config::TunableConfig;
+use super::status::{CoordinatorStatus, ServiceStatus};
+use crate::common::config::ConfigError;
+use crate::common::utils::{constant_time_eq, strip_prefix};
+use crate::common::version::UNDERMOON_VERSION;
+use actix_web::{http, middleware, server, App, HttpRequest, HttpResponse, Json, Path, State};
+use std::collections::HashMap;
+use std::io;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+// The probes are always allowed for the health checks of the deployment.
+const PUBLIC_PATHS: [&str; 3] = ["/api/version", "/api/health", "/api/ready"];
+
+// The states of the coordinator exposed by the admin http api.
+pub struct CoordinatorAdmin {
+    tunable: Arc<TunableConfig>,
+    // One for each broker.
+    statuses: Vec<Arc<CoordinatorStatus>>,
+    // The same bearer token used to access the broker. Empty for no authentication.
+    token: String,
+}
+
+impl CoordinatorAdmin {
+    pub fn new(tunable: Arc<TunableConfig>, statuses: Vec<Arc<CoordinatorStatus>>) -> Self {
+        Self {
+            tunable,
+            statuses,
+            token: String::new(),
+        }
+    }
+
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = token;
+        self
+    }
+
+    fn is_authorized(&self, path: &str, authorization: Option<&str>) -> bool {
+        if self.token.is_empty() || PUBLIC_PATHS.contains(&path.trim_end_matches('/')) {
+            return true;
+        }
+        authorization
+            .and_then(|value| strip_prefix(value.trim(), "Bearer "))
+            .map(|token| constant_time_eq(token.trim().as_bytes(), self.token.as_bytes()))
+            .unwrap_or(false)
+    }
+}
+
+// Binds the address in the caller so that the error will not be lost in the server thread.
+pub fn run_admin_server(admin: Arc<CoordinatorAdmin>, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    thread::spawn(move || {
+        server::new(move || gen_app(admin.clone()))
+            .listen(listener)
+            .run()
+    });
+    Ok(())
+}
+
+pub fn gen_app(admin: Arc<CoordinatorAdmin>) -> App<Arc<CoordinatorAdmin>> {
+    App::with_state(admin)
+        .middleware(middleware::Logger::default())
+        .middleware(AdminAuthMiddleware)
+        .prefix("/api")
+        .resource("/version", |r| r.method(http::Method::GET).f(get_version))
+        .resource("/config", |r| {
+            r.method(http::Method::GET).f(get_config);
+            r.method(http::Method::PATCH).with(change_config);
+        })
+        .resource("/health", |r| r.method(http::Method::GET).f(get_health))
+        .resource("/ready", |r| r.method(http::Method::GET).f(get_readiness))
+        .resource("/status", |r| r.method(http::Method::GET).f(get_status))
+        .resource("/proxies/{proxy_address}/sync", |r| {
+            r.method(http::Method::POST).with(sync_proxy_meta)
+        })
+}
+
+type AdminState = State<Arc<CoordinatorAdmin>>;
+
+struct AdminAuthMiddleware;
+
+impl middleware::Middleware<Arc<CoordinatorAdmin>> for AdminAuthMiddleware {
+    fn start(
+        &self,
+        req: &HttpRequest<Arc<CoordinatorAdmin>>,
+    ) -> actix_web::Result<middleware::Started> {
+        let authorization = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        if req.state().is_authorized(req.path(), authorization) {
+            return Ok(middleware::Started::Done);
+        }
+        let resp = HttpResponse::Unauthorized()
+            .header(http::header::WWW_AUTHENTICATE, "Bearer")
+            .body("UNAUTHORIZED: missing or invalid bearer token");
+        Ok(middleware::Started::Response(resp))
+    }
+}
+
+fn get_version(_req: &HttpRequest<Arc<CoordinatorAdmin>>) -> &'static str {
+    UNDERMOON_VERSION
+}
+
+fn get_config(req: &HttpRequest<Arc<CoordinatorAdmin>>) -> Json<HashMap<String, String>> {
+    Json(req.state().tunable.to_str_map())
+}
+
+// The fields are set one by one and the rest will be skipped on the first error.
+fn change_config((fields, state): (Json<HashMap<String, String>>, AdminState)) -> HttpResponse {
+    for (field, value) in fields.into_inner().into_iter() {
+        if let Err(err) = state.tunable.set_value(&field, &value) {
+            let reason = match err {
+                ConfigError::ReadonlyField => "READONLY_FIELD",
+                ConfigError::FieldNotFound => "FIELD_NOT_FOUND",
+                ConfigError::InvalidValue => "INVALID_VALUE",
+            };
+            return HttpResponse::BadRequest().body(format!("{}: {}", reason, field));
+        }
+        info!("coordinator config {} is changed to {}", field, value);
+    }
+    HttpResponse::Ok().json(state.tunable.to_str_map())
+}
+
+// Unhealthy once any service stops.
+fn get_health(req: &HttpRequest<Arc<CoordinatorAdmin>>) -> HttpResponse {
+    if req
+        .state()
+        .statuses
+        .iter()
+        .any(|status| status.is_stopped())
+    {
+        HttpResponse::ServiceUnavailable().body("STOPPED")
+    } else {
+        HttpResponse::Ok().body("OK")
+    }
+}
+
+// Ready after all the loops of all the services have run at least once.
+fn get_readiness(req: &HttpRequest<Arc<CoordinatorAdmin>>) -> HttpResponse {
+    if req.state().statuses.iter().all(|status| status.is_ready()) {
+        HttpResponse::Ok().body("READY")
+    } else {
+        HttpResponse::ServiceUnavailable().body("NOT_READY")
+    }
+}
+
+fn get_status(req: &HttpRequest<Arc<CoordinatorAdmin>>) -> Json<Vec<ServiceStatus>> {
+    let statuses = req
+        .state()
+        .statuses
+        .iter()
+        .map(|status| status.get_status())
+        .collect();
+    Json(statuses)
+}
+
+// The metadata will be sent by the services in the background.
+fn sync_proxy_meta((path, state): (Path<(String,)>, AdminState)) -> HttpResponse {
+    let proxy_address = path.into_inner().0;
+    for status in state.statuses.iter() {
+        status.request_meta_sync(proxy_address.clone());
+    }
+    HttpResponse::Accepted().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_authorization() {
+        let tunable = Arc::new(TunableConfig::default());
+        let admin = CoordinatorAdmin::new(tunable.clone(), vec![]);
+        assert!(admin.is_authorized("/api/config", None));
+
+        let admin = CoordinatorAdmin::new(tunable, vec![]).with_token("token1".to_string());
+        assert!(admin.is_authorized("/api/health", None));
+        assert!(admin.is_authorized("/api/ready/", None));
+        assert!(!admin.is_authorized("/api/config", None));
+        assert!(!admin.is_authorized("/api/config", Some("Bearer token2")));
+        assert!(!admin.is_authorized("/api/proxies/127.0.0.1:5299/sync", Some("token1")));
+        assert!(admin.is_authorized("/api/config", Some("Bearer token1")));
+    }
+}
@@ -1,4 +1,6 @@
-use crate::common::cluster::{Cluster, DBName, MigrationTaskMeta, Proxy};
+use crate::common::cluster::{
+    Cluster, DBName, MetaChanges, MigrationTaskMeta, Node, NodeFailover, Proxy,
+};
 use crate::common::utils::ThreadSafe;
 use futures::{Future, Stream};
 use mockall::automock;
@@ -6,6 +8,7 @@ use std::error::Error;
 use std::fmt;
 use std::io;
 use std::pin::Pin;
+use std::time::Duration;
 
 // To support large result set, return Stream here in some APIs.
 #[automock]
@@ -37,6 +40,13 @@ pub trait MetaDataBroker: ThreadSafe {
     fn get_failures<'s>(
         &'s self,
     ) -> Pin<Box<dyn Stream<Item = Result<String, MetaDataBrokerError>> + Send + 's>>;
+
+    // Wait until the metadata changes after `since_epoch` or timeout.
+    fn get_changes<'s>(
+        &'s self,
+        since_epoch: u64,
+        timeout: Duration,
+    ) -> Pin<Box<dyn Future<Output = Result<MetaChanges, MetaDataBrokerError>> + Send + 's>>;
 }
 
 // Maybe we would want to support other database supporting redis protocol.
@@ -48,10 +58,30 @@ pub trait MetaManipulationBroker: ThreadSafe {
         failed_proxy_address: String,
     ) -> Pin<Box<dyn Future<Output = Result<Proxy, MetaManipulationBrokerError>> + Send + 's>>;
 
+    // Handle the failed node whose proxy is still alive
+    // by promoting its replica and replacing it with a free node.
+    fn failover_node<'s>(
+        &'s self,
+        failed_node_address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<NodeFailover, MetaManipulationBrokerError>> + Send + 's>>;
+
+    // Allocate a free node on another proxy as a new replica of the master.
+    fn add_replica<'s>(
+        &'s self,
+        cluster_name: String,
+        master_node_address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<Node, MetaManipulationBrokerError>> + Send + 's>>;
+
     fn commit_migration<'s>(
         &'s self,
         meta: MigrationTaskMeta,
     ) -> Pin<Box<dyn Future<Output = Result<(), MetaManipulationBrokerError>> + Send + 's>>;
+
+    fn ack_migration_rollback<'s>(
+        &'s self,
+        proxy_address: String,
+        meta: MigrationTaskMeta,
+    ) -> Pin<Box<dyn Future<Output = Result<(), MetaManipulationBrokerError>> + Send + 's>>;
 }
 
 #[derive(Debug)]
@@ -0,0 +1,249 @@
+use super::core::{
+    DEFAULT_DETECT_CONCURRENCY, DEFAULT_FAILURE_HANDLER_CONCURRENCY, DEFAULT_HOST_SYNC_CONCURRENCY,
+    DEFAULT_MIGRATION_SYNC_CONCURRENCY,
+};
+use crate::common::config::ConfigError;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+pub const DEFAULT_CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_CLIENT_POOL_SIZE: usize = 2;
+// Avoid busy loops and flooding the broker with a tiny interval.
+pub const MIN_INTERVAL_MS: u64 = 10;
+
+// The fields which could be changed at runtime.
+pub const TUNABLE_FIELDS: [&str; 11] = [
+    "detect_interval_ms",
+    "host_sync_interval_ms",
+    "failure_handler_interval_ms",
+    "migration_sync_interval_ms",
+    "replica_repair_interval_ms",
+    "full_meta_sync_interval_ms",
+    "meta_changes_timeout_ms",
+    "detect_concurrency",
+    "host_sync_concurrency",
+    "failure_handler_concurrency",
+    "migration_sync_concurrency",
+];
+
+// Shared by all the coordinator services in the process.
+// The atomic fields could be changed through the admin api
+// and take effect in the next round of the loops.
+#[derive(Debug)]
+pub struct TunableConfig {
+    client_timeout: Duration,
+    client_pool_size: usize,
+    // All the intervals and timeouts are in milliseconds.
+    detect_interval: AtomicU64,
+    host_sync_interval: AtomicU64,
+    failure_handler_interval: AtomicU64,
+    migration_sync_interval: AtomicU64,
+    replica_repair_interval: AtomicU64,
+    // The restarted proxies could lose their metadata without any change in the broker,
+    // so all the proxies still need to be synchronized periodically.
+    full_meta_sync_interval: AtomicU64,
+    // Wait for the changes of the broker for at most this time in every round.
+    meta_changes_timeout: AtomicU64,
+    detect_concurrency: AtomicUsize,
+    host_sync_concurrency: AtomicUsize,
+    failure_handler_concurrency: AtomicUsize,
+    migration_sync_concurrency: AtomicUsize,
+}
+
+impl Default for TunableConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_CLIENT_TIMEOUT, DEFAULT_CLIENT_POOL_SIZE)
+    }
+}
+
+impl TunableConfig {
+    pub fn new(client_timeout: Duration, client_pool_size: usize) -> Self {
+        Self {
+            client_timeout,
+            client_pool_size,
+            detect_interval: AtomicU64::new(1000),
+            host_sync_interval: AtomicU64::new(1000),
+            failure_handler_interval: AtomicU64::new(1000),
+            migration_sync_interval: AtomicU64::new(1000),
+            // Repairing replicas is not urgent and could add new proxies to the clusters.
+            replica_repair_interval: AtomicU64::new(10_000),
+            full_meta_sync_interval: AtomicU64::new(60_000),
+            meta_changes_timeout: AtomicU64::new(10_000),
+            detect_concurrency: AtomicUsize::new(DEFAULT_DETECT_CONCURRENCY),
+            host_sync_concurrency: AtomicUsize::new(DEFAULT_HOST_SYNC_CONCURRENCY),
+            failure_handler_concurrency: AtomicUsize::new(DEFAULT_FAILURE_HANDLER_CONCURRENCY),
+            migration_sync_concurrency: AtomicUsize::new(DEFAULT_MIGRATION_SYNC_CONCURRENCY),
+        }
+    }
+
+    fn get_millis_field(&self, field: &str) -> Option<&AtomicU64> {
+        let value = match field {
+            "detect_interval_ms" => &self.detect_interval,
+            "host_sync_interval_ms" => &self.host_sync_interval,
+            "failure_handler_interval_ms" => &self.failure_handler_interval,
+            "migration_sync_interval_ms" => &self.migration_sync_interval,
+            "replica_repair_interval_ms" => &self.replica_repair_interval,
+            "full_meta_sync_interval_ms" => &self.full_meta_sync_interval,
+            "meta_changes_timeout_ms" => &self.meta_changes_timeout,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn get_concurrency_field(&self, field: &str) -> Option<&AtomicUsize> {
+        let value = match field {
+            "detect_concurrency" => &self.detect_concurrency,
+            "host_sync_concurrency" => &self.host_sync_concurrency,
+            "failure_handler_concurrency" => &self.failure_handler_concurrency,
+            "migration_sync_concurrency" => &self.migration_sync_concurrency,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    pub fn get_field(&self, field: &str) -> Result<String, ConfigError> {
+        let field = field.to_lowercase();
+        match field.as_str() {
+            "client_timeout_ms" => Ok(self.client_timeout.as_millis().to_string()),
+            "client_pool_size" => Ok(self.client_pool_size.to_string()),
+            _ => {
+                if let Some(value) = self.get_millis_field(&field) {
+                    Ok(value.load(Ordering::SeqCst).to_string())
+                } else if let Some(value) = self.get_concurrency_field(&field) {
+                    Ok(value.load(Ordering::SeqCst).to_string())
+                } else {
+                    Err(ConfigError::FieldNotFound)
+                }
+            }
+        }
+    }
+
+    pub fn set_value(&self, field: &str, value: &str) -> Result<(), ConfigError> {
+        let field = field.to_lowercase();
+        match field.as_str() {
+            "client_timeout_ms" | "client_pool_size" => Err(ConfigError::ReadonlyField),
+            _ => {
+                if let Some(v) = self.get_millis_field(&field) {
+                    let millis = value
+                        .parse::<u64>()
+                        .map_err(|_| ConfigError::InvalidValue)?;
+                    if millis < MIN_INTERVAL_MS {
+                        return Err(ConfigError::InvalidValue);
+                    }
+                    v.store(millis, Ordering::SeqCst);
+                    Ok(())
+                } else if let Some(v) = self.get_concurrency_field(&field) {
+                    let concurrency = value
+                        .parse::<usize>()
+                        .map_err(|_| ConfigError::InvalidValue)?;
+                    if concurrency == 0 {
+                        return Err(ConfigError::InvalidValue);
+                    }
+                    v.store(concurrency, Ordering::SeqCst);
+                    Ok(())
+                } else {
+                    Err(ConfigError::FieldNotFound)
+                }
+            }
+        }
+    }
+
+    pub fn to_str_map(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        for field in ["client_timeout_ms", "client_pool_size"]
+            .iter()
+            .chain(TUNABLE_FIELDS.iter())
+        {
+            if let Ok(value) = self.get_field(field) {
+                map.insert(field.to_string(), value);
+            }
+        }
+        map
+    }
+
+    pub fn get_client_timeout(&self) -> Duration {
+        self.client_timeout
+    }
+
+    pub fn get_client_pool_size(&self) -> usize {
+        self.client_pool_size
+    }
+
+    fn load_millis(value: &AtomicU64) -> Duration {
+        Duration::from_millis(value.load(Ordering::SeqCst))
+    }
+
+    pub fn get_detect_interval(&self) -> Duration {
+        Self::load_millis(&self.detect_interval)
+    }
+
+    pub fn get_host_sync_interval(&self) -> Duration {
+        Self::load_millis(&self.host_sync_interval)
+    }
+
+    pub fn get_failure_handler_interval(&self) -> Duration {
+        Self::load_millis(&self.failure_handler_interval)
+    }
+
+    pub fn get_migration_sync_interval(&self) -> Duration {
+        Self::load_millis(&self.migration_sync_interval)
+    }
+
+    pub fn get_replica_repair_interval(&self) -> Duration {
+        Self::load_millis(&self.replica_repair_interval)
+    }
+
+    pub fn get_full_meta_sync_interval(&self) -> Duration {
+        Self::load_millis(&self.full_meta_sync_interval)
+    }
+
+    pub fn get_meta_changes_timeout(&self) -> Duration {
+        Self::load_millis(&self.meta_changes_timeout)
+    }
+
+    pub fn get_detect_concurrency(&self) -> usize {
+        self.detect_concurrency.load(Ordering::SeqCst)
+    }
+
+    pub fn get_host_sync_concurrency(&self) -> usize {
+        self.host_sync_concurrency.load(Ordering::SeqCst)
+    }
+
+    pub fn get_failure_handler_concurrency(&self) -> usize {
+        self.failure_handler_concurrency.load(Ordering::SeqCst)
+    }
+
+    pub fn get_migration_sync_concurrency(&self) -> usize {
+        self.migration_sync_concurrency.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tunable_config() {
+        let config = TunableConfig::default();
+        config
+            .set_value("DETECT_INTERVAL_MS", "3000")
+            .expect("test_tunable_config");
+        assert_eq!(config.get_detect_interval(), Duration::from_secs(3));
+        config
+            .set_value("migration_sync_concurrency", "5")
+            .expect("test_tunable_config");
+        assert_eq!(config.get_migration_sync_concurrency(), 5);
+
+        assert!(config.set_value("detect_concurrency", "0").is_err());
+        assert!(config.set_value("detect_interval_ms", "0").is_err());
+        assert!(config.set_value("meta_changes_timeout_ms", "9").is_err());
+        assert_eq!(config.get_detect_interval(), Duration::from_secs(3));
+        assert!(config.set_value("client_pool_size", "3").is_err());
+        assert!(config.set_value("unknown_field", "3").is_err());
+
+        let map = config.to_str_map();
+        assert_eq!(map.len(), TUNABLE_FIELDS.len() + 2);
+        assert_eq!(map.get("client_timeout_ms"), Some(&"2000".to_string()));
+    }
+}
@@ -4,6 +4,7 @@ use crate::protocol::RedisClientError;
 use futures::{future, stream, Future, FutureExt, Stream, StreamExt, TryFutureExt};
 use futures_batch::ChunksTimeoutStreamExt;
 use mockall::automock;
+use std::cmp;
 use std::error::Error;
 use std::fmt;
 use std::io;
@@ -11,6 +12,12 @@ use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
+// The default number of the proxies handled concurrently in the loops.
+pub const DEFAULT_DETECT_CONCURRENCY: usize = 30;
+pub const DEFAULT_FAILURE_HANDLER_CONCURRENCY: usize = 10;
+pub const DEFAULT_HOST_SYNC_CONCURRENCY: usize = 10;
+pub const DEFAULT_MIGRATION_SYNC_CONCURRENCY: usize = 10;
+
 pub trait ProxiesRetriever: Sync + Send + 'static {
     fn retrieve_proxies<'s>(
         &'s self,
@@ -50,9 +57,15 @@ pub struct ParFailureDetector<
     retriever: Retriever,
     checker: Arc<Checker>,
     reporter: Arc<Reporter>,
+    concurrency: usize,
 }
 
 impl<T: ProxiesRetriever, C: FailureChecker, P: FailureReporter> ParFailureDetector<T, C, P> {
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = cmp::max(concurrency, 1);
+        self
+    }
+
     async fn check_and_report(
         checker: &C,
         reporter: &P,
@@ -72,14 +85,13 @@ impl<T: ProxiesRetriever, C: FailureChecker, P: FailureReporter> ParFailureDetec
     async fn run_impl(&self) -> Result<(), CoordinateError> {
         let checker = self.checker.clone();
         let reporter = self.reporter.clone();
-        const BATCH_SIZE: usize = 30;
         const BATCH_TIME: Duration = Duration::from_millis(1);
 
         let mut res = Ok(());
         let mut s = self
             .retriever
             .retrieve_proxies()
-            .chunks_timeout(BATCH_SIZE, BATCH_TIME);
+            .chunks_timeout(self.concurrency, BATCH_TIME);
 
         while let Some(results) = s.next().await {
             let mut proxies = vec![];
@@ -120,6 +132,7 @@ impl<T: ProxiesRetriever, C: FailureChecker, P: FailureReporter> FailureDetector
             retriever,
             checker: Arc::new(checker),
             reporter: Arc::new(reporter),
+            concurrency: DEFAULT_DETECT_CONCURRENCY,
         }
     }
 
@@ -152,12 +165,17 @@ pub trait FailureHandler {
 pub struct ParFailureHandler<PFRetriever: ProxyFailureRetriever, Handler: ProxyFailureHandler> {
     proxy_failure_retriever: PFRetriever,
     handler: Arc<Handler>,
+    concurrency: usize,
 }
 
 impl<P: ProxyFailureRetriever, H: ProxyFailureHandler> ParFailureHandler<P, H> {
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = cmp::max(concurrency, 1);
+        self
+    }
+
     async fn run_impl(&self) -> Result<(), CoordinateError> {
         let handler = self.handler.clone();
-        const BATCH_SIZE: usize = 10;
         const BATCH_TIME: Duration = Duration::from_millis(1);
 
         let mut res = Ok(());
@@ -165,7 +183,7 @@ impl<P: ProxyFailureRetriever, H: ProxyFailureHandler> ParFailureHandler<P, H> {
         let mut s = self
             .proxy_failure_retriever
             .retrieve_proxy_failures()
-            .chunks_timeout(BATCH_SIZE, BATCH_TIME);
+            .chunks_timeout(self.concurrency, BATCH_TIME);
         while let Some(results) = s.next().await {
             let mut proxies = vec![];
             for r in results {
@@ -208,6 +226,7 @@ impl<P: ProxyFailureRetriever, H: ProxyFailureHandler> FailureHandler for ParFai
         Self {
             proxy_failure_retriever,
             handler: Arc::new(handler),
+            concurrency: DEFAULT_FAILURE_HANDLER_CONCURRENCY,
         }
     }
 
@@ -256,11 +275,17 @@ pub struct ProxyMetaRespSynchronizer<
     proxy_retriever: PRetriever,
     meta_retriever: Arc<MRetriever>,
     sender: Arc<Sender>,
+    concurrency: usize,
 }
 
 impl<P: ProxiesRetriever, M: ProxyMetaRetriever, S: ProxyMetaSender>
     ProxyMetaRespSynchronizer<P, M, S>
 {
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = cmp::max(concurrency, 1);
+        self
+    }
+
     async fn retrieve_and_send_meta(
         meta_retriever: &M,
         sender: &S,
@@ -281,14 +306,13 @@ impl<P: ProxiesRetriever, M: ProxyMetaRetriever, S: ProxyMetaSender>
     async fn run_impl(&self) -> Result<(), CoordinateError> {
         let meta_retriever = self.meta_retriever.clone();
         let sender = self.sender.clone();
-        const BATCH_SIZE: usize = 10;
         const BATCH_TIME: Duration = Duration::from_millis(1);
 
         let mut res = Ok(());
         let mut s = self
             .proxy_retriever
             .retrieve_proxies()
-            .chunks_timeout(BATCH_SIZE, BATCH_TIME);
+            .chunks_timeout(self.concurrency, BATCH_TIME);
         while let Some(results) = s.next().await {
             let mut proxies = vec![];
             for r in results {
@@ -332,6 +356,7 @@ impl<P: ProxiesRetriever, M: ProxyMetaRetriever, S: ProxyMetaSender> ProxyMetaSy
             proxy_retriever,
             meta_retriever: Arc::new(meta_retriever),
             sender: Arc::new(sender),
+            concurrency: DEFAULT_HOST_SYNC_CONCURRENCY,
         }
     }
 
@@ -349,6 +374,11 @@ pub trait MigrationStateChecker: Sync + Send + 'static {
         &'s self,
         address: String,
     ) -> Pin<Box<dyn Stream<Item = Result<MigrationTaskMeta, CoordinateError>> + Send + 's>>;
+    // The tasks which are still running or being aborted.
+    fn get_active_tasks<'s>(
+        &'s self,
+        address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<MigrationTaskMeta>, CoordinateError>> + Send + 's>>;
 }
 
 pub trait MigrationCommitter: Sync + Send + 'static {
@@ -356,6 +386,11 @@ pub trait MigrationCommitter: Sync + Send + 'static {
         &'s self,
         meta: MigrationTaskMeta,
     ) -> Pin<Box<dyn Future<Output = Result<(), CoordinateError>> + Send + 's>>;
+    fn commit_rollback<'s>(
+        &'s self,
+        proxy_address: String,
+        meta: MigrationTaskMeta,
+    ) -> Pin<Box<dyn Future<Output = Result<(), CoordinateError>> + Send + 's>>;
 }
 
 pub trait MigrationStateSynchronizer: Sync + Send + 'static {
@@ -387,6 +422,7 @@ pub struct ParMigrationStateSynchronizer<
     committer: Arc<MC>,
     meta_retriever: Arc<MR>,
     sender: Arc<S>,
+    concurrency: usize,
 }
 
 impl<
@@ -397,6 +433,11 @@ impl<
         S: ProxyMetaSender,
     > ParMigrationStateSynchronizer<PR, SC, MC, MR, S>
 {
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = cmp::max(concurrency, 1);
+        self
+    }
+
     async fn set_db_meta(
         address: String,
         meta_retriever: &MR,
@@ -443,6 +484,41 @@ impl<
         Ok(())
     }
 
+    // Abort the canceled migration tasks inside the proxy before committing the rollback.
+    // The rollback is only committed after the proxy has completely stopped the task,
+    // and the proxy keeps running the tasks which have already switched the slots.
+    async fn rollback_migrations(
+        checker: &SC,
+        commiter: &MC,
+        meta_retriever: &MR,
+        sender: &S,
+        address: String,
+    ) -> Result<(), CoordinateError> {
+        let host = match meta_retriever.get_host_meta(address.clone()).await? {
+            Some(host) => host,
+            None => return Ok(()),
+        };
+        let canceling_migrations = host.get_canceling_migrations().to_vec();
+        if canceling_migrations.is_empty() {
+            return Ok(());
+        }
+
+        info!("sending meta to abort canceled migrations {}", address);
+        sender.send_meta(host).await?;
+        let active_tasks = checker.get_active_tasks(address.clone()).await?;
+        for meta in canceling_migrations.into_iter() {
+            if active_tasks.iter().any(|task| is_same_task(task, &meta)) {
+                info!("wait for the migration task to be aborted {:?}", meta);
+                continue;
+            }
+            if let Err(err) = commiter.commit_rollback(address.clone(), meta).await {
+                error!("failed to commit migration rollback: {:?}", err);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
     async fn check_and_sync(
         checker: &SC,
         commiter: &MC,
@@ -450,6 +526,9 @@ impl<
         sender: &S,
         address: String,
     ) -> Result<(), CoordinateError> {
+        Self::rollback_migrations(checker, commiter, meta_retriever, sender, address.clone())
+            .await?;
+
         let mut s = checker.check(address);
         while let Some(res) = s.next().await {
             let meta = match res {
@@ -467,14 +546,13 @@ impl<
         let meta_retriever = self.meta_retriever.clone();
         let sender = self.sender.clone();
 
-        const CHUNK_SIZE: usize = 10;
         const BATCH_SIZE: Duration = Duration::from_millis(1);
 
         let mut res = Ok(());
         let mut s = self
             .proxy_retriever
             .retrieve_proxies()
-            .chunks_timeout(CHUNK_SIZE, BATCH_SIZE);
+            .chunks_timeout(self.concurrency, BATCH_SIZE);
         while let Some(results) = s.next().await {
             let mut proxies = vec![];
             for r in results {
@@ -531,6 +609,7 @@ impl<
             committer: Arc::new(committer),
             meta_retriever: Arc::new(meta_retriever),
             sender: Arc::new(sender),
+            concurrency: DEFAULT_MIGRATION_SYNC_CONCURRENCY,
         }
     }
 
@@ -543,6 +622,14 @@ impl<
     }
 }
 
+// The importing tasks in the destination proxy have different tags.
+fn is_same_task(task: &MigrationTaskMeta, other: &MigrationTaskMeta) -> bool {
+    task.db_name == other.db_name
+        && task.slot_range.start == other.slot_range.start
+        && task.slot_range.end == other.slot_range.end
+        && task.slot_range.tag.get_migration_meta() == other.slot_range.tag.get_migration_meta()
+}
+
 #[derive(Debug)]
 pub enum CoordinateError {
     Io(io::Error),
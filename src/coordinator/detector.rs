@@ -1,14 +1,17 @@
+use super::accrual::PhiAccrualDetector;
 use super::broker::MetaDataBroker;
 use super::core::{CoordinateError, FailureChecker, FailureReporter, ProxiesRetriever};
-use crate::common::cluster::Cluster;
-use crate::protocol::{RedisClient, RedisClientFactory};
+use crate::common::cluster::{Cluster, MetaChanges, Role};
+use crate::common::utils::strip_prefix;
+use crate::protocol::{BulkStr, RedisClient, RedisClientFactory, Resp};
 use futures::{future, stream, Future, FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt};
 use futures_batch::ChunksTimeoutStreamExt;
 use std::cmp;
 use std::collections::HashSet;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub struct BrokerProxiesRetriever<B: MetaDataBroker> {
     meta_data_broker: Arc<B>,
@@ -130,43 +133,51 @@ impl<B: MetaDataBroker> BrokerOrderedProxiesRetriever<B> {
                     }
                 };
 
-                let mut nodes = cluster.get_nodes().to_vec();
-                // The order is:
-                // nodes with slots including migrating slots < nodes without slots < nodes with importing slots
-                nodes.sort_unstable_by(|a, b| {
-                    for slot_range in a.get_slots() {
-                        if slot_range.tag.is_importing() {
-                            return cmp::Ordering::Less;
-                        }
-                    }
-                    for slot_range in b.get_slots() {
-                        if slot_range.tag.is_importing() {
-                            return cmp::Ordering::Greater;
-                        }
-                    }
-                    if a.get_slots().is_empty() {
-                        return cmp::Ordering::Less;
-                    }
-                    if b.get_slots().is_empty() {
-                        return cmp::Ordering::Greater;
-                    }
-                    cmp::Ordering::Equal
-                });
-
-                for node in &nodes {
-                    let proxy_address = node.get_proxy_address().to_string();
-                    if added_tag.contains(&proxy_address) {
-                        continue;
-                    }
-                    added_tag.insert(proxy_address.clone());
-                    res.push(Ok(proxy_address));
-                }
+                append_ordered_proxies(&cluster, &mut res, &mut added_tag);
             }
         }
         (res, added_tag)
     }
 }
 
+fn append_ordered_proxies(
+    cluster: &Cluster,
+    res: &mut Vec<Result<String, CoordinateError>>,
+    added_tag: &mut HashSet<String>,
+) {
+    let mut nodes = cluster.get_nodes().to_vec();
+    // The order is:
+    // nodes with slots including migrating slots < nodes without slots < nodes with importing slots
+    nodes.sort_unstable_by(|a, b| {
+        for slot_range in a.get_slots() {
+            if slot_range.tag.is_importing() {
+                return cmp::Ordering::Less;
+            }
+        }
+        for slot_range in b.get_slots() {
+            if slot_range.tag.is_importing() {
+                return cmp::Ordering::Greater;
+            }
+        }
+        if a.get_slots().is_empty() {
+            return cmp::Ordering::Less;
+        }
+        if b.get_slots().is_empty() {
+            return cmp::Ordering::Greater;
+        }
+        cmp::Ordering::Equal
+    });
+
+    for node in &nodes {
+        let proxy_address = node.get_proxy_address().to_string();
+        if added_tag.contains(&proxy_address) {
+            continue;
+        }
+        added_tag.insert(proxy_address.clone());
+        res.push(Ok(proxy_address));
+    }
+}
+
 impl<B: MetaDataBroker> ProxiesRetriever for BrokerOrderedProxiesRetriever<B> {
     fn retrieve_proxies<'s>(
         &'s self,
@@ -179,6 +190,94 @@ impl<B: MetaDataBroker> ProxiesRetriever for BrokerOrderedProxiesRetriever<B> {
     }
 }
 
+// Only retrieves the proxies whose metadata could have changed after `since_epoch`
+// in the same order as BrokerOrderedProxiesRetriever.
+// Falls back to all the proxies if the broker fails to return the changes.
+pub struct BrokerChangedProxiesRetriever<B: MetaDataBroker> {
+    meta_data_broker: Arc<B>,
+    since_epoch: u64,
+    timeout: Duration,
+    // Will be set to the epoch of the retrieved changes.
+    latest_epoch: Arc<AtomicU64>,
+}
+
+impl<B: MetaDataBroker> BrokerChangedProxiesRetriever<B> {
+    pub fn new(
+        meta_data_broker: Arc<B>,
+        since_epoch: u64,
+        timeout: Duration,
+        latest_epoch: Arc<AtomicU64>,
+    ) -> Self {
+        Self {
+            meta_data_broker,
+            since_epoch,
+            timeout,
+            latest_epoch,
+        }
+    }
+
+    async fn get_changed_proxies(&self) -> Vec<Result<String, CoordinateError>> {
+        let changes = match self
+            .meta_data_broker
+            .get_changes(self.since_epoch, self.timeout)
+            .await
+        {
+            Ok(changes) => changes,
+            Err(err) => {
+                warn!("failed to get changes, fall back to all proxies: {:?}", err);
+                return BrokerOrderedProxiesRetriever::new(self.meta_data_broker.clone())
+                    .get_ordered_proxies()
+                    .await;
+            }
+        };
+        let MetaChanges {
+            epoch,
+            clusters,
+            proxies,
+        } = changes;
+
+        let mut res = vec![];
+        let mut added_tag = HashSet::new();
+
+        let futs: Vec<_> = clusters
+            .into_iter()
+            .map(|cluster_name| {
+                self.meta_data_broker
+                    .get_cluster(cluster_name)
+                    .map_err(CoordinateError::MetaData)
+            })
+            .collect();
+        for r in future::join_all(futs).await.into_iter() {
+            match r {
+                Ok(Some(cluster)) => append_ordered_proxies(&cluster, &mut res, &mut added_tag),
+                Ok(None) => continue,
+                Err(err) => res.push(Err(err)),
+            }
+        }
+
+        for proxy_address in proxies.into_iter() {
+            if added_tag.insert(proxy_address.clone()) {
+                res.push(Ok(proxy_address));
+            }
+        }
+
+        self.latest_epoch.store(epoch, Ordering::SeqCst);
+        res
+    }
+}
+
+impl<B: MetaDataBroker> ProxiesRetriever for BrokerChangedProxiesRetriever<B> {
+    fn retrieve_proxies<'s>(
+        &'s self,
+    ) -> Pin<Box<dyn Stream<Item = Result<String, CoordinateError>> + Send + 's>> {
+        Box::pin(
+            self.get_changed_proxies()
+                .map(stream::iter)
+                .flatten_stream(),
+        )
+    }
+}
+
 pub struct PingFailureDetector<F: RedisClientFactory> {
     client_factory: Arc<F>,
 }
@@ -231,6 +330,205 @@ impl<F: RedisClientFactory> FailureChecker for PingFailureDetector<F> {
     }
 }
 
+// Only reports the proxy when the phi of its heartbeats exceeds the threshold,
+// so a few failed PINGs caused by GC pauses or network blips will be tolerated.
+pub struct PhiAccrualFailureChecker<F: RedisClientFactory> {
+    ping_detector: PingFailureDetector<F>,
+    detector: Arc<PhiAccrualDetector>,
+}
+
+impl<F: RedisClientFactory> PhiAccrualFailureChecker<F> {
+    pub fn new(client_factory: Arc<F>, detector: Arc<PhiAccrualDetector>) -> Self {
+        Self {
+            ping_detector: PingFailureDetector::new(client_factory),
+            detector,
+        }
+    }
+
+    async fn check_impl(&self, address: String) -> Result<Option<String>, CoordinateError> {
+        if let Ok(None) = self.ping_detector.ping(address.clone()).await {
+            self.detector.heartbeat(&address, Instant::now());
+            return Ok(None);
+        }
+        let now = Instant::now();
+        if self.detector.is_available(&address, now) {
+            return Ok(None);
+        }
+        warn!(
+            "proxy {} is suspected with phi {}",
+            address,
+            self.detector.phi(&address, now)
+        );
+        Ok(Some(address))
+    }
+}
+
+impl<F: RedisClientFactory> FailureChecker for PhiAccrualFailureChecker<F> {
+    fn check<'s>(
+        &'s self,
+        address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<String>, CoordinateError>> + Send + 's>> {
+        Box::pin(self.check_impl(address))
+    }
+}
+
+// The failure detection strategy chosen by the config.
+pub enum ProxyFailureChecker<F: RedisClientFactory> {
+    Ping(PingFailureDetector<F>),
+    PhiAccrual(PhiAccrualFailureChecker<F>),
+}
+
+impl<F: RedisClientFactory> FailureChecker for ProxyFailureChecker<F> {
+    fn check<'s>(
+        &'s self,
+        address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<String>, CoordinateError>> + Send + 's>> {
+        match self {
+            Self::Ping(checker) => checker.check(address),
+            Self::PhiAccrual(checker) => checker.check(address),
+        }
+    }
+}
+
+// Parse the unhealthy nodes from the report of `UMCTL HEALTH`.
+pub fn parse_unhealthy_nodes(report: &str) -> Vec<String> {
+    report
+        .split("\n\n")
+        .filter(|section| section.lines().any(|line| line.trim() == "healthy:false"))
+        .filter_map(|section| {
+            section
+                .lines()
+                .find_map(|line| strip_prefix(line.trim(), "node_address:"))
+                .map(|address| address.to_string())
+        })
+        .collect()
+}
+
+// The server proxy replies PING by itself, so the failures of its Redis nodes
+// could only be found in the health report of the backends.
+// The unhealthy masters are reported to the broker like the failed proxies
+// and failed over by the failure handler while the whole proxy still stays in use.
+pub struct BackendHealthChecker<
+    C: FailureChecker,
+    F: RedisClientFactory,
+    B: MetaDataBroker,
+    R: FailureReporter,
+> {
+    checker: C,
+    client_factory: Arc<F>,
+    meta_data_broker: Arc<B>,
+    reporter: R,
+}
+
+impl<C: FailureChecker, F: RedisClientFactory, B: MetaDataBroker, R: FailureReporter>
+    BackendHealthChecker<C, F, B, R>
+{
+    pub fn new(checker: C, client_factory: Arc<F>, meta_data_broker: Arc<B>, reporter: R) -> Self {
+        Self {
+            checker,
+            client_factory,
+            meta_data_broker,
+            reporter,
+        }
+    }
+
+    async fn get_unhealthy_nodes(&self, address: String) -> Result<Vec<String>, CoordinateError> {
+        let mut client = self
+            .client_factory
+            .create_client(address)
+            .await
+            .map_err(CoordinateError::Redis)?;
+        let cmd = vec![b"UMCTL".to_vec(), b"HEALTH".to_vec()];
+        match client
+            .execute_single(cmd)
+            .await
+            .map_err(CoordinateError::Redis)?
+        {
+            Resp::Bulk(BulkStr::Str(report)) => {
+                Ok(parse_unhealthy_nodes(&String::from_utf8_lossy(&report)))
+            }
+            other => {
+                error!("invalid reply of UMCTL HEALTH: {:?}", other);
+                Err(CoordinateError::InvalidReply)
+            }
+        }
+    }
+
+    // Only the masters serving slots need failover.
+    // The replicas and the demoted masters are skipped.
+    async fn get_failover_nodes(
+        &self,
+        address: String,
+        unhealthy_nodes: Vec<String>,
+    ) -> Result<Vec<String>, CoordinateError> {
+        let proxy = match self
+            .meta_data_broker
+            .get_host(address)
+            .await
+            .map_err(CoordinateError::MetaData)?
+        {
+            Some(proxy) => proxy,
+            None => return Ok(vec![]),
+        };
+        let masters: HashSet<&str> = proxy
+            .get_nodes()
+            .iter()
+            .filter(|node| node.get_role() == Role::Master && !node.get_slots().is_empty())
+            .map(|node| node.get_address())
+            .collect();
+        Ok(unhealthy_nodes
+            .into_iter()
+            .filter(|node_address| masters.contains(node_address.as_str()))
+            .collect())
+    }
+
+    async fn check_impl(&self, address: String) -> Result<Option<String>, CoordinateError> {
+        if let Some(failed_address) = self.checker.check(address.clone()).await? {
+            return Ok(Some(failed_address));
+        }
+
+        let nodes = match self.get_unhealthy_nodes(address.clone()).await {
+            Ok(nodes) => nodes,
+            Err(err) => {
+                warn!("failed to get backend health of {}: {:?}", address, err);
+                return Ok(None);
+            }
+        };
+        if nodes.is_empty() {
+            return Ok(None);
+        }
+
+        let nodes = match self.get_failover_nodes(address.clone(), nodes).await {
+            Ok(nodes) => nodes,
+            Err(err) => {
+                warn!("failed to get the nodes of {}: {:?}", address, err);
+                return Ok(None);
+            }
+        };
+        for node_address in nodes.into_iter() {
+            info!("report unhealthy node {} in {}", node_address, address);
+            if let Err(err) = self.reporter.report(node_address.clone()).await {
+                error!(
+                    "failed to report unhealthy node {} in {}: {:?}",
+                    node_address, address, err
+                );
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<C: FailureChecker, F: RedisClientFactory, B: MetaDataBroker, R: FailureReporter> FailureChecker
+    for BackendHealthChecker<C, F, B, R>
+{
+    fn check<'s>(
+        &'s self,
+        address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<String>, CoordinateError>> + Send + 's>> {
+        Box::pin(self.check_impl(address))
+    }
+}
+
 pub struct BrokerFailureReporter<B: MetaDataBroker> {
     reporter_id: String,
     meta_data_broker: Arc<B>,
@@ -264,19 +562,22 @@ mod tests {
     use super::super::core::{FailureDetector, ParFailureDetector};
     use super::*;
     use crate::common::cluster::{
-        DBName, MigrationMeta, Node, ReplMeta, Role, SlotRange, SlotRangeTag,
+        DBName, MigrationMeta, Node, Proxy, ReplMeta, Role, SlotRange, SlotRangeTag,
     };
     use crate::common::config::ClusterConfig;
     use crate::protocol::{
         Array, BinSafeStr, OptionalMulti, RedisClient, RedisClientError, Resp, RespVec,
     };
     use futures::{future, stream, StreamExt};
+    use std::collections::HashMap;
     use std::pin::Pin;
     use std::sync::Arc;
     use tokio;
 
     const NODE1: &'static str = "127.0.0.1:7000";
     const NODE2: &'static str = "127.0.0.1:7001";
+    const NODE3: &str = "127.0.0.1:7002";
+    const NODE4: &str = "127.0.0.1:7003";
 
     #[derive(Debug)]
     struct DummyClient {
@@ -446,6 +747,94 @@ mod tests {
         assert_eq!(addrs[4], "host5:port5");
     }
 
+    #[tokio::test]
+    async fn test_changed_proxy_retriever() {
+        let mut mock_broker = MockMetaDataBroker::new();
+        mock_broker
+            .expect_get_changes()
+            .withf(|since_epoch: &u64, _| *since_epoch == 3)
+            .returning(|_, _| {
+                Box::pin(future::ok(MetaChanges {
+                    epoch: 7,
+                    clusters: vec![DBName::from("dybdb").unwrap()],
+                    proxies: vec!["host1:port1".to_string(), "host3:port3".to_string()],
+                }))
+            });
+        let nodes = vec![
+            Node::new(
+                "redis1:port1".to_string(),
+                "host1:port1".to_string(),
+                DBName::from("dybdb").unwrap(),
+                vec![SlotRange {
+                    start: 0,
+                    end: 233,
+                    tag: SlotRangeTag::None,
+                }],
+                ReplMeta::new(Role::Master, Vec::new()),
+            ),
+            Node::new(
+                "redis2:port2".to_string(),
+                "host2:port2".to_string(),
+                DBName::from("dybdb").unwrap(),
+                vec![],
+                ReplMeta::new(Role::Master, Vec::new()),
+            ),
+        ];
+        mock_broker
+            .expect_get_cluster()
+            .returning(move |cluster_name| {
+                Box::pin(future::ok(Some(Cluster::new(
+                    cluster_name,
+                    7,
+                    nodes.clone(),
+                    ClusterConfig::default(),
+                ))))
+            });
+
+        let latest_epoch = Arc::new(AtomicU64::new(3));
+        let retriever = BrokerChangedProxiesRetriever::new(
+            Arc::new(mock_broker),
+            3,
+            Duration::from_secs(1),
+            latest_epoch.clone(),
+        );
+        let addrs: Vec<Result<String, CoordinateError>> =
+            retriever.retrieve_proxies().collect().await;
+        let addrs: Vec<String> = addrs.into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(addrs, vec!["host2:port2", "host1:port1", "host3:port3"]);
+        assert_eq!(latest_epoch.load(Ordering::SeqCst), 7);
+    }
+
+    #[tokio::test]
+    async fn test_changed_proxy_retriever_fallback() {
+        let mut mock_broker = MockMetaDataBroker::new();
+        mock_broker
+            .expect_get_changes()
+            .returning(|_, _| Box::pin(future::err(MetaDataBrokerError::InvalidReply)));
+        mock_broker
+            .expect_get_cluster_names()
+            .returning(|| Box::pin(stream::iter(vec![])));
+        mock_broker.expect_get_host_addresses().returning(|| {
+            Box::pin(stream::iter(vec![
+                Ok(NODE1.to_string()),
+                Ok(NODE2.to_string()),
+            ]))
+        });
+
+        let latest_epoch = Arc::new(AtomicU64::new(3));
+        let retriever = BrokerChangedProxiesRetriever::new(
+            Arc::new(mock_broker),
+            3,
+            Duration::from_secs(1),
+            latest_epoch.clone(),
+        );
+        let addrs: Vec<Result<String, CoordinateError>> =
+            retriever.retrieve_proxies().collect().await;
+        let addrs: Vec<String> = addrs.into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(addrs, vec![NODE1, NODE2]);
+        assert_eq!(latest_epoch.load(Ordering::SeqCst), 3);
+    }
+
     #[tokio::test]
     async fn test_failure_detector() {
         let mut mock_broker = MockMetaDataBroker::new();
@@ -540,4 +929,110 @@ mod tests {
         let res = detector.run().into_future().await;
         assert!(res.is_err());
     }
+
+    #[test]
+    fn test_parse_unhealthy_nodes() {
+        let report = "node_address:127.0.0.1:7000\nhealthy:true\nconn_failures:0\nclosed:false\nping_failures:0\nping_latency_us:100\n\n\
+            node_address:127.0.0.1:7001\nhealthy:false\nconn_failures:3\nclosed:false\nping_failures:3\nping_latency_us:-1\n\n";
+        assert_eq!(
+            parse_unhealthy_nodes(report),
+            vec!["127.0.0.1:7001".to_string()]
+        );
+        assert!(parse_unhealthy_nodes("").is_empty());
+    }
+
+    #[derive(Debug)]
+    struct HealthReportClient;
+
+    impl RedisClient for HealthReportClient {
+        fn execute<'s>(
+            &'s mut self,
+            _command: OptionalMulti<Vec<BinSafeStr>>,
+        ) -> Pin<
+            Box<dyn Future<Output = Result<OptionalMulti<RespVec>, RedisClientError>> + Send + 's>,
+        > {
+            let report = format!(
+                "node_address:{}\nhealthy:true\n\nnode_address:{}\nhealthy:false\n\n\
+                 node_address:{}\nhealthy:false\n\nnode_address:{}\nhealthy:false\n\n",
+                NODE1, NODE2, NODE3, NODE4
+            );
+            Box::pin(future::ok(OptionalMulti::Single(Resp::Bulk(BulkStr::Str(
+                report.into_bytes(),
+            )))))
+        }
+    }
+
+    struct HealthReportClientFactory;
+
+    impl RedisClientFactory for HealthReportClientFactory {
+        type Client = HealthReportClient;
+
+        fn create_client(
+            &self,
+            _address: String,
+        ) -> Pin<Box<dyn Future<Output = Result<Self::Client, RedisClientError>> + Send>> {
+            Box::pin(future::ok(HealthReportClient))
+        }
+    }
+
+    fn gen_health_report_proxy() -> Proxy {
+        let db_name = DBName::from("mydb").unwrap();
+        let gen_node = |address: &str, slots: Vec<SlotRange>, role: Role| {
+            Node::new(
+                address.to_string(),
+                "127.0.0.1:6000".to_string(),
+                db_name.clone(),
+                slots,
+                ReplMeta::new(role, Vec::new()),
+            )
+        };
+        let slots = vec![SlotRange {
+            start: 0,
+            end: 100,
+            tag: SlotRangeTag::None,
+        }];
+        Proxy::new(
+            "127.0.0.1:6000".to_string(),
+            1,
+            vec![
+                gen_node(NODE1, slots.clone(), Role::Master),
+                gen_node(NODE2, slots, Role::Master),
+                gen_node(NODE3, vec![], Role::Replica),
+                gen_node(NODE4, vec![], Role::Master),
+            ],
+            vec![],
+            vec![],
+            HashMap::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_backend_health_checker() {
+        let mut mock_broker = MockMetaDataBroker::new();
+        mock_broker
+            .expect_get_host()
+            .withf(|address: &String| address == "127.0.0.1:6000")
+            .times(1)
+            .returning(|_| Box::pin(future::ok(Some(gen_health_report_proxy()))));
+        // Only the unhealthy master with slots is reported.
+        mock_broker
+            .expect_add_failure()
+            .withf(|address: &String, reporter_id: &String| {
+                address == NODE2 && reporter_id == "test_id"
+            })
+            .times(1)
+            .returning(|_, _| Box::pin(future::ok(())));
+        let broker = Arc::new(mock_broker);
+
+        let client_factory = Arc::new(HealthReportClientFactory);
+        let checker = BackendHealthChecker::new(
+            PingFailureDetector::new(client_factory.clone()),
+            client_factory,
+            broker.clone(),
+            BrokerFailureReporter::new("test_id".to_string(), broker),
+        );
+        // The proxy itself is still alive.
+        let res = checker.check("127.0.0.1:6000".to_string()).await.unwrap();
+        assert!(res.is_none());
+    }
 }
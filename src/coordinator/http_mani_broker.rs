@@ -1,5 +1,5 @@
 use super::broker::{MetaManipulationBroker, MetaManipulationBrokerError};
-use crate::common::cluster::{MigrationTaskMeta, Proxy};
+use crate::common::cluster::{MigrationTaskMeta, Node, NodeFailover, Proxy};
 use futures::Future;
 use reqwest;
 use std::pin::Pin;
@@ -8,13 +8,25 @@ use std::pin::Pin;
 pub struct HttpMetaManipulationBroker {
     broker_address: String,
     client: reqwest::Client,
+    // Sent as the bearer token if it's not empty.
+    auth_token: String,
 }
 
 impl HttpMetaManipulationBroker {
-    pub fn new(broker_address: String, client: reqwest::Client) -> Self {
+    pub fn new(broker_address: String, client: reqwest::Client, auth_token: String) -> Self {
         HttpMetaManipulationBroker {
             broker_address,
             client,
+            auth_token,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let builder = self.client.request(method, url);
+        if self.auth_token.is_empty() {
+            builder
+        } else {
+            builder.bearer_auth(&self.auth_token)
         }
     }
 }
@@ -28,10 +40,14 @@ impl HttpMetaManipulationBroker {
             "http://{}/api/proxies/failover/{}",
             self.broker_address, failed_proxy_address
         );
-        let response = self.client.post(&url).send().await.map_err(|e| {
-            error!("Failed to replace proxy {:?}", e);
-            MetaManipulationBrokerError::InvalidReply
-        })?;
+        let response = self
+            .request(reqwest::Method::POST, &url)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to replace proxy {:?}", e);
+                MetaManipulationBrokerError::InvalidReply
+            })?;
 
         let status = response.status();
 
@@ -59,6 +75,85 @@ impl HttpMetaManipulationBroker {
         }
     }
 
+    async fn failover_node_impl(
+        &self,
+        failed_node_address: String,
+    ) -> Result<NodeFailover, MetaManipulationBrokerError> {
+        let url = format!(
+            "http://{}/api/nodes/failover/{}",
+            self.broker_address, failed_node_address
+        );
+        let response = self
+            .request(reqwest::Method::POST, &url)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to failover node {:?}", e);
+                MetaManipulationBrokerError::InvalidReply
+            })?;
+
+        let status = response.status();
+
+        if status.is_success() {
+            response.json().await.map_err(|e| {
+                error!("Failed to get json payload {:?}", e);
+                MetaManipulationBrokerError::InvalidReply
+            })
+        } else {
+            error!(
+                "failover_node: Failed to failover node: status code {:?}",
+                status
+            );
+            match response.text().await {
+                Ok(body) => error!("failover_node: Error body: {:?}", body),
+                Err(e) => error!("failover_node: Failed to get body: {:?}", e),
+            }
+            Err(MetaManipulationBrokerError::InvalidReply)
+        }
+    }
+
+    async fn add_replica_impl(
+        &self,
+        cluster_name: String,
+        master_node_address: String,
+    ) -> Result<Node, MetaManipulationBrokerError> {
+        let url = format!(
+            "http://{}/api/clusters/{}/replications/{}",
+            self.broker_address, cluster_name, master_node_address
+        );
+        let response = self
+            .request(reqwest::Method::POST, &url)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to add replica {:?}", e);
+                MetaManipulationBrokerError::InvalidReply
+            })?;
+
+        let status = response.status();
+
+        if status.is_success() {
+            response.json().await.map_err(|e| {
+                error!("Failed to get json payload {:?}", e);
+                MetaManipulationBrokerError::InvalidReply
+            })
+        } else {
+            error!(
+                "add_replica: Failed to add replica: status code {:?}",
+                status
+            );
+            match response.text().await {
+                Ok(body) => error!("add_replica: Error body: {:?}", body),
+                Err(e) => error!("add_replica: Failed to get body: {:?}", e),
+            }
+            if status == reqwest::StatusCode::CONFLICT {
+                Err(MetaManipulationBrokerError::ResourceNotAvailable)
+            } else {
+                Err(MetaManipulationBrokerError::InvalidReply)
+            }
+        }
+    }
+
     async fn commit_migration_impl(
         &self,
         meta: MigrationTaskMeta,
@@ -66,8 +161,7 @@ impl HttpMetaManipulationBroker {
         let url = format!("http://{}/api/clusters/migrations", self.broker_address);
 
         let response = self
-            .client
-            .put(&url)
+            .request(reqwest::Method::PUT, &url)
             .json(&meta)
             .send()
             .await
@@ -101,6 +195,52 @@ impl HttpMetaManipulationBroker {
             }
         }
     }
+
+    async fn ack_migration_rollback_impl(
+        &self,
+        proxy_address: String,
+        meta: MigrationTaskMeta,
+    ) -> Result<(), MetaManipulationBrokerError> {
+        let url = format!(
+            "http://{}/api/clusters/migrations/rollback/{}",
+            self.broker_address, proxy_address
+        );
+
+        let response = self
+            .request(reqwest::Method::PUT, &url)
+            .json(&meta)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to ack migration rollback {:?}", e);
+                MetaManipulationBrokerError::InvalidReply
+            })?;
+
+        let status = response.status();
+
+        if status.is_success() {
+            Ok(())
+        } else {
+            error!("Failed to ack migration rollback status code {:?}", status);
+            let result = response.text().await;
+            match result {
+                Ok(body) => {
+                    error!(
+                        "HttpMetaManipulationBroker::ack_migration_rollback Error body: {:?}",
+                        body
+                    );
+                    Err(MetaManipulationBrokerError::InvalidReply)
+                }
+                Err(e) => {
+                    error!(
+                        "HttpMetaManipulationBroker::ack_migration_rollback Failed to get body: {:?}",
+                        e
+                    );
+                    Err(MetaManipulationBrokerError::InvalidReply)
+                }
+            }
+        }
+    }
 }
 
 impl MetaManipulationBroker for HttpMetaManipulationBroker {
@@ -111,10 +251,34 @@ impl MetaManipulationBroker for HttpMetaManipulationBroker {
         Box::pin(self.replace_proxy_impl(failed_proxy_address))
     }
 
+    fn failover_node<'s>(
+        &'s self,
+        failed_node_address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<NodeFailover, MetaManipulationBrokerError>> + Send + 's>>
+    {
+        Box::pin(self.failover_node_impl(failed_node_address))
+    }
+
+    fn add_replica<'s>(
+        &'s self,
+        cluster_name: String,
+        master_node_address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<Node, MetaManipulationBrokerError>> + Send + 's>> {
+        Box::pin(self.add_replica_impl(cluster_name, master_node_address))
+    }
+
     fn commit_migration<'s>(
         &'s self,
         meta: MigrationTaskMeta,
     ) -> Pin<Box<dyn Future<Output = Result<(), MetaManipulationBrokerError>> + Send + 's>> {
         Box::pin(self.commit_migration_impl(meta))
     }
+
+    fn ack_migration_rollback<'s>(
+        &'s self,
+        proxy_address: String,
+        meta: MigrationTaskMeta,
+    ) -> Pin<Box<dyn Future<Output = Result<(), MetaManipulationBrokerError>> + Send + 's>> {
+        Box::pin(self.ack_migration_rollback_impl(proxy_address, meta))
+    }
 }
@@ -1,22 +1,35 @@
 use super::broker::{MetaDataBroker, MetaDataBrokerError};
-use crate::common::cluster::{Cluster, DBName, Proxy};
+use crate::common::cluster::{Cluster, DBName, MetaChanges, Proxy};
 use crate::common::utils::vec_result_to_stream;
 use futures::{Future, FutureExt, Stream};
 use reqwest;
 use serde_derive::Deserialize;
 use std::pin::Pin;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct HttpMetaBroker {
     broker_address: String,
     client: reqwest::Client,
+    // Sent as the bearer token if it's not empty.
+    auth_token: String,
 }
 
 impl HttpMetaBroker {
-    pub fn new(broker_address: String, client: reqwest::Client) -> Self {
+    pub fn new(broker_address: String, client: reqwest::Client, auth_token: String) -> Self {
         HttpMetaBroker {
             broker_address,
             client,
+            auth_token,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let builder = self.client.request(method, url);
+        if self.auth_token.is_empty() {
+            builder
+        } else {
+            builder.bearer_auth(&self.auth_token)
         }
     }
 }
@@ -24,10 +37,14 @@ impl HttpMetaBroker {
 impl HttpMetaBroker {
     async fn get_cluster_names_impl(&self) -> Result<Vec<DBName>, MetaDataBrokerError> {
         let url = format!("http://{}/api/clusters/names", self.broker_address);
-        let response = self.client.get(&url).send().await.map_err(|e| {
-            error!("failed to get cluster names {:?}", e);
-            MetaDataBrokerError::InvalidReply
-        })?;
+        let response = self
+            .request(reqwest::Method::GET, &url)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("failed to get cluster names {:?}", e);
+                MetaDataBrokerError::InvalidReply
+            })?;
         let ClusterNamesPayload { names } = response.json().await.map_err(|e| {
             error!("failed to get cluster names from json {:?}", e);
             MetaDataBrokerError::InvalidReply
@@ -37,10 +54,14 @@ impl HttpMetaBroker {
 
     async fn get_cluster_impl(&self, name: DBName) -> Result<Option<Cluster>, MetaDataBrokerError> {
         let url = format!("http://{}/api/clusters/meta/{}", self.broker_address, name);
-        let response = self.client.get(&url).send().await.map_err(|e| {
-            error!("failed to get cluster {:?}", e);
-            MetaDataBrokerError::InvalidReply
-        })?;
+        let response = self
+            .request(reqwest::Method::GET, &url)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("failed to get cluster {:?}", e);
+                MetaDataBrokerError::InvalidReply
+            })?;
         let ClusterPayload { cluster } = response.json().await.map_err(|e| {
             error!("failed to get cluster from json {:?}", e);
             MetaDataBrokerError::InvalidReply
@@ -50,10 +71,14 @@ impl HttpMetaBroker {
 
     async fn get_host_addresses_impl(&self) -> Result<Vec<String>, MetaDataBrokerError> {
         let url = format!("http://{}/api/proxies/addresses", self.broker_address);
-        let response = self.client.get(&url).send().await.map_err(|e| {
-            error!("failed to get host addresses {:?}", e);
-            MetaDataBrokerError::InvalidReply
-        })?;
+        let response = self
+            .request(reqwest::Method::GET, &url)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("failed to get host addresses {:?}", e);
+                MetaDataBrokerError::InvalidReply
+            })?;
         let ProxyAddressesPayload { addresses } = response.json().await.map_err(|e| {
             error!("failed to get host adddresses from json {:?}", e);
             MetaDataBrokerError::InvalidReply
@@ -66,10 +91,14 @@ impl HttpMetaBroker {
             "http://{}/api/proxies/meta/{}",
             self.broker_address, address
         );
-        let response = self.client.get(&url).send().await.map_err(|e| {
-            error!("failed to get host {:?}", e);
-            MetaDataBrokerError::InvalidReply
-        })?;
+        let response = self
+            .request(reqwest::Method::GET, &url)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("failed to get host {:?}", e);
+                MetaDataBrokerError::InvalidReply
+            })?;
         let ProxyPayload { host } = response.json().await.map_err(move |e| {
             error!("failed to get host {} from json {:?}", address, e);
             MetaDataBrokerError::InvalidReply
@@ -86,10 +115,14 @@ impl HttpMetaBroker {
             "http://{}/api/failures/{}/{}",
             self.broker_address, address, reporter_id
         );
-        let response = self.client.post(&url).send().await.map_err(|e| {
-            error!("failed to add failures {:?}", e);
-            MetaDataBrokerError::InvalidReply
-        })?;
+        let response = self
+            .request(reqwest::Method::POST, &url)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("failed to add failures {:?}", e);
+                MetaDataBrokerError::InvalidReply
+            })?;
         let status = response.status();
         if status.is_success() {
             Ok(())
@@ -110,16 +143,46 @@ impl HttpMetaBroker {
 
     async fn get_failures_impl(&self) -> Result<Vec<String>, MetaDataBrokerError> {
         let url = format!("http://{}/api/failures", self.broker_address);
-        let response = self.client.get(&url).send().await.map_err(|e| {
-            error!("Failed to get failures {:?}", e);
-            MetaDataBrokerError::InvalidReply
-        })?;
+        let response = self
+            .request(reqwest::Method::GET, &url)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to get failures {:?}", e);
+                MetaDataBrokerError::InvalidReply
+            })?;
         let FailuresPayload { addresses } = response.json().await.map_err(|e| {
             error!("Failed to get cluster names from json {:?}", e);
             MetaDataBrokerError::InvalidReply
         })?;
         Ok(addresses)
     }
+
+    async fn get_changes_impl(
+        &self,
+        since_epoch: u64,
+        timeout: Duration,
+    ) -> Result<MetaChanges, MetaDataBrokerError> {
+        let url = format!(
+            "http://{}/api/changes?since={}&timeout={}",
+            self.broker_address,
+            since_epoch,
+            timeout.as_secs()
+        );
+        let response = self
+            .request(reqwest::Method::GET, &url)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("failed to get changes {:?}", e);
+                MetaDataBrokerError::InvalidReply
+            })?;
+        let changes = response.json().await.map_err(|e| {
+            error!("failed to get changes from json {:?}", e);
+            MetaDataBrokerError::InvalidReply
+        })?;
+        Ok(changes)
+    }
 }
 
 impl MetaDataBroker for HttpMetaBroker {
@@ -175,6 +238,14 @@ impl MetaDataBroker for HttpMetaBroker {
                 .flatten_stream(),
         )
     }
+
+    fn get_changes<'s>(
+        &'s self,
+        since_epoch: u64,
+        timeout: Duration,
+    ) -> Pin<Box<dyn Future<Output = Result<MetaChanges, MetaDataBrokerError>> + Send + 's>> {
+        Box::pin(self.get_changes_impl(since_epoch, timeout))
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -38,13 +38,17 @@ impl<F: RedisClientFactory> MigrationStateRespChecker<F> {
 }
 
 impl<F: RedisClientFactory> MigrationStateRespChecker<F> {
-    async fn check_impl(&self, address: String) -> Result<Vec<MigrationTaskMeta>, CoordinateError> {
+    async fn get_tasks(
+        &self,
+        address: String,
+        sub_command: &str,
+    ) -> Result<Vec<MigrationTaskMeta>, CoordinateError> {
         let mut client = self
             .client_factory
             .create_client(address.clone())
             .await
             .map_err(CoordinateError::Redis)?;
-        let cmd = vec!["UMCTL".to_string(), "INFOMGR".to_string()]
+        let cmd = vec!["UMCTL".to_string(), sub_command.to_string()]
             .into_iter()
             .map(String::into_bytes)
             .collect();
@@ -73,6 +77,10 @@ impl<F: RedisClientFactory> MigrationStateRespChecker<F> {
             }
         }
     }
+
+    async fn check_impl(&self, address: String) -> Result<Vec<MigrationTaskMeta>, CoordinateError> {
+        self.get_tasks(address, "INFOMGR").await
+    }
 }
 
 impl<F: RedisClientFactory> MigrationStateChecker for MigrationStateRespChecker<F> {
@@ -86,6 +94,14 @@ impl<F: RedisClientFactory> MigrationStateChecker for MigrationStateRespChecker<
                 .flatten_stream(),
         )
     }
+
+    fn get_active_tasks<'s>(
+        &'s self,
+        address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<MigrationTaskMeta>, CoordinateError>> + Send + 's>>
+    {
+        Box::pin(self.get_tasks(address, "LISTMGR"))
+    }
 }
 
 pub struct BrokerMigrationCommitter<MB: MetaManipulationBroker> {
@@ -116,6 +132,28 @@ impl<MB: MetaManipulationBroker> MigrationCommitter for BrokerMigrationCommitter
                 }),
         )
     }
+
+    fn commit_rollback<'s>(
+        &'s self,
+        proxy_address: String,
+        meta: MigrationTaskMeta,
+    ) -> Pin<Box<dyn Future<Output = Result<(), CoordinateError>> + Send + 's>> {
+        let meta_clone = meta.clone();
+        Box::pin(
+            self.mani_broker
+                .ack_migration_rollback(proxy_address, meta.clone())
+                .map_err(move |e| {
+                    error!("failed to commit migration rollback {:?} {:?}", meta, e);
+                    CoordinateError::MetaMani(e)
+                })
+                .map_ok(move |()| {
+                    info!(
+                        "successfully commit the migration rollback {:?}",
+                        meta_clone
+                    );
+                }),
+        )
+    }
 }
 
 #[cfg(test)]
@@ -283,4 +321,136 @@ mod tests {
         assert_eq!(res.len(), 1);
         assert!(res[0].is_ok());
     }
+
+    #[tokio::test]
+    async fn test_migration_rollback_sync() {
+        let factory = Arc::new(DummyRedisClientFactory::new(|| {
+            let mut mock_client = MockRedisClient::new();
+            mock_client
+                .expect_execute_single()
+                .returning(|_| Box::pin(async { Ok(Resp::Arr(Array::Arr(vec![]))) }));
+            mock_client
+        }));
+        let checker = MigrationStateRespChecker::new(factory);
+
+        let mut mock_mani_broker = MockMetaManipulationBroker::new();
+        let meta = gen_testing_migration_task_meta();
+        let meta2 = meta.clone();
+        mock_mani_broker.expect_commit_migration().times(0);
+        mock_mani_broker
+            .expect_ack_migration_rollback()
+            .withf(move |proxy_addr, m| proxy_addr == "127.0.0.1:6000" && m == &meta2)
+            .times(1)
+            .returning(move |_, _| Box::pin(async { Ok(()) }));
+        let mock_mani_broker = Arc::new(mock_mani_broker);
+
+        let mut mock_data_broker = MockMetaDataBroker::new();
+        mock_data_broker
+            .expect_get_host_addresses()
+            .returning(move || {
+                let results = vec![Ok("127.0.0.1:6000".to_string())];
+                Box::pin(stream::iter(results))
+            });
+        mock_data_broker
+            .expect_get_host()
+            .withf(|proxy_addr| proxy_addr == "127.0.0.1:6000")
+            .returning(move |_| {
+                let mut proxy = gen_testing_dummy_proxy("127.0.0.1:6000");
+                proxy.set_canceling_migrations(vec![meta.clone()]);
+                Box::pin(async { Ok(Some(proxy)) })
+            });
+        let mock_data_broker = Arc::new(mock_data_broker);
+
+        let proxies_retriever = BrokerProxiesRetriever::new(mock_data_broker.clone());
+        let committer = BrokerMigrationCommitter::new(mock_mani_broker.clone());
+        let meta_retriever = BrokerMetaRetriever::new(mock_data_broker);
+
+        let mut mock_meta_sender = MockProxyMetaSender::new();
+        mock_meta_sender
+            .expect_send_meta()
+            .withf(|proxy| {
+                proxy.get_address() == "127.0.0.1:6000"
+                    && proxy.get_canceling_migrations().len() == 1
+            })
+            .times(1)
+            .returning(|_| Box::pin(async { Ok(()) }));
+
+        let sync = ParMigrationStateSynchronizer::new(
+            proxies_retriever,
+            checker,
+            committer,
+            meta_retriever,
+            mock_meta_sender,
+        );
+        let res: Vec<_> = sync.run().collect().await;
+        assert_eq!(res.len(), 1);
+        assert!(res[0].is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_migration_rollback_wait_for_aborting() {
+        let meta = gen_testing_migration_task_meta();
+        let active_meta = meta.clone();
+        let factory = Arc::new(DummyRedisClientFactory::new(move || {
+            let active_meta = active_meta.clone();
+            let mut mock_client = MockRedisClient::new();
+            let list_mgr_cmd = vec![b"UMCTL".to_vec(), b"LISTMGR".to_vec()];
+            mock_client
+                .expect_execute_single()
+                .withf(move |command: &Vec<BinSafeStr>| command.eq(&list_mgr_cmd))
+                .returning(move |_| {
+                    let task = active_meta.clone().into_strings().join(" ").into_bytes();
+                    let resp = Resp::Arr(Array::Arr(vec![Resp::Bulk(BulkStr::Str(task))]));
+                    Box::pin(async { Ok(resp) })
+                });
+            mock_client
+                .expect_execute_single()
+                .returning(|_| Box::pin(async { Ok(Resp::Arr(Array::Arr(vec![]))) }));
+            mock_client
+        }));
+        let checker = MigrationStateRespChecker::new(factory);
+
+        // The task has not stopped in the proxy.
+        let mut mock_mani_broker = MockMetaManipulationBroker::new();
+        mock_mani_broker.expect_ack_migration_rollback().times(0);
+        let mock_mani_broker = Arc::new(mock_mani_broker);
+
+        let mut mock_data_broker = MockMetaDataBroker::new();
+        mock_data_broker
+            .expect_get_host_addresses()
+            .returning(move || {
+                let results = vec![Ok("127.0.0.1:6000".to_string())];
+                Box::pin(stream::iter(results))
+            });
+        mock_data_broker
+            .expect_get_host()
+            .withf(|proxy_addr| proxy_addr == "127.0.0.1:6000")
+            .returning(move |_| {
+                let mut proxy = gen_testing_dummy_proxy("127.0.0.1:6000");
+                proxy.set_canceling_migrations(vec![meta.clone()]);
+                Box::pin(async { Ok(Some(proxy)) })
+            });
+        let mock_data_broker = Arc::new(mock_data_broker);
+
+        let proxies_retriever = BrokerProxiesRetriever::new(mock_data_broker.clone());
+        let committer = BrokerMigrationCommitter::new(mock_mani_broker.clone());
+        let meta_retriever = BrokerMetaRetriever::new(mock_data_broker);
+
+        let mut mock_meta_sender = MockProxyMetaSender::new();
+        mock_meta_sender
+            .expect_send_meta()
+            .times(1)
+            .returning(|_| Box::pin(async { Ok(()) }));
+
+        let sync = ParMigrationStateSynchronizer::new(
+            proxies_retriever,
+            checker,
+            committer,
+            meta_retriever,
+            mock_meta_sender,
+        );
+        let res: Vec<_> = sync.run().collect().await;
+        assert_eq!(res.len(), 1);
+        assert!(res[0].is_ok());
+    }
 }
@@ -1,11 +1,18 @@
+pub mod accrual;
+pub mod admin;
 // Suppress warning from automock.
 #[allow(clippy::ptr_arg)]
 pub mod broker;
+pub mod config;
 mod core;
 mod detector;
 pub mod http_mani_broker;
 pub mod http_meta_broker;
 mod migration;
 mod recover;
+mod repair;
 pub mod service;
+#[cfg(test)]
+mod simulation;
+pub mod status;
 mod sync;
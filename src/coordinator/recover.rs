@@ -1,6 +1,6 @@
 use super::broker::{MetaDataBroker, MetaManipulationBroker};
 use super::core::{CoordinateError, ProxyFailure, ProxyFailureHandler, ProxyFailureRetriever};
-use futures::{Future, Stream, TryFutureExt, TryStreamExt};
+use futures::{Future, Stream, TryStreamExt};
 use std::pin::Pin;
 use std::sync::Arc;
 
@@ -26,36 +26,76 @@ impl<B: MetaDataBroker> ProxyFailureRetriever for BrokerProxyFailureRetriever<B>
     }
 }
 
-pub struct ReplaceNodeHandler<MB: MetaManipulationBroker> {
+// The failures could be either the failed proxies
+// or the unhealthy nodes reported by `BackendHealthChecker`.
+pub struct ReplaceNodeHandler<B: MetaDataBroker, MB: MetaManipulationBroker> {
+    data_broker: Arc<B>,
     mani_broker: Arc<MB>,
 }
 
-impl<MB: MetaManipulationBroker> ReplaceNodeHandler<MB> {
-    pub fn new(mani_broker: Arc<MB>) -> Self {
-        Self { mani_broker }
+impl<B: MetaDataBroker, MB: MetaManipulationBroker> ReplaceNodeHandler<B, MB> {
+    pub fn new(data_broker: Arc<B>, mani_broker: Arc<MB>) -> Self {
+        Self {
+            data_broker,
+            mani_broker,
+        }
+    }
+
+    async fn replace_proxy(&self, proxy_failure: ProxyFailure) -> Result<(), CoordinateError> {
+        match self.mani_broker.replace_proxy(proxy_failure.clone()).await {
+            Ok(new_host) => {
+                info!(
+                    "successfully replace {} with new host {:?}",
+                    proxy_failure, new_host
+                );
+                Ok(())
+            }
+            Err(e) => {
+                error!("failed to replace proxy {} {:?}", proxy_failure, e);
+                Err(CoordinateError::MetaMani(e))
+            }
+        }
+    }
+
+    async fn failover_node(&self, node_address: String) -> Result<(), CoordinateError> {
+        match self.mani_broker.failover_node(node_address.clone()).await {
+            Ok(failover) => {
+                info!(
+                    "failover unhealthy node {}: promoted {:?} new replica {:?}",
+                    node_address,
+                    failover.promoted.as_ref().map(|node| node.get_address()),
+                    failover.new_replica.as_ref().map(|node| node.get_address()),
+                );
+                Ok(())
+            }
+            Err(e) => {
+                error!("failed to failover node {} {:?}", node_address, e);
+                Err(CoordinateError::MetaMani(e))
+            }
+        }
+    }
+
+    async fn handle_failure_impl(&self, failure: ProxyFailure) -> Result<(), CoordinateError> {
+        let host = self
+            .data_broker
+            .get_host(failure.clone())
+            .await
+            .map_err(CoordinateError::MetaData)?;
+        match host {
+            Some(_) => self.replace_proxy(failure).await,
+            None => self.failover_node(failure).await,
+        }
     }
 }
 
-impl<MB: MetaManipulationBroker> ProxyFailureHandler for ReplaceNodeHandler<MB> {
+impl<B: MetaDataBroker, MB: MetaManipulationBroker> ProxyFailureHandler
+    for ReplaceNodeHandler<B, MB>
+{
     fn handle_proxy_failure<'s>(
         &'s self,
         proxy_failure: ProxyFailure,
     ) -> Pin<Box<dyn Future<Output = Result<(), CoordinateError>> + Send + 's>> {
-        let proxy_failure2 = proxy_failure.clone();
-        Box::pin(
-            self.mani_broker
-                .replace_proxy(proxy_failure.clone())
-                .map_err(move |e| {
-                    error!("failed to replace proxy {} {:?}", proxy_failure2, e);
-                    CoordinateError::MetaMani(e)
-                })
-                .map_ok(move |new_host| {
-                    info!(
-                        "successfully replace {} with new host {:?}",
-                        proxy_failure, new_host
-                    );
-                }),
-        )
+        Box::pin(self.handle_failure_impl(proxy_failure))
     }
 }
 
@@ -64,7 +104,7 @@ mod tests {
     use super::super::broker::{MockMetaDataBroker, MockMetaManipulationBroker};
     use super::super::core::ParFailureHandler;
     use super::*;
-    use crate::common::cluster::Proxy;
+    use crate::common::cluster::{NodeFailover, Proxy};
     use crate::coordinator::core::FailureHandler;
     use futures::{stream, StreamExt};
     use std::collections::HashMap;
@@ -97,6 +137,13 @@ mod tests {
 
     #[tokio::test]
     async fn test_handler() {
+        let mut mock_data_broker = MockMetaDataBroker::new();
+        mock_data_broker
+            .expect_get_host()
+            .times(1)
+            .returning(move |_| Box::pin(async { Ok(Some(gen_testing_dummy_proxy())) }));
+        let mock_data_broker = Arc::new(mock_data_broker);
+
         let mut mock_broker = MockMetaManipulationBroker::new();
         let failure = "127.0.0.1:6000";
         let failure2 = failure;
@@ -107,7 +154,39 @@ mod tests {
             .returning(move |_| Box::pin(async { Ok(gen_testing_dummy_proxy()) }));
         let mock_broker = Arc::new(mock_broker);
 
-        let handler = ReplaceNodeHandler::new(mock_broker);
+        let handler = ReplaceNodeHandler::new(mock_data_broker, mock_broker);
+        let res = handler.handle_proxy_failure(failure.to_string()).await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_handler_failover_node() {
+        let mut mock_data_broker = MockMetaDataBroker::new();
+        mock_data_broker
+            .expect_get_host()
+            .times(1)
+            .returning(move |_| Box::pin(async { Ok(None) }));
+        let mock_data_broker = Arc::new(mock_data_broker);
+
+        let mut mock_broker = MockMetaManipulationBroker::new();
+        let failure = "127.0.0.1:7000";
+        let failure2 = failure;
+        mock_broker.expect_replace_proxy().times(0);
+        mock_broker
+            .expect_failover_node()
+            .withf(move |f| f == failure2)
+            .times(1)
+            .returning(move |_| {
+                Box::pin(async {
+                    Ok(NodeFailover {
+                        promoted: None,
+                        new_replica: None,
+                    })
+                })
+            });
+        let mock_broker = Arc::new(mock_broker);
+
+        let handler = ReplaceNodeHandler::new(mock_data_broker, mock_broker);
         let res = handler.handle_proxy_failure(failure.to_string()).await;
         assert!(res.is_ok());
     }
@@ -119,6 +198,10 @@ mod tests {
             .expect_get_failures()
             .times(1)
             .returning(move || Box::pin(stream::iter(vec![Ok("127.0.0.1:6000".to_string())])));
+        mock_data_broker
+            .expect_get_host()
+            .times(1)
+            .returning(move |_| Box::pin(async { Ok(Some(gen_testing_dummy_proxy())) }));
         let mock_data_broker = Arc::new(mock_data_broker);
 
         let mut mock_mani_broker = MockMetaManipulationBroker::new();
@@ -131,8 +214,8 @@ mod tests {
             .returning(move |_| Box::pin(async { Ok(gen_testing_dummy_proxy()) }));
         let mock_mani_broker = Arc::new(mock_mani_broker);
 
-        let retriever = BrokerProxyFailureRetriever::new(mock_data_broker);
-        let handler = ReplaceNodeHandler::new(mock_mani_broker);
+        let retriever = BrokerProxyFailureRetriever::new(mock_data_broker.clone());
+        let handler = ReplaceNodeHandler::new(mock_data_broker, mock_mani_broker);
         let failure_handler = ParFailureHandler::new(retriever, handler);
         let res: Vec<_> = failure_handler.run().collect().await;
         assert_eq!(res.len(), 1);
@@ -0,0 +1,195 @@
+use super::broker::{MetaDataBroker, MetaManipulationBroker};
+use super::core::CoordinateError;
+use crate::common::cluster::{Cluster, DBName, Role};
+use futures::TryStreamExt;
+use std::sync::Arc;
+
+// Add replicas for the masters with fewer replicas than `replicas_per_master`,
+// so that the clusters regain the redundancy after failover.
+// The new replicas will be set up by the `UMCTL SETREPL` of the metadata synchronization.
+pub struct ReplicaRepairer<DB: MetaDataBroker, MB: MetaManipulationBroker> {
+    data_broker: Arc<DB>,
+    mani_broker: Arc<MB>,
+}
+
+impl<DB: MetaDataBroker, MB: MetaManipulationBroker> ReplicaRepairer<DB, MB> {
+    pub fn new(data_broker: Arc<DB>, mani_broker: Arc<MB>) -> Self {
+        Self {
+            data_broker,
+            mani_broker,
+        }
+    }
+
+    pub async fn run(&self) -> Result<(), CoordinateError> {
+        let cluster_names: Vec<DBName> = self
+            .data_broker
+            .get_cluster_names()
+            .try_collect()
+            .await
+            .map_err(CoordinateError::MetaData)?;
+
+        let mut res = Ok(());
+        for cluster_name in cluster_names.into_iter() {
+            if let Err(err) = self.repair_cluster(cluster_name.clone()).await {
+                error!("failed to repair replicas of {}: {:?}", cluster_name, err);
+                res = Err(err);
+            }
+        }
+        res
+    }
+
+    async fn repair_cluster(&self, cluster_name: DBName) -> Result<(), CoordinateError> {
+        let cluster = match self
+            .data_broker
+            .get_cluster(cluster_name)
+            .await
+            .map_err(CoordinateError::MetaData)?
+        {
+            Some(cluster) => cluster,
+            None => return Ok(()),
+        };
+
+        for (master_address, missing_num) in get_missing_replicas(&cluster).into_iter() {
+            for _ in 0..missing_num {
+                let replica = self
+                    .mani_broker
+                    .add_replica(cluster.get_name().to_string(), master_address.clone())
+                    .await
+                    .map_err(CoordinateError::MetaMani)?;
+                info!(
+                    "added replica {} for master {} in {}",
+                    replica.get_address(),
+                    master_address,
+                    cluster.get_name()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns the masters with slots and the number of their missing replicas.
+// The masters without slots are free nodes.
+fn get_missing_replicas(cluster: &Cluster) -> Vec<(String, usize)> {
+    let replicas_per_master = cluster.get_config().replicas_per_master;
+    cluster
+        .get_nodes()
+        .iter()
+        .filter(|node| node.get_role() == Role::Master && !node.get_slots().is_empty())
+        .filter_map(|node| {
+            let replica_num = node.get_repl_meta().get_peers().len();
+            if replica_num < replicas_per_master {
+                Some((
+                    node.get_address().to_string(),
+                    replicas_per_master - replica_num,
+                ))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::broker::{MockMetaDataBroker, MockMetaManipulationBroker};
+    use super::*;
+    use crate::common::cluster::{Node, ReplMeta, ReplPeer, SlotRange, SlotRangeTag};
+    use crate::common::config::ClusterConfig;
+    use futures::stream;
+    use tokio;
+
+    fn gen_testing_cluster() -> Cluster {
+        let cluster_name = DBName::from("mydb").unwrap();
+        let slots = |start, end| {
+            vec![SlotRange {
+                start,
+                end,
+                tag: SlotRangeTag::None,
+            }]
+        };
+        let peer = |node_address: &str, proxy_address: &str| ReplPeer {
+            node_address: node_address.to_string(),
+            proxy_address: proxy_address.to_string(),
+        };
+        let nodes = vec![
+            // Promoted by failover without any replica.
+            Node::new(
+                "127.0.0.1:7001".to_string(),
+                "127.0.0.1:6001".to_string(),
+                cluster_name.clone(),
+                slots(0, 8191),
+                ReplMeta::new(Role::Master, vec![]),
+            ),
+            Node::new(
+                "127.0.0.1:7002".to_string(),
+                "127.0.0.1:6001".to_string(),
+                cluster_name.clone(),
+                slots(8192, 16383),
+                ReplMeta::new(Role::Master, vec![peer("127.0.0.1:7003", "127.0.0.1:6002")]),
+            ),
+            Node::new(
+                "127.0.0.1:7003".to_string(),
+                "127.0.0.1:6002".to_string(),
+                cluster_name.clone(),
+                vec![],
+                ReplMeta::new(
+                    Role::Replica,
+                    vec![peer("127.0.0.1:7002", "127.0.0.1:6001")],
+                ),
+            ),
+            // Free node
+            Node::new(
+                "127.0.0.1:7004".to_string(),
+                "127.0.0.1:6002".to_string(),
+                cluster_name.clone(),
+                vec![],
+                ReplMeta::new(Role::Master, vec![]),
+            ),
+        ];
+        let config = ClusterConfig {
+            replicas_per_master: 1,
+            ..Default::default()
+        };
+        Cluster::new(cluster_name, 1, nodes, config)
+    }
+
+    #[test]
+    fn test_get_missing_replicas() {
+        let cluster = gen_testing_cluster();
+        assert_eq!(
+            get_missing_replicas(&cluster),
+            vec![("127.0.0.1:7001".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replica_repairer() {
+        let mut mock_data_broker = MockMetaDataBroker::new();
+        mock_data_broker
+            .expect_get_cluster_names()
+            .times(1)
+            .returning(|| Box::pin(stream::iter(vec![Ok(DBName::from("mydb").unwrap())])));
+        mock_data_broker
+            .expect_get_cluster()
+            .times(1)
+            .returning(|_| Box::pin(async { Ok(Some(gen_testing_cluster())) }));
+
+        let mut mock_mani_broker = MockMetaManipulationBroker::new();
+        mock_mani_broker
+            .expect_add_replica()
+            .withf(|cluster_name, master| cluster_name == "mydb" && master == "127.0.0.1:7001")
+            .times(1)
+            .returning(|_, _| {
+                Box::pin(async {
+                    Ok(gen_testing_cluster()
+                        .get_node("127.0.0.1:7004")
+                        .cloned()
+                        .unwrap())
+                })
+            });
+
+        let repairer = ReplicaRepairer::new(Arc::new(mock_data_broker), Arc::new(mock_mani_broker));
+        assert!(repairer.run().await.is_ok());
+    }
+}
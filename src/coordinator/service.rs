@@ -1,29 +1,53 @@
+use super::accrual::{PhiAccrualConfig, PhiAccrualDetector};
 use super::broker::{MetaDataBroker, MetaManipulationBroker};
+use super::config::TunableConfig;
 use super::core::{
     CoordinateError, FailureDetector, FailureHandler, MigrationStateSynchronizer,
     ParFailureDetector, ParFailureHandler, ParMigrationStateSynchronizer,
-    ProxyMetaRespSynchronizer, ProxyMetaSynchronizer,
+    ProxyMetaRespSynchronizer, ProxyMetaRetriever, ProxyMetaSender, ProxyMetaSynchronizer,
 };
 use super::detector::{
-    BrokerFailureReporter, BrokerOrderedProxiesRetriever, BrokerProxiesRetriever,
-    PingFailureDetector,
+    BackendHealthChecker, BrokerChangedProxiesRetriever, BrokerFailureReporter,
+    BrokerProxiesRetriever, PhiAccrualFailureChecker, PingFailureDetector, ProxyFailureChecker,
 };
 use super::migration::{BrokerMigrationCommitter, MigrationStateRespChecker};
 use super::recover::{BrokerProxyFailureRetriever, ReplaceNodeHandler};
-use super::sync::{BrokerMetaRetriever, ProxyMetaRespSender};
+use super::repair::ReplicaRepairer;
+use super::status::{
+    CoordinatorStatus, StatusFailureReporter, StatusMigrationCommitter, DETECT_LOOP,
+    FAILURE_HANDLER_LOOP, HOST_SYNC_LOOP, MIGRATION_SYNC_LOOP, REPLICA_REPAIR_LOOP,
+};
+use super::sync::{BrokerMetaRetriever, ProxyEpochCache, ProxyMetaRespSender};
 use crate::common::utils::ThreadSafe;
 use crate::protocol::RedisClientFactory;
 use futures::future::select_all;
-use futures::{Future, StreamExt};
+use futures::{Future, StreamExt, TryStreamExt};
 use futures_timer::Delay;
+use std::collections::HashSet;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+// Check the manual requests of meta synchronization from the admin api in this interval.
+const SYNC_REQUEST_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub enum FailureDetectorConfig {
+    // Report the proxy after consecutive PING failures in a single round.
+    Ping,
+    // Report the proxy by the heartbeat history across the rounds.
+    PhiAccrual(PhiAccrualConfig),
+}
 
 #[derive(Debug, Clone)]
 pub struct CoordinatorConfig {
     pub broker_address: String,
     pub reporter_id: String,
+    // Empty if the broker does not enable the authentication.
+    pub broker_token: String,
+    pub failure_detector: FailureDetectorConfig,
+    pub tunable: Arc<TunableConfig>,
 }
 
 pub struct CoordinatorService<
@@ -35,6 +59,7 @@ pub struct CoordinatorService<
     data_broker: Arc<DB>,
     mani_broker: Arc<MB>,
     client_factory: Arc<F>,
+    status: Arc<CoordinatorStatus>,
 }
 
 type CoordResult = Result<(), CoordinateError>;
@@ -51,14 +76,20 @@ impl<
         mani_broker: Arc<MB>,
         client_factory: F,
     ) -> Self {
+        let status = Arc::new(CoordinatorStatus::new(config.broker_address.clone()));
         Self {
             config,
             data_broker,
             mani_broker,
             client_factory: Arc::new(client_factory),
+            status,
         }
     }
 
+    pub fn get_status(&self) -> Arc<CoordinatorStatus> {
+        self.status.clone()
+    }
+
     pub async fn run(&self) -> Result<(), CoordinateError> {
         info!("coordinator config: {:?}", self.config);
 
@@ -66,11 +97,15 @@ impl<
             Box::pin(self.loop_detect()),
             Box::pin(self.loop_host_sync()),
             Box::pin(self.loop_failure_handler()),
+            Box::pin(self.loop_replica_repair()),
             Box::pin(self.loop_migration_sync()),
+            Box::pin(self.loop_sync_requests()),
         ];
 
         let (res, _, _) = select_all(futs).await;
         error!("service stopped: {:?}", res);
+        self.status
+            .set_stopped(format!("service stopped: {:?}", res));
         res.map(|_| ())
     }
 
@@ -78,37 +113,74 @@ impl<
         reporter_id: String,
         data_broker: Arc<DB>,
         client_factory: Arc<F>,
+        phi_detector: Option<Arc<PhiAccrualDetector>>,
+        tunable: &TunableConfig,
+        status: Arc<CoordinatorStatus>,
     ) -> impl FailureDetector {
         let retriever = BrokerProxiesRetriever::new(data_broker.clone());
-        let checker = PingFailureDetector::new(client_factory);
-        let reporter = BrokerFailureReporter::new(reporter_id, data_broker);
+        let proxy_checker = match phi_detector {
+            Some(detector) => ProxyFailureChecker::PhiAccrual(PhiAccrualFailureChecker::new(
+                client_factory.clone(),
+                detector,
+            )),
+            None => ProxyFailureChecker::Ping(PingFailureDetector::new(client_factory.clone())),
+        };
+        let checker = BackendHealthChecker::new(
+            proxy_checker,
+            client_factory,
+            data_broker.clone(),
+            BrokerFailureReporter::new(reporter_id.clone(), data_broker.clone()),
+        );
+        let reporter = StatusFailureReporter::new(
+            BrokerFailureReporter::new(reporter_id, data_broker),
+            status,
+        );
         ParFailureDetector::new(retriever, checker, reporter)
+            .with_concurrency(tunable.get_detect_concurrency())
     }
 
     fn gen_host_meta_synchronizer(
         data_broker: Arc<DB>,
         client_factory: Arc<F>,
+        since_epoch: u64,
+        latest_epoch: Arc<AtomicU64>,
+        epoch_cache: Arc<ProxyEpochCache>,
+        tunable: &TunableConfig,
     ) -> impl ProxyMetaSynchronizer {
-        let proxy_retriever = BrokerOrderedProxiesRetriever::new(data_broker.clone());
+        let proxy_retriever = BrokerChangedProxiesRetriever::new(
+            data_broker.clone(),
+            since_epoch,
+            tunable.get_meta_changes_timeout(),
+            latest_epoch,
+        );
         let meta_retriever = BrokerMetaRetriever::new(data_broker);
-        let sender = ProxyMetaRespSender::new(client_factory);
+        let sender = ProxyMetaRespSender::new(client_factory).with_epoch_cache(epoch_cache);
         ProxyMetaRespSynchronizer::new(proxy_retriever, meta_retriever, sender)
+            .with_concurrency(tunable.get_host_sync_concurrency())
     }
 
-    fn gen_failure_handler(data_broker: Arc<DB>, mani_broker: Arc<MB>) -> impl FailureHandler {
-        let proxy_retriever = BrokerProxyFailureRetriever::new(data_broker);
-        let handler = ReplaceNodeHandler::new(mani_broker);
+    fn gen_failure_handler(
+        data_broker: Arc<DB>,
+        mani_broker: Arc<MB>,
+        tunable: &TunableConfig,
+    ) -> impl FailureHandler {
+        let proxy_retriever = BrokerProxyFailureRetriever::new(data_broker.clone());
+        let handler = ReplaceNodeHandler::new(data_broker, mani_broker);
         ParFailureHandler::new(proxy_retriever, handler)
+            .with_concurrency(tunable.get_failure_handler_concurrency())
     }
 
     fn gen_migration_state_synchronizer(
         data_broker: Arc<DB>,
         mani_broker: Arc<MB>,
         client_factory: Arc<F>,
+        tunable: &TunableConfig,
+        status: Arc<CoordinatorStatus>,
     ) -> impl MigrationStateSynchronizer {
         let proxy_retriever = BrokerProxiesRetriever::new(data_broker.clone());
         let checker = MigrationStateRespChecker::new(client_factory.clone());
-        let committer = BrokerMigrationCommitter::new(mani_broker);
+        let committer =
+            StatusMigrationCommitter::new(BrokerMigrationCommitter::new(mani_broker), status);
         let meta_retriever = BrokerMetaRetriever::new(data_broker);
         let sender = ProxyMetaRespSender::new(client_factory);
         ParMigrationStateSynchronizer::new(
@@ -118,83 +190,237 @@ impl<
             meta_retriever,
             sender,
         )
+        .with_concurrency(tunable.get_migration_sync_concurrency())
+    }
+
+    // Run every loop once without waiting, used by the simulation tests.
+    #[cfg(test)]
+    pub(crate) async fn run_round(&self) {
+        self.detect_round(None).await;
+        self.failure_handler_round().await;
+        self.replica_repair_round().await;
+        self.migration_sync_round().await;
+        self.host_sync_round(0, Arc::new(ProxyEpochCache::default()))
+            .await;
     }
 
     async fn loop_detect(&self) -> Result<(), CoordinateError> {
-        let data_broker = self.data_broker.clone();
-        let client_factory = self.client_factory.clone();
-        let reporter_id = self.config.reporter_id.clone();
+        // The heartbeat history needs to be kept across the rounds.
+        let phi_detector = match &self.config.failure_detector {
+            FailureDetectorConfig::Ping => None,
+            FailureDetectorConfig::PhiAccrual(config) => {
+                Some(Arc::new(PhiAccrualDetector::new(config.clone())))
+            }
+        };
         loop {
-            debug!("start detecting failures");
-            defer!(debug!("detecting finished a round"));
-            if let Err(e) = Self::gen_detector(
-                reporter_id.clone(),
-                data_broker.clone(),
-                client_factory.clone(),
-            )
-            .run()
-            .await
-            {
+            self.detect_round(phi_detector.clone()).await;
+            Delay::new(self.config.tunable.get_detect_interval()).await;
+        }
+    }
+
+    async fn detect_round(&self, phi_detector: Option<Arc<PhiAccrualDetector>>) {
+        debug!("start detecting failures");
+        defer!(debug!("detecting finished a round"));
+        self.status.start_detect_round();
+        let res = Self::gen_detector(
+            self.config.reporter_id.clone(),
+            self.data_broker.clone(),
+            self.client_factory.clone(),
+            phi_detector.clone(),
+            &self.config.tunable,
+            self.status.clone(),
+        )
+        .run()
+        .await;
+        self.status.finish_detect_round();
+        match res {
+            Ok(()) => {
+                if let Some(detector) = phi_detector.as_ref() {
+                    detector.remove_unchecked();
+                }
+                self.status.record_round(DETECT_LOOP, None);
+            }
+            Err(e) => {
                 error!("detector stream err {:?}", e);
+                self.status
+                    .record_round(DETECT_LOOP, Some(format!("{:?}", e)));
             }
-            Delay::new(Duration::from_secs(1)).await;
         }
     }
 
     async fn loop_host_sync(&self) -> Result<(), CoordinateError> {
-        let data_broker = self.data_broker.clone();
-        let client_factory = self.client_factory.clone();
+        let mut synced_epoch = 0;
+        let mut last_full_sync = Instant::now();
+        let epoch_cache = Arc::new(ProxyEpochCache::default());
         loop {
-            debug!("start sync host meta data");
-            defer!(debug!("host meta sync finished a round"));
-            let sync =
-                Self::gen_host_meta_synchronizer(data_broker.clone(), client_factory.clone());
-            let mut s = sync.run();
-            while let Some(r) = s.next().await {
-                if let Err(e) = r {
-                    error!("sync stream err {:?}", e);
-                }
+            // Epoch 0 will retrieve all the proxies.
+            let full_sync_interval = self.config.tunable.get_full_meta_sync_interval();
+            let since_epoch = if last_full_sync.elapsed() >= full_sync_interval {
+                last_full_sync = Instant::now();
+                // Check the epochs of all the proxies again to find the restarted ones.
+                epoch_cache.expire_epochs();
+                self.evict_removed_hosts(&epoch_cache).await;
+                0
+            } else {
+                synced_epoch
+            };
+            // Retry all the changes in the next round if any of them fails.
+            if let Some(latest_epoch) = self.host_sync_round(since_epoch, epoch_cache.clone()).await
+            {
+                synced_epoch = latest_epoch;
+            }
+            Delay::new(self.config.tunable.get_host_sync_interval()).await;
+        }
+    }
+
+    async fn evict_removed_hosts(&self, epoch_cache: &ProxyEpochCache) {
+        let res = self
+            .data_broker
+            .get_host_addresses()
+            .try_collect::<HashSet<String>>()
+            .await;
+        match res {
+            Ok(addresses) => epoch_cache.retain_hosts(&addresses),
+            Err(err) => warn!("failed to get host addresses for the epoch cache {:?}", err),
+        }
+    }
+
+    // Returns the latest epoch if all the proxies are synchronized.
+    async fn host_sync_round(
+        &self,
+        since_epoch: u64,
+        epoch_cache: Arc<ProxyEpochCache>,
+    ) -> Option<u64> {
+        debug!("start sync host meta data");
+        defer!(debug!("host meta sync finished a round"));
+        let latest_epoch = Arc::new(AtomicU64::new(since_epoch));
+        let sync = Self::gen_host_meta_synchronizer(
+            self.data_broker.clone(),
+            self.client_factory.clone(),
+            since_epoch,
+            latest_epoch.clone(),
+            epoch_cache,
+            &self.config.tunable,
+        );
+        let mut s = sync.run();
+        let mut last_err = None;
+        while let Some(r) = s.next().await {
+            if let Err(e) = r {
+                error!("sync stream err {:?}", e);
+                last_err = Some(format!("{:?}", e));
             }
-            Delay::new(Duration::from_secs(1)).await;
+        }
+        let synced = last_err.is_none();
+        self.status.record_round(HOST_SYNC_LOOP, last_err);
+        if synced {
+            Some(latest_epoch.load(Ordering::SeqCst))
+        } else {
+            None
         }
     }
 
     async fn loop_failure_handler(&self) -> Result<(), CoordinateError> {
-        let data_broker = self.data_broker.clone();
-        let mani_broker = self.mani_broker.clone();
         loop {
-            debug!("start handling failures");
-            defer!(debug!("handling failures finished a round"));
-            let handler = Self::gen_failure_handler(data_broker.clone(), mani_broker.clone());
-            let mut s = handler.run();
-            while let Some(r) = s.next().await {
-                if let Err(e) = r {
-                    error!("failure handler stream err {:?}", e)
-                }
+            self.failure_handler_round().await;
+            Delay::new(self.config.tunable.get_failure_handler_interval()).await;
+        }
+    }
+
+    async fn failure_handler_round(&self) {
+        debug!("start handling failures");
+        defer!(debug!("handling failures finished a round"));
+        let handler = Self::gen_failure_handler(
+            self.data_broker.clone(),
+            self.mani_broker.clone(),
+            &self.config.tunable,
+        );
+        let mut s = handler.run();
+        let mut last_err = None;
+        while let Some(r) = s.next().await {
+            if let Err(e) = r {
+                error!("failure handler stream err {:?}", e);
+                last_err = Some(format!("{:?}", e));
             }
-            Delay::new(Duration::from_secs(1)).await;
         }
+        self.status.record_round(FAILURE_HANDLER_LOOP, last_err);
+    }
+
+    async fn loop_replica_repair(&self) -> Result<(), CoordinateError> {
+        loop {
+            self.replica_repair_round().await;
+            Delay::new(self.config.tunable.get_replica_repair_interval()).await;
+        }
+    }
+
+    async fn replica_repair_round(&self) {
+        debug!("start repairing replicas");
+        defer!(debug!("repairing replicas finished a round"));
+        let repairer = ReplicaRepairer::new(self.data_broker.clone(), self.mani_broker.clone());
+        let res = repairer.run().await;
+        if let Err(e) = res.as_ref() {
+            error!("replica repairer err {:?}", e);
+        }
+        self.status
+            .record_round(REPLICA_REPAIR_LOOP, res.err().map(|e| format!("{:?}", e)));
     }
 
     async fn loop_migration_sync(&self) -> Result<(), CoordinateError> {
-        let data_broker = self.data_broker.clone();
-        let mani_broker = self.mani_broker.clone();
-        let client_factory = self.client_factory.clone();
         loop {
-            debug!("start handling migration sync");
-            defer!(debug!("handling migration finished a round"));
-            let sync = Self::gen_migration_state_synchronizer(
-                data_broker.clone(),
-                mani_broker.clone(),
-                client_factory.clone(),
-            );
-            let mut s = sync.run();
-            while let Some(r) = s.next().await {
-                if let Err(e) = r {
-                    error!("migration sync stream err {:?}", e)
+            self.migration_sync_round().await;
+            Delay::new(self.config.tunable.get_migration_sync_interval()).await;
+        }
+    }
+
+    async fn migration_sync_round(&self) {
+        debug!("start handling migration sync");
+        defer!(debug!("handling migration finished a round"));
+        let sync = Self::gen_migration_state_synchronizer(
+            self.data_broker.clone(),
+            self.mani_broker.clone(),
+            self.client_factory.clone(),
+            &self.config.tunable,
+            self.status.clone(),
+        );
+        self.status.start_migration_sync_round();
+        let mut s = sync.run();
+        let mut last_err = None;
+        while let Some(r) = s.next().await {
+            if let Err(e) = r {
+                error!("migration sync stream err {:?}", e);
+                last_err = Some(format!("{:?}", e));
+            }
+        }
+        self.status.finish_migration_sync_round();
+        self.status.record_round(MIGRATION_SYNC_LOOP, last_err);
+    }
+
+    // Synchronize the metadata to the proxies requested by the admin api
+    // without waiting for the changes of the broker.
+    async fn loop_sync_requests(&self) -> Result<(), CoordinateError> {
+        let meta_retriever = BrokerMetaRetriever::new(self.data_broker.clone());
+        let sender = ProxyMetaRespSender::new(self.client_factory.clone());
+        loop {
+            for address in self.status.take_sync_requests().into_iter() {
+                let host = match meta_retriever.get_host_meta(address.clone()).await {
+                    Ok(Some(host)) => host,
+                    Ok(None) => {
+                        warn!("requested proxy {} is not found in broker", address);
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("failed to get meta of requested proxy {}: {:?}", address, e);
+                        continue;
+                    }
+                };
+                match sender.send_meta(host).await {
+                    Ok(()) => info!("synchronized meta to requested proxy {}", address),
+                    Err(e) => error!(
+                        "failed to sync meta to requested proxy {}: {:?}",
+                        address, e
+                    ),
                 }
             }
-            Delay::new(Duration::from_secs(1)).await;
+            Delay::new(SYNC_REQUEST_CHECK_INTERVAL).await;
         }
     }
 }
@@ -0,0 +1,643 @@
+use super::broker::{
+    MetaDataBroker, MetaDataBrokerError, MetaManipulationBroker, MetaManipulationBrokerError,
+};
+use super::config::TunableConfig;
+use super::service::{CoordinatorConfig, CoordinatorService, FailureDetectorConfig};
+use crate::broker::store::{MetaStore, MetaStoreError};
+use crate::common::cluster::{
+    Cluster, DBName, MetaChanges, MigrationTaskMeta, Node, NodeFailover, Proxy, SlotRangeTag,
+};
+use crate::common::db::{ProxyDBMeta, ProxyDBMetaDelta};
+use crate::common::utils::{BASE_EPOCH_MISMATCH_REPLY, OLD_EPOCH_REPLY};
+use crate::protocol::{
+    Array, BinSafeStr, BulkStr, OptionalMulti, RedisClient, RedisClientError, RedisClientFactory,
+    Resp, RespVec,
+};
+use futures::{future, stream, Future, Stream};
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+// An in-process fake cluster to test the coordinator deterministically without Docker.
+// The brokers are backed by a real `MetaStore`,
+// and the proxies only keep the metadata sent to them.
+
+const FAILURE_TTL_SECS: i64 = 60;
+
+#[derive(Clone)]
+pub struct SimBroker {
+    store: Arc<RwLock<MetaStore>>,
+    available: Arc<AtomicBool>,
+}
+
+impl SimBroker {
+    pub fn new(store: MetaStore) -> Self {
+        Self {
+            store: Arc::new(RwLock::new(store)),
+            available: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    // All the requests fail when it's not available.
+    pub fn set_available(&self, available: bool) {
+        self.available.store(available, Ordering::SeqCst);
+    }
+
+    pub fn get_store(&self) -> MetaStore {
+        self.store.read().expect("SimBroker::get_store").clone()
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&MetaStore) -> T) -> Result<T, MetaDataBrokerError> {
+        if !self.available.load(Ordering::SeqCst) {
+            return Err(MetaDataBrokerError::Io(io::Error::from(
+                io::ErrorKind::ConnectionRefused,
+            )));
+        }
+        Ok(f(&self.store.read().expect("SimBroker::read")))
+    }
+
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&mut MetaStore) -> Result<T, MetaStoreError>,
+    ) -> Result<T, MetaManipulationBrokerError> {
+        if !self.available.load(Ordering::SeqCst) {
+            return Err(MetaManipulationBrokerError::Io(io::Error::from(
+                io::ErrorKind::ConnectionRefused,
+            )));
+        }
+        f(&mut self.store.write().expect("SimBroker::write")).map_err(|err| match err {
+            MetaStoreError::NoAvailableResource(_) => {
+                MetaManipulationBrokerError::ResourceNotAvailable
+            }
+            _ => MetaManipulationBrokerError::InvalidReply,
+        })
+    }
+}
+
+type DataFuture<'s, T> = Pin<Box<dyn Future<Output = Result<T, MetaDataBrokerError>> + Send + 's>>;
+type DataStream<'s, T> = Pin<Box<dyn Stream<Item = Result<T, MetaDataBrokerError>> + Send + 's>>;
+type ManiFuture<'s, T> =
+    Pin<Box<dyn Future<Output = Result<T, MetaManipulationBrokerError>> + Send + 's>>;
+
+fn result_to_stream<'s, T: Send + 's>(
+    res: Result<Vec<T>, MetaDataBrokerError>,
+) -> DataStream<'s, T> {
+    match res {
+        Ok(items) => Box::pin(stream::iter(items.into_iter().map(Ok))),
+        Err(err) => Box::pin(stream::iter(vec![Err(err)])),
+    }
+}
+
+impl MetaDataBroker for SimBroker {
+    fn get_cluster_names<'s>(&'s self) -> DataStream<'s, DBName> {
+        result_to_stream(self.read(|store| store.get_cluster_names()))
+    }
+
+    fn get_cluster<'s>(&'s self, name: DBName) -> DataFuture<'s, Option<Cluster>> {
+        let res = self.read(|store| store.get_cluster_by_name(&name.to_string()));
+        Box::pin(future::ready(res))
+    }
+
+    fn get_host_addresses<'s>(&'s self) -> DataStream<'s, String> {
+        result_to_stream(self.read(|store| store.get_hosts()))
+    }
+
+    fn get_host<'s>(&'s self, address: String) -> DataFuture<'s, Option<Proxy>> {
+        let res = self.read(|store| store.get_host_by_address(&address));
+        Box::pin(future::ready(res))
+    }
+
+    fn add_failure<'s>(&'s self, address: String, reporter_id: String) -> DataFuture<'s, ()> {
+        let res = self
+            .write(|store| {
+                store.add_failure(address, reporter_id);
+                Ok(())
+            })
+            .map_err(|_| MetaDataBrokerError::InvalidReply);
+        Box::pin(future::ready(res))
+    }
+
+    fn get_failures<'s>(&'s self) -> DataStream<'s, String> {
+        let res = self
+            .write(|store| Ok(store.get_failures(chrono::Duration::seconds(FAILURE_TTL_SECS))))
+            .map_err(|_| MetaDataBrokerError::InvalidReply);
+        result_to_stream(res)
+    }
+
+    // Returns immediately so that the rounds won't wait for the changes.
+    fn get_changes<'s>(
+        &'s self,
+        since_epoch: u64,
+        _timeout: Duration,
+    ) -> DataFuture<'s, MetaChanges> {
+        let res = self.read(|store| store.get_changes(since_epoch));
+        Box::pin(future::ready(res))
+    }
+}
+
+impl MetaManipulationBroker for SimBroker {
+    fn replace_proxy<'s>(&'s self, failed_proxy_address: String) -> ManiFuture<'s, Proxy> {
+        let res =
+            self.write(|store| store.replace_failed_proxy(failed_proxy_address, &HashMap::new()));
+        Box::pin(future::ready(res))
+    }
+
+    fn failover_node<'s>(&'s self, failed_node_address: String) -> ManiFuture<'s, NodeFailover> {
+        let res = self.write(|store| store.failover_node(&failed_node_address, None));
+        Box::pin(future::ready(res))
+    }
+
+    fn add_replica<'s>(
+        &'s self,
+        cluster_name: String,
+        master_node_address: String,
+    ) -> ManiFuture<'s, Node> {
+        let res = self.write(|store| store.add_replica(cluster_name, master_node_address));
+        Box::pin(future::ready(res))
+    }
+
+    fn commit_migration<'s>(&'s self, meta: MigrationTaskMeta) -> ManiFuture<'s, ()> {
+        let res = self.write(|store| store.commit_migration(meta));
+        Box::pin(future::ready(res))
+    }
+
+    fn ack_migration_rollback<'s>(
+        &'s self,
+        proxy_address: String,
+        meta: MigrationTaskMeta,
+    ) -> ManiFuture<'s, ()> {
+        let res = self.write(|store| store.ack_migration_rollback(proxy_address, meta));
+        Box::pin(future::ready(res))
+    }
+}
+
+struct SimProxyState {
+    alive: bool,
+    db_meta: Option<ProxyDBMeta>,
+    repl_epoch: u64,
+    // The finished migration tasks will be reported by `UMCTL INFOMGR`.
+    finish_migration: bool,
+}
+
+impl Default for SimProxyState {
+    fn default() -> Self {
+        Self {
+            alive: true,
+            db_meta: None,
+            repl_epoch: 0,
+            finish_migration: true,
+        }
+    }
+}
+
+impl SimProxyState {
+    fn get_epoch(&self) -> u64 {
+        let db_epoch = self.db_meta.as_ref().map_or(0, |meta| meta.get_epoch());
+        std::cmp::min(db_epoch, self.repl_epoch)
+    }
+
+    fn set_meta(&mut self, db_meta: ProxyDBMeta) -> RespVec {
+        let epoch = self.db_meta.as_ref().map_or(0, |meta| meta.get_epoch());
+        if db_meta.get_epoch() <= epoch && !db_meta.get_flags().force {
+            return Resp::Error(OLD_EPOCH_REPLY.as_bytes().to_vec());
+        }
+        self.db_meta = Some(db_meta);
+        Resp::Simple(b"OK".to_vec())
+    }
+
+    // The tasks are created and stopped as soon as the metadata is set.
+    fn get_migration_tasks(&self) -> Vec<MigrationTaskMeta> {
+        let db_meta = match self.db_meta.as_ref() {
+            Some(db_meta) => db_meta,
+            None => return vec![],
+        };
+        let mut tasks = vec![];
+        for (db_name, nodes) in db_meta.get_local().get_map().iter() {
+            for slot_range in nodes.values().flatten() {
+                if slot_range.tag != SlotRangeTag::None {
+                    tasks.push(MigrationTaskMeta {
+                        db_name: db_name.clone(),
+                        slot_range: slot_range.clone(),
+                    });
+                }
+            }
+        }
+        tasks
+    }
+
+    // Only the source proxy reports the migrating slots.
+    fn get_finished_migrations(&self) -> Vec<MigrationTaskMeta> {
+        if !self.finish_migration {
+            return vec![];
+        }
+        self.get_migration_tasks()
+            .into_iter()
+            .filter(|task| match task.slot_range.tag {
+                SlotRangeTag::Migrating(_) => true,
+                _ => false,
+            })
+            .collect()
+    }
+
+    fn tasks_to_resp(tasks: Vec<MigrationTaskMeta>) -> RespVec {
+        let tasks = tasks
+            .into_iter()
+            .map(|task| Resp::Bulk(BulkStr::Str(task.into_strings().join(" ").into_bytes())))
+            .collect();
+        Resp::Arr(Array::Arr(tasks))
+    }
+
+    fn handle_umctl(&mut self, sub_command: &str, args: Vec<String>) -> RespVec {
+        let mut it = args.into_iter().peekable();
+        match sub_command {
+            "GETEPOCH" => Resp::Integer(self.get_epoch().to_string().into_bytes()),
+            "HEALTH" => Resp::Bulk(BulkStr::Str(vec![])),
+            "SETREPL" => match it.next().and_then(|epoch| epoch.parse::<u64>().ok()) {
+                Some(epoch) if epoch > self.repl_epoch => {
+                    self.repl_epoch = epoch;
+                    Resp::Simple(b"OK".to_vec())
+                }
+                Some(_) => Resp::Error(OLD_EPOCH_REPLY.as_bytes().to_vec()),
+                None => Resp::Error(b"Invalid arguments".to_vec()),
+            },
+            "SETDB" => match ProxyDBMeta::parse(&mut it) {
+                Ok((db_meta, _)) => self.set_meta(db_meta),
+                Err(_) => Resp::Error(b"Invalid arguments".to_vec()),
+            },
+            "SETDBDELTA" => match ProxyDBMetaDelta::parse(&mut it) {
+                Ok((delta, _)) => match self.db_meta.as_ref() {
+                    Some(base) if base.get_epoch() == delta.get_base_epoch() => {
+                        let db_meta = delta.apply(base);
+                        self.set_meta(db_meta)
+                    }
+                    _ => Resp::Error(BASE_EPOCH_MISMATCH_REPLY.as_bytes().to_vec()),
+                },
+                Err(_) => Resp::Error(b"Invalid arguments".to_vec()),
+            },
+            "INFOMGR" => Self::tasks_to_resp(self.get_finished_migrations()),
+            "LISTMGR" => Self::tasks_to_resp(self.get_migration_tasks()),
+            _ => Resp::Error(b"Invalid sub command".to_vec()),
+        }
+    }
+
+    fn handle(&mut self, command: Vec<BinSafeStr>) -> RespVec {
+        let mut it = command
+            .into_iter()
+            .map(|element| String::from_utf8_lossy(&element).to_string());
+        let cmd_name = it.next().unwrap_or_default().to_uppercase();
+        match cmd_name.as_str() {
+            "PING" => Resp::Simple(b"PONG".to_vec()),
+            "UMCTL" => {
+                let sub_command = it.next().unwrap_or_default().to_uppercase();
+                self.handle_umctl(&sub_command, it.collect())
+            }
+            _ => Resp::Error(b"unsupported command".to_vec()),
+        }
+    }
+}
+
+// The fake server proxies keyed by their addresses.
+#[derive(Clone, Default)]
+pub struct SimProxies {
+    proxies: Arc<Mutex<HashMap<String, SimProxyState>>>,
+}
+
+impl SimProxies {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, SimProxyState>> {
+        self.proxies.lock().expect("SimProxies::lock")
+    }
+
+    pub fn add_proxy(&self, address: String) {
+        self.lock().insert(address, SimProxyState::default());
+    }
+
+    // A dead proxy refuses all the connections.
+    pub fn set_alive(&self, address: &str, alive: bool) {
+        if let Some(proxy) = self.lock().get_mut(address) {
+            proxy.alive = alive;
+        }
+    }
+
+    // Keep the migration running when it's false.
+    pub fn set_finish_migration(&self, address: &str, finish_migration: bool) {
+        if let Some(proxy) = self.lock().get_mut(address) {
+            proxy.finish_migration = finish_migration;
+        }
+    }
+
+    pub fn get_epoch(&self, address: &str) -> Option<u64> {
+        self.lock().get(address).map(|proxy| proxy.get_epoch())
+    }
+
+    fn execute(
+        &self,
+        address: &str,
+        command: Vec<BinSafeStr>,
+    ) -> Result<RespVec, RedisClientError> {
+        match self.lock().get_mut(address) {
+            Some(proxy) if proxy.alive => Ok(proxy.handle(command)),
+            _ => Err(RedisClientError::Closed),
+        }
+    }
+
+    fn is_alive(&self, address: &str) -> bool {
+        self.lock().get(address).map_or(false, |proxy| proxy.alive)
+    }
+}
+
+pub struct SimRedisClient {
+    address: String,
+    proxies: SimProxies,
+}
+
+impl RedisClient for SimRedisClient {
+    fn execute<'s>(
+        &'s mut self,
+        command: OptionalMulti<Vec<BinSafeStr>>,
+    ) -> Pin<Box<dyn Future<Output = Result<OptionalMulti<RespVec>, RedisClientError>> + Send + 's>>
+    {
+        let res = match command {
+            OptionalMulti::Single(command) => self
+                .proxies
+                .execute(&self.address, command)
+                .map(OptionalMulti::Single),
+            OptionalMulti::Multi(commands) => commands
+                .into_iter()
+                .map(|command| self.proxies.execute(&self.address, command))
+                .collect::<Result<Vec<_>, _>>()
+                .map(OptionalMulti::Multi),
+        };
+        Box::pin(future::ready(res))
+    }
+}
+
+pub struct SimRedisClientFactory {
+    proxies: SimProxies,
+}
+
+impl SimRedisClientFactory {
+    pub fn new(proxies: SimProxies) -> Self {
+        Self { proxies }
+    }
+}
+
+impl RedisClientFactory for SimRedisClientFactory {
+    type Client = SimRedisClient;
+
+    fn create_client<'s>(
+        &'s self,
+        address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<Self::Client, RedisClientError>> + Send + 's>> {
+        let res = if self.proxies.is_alive(&address) {
+            Ok(SimRedisClient {
+                address,
+                proxies: self.proxies.clone(),
+            })
+        } else {
+            Err(RedisClientError::Io(io::Error::from(
+                io::ErrorKind::ConnectionRefused,
+            )))
+        };
+        Box::pin(future::ready(res))
+    }
+}
+
+pub type SimCoordinator = CoordinatorService<SimBroker, SimBroker, SimRedisClientFactory>;
+
+pub fn gen_coordinator(
+    reporter_id: &str,
+    broker: SimBroker,
+    proxies: SimProxies,
+) -> SimCoordinator {
+    let config = CoordinatorConfig {
+        broker_address: "sim_broker".to_string(),
+        reporter_id: reporter_id.to_string(),
+        broker_token: String::new(),
+        failure_detector: FailureDetectorConfig::Ping,
+        tunable: Arc::new(TunableConfig::default()),
+    };
+    let broker = Arc::new(broker);
+    CoordinatorService::new(
+        config,
+        broker.clone(),
+        broker,
+        SimRedisClientFactory::new(proxies),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::placement::PlacementOptions;
+    use crate::broker::store::MigrationType;
+    use crate::common::cluster::Role;
+    use tokio;
+
+    const CLUSTER_NAME: &str = "mydb";
+
+    // 6 proxies with 2 nodes each, and a cluster with 2 masters and 1 replica for each master.
+    // The proxies not used by the cluster are free for replacing the failed ones.
+    fn gen_sim_cluster() -> (SimBroker, SimProxies) {
+        let mut store = MetaStore::default();
+        let proxies = SimProxies::default();
+        for i in 0..6 {
+            let proxy_address = format!("127.0.0.1:{}", 6000 + i);
+            let nodes = vec![
+                format!("127.0.0.1:{}", 7000 + 2 * i),
+                format!("127.0.0.1:{}", 7001 + 2 * i),
+            ];
+            store
+                .add_hosts(proxy_address.clone(), nodes, HashMap::new())
+                .expect("gen_sim_cluster");
+            proxies.add_proxy(proxy_address);
+        }
+        let options = PlacementOptions {
+            master_num: 2,
+            replicas_per_master: 1,
+            anti_affinity: vec![],
+        };
+        store
+            .add_cluster(CLUSTER_NAME.to_string(), HashMap::new(), Some(options))
+            .expect("gen_sim_cluster");
+        (SimBroker::new(store), proxies)
+    }
+
+    async fn run_rounds(coordinators: &[&SimCoordinator], rounds: usize) {
+        for _ in 0..rounds {
+            for coordinator in coordinators.iter() {
+                coordinator.run_round().await;
+            }
+        }
+    }
+
+    fn get_cluster(broker: &SimBroker) -> Cluster {
+        broker
+            .get_store()
+            .get_cluster_by_name(CLUSTER_NAME)
+            .expect("get_cluster")
+    }
+
+    fn get_master_proxy(broker: &SimBroker) -> String {
+        get_cluster(broker)
+            .get_nodes()
+            .iter()
+            .find(|node| node.get_role() == Role::Master && !node.get_slots().is_empty())
+            .map(|node| node.get_proxy_address().to_string())
+            .expect("get_master_proxy")
+    }
+
+    fn get_free_proxy_num(broker: &SimBroker) -> usize {
+        let store = broker.get_store();
+        store
+            .get_hosts()
+            .into_iter()
+            .filter(|address| {
+                store
+                    .get_host_by_address(address)
+                    .map_or(false, |host| host.get_nodes().is_empty())
+            })
+            .count()
+    }
+
+    // All the proxies in use have the latest metadata and all the masters have a replica.
+    fn check_converged(broker: &SimBroker, proxies: &SimProxies) {
+        let store = broker.get_store();
+        assert!(store.validate().is_ok());
+        for address in store.get_hosts().into_iter() {
+            let host = store
+                .get_host_by_address(&address)
+                .expect("check_converged");
+            if !host.get_nodes().is_empty() {
+                assert_eq!(proxies.get_epoch(&address), Some(host.get_epoch()));
+            }
+        }
+        let cluster = get_cluster(broker);
+        for node in cluster.get_nodes().iter() {
+            assert!(node
+                .get_slots()
+                .iter()
+                .all(|sr| sr.tag == SlotRangeTag::None));
+            if node.get_role() == Role::Master && !node.get_slots().is_empty() {
+                assert_eq!(node.get_repl_meta().get_peers().len(), 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sim_proxy_failure() {
+        let (broker, proxies) = gen_sim_cluster();
+        let coordinator = gen_coordinator("coordinator1", broker.clone(), proxies.clone());
+        run_rounds(&[&coordinator], 1).await;
+        check_converged(&broker, &proxies);
+
+        let failed_proxy = get_master_proxy(&broker);
+        proxies.set_alive(&failed_proxy, false);
+        run_rounds(&[&coordinator], 3).await;
+
+        check_converged(&broker, &proxies);
+        let cluster = get_cluster(&broker);
+        assert!(cluster
+            .get_nodes()
+            .iter()
+            .all(|node| node.get_proxy_address() != failed_proxy));
+    }
+
+    #[tokio::test]
+    async fn test_sim_proxy_failure_during_migration() {
+        let (broker, proxies) = gen_sim_cluster();
+        let coordinator = gen_coordinator("coordinator1", broker.clone(), proxies.clone());
+        run_rounds(&[&coordinator], 1).await;
+
+        // Scale out and migrate half of the slots to the new empty master.
+        let src_proxy = {
+            let mut store = broker.store.write().unwrap();
+            let new_nodes = store
+                .auto_add_nodes(CLUSTER_NAME.to_string())
+                .expect("test_sim_proxy_failure_during_migration");
+            let cluster = store
+                .get_cluster_by_name(CLUSTER_NAME)
+                .expect("test_sim_proxy_failure_during_migration");
+            let src_master = cluster
+                .get_nodes()
+                .iter()
+                .find(|node| node.get_role() == Role::Master && !node.get_slots().is_empty())
+                .expect("test_sim_proxy_failure_during_migration");
+            let src_proxy = src_master.get_proxy_address().to_string();
+            store
+                .migrate_slots(
+                    CLUSTER_NAME.to_string(),
+                    src_master.get_address().to_string(),
+                    new_nodes[0].get_address().to_string(),
+                    MigrationType::Half,
+                )
+                .expect("test_sim_proxy_failure_during_migration");
+            src_proxy
+        };
+
+        // The source proxy dies before the migration finishes.
+        proxies.set_finish_migration(&src_proxy, false);
+        run_rounds(&[&coordinator], 1).await;
+        let cluster = get_cluster(&broker);
+        assert!(cluster.get_nodes().iter().any(|node| node
+            .get_slots()
+            .iter()
+            .any(|sr| sr.tag != SlotRangeTag::None)));
+
+        proxies.set_alive(&src_proxy, false);
+        run_rounds(&[&coordinator], 3).await;
+
+        check_converged(&broker, &proxies);
+        let cluster = get_cluster(&broker);
+        assert!(cluster
+            .get_nodes()
+            .iter()
+            .all(|node| node.get_proxy_address() != src_proxy));
+    }
+
+    #[tokio::test]
+    async fn test_sim_broker_flapping() {
+        let (broker, proxies) = gen_sim_cluster();
+        let coordinator = gen_coordinator("coordinator1", broker.clone(), proxies.clone());
+
+        broker.set_available(false);
+        run_rounds(&[&coordinator], 2).await;
+        let failed_proxy = get_master_proxy(&broker);
+        assert_eq!(proxies.get_epoch(&failed_proxy), Some(0));
+
+        broker.set_available(true);
+        run_rounds(&[&coordinator], 1).await;
+        proxies.set_alive(&failed_proxy, false);
+        broker.set_available(false);
+        run_rounds(&[&coordinator], 2).await;
+        // Nothing is changed while the broker is not available.
+        let cluster = get_cluster(&broker);
+        assert!(cluster
+            .get_nodes()
+            .iter()
+            .any(|node| node.get_proxy_address() == failed_proxy));
+
+        broker.set_available(true);
+        run_rounds(&[&coordinator], 3).await;
+        check_converged(&broker, &proxies);
+    }
+
+    #[tokio::test]
+    async fn test_sim_two_coordinators() {
+        let (broker, proxies) = gen_sim_cluster();
+        let coordinator1 = gen_coordinator("coordinator1", broker.clone(), proxies.clone());
+        let coordinator2 = gen_coordinator("coordinator2", broker.clone(), proxies.clone());
+        run_rounds(&[&coordinator1, &coordinator2], 1).await;
+
+        let free_proxy_num = get_free_proxy_num(&broker);
+        let failed_proxy = get_master_proxy(&broker);
+        proxies.set_alive(&failed_proxy, false);
+        run_rounds(&[&coordinator1, &coordinator2], 3).await;
+
+        check_converged(&broker, &proxies);
+        // The failed proxy should only be replaced once.
+        assert_eq!(get_free_proxy_num(&broker), free_proxy_num - 1);
+    }
+}
@@ -0,0 +1,324 @@
+use super::core::{CoordinateError, FailureReporter, MigrationCommitter};
+use crate::common::cluster::MigrationTaskMeta;
+use chrono;
+use futures::Future;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+pub const DETECT_LOOP: &str = "detect";
+pub const HOST_SYNC_LOOP: &str = "host_sync";
+pub const FAILURE_HANDLER_LOOP: &str = "failure_handler";
+pub const REPLICA_REPAIR_LOOP: &str = "replica_repair";
+pub const MIGRATION_SYNC_LOOP: &str = "migration_sync";
+const LOOP_NAMES: [&str; 5] = [
+    DETECT_LOOP,
+    HOST_SYNC_LOOP,
+    FAILURE_HANDLER_LOOP,
+    REPLICA_REPAIR_LOOP,
+    MIGRATION_SYNC_LOOP,
+];
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LoopStatus {
+    rounds: u64,
+    // The unix timestamp in seconds when the last round finished.
+    last_run: Option<i64>,
+    // The error of the latest failed round, which is kept after later successful rounds.
+    last_error: Option<String>,
+    last_error_time: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationSyncStatus {
+    task: MigrationTaskMeta,
+    // Set if committing the migration failed and will be retried.
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceStatus {
+    broker_address: String,
+    // The reason if the service has stopped.
+    stopped: Option<String>,
+    loops: BTreeMap<String, LoopStatus>,
+    // The proxies reported as failed in the last round of detection.
+    failed_proxies: Vec<String>,
+    migration_syncs: Vec<MigrationSyncStatus>,
+}
+
+#[derive(Default)]
+struct StatusState {
+    stopped: Option<String>,
+    loops: BTreeMap<String, LoopStatus>,
+    failed_proxies: HashSet<String>,
+    detecting_failed_proxies: HashSet<String>,
+    migration_syncs: HashMap<MigrationTaskMeta, Option<String>>,
+    // The migration tasks found in the current round of migration sync.
+    syncing_migrations: HashSet<MigrationTaskMeta>,
+    // The proxies requested to synchronize the metadata immediately.
+    sync_requests: HashSet<String>,
+}
+
+// The states of the loops in a coordinator service for the admin api.
+pub struct CoordinatorStatus {
+    broker_address: String,
+    state: Mutex<StatusState>,
+}
+
+impl CoordinatorStatus {
+    pub fn new(broker_address: String) -> Self {
+        let mut state = StatusState::default();
+        for name in LOOP_NAMES.iter() {
+            state.loops.insert(name.to_string(), LoopStatus::default());
+        }
+        Self {
+            broker_address,
+            state: Mutex::new(state),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StatusState> {
+        self.state.lock().expect("CoordinatorStatus::lock")
+    }
+
+    pub fn record_round(&self, loop_name: &str, error: Option<String>) {
+        let now = chrono::Utc::now().timestamp();
+        let mut state = self.lock();
+        let status = state.loops.entry(loop_name.to_string()).or_default();
+        status.rounds += 1;
+        status.last_run = Some(now);
+        if error.is_some() {
+            status.last_error = error;
+            status.last_error_time = Some(now);
+        }
+    }
+
+    pub fn set_stopped(&self, reason: String) {
+        self.lock().stopped = Some(reason);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.lock().stopped.is_some()
+    }
+
+    // Ready after every loop has finished at least one round.
+    pub fn is_ready(&self) -> bool {
+        let state = self.lock();
+        state.stopped.is_none() && state.loops.values().all(|status| status.rounds > 0)
+    }
+
+    pub fn start_detect_round(&self) {
+        self.lock().detecting_failed_proxies.clear();
+    }
+
+    fn add_failed_proxy(&self, address: String) {
+        self.lock().detecting_failed_proxies.insert(address);
+    }
+
+    pub fn finish_detect_round(&self) {
+        let mut state = self.lock();
+        let failed_proxies = mem::take(&mut state.detecting_failed_proxies);
+        state.failed_proxies = failed_proxies;
+    }
+
+    pub fn start_migration_sync_round(&self) {
+        self.lock().syncing_migrations.clear();
+    }
+
+    // The tasks not found in this round have been committed or removed by others.
+    pub fn finish_migration_sync_round(&self) {
+        let mut state = self.lock();
+        let syncing_migrations = mem::take(&mut state.syncing_migrations);
+        state
+            .migration_syncs
+            .retain(|task, _| syncing_migrations.contains(task));
+    }
+
+    fn start_migration_sync(&self, task: MigrationTaskMeta) {
+        let mut state = self.lock();
+        state.syncing_migrations.insert(task.clone());
+        state.migration_syncs.entry(task).or_insert(None);
+    }
+
+    fn finish_migration_sync(&self, task: MigrationTaskMeta, error: Option<String>) {
+        let mut state = self.lock();
+        match error {
+            None => {
+                state.migration_syncs.remove(&task);
+            }
+            Some(err) => {
+                state.migration_syncs.insert(task, Some(err));
+            }
+        }
+    }
+
+    pub fn request_meta_sync(&self, proxy_address: String) {
+        self.lock().sync_requests.insert(proxy_address);
+    }
+
+    pub fn take_sync_requests(&self) -> Vec<String> {
+        self.lock().sync_requests.drain().collect()
+    }
+
+    pub fn get_status(&self) -> ServiceStatus {
+        let state = self.lock();
+        let mut failed_proxies: Vec<String> = state.failed_proxies.iter().cloned().collect();
+        failed_proxies.sort();
+        let migration_syncs = state
+            .migration_syncs
+            .iter()
+            .map(|(task, last_error)| MigrationSyncStatus {
+                task: task.clone(),
+                last_error: last_error.clone(),
+            })
+            .collect();
+        ServiceStatus {
+            broker_address: self.broker_address.clone(),
+            stopped: state.stopped.clone(),
+            loops: state.loops.clone(),
+            failed_proxies,
+            migration_syncs,
+        }
+    }
+}
+
+// Records the failed proxies of the current detection round.
+pub struct StatusFailureReporter<R: FailureReporter> {
+    reporter: R,
+    status: Arc<CoordinatorStatus>,
+}
+
+impl<R: FailureReporter> StatusFailureReporter<R> {
+    pub fn new(reporter: R, status: Arc<CoordinatorStatus>) -> Self {
+        Self { reporter, status }
+    }
+
+    async fn report_impl(&self, address: String) -> Result<(), CoordinateError> {
+        self.status.add_failed_proxy(address.clone());
+        self.reporter.report(address).await
+    }
+}
+
+impl<R: FailureReporter> FailureReporter for StatusFailureReporter<R> {
+    fn report<'s>(
+        &'s self,
+        address: String,
+    ) -> Pin<Box<dyn Future<Output = Result<(), CoordinateError>> + Send + 's>> {
+        Box::pin(self.report_impl(address))
+    }
+}
+
+// Records the migration tasks being committed.
+// The failed ones are kept until they're committed in the later rounds.
+pub struct StatusMigrationCommitter<C: MigrationCommitter> {
+    committer: C,
+    status: Arc<CoordinatorStatus>,
+}
+
+impl<C: MigrationCommitter> StatusMigrationCommitter<C> {
+    pub fn new(committer: C, status: Arc<CoordinatorStatus>) -> Self {
+        Self { committer, status }
+    }
+
+    async fn commit_impl(&self, meta: MigrationTaskMeta) -> Result<(), CoordinateError> {
+        self.status.start_migration_sync(meta.clone());
+        let res = self.committer.commit(meta.clone()).await;
+        let error = res.as_ref().err().map(|err| format!("{:?}", err));
+        self.status.finish_migration_sync(meta, error);
+        res
+    }
+}
+
+impl<C: MigrationCommitter> MigrationCommitter for StatusMigrationCommitter<C> {
+    fn commit<'s>(
+        &'s self,
+        meta: MigrationTaskMeta,
+    ) -> Pin<Box<dyn Future<Output = Result<(), CoordinateError>> + Send + 's>> {
+        Box::pin(self.commit_impl(meta))
+    }
+
+    fn commit_rollback<'s>(
+        &'s self,
+        proxy_address: String,
+        meta: MigrationTaskMeta,
+    ) -> Pin<Box<dyn Future<Output = Result<(), CoordinateError>> + Send + 's>> {
+        self.committer.commit_rollback(proxy_address, meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::cluster::{DBName, MigrationMeta, SlotRange, SlotRangeTag};
+
+    #[test]
+    fn test_coordinator_status() {
+        let status = CoordinatorStatus::new("127.0.0.1:7799".to_string());
+        assert!(!status.is_ready());
+        for name in LOOP_NAMES.iter() {
+            status.record_round(name, None);
+        }
+        assert!(status.is_ready());
+
+        status.record_round(DETECT_LOOP, Some("broker error".to_string()));
+        status.record_round(DETECT_LOOP, None);
+        status.start_detect_round();
+        status.add_failed_proxy("127.0.0.1:6000".to_string());
+        status.finish_detect_round();
+
+        let service_status = status.get_status();
+        let detect_status = service_status.loops.get(DETECT_LOOP).unwrap();
+        assert_eq!(detect_status.rounds, 3);
+        assert_eq!(detect_status.last_error, Some("broker error".to_string()));
+        assert_eq!(service_status.failed_proxies, vec!["127.0.0.1:6000"]);
+
+        status.request_meta_sync("127.0.0.1:6001".to_string());
+        assert_eq!(status.take_sync_requests(), vec!["127.0.0.1:6001"]);
+        assert!(status.take_sync_requests().is_empty());
+
+        status.set_stopped("service stopped".to_string());
+        assert!(status.is_stopped());
+        assert!(!status.is_ready());
+    }
+
+    fn gen_task(db_name: &str) -> MigrationTaskMeta {
+        let tag = SlotRangeTag::Migrating(MigrationMeta {
+            epoch: 7799,
+            src_proxy_address: "127.0.0.1:6000".to_string(),
+            src_node_address: "127.0.0.1:7000".to_string(),
+            dst_proxy_address: "127.0.0.1:6001".to_string(),
+            dst_node_address: "127.0.0.1:7001".to_string(),
+        });
+        MigrationTaskMeta {
+            db_name: DBName::from(db_name).unwrap(),
+            slot_range: SlotRange {
+                start: 0,
+                end: 100,
+                tag,
+            },
+        }
+    }
+
+    #[test]
+    fn test_prune_migration_syncs() {
+        let status = CoordinatorStatus::new("127.0.0.1:7799".to_string());
+        status.start_migration_sync_round();
+        status.start_migration_sync(gen_task("mydb1"));
+        status.finish_migration_sync(gen_task("mydb1"), Some("broker error".to_string()));
+        status.start_migration_sync(gen_task("mydb2"));
+        status.finish_migration_sync(gen_task("mydb2"), Some("broker error".to_string()));
+        status.finish_migration_sync_round();
+        assert_eq!(status.get_status().migration_syncs.len(), 2);
+
+        // mydb2 is committed by others and will not show up again.
+        status.start_migration_sync_round();
+        status.start_migration_sync(gen_task("mydb1"));
+        status.finish_migration_sync(gen_task("mydb1"), Some("broker error".to_string()));
+        status.finish_migration_sync_round();
+        let migration_syncs = status.get_status().migration_syncs;
+        assert_eq!(migration_syncs.len(), 1);
+        assert_eq!(migration_syncs[0].task, gen_task("mydb1"));
+    }
+}
@@ -1,45 +1,179 @@
 use super::broker::MetaDataBroker;
 use super::core::{CoordinateError, ProxyMetaRetriever, ProxyMetaSender};
 use crate::common::cluster::{DBName, Proxy, Role, SlotRange};
-use crate::common::db::{ClusterConfigMap, DBMapFlags, ProxyDBMap, ProxyDBMeta};
-use crate::common::utils::{OK_REPLY, OLD_EPOCH_REPLY};
-use crate::protocol::{RedisClient, RedisClientFactory, Resp};
+use crate::common::db::{ClusterConfigMap, DBMapFlags, ProxyDBMap, ProxyDBMeta, ProxyDBMetaDelta};
+use crate::common::utils::{BASE_EPOCH_MISMATCH_REPLY, OK_REPLY, OLD_EPOCH_REPLY};
+use crate::protocol::{BinSafeStr, RedisClient, RedisClientFactory, Resp, RespVec};
 use crate::replication::replicator::{encode_repl_meta, MasterMeta, ReplicaMeta, ReplicatorMeta};
+use btoi::btou;
 use futures::{Future, TryFutureExt};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+
+struct ProxySyncRecord {
+    epoch: u64,
+    // The metadata sent in the last successful synchronization.
+    synced_host: Option<Proxy>,
+}
+
+// The epochs of the metadata applied by the proxies,
+// learned from `UMCTL GETEPOCH` and the successful synchronization.
+// The epochs should be expired periodically to find the restarted proxies.
+#[derive(Default)]
+pub struct ProxyEpochCache {
+    records: Mutex<HashMap<String, ProxySyncRecord>>,
+}
+
+impl ProxyEpochCache {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, ProxySyncRecord>> {
+        self.records.lock().expect("ProxyEpochCache::lock")
+    }
+
+    pub fn get(&self, proxy_address: &str) -> Option<u64> {
+        self.lock().get(proxy_address).map(|record| record.epoch)
+    }
+
+    pub fn set(&self, proxy_address: String, epoch: u64) {
+        self.lock()
+            .entry(proxy_address)
+            .and_modify(|record| record.epoch = epoch)
+            .or_insert(ProxySyncRecord {
+                epoch,
+                synced_host: None,
+            });
+    }
+
+    pub fn set_synced(&self, host: Proxy) {
+        let record = ProxySyncRecord {
+            epoch: host.get_epoch(),
+            synced_host: Some(host.clone()),
+        };
+        self.lock().insert(host.get_address().to_string(), record);
+    }
+
+    // Returns the synced metadata if the proxy is still in the same epoch.
+    pub fn get_synced_host(&self, proxy_address: &str, epoch: u64) -> Option<Proxy> {
+        self.lock()
+            .get(proxy_address)
+            .and_then(|record| record.synced_host.as_ref())
+            .filter(|host| host.get_epoch() == epoch)
+            .cloned()
+    }
+
+    // The synced metadata are kept as the base of the delta updates.
+    pub fn expire_epochs(&self) {
+        let mut records = self.lock();
+        records.retain(|_, record| record.synced_host.is_some());
+        for record in records.values_mut() {
+            record.epoch = 0;
+        }
+    }
+
+    // The proxies removed from the broker will never be synchronized again.
+    pub fn retain_hosts(&self, proxy_addresses: &HashSet<String>) {
+        self.lock()
+            .retain(|address, _| proxy_addresses.contains(address));
+    }
+}
 
 pub struct ProxyMetaRespSender<F: RedisClientFactory> {
     client_factory: Arc<F>,
+    // Always send the full metadata without it.
+    epoch_cache: Option<Arc<ProxyEpochCache>>,
 }
 
 impl<F: RedisClientFactory> ProxyMetaRespSender<F> {
     pub fn new(client_factory: Arc<F>) -> Self {
-        Self { client_factory }
+        Self {
+            client_factory,
+            epoch_cache: None,
+        }
+    }
+
+    pub fn with_epoch_cache(mut self, epoch_cache: Arc<ProxyEpochCache>) -> Self {
+        self.epoch_cache = Some(epoch_cache);
+        self
     }
 }
 
 impl<F: RedisClientFactory> ProxyMetaRespSender<F> {
     async fn send_meta_impl(&self, host: Proxy) -> Result<(), CoordinateError> {
+        let address = host.get_address().to_string();
+        let epoch = host.get_epoch();
+        let cached_epoch = self
+            .epoch_cache
+            .as_ref()
+            .and_then(|cache| cache.get(&address));
+        if let Some(cached_epoch) = cached_epoch {
+            if cached_epoch >= epoch {
+                return Ok(());
+            }
+        }
+
         let mut client = self
             .client_factory
-            .create_client(host.get_address().to_string())
+            .create_client(address.clone())
             .await
             .map_err(CoordinateError::Redis)?;
-        let host_with_only_masters = filter_host_masters(host.clone());
-        send_meta(
-            &mut client,
-            "SETREPL".to_string(),
-            generate_repl_meta_cmd_args(host, DBMapFlags { force: false }),
-        )
-        .await?;
-        send_meta(
-            &mut client,
-            "SETDB".to_string(),
-            generate_host_meta_cmd_args(DBMapFlags { force: false }, host_with_only_masters),
-        )
-        .await?;
+
+        let mut base_host = None;
+        if let Some(cache) = self.epoch_cache.as_ref() {
+            if let Some(proxy_epoch) = get_proxy_epoch(&mut client).await? {
+                cache.set(address.clone(), proxy_epoch);
+                if proxy_epoch >= epoch {
+                    debug!(
+                        "skip syncing meta to {} with epoch {}",
+                        address, proxy_epoch
+                    );
+                    return Ok(());
+                }
+                base_host = cache.get_synced_host(&address, proxy_epoch);
+            }
+        }
+
+        let synced_host = self.epoch_cache.as_ref().map(|_| host.clone());
+        let flags = DBMapFlags { force: false };
+        let host_meta = generate_host_meta(flags.clone(), filter_host_masters(host.clone()));
+        let (set_db_cmd, is_delta) = match base_host {
+            Some(base_host) => {
+                let base_meta = generate_host_meta(flags.clone(), filter_host_masters(base_host));
+                let delta = ProxyDBMetaDelta::diff(&base_meta, host_meta.clone());
+                (gen_meta_cmd("SETDBDELTA", delta.to_args()), true)
+            }
+            None => (gen_meta_cmd("SETDB", host_meta.to_args()), false),
+        };
+
+        // Send them in one batch. The proxy will still report the old epoch
+        // by `UMCTL GETEPOCH` if any of them fails.
+        let commands = vec![
+            gen_meta_cmd("SETREPL", generate_repl_meta_cmd_args(host, flags)),
+            set_db_cmd,
+        ];
+        let resps = client.execute_multi(commands).await.map_err(|e| {
+            error!("failed to send meta data of host {:?}", e);
+            CoordinateError::Redis(e)
+        })?;
+        let mut resps = resps.into_iter();
+        let (set_repl_resp, set_db_resp) = match (resps.next(), resps.next(), resps.next()) {
+            (Some(set_repl_resp), Some(set_db_resp), None) => (set_repl_resp, set_db_resp),
+            _ => {
+                error!("failed to send meta, invalid number of replies");
+                return Err(CoordinateError::InvalidReply);
+            }
+        };
+        check_meta_reply("SETREPL", set_repl_resp)?;
+        match set_db_resp {
+            Resp::Error(err) if is_delta && err == BASE_EPOCH_MISMATCH_REPLY.as_bytes() => {
+                info!("fall back to full meta synchronization for {}", address);
+                send_meta(&mut client, "SETDB".to_string(), host_meta.to_args()).await?;
+            }
+            resp => check_meta_reply(if is_delta { "SETDBDELTA" } else { "SETDB" }, resp)?,
+        }
+
+        if let (Some(cache), Some(synced_host)) = (self.epoch_cache.as_ref(), synced_host) {
+            cache.set_synced(synced_host);
+        }
         Ok(())
     }
 }
@@ -91,16 +225,14 @@ impl<B: MetaDataBroker> ProxyMetaRetriever for BrokerMetaRetriever<B> {
     }
 }
 
-fn generate_host_meta_cmd_args(flags: DBMapFlags, proxy: Proxy) -> Vec<String> {
+fn generate_host_meta(flags: DBMapFlags, proxy: Proxy) -> ProxyDBMeta {
     let epoch = proxy.get_epoch();
     let clusters_config = ClusterConfigMap::new(proxy.get_clusters_config().clone());
 
     let mut db_map: HashMap<DBName, HashMap<String, Vec<SlotRange>>> = HashMap::new();
 
     for peer_proxy in proxy.get_peers().iter() {
-        let dbs = db_map
-            .entry(peer_proxy.cluster_name.clone())
-            .or_insert_with(HashMap::new);
+        let dbs = db_map.entry(peer_proxy.cluster_name.clone()).or_default();
         dbs.insert(peer_proxy.proxy_address.clone(), peer_proxy.slots.clone());
     }
     let peer = ProxyDBMap::new(db_map);
@@ -108,15 +240,45 @@ fn generate_host_meta_cmd_args(flags: DBMapFlags, proxy: Proxy) -> Vec<String> {
     let mut db_map: HashMap<DBName, HashMap<String, Vec<SlotRange>>> = HashMap::new();
 
     for node in proxy.into_nodes() {
-        let dbs = db_map
-            .entry(node.get_cluster_name().clone())
-            .or_insert_with(HashMap::new);
+        let dbs = db_map.entry(node.get_cluster_name().clone()).or_default();
         dbs.insert(node.get_address().to_string(), node.into_slots().clone());
     }
     let local = ProxyDBMap::new(db_map);
 
-    let proxy_db_meta = ProxyDBMeta::new(epoch, flags, local, peer, clusters_config);
-    proxy_db_meta.to_args()
+    ProxyDBMeta::new(epoch, flags, local, peer, clusters_config)
+}
+
+// Returns None if the proxy does not support `UMCTL GETEPOCH`.
+async fn get_proxy_epoch<C: RedisClient>(client: &mut C) -> Result<Option<u64>, CoordinateError> {
+    let cmd = vec![b"UMCTL".to_vec(), b"GETEPOCH".to_vec()];
+    let resp = client
+        .execute_single(cmd)
+        .await
+        .map_err(CoordinateError::Redis)?;
+    match resp {
+        Resp::Integer(data) => match btou::<u64>(&data) {
+            Ok(epoch) => Ok(Some(epoch)),
+            Err(_) => {
+                error!("invalid reply of UMCTL GETEPOCH: {:?}", data);
+                Err(CoordinateError::InvalidReply)
+            }
+        },
+        Resp::Error(err_str) => {
+            warn!("failed to get epoch of proxy {:?}", err_str);
+            Ok(None)
+        }
+        other => {
+            error!("invalid reply of UMCTL GETEPOCH: {:?}", other);
+            Err(CoordinateError::InvalidReply)
+        }
+    }
+}
+
+fn gen_meta_cmd(sub_command: &str, args: Vec<String>) -> Vec<BinSafeStr> {
+    debug!("sending meta {} {:?}", sub_command, args);
+    let mut cmd = vec!["UMCTL".to_string(), sub_command.to_string()];
+    cmd.extend(args);
+    cmd.into_iter().map(String::into_bytes).collect()
 }
 
 // sub_command should be SETDB
@@ -125,16 +287,17 @@ async fn send_meta<C: RedisClient>(
     sub_command: String,
     args: Vec<String>,
 ) -> Result<(), CoordinateError> {
-    debug!("sending meta {} {:?}", sub_command, args);
-    let mut cmd = vec!["UMCTL".to_string(), sub_command.clone()];
-    cmd.extend(args);
     let resp = client
-        .execute_single(cmd.into_iter().map(String::into_bytes).collect())
+        .execute_single(gen_meta_cmd(&sub_command, args))
         .await
         .map_err(|e| {
             error!("failed to send meta data of host {:?}", e);
             CoordinateError::Redis(e)
         })?;
+    check_meta_reply(&sub_command, resp)
+}
+
+fn check_meta_reply(sub_command: &str, resp: RespVec) -> Result<(), CoordinateError> {
     match resp {
         Resp::Error(err_str) => {
             if err_str == OLD_EPOCH_REPLY.as_bytes() {
@@ -228,7 +391,6 @@ mod tests {
     use crate::common::config::ClusterConfig;
     use crate::protocol::{BinSafeStr, DummyRedisClientFactory, MockRedisClient, Resp};
     use futures::{stream, StreamExt};
-    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
     use tokio;
 
@@ -336,9 +498,16 @@ mod tests {
         assert!(res.is_ok());
     }
 
-    fn create_client_func() -> impl RedisClient {
-        let call_times = Arc::new(AtomicUsize::new(0));
+    #[test]
+    fn test_check_meta_reply() {
+        assert!(check_meta_reply("SETDB", Resp::Simple(b"OK".to_vec())).is_ok());
+        let old_epoch = Resp::Error(OLD_EPOCH_REPLY.as_bytes().to_vec());
+        assert!(check_meta_reply("SETDB", old_epoch).is_ok());
+        let err = Resp::Error(b"Invalid arguments".to_vec());
+        assert!(check_meta_reply("SETDB", err).is_err());
+    }
 
+    fn create_client_func() -> impl RedisClient {
         let mut mock_client = MockRedisClient::new();
 
         let mut set_repl_cmd = vec![b"UMCTL".to_vec(), b"SETREPL".to_vec()];
@@ -358,19 +527,22 @@ mod tests {
         set_db_cmd.push(b"CONFIG".to_vec());
 
         mock_client
-            .expect_execute_single()
-            .withf(move |command: &Vec<BinSafeStr>| {
-                if call_times.load(Ordering::SeqCst) == 0 {
-                    call_times.fetch_add(1, Ordering::SeqCst);
-                    command.eq(&set_repl_cmd)
-                } else {
+            .expect_execute_multi()
+            .withf(move |commands: &Vec<Vec<BinSafeStr>>| {
+                commands.len() == 2
+                    && commands[0].eq(&set_repl_cmd)
                     // Ignore the config part
-                    let cmd = command.get(0..set_db_cmd.len()).unwrap().to_vec();
-                    cmd.eq(&set_db_cmd)
-                }
+                    && commands[1].get(0..set_db_cmd.len()) == Some(&set_db_cmd[..])
             })
-            .times(2)
-            .returning(|_| Box::pin(async { Ok(Resp::Simple(b"ok".to_vec())) }));
+            .times(1)
+            .returning(|_| {
+                Box::pin(async {
+                    Ok(vec![
+                        Resp::Simple(b"ok".to_vec()),
+                        Resp::Simple(b"ok".to_vec()),
+                    ])
+                })
+            });
 
         mock_client
     }
@@ -384,6 +556,102 @@ mod tests {
         assert!(res.is_ok());
     }
 
+    fn create_synced_client_func() -> impl RedisClient {
+        let mut mock_client = MockRedisClient::new();
+        let cmd = vec![b"UMCTL".to_vec(), b"GETEPOCH".to_vec()];
+        mock_client
+            .expect_execute_single()
+            .withf(move |command: &Vec<BinSafeStr>| command.eq(&cmd))
+            .times(1)
+            .returning(|_| Box::pin(async { Ok(Resp::Integer(b"7799".to_vec())) }));
+        mock_client.expect_execute_multi().times(0);
+        mock_client
+    }
+
+    #[tokio::test]
+    async fn test_meta_resp_sender_skip_synced_proxy() {
+        let client_factory = DummyRedisClientFactory::new(create_synced_client_func);
+        let epoch_cache = Arc::new(ProxyEpochCache::default());
+        let sender = ProxyMetaRespSender::new(Arc::new(client_factory))
+            .with_epoch_cache(epoch_cache.clone());
+        let proxy = gen_testing_proxy(Role::Master);
+        let address = proxy.get_address().to_string();
+        assert!(sender.send_meta(proxy.clone()).await.is_ok());
+        assert_eq!(epoch_cache.get(&address), Some(7799));
+        // Skipped by the cache without connecting to the proxy.
+        assert!(sender.send_meta(proxy).await.is_ok());
+    }
+
+    fn create_delta_client_func() -> impl RedisClient {
+        let mut mock_client = MockRedisClient::new();
+        let get_epoch_cmd = vec![b"UMCTL".to_vec(), b"GETEPOCH".to_vec()];
+        mock_client
+            .expect_execute_single()
+            .withf(move |command: &Vec<BinSafeStr>| command.eq(&get_epoch_cmd))
+            .times(1)
+            .returning(|_| Box::pin(async { Ok(Resp::Integer(b"7000".to_vec())) }));
+        mock_client
+            .expect_execute_multi()
+            .withf(|commands: &Vec<Vec<BinSafeStr>>| {
+                commands.len() == 2 && commands[1].get(1) == Some(&b"SETDBDELTA".to_vec())
+            })
+            .times(1)
+            .returning(|_| {
+                Box::pin(async {
+                    Ok(vec![
+                        Resp::Simple(b"ok".to_vec()),
+                        Resp::Error(BASE_EPOCH_MISMATCH_REPLY.as_bytes().to_vec()),
+                    ])
+                })
+            });
+        // Fall back to the full metadata.
+        mock_client
+            .expect_execute_single()
+            .withf(|command: &Vec<BinSafeStr>| command.get(1) == Some(&b"SETDB".to_vec()))
+            .times(1)
+            .returning(|_| Box::pin(async { Ok(Resp::Simple(b"ok".to_vec())) }));
+        mock_client
+    }
+
+    #[tokio::test]
+    async fn test_meta_resp_sender_with_delta() {
+        let client_factory = DummyRedisClientFactory::new(create_delta_client_func);
+        let epoch_cache = Arc::new(ProxyEpochCache::default());
+        let proxy = gen_testing_proxy(Role::Master);
+        let base_proxy = Proxy::new(
+            proxy.get_address().to_string(),
+            7000,
+            proxy.get_nodes().to_vec(),
+            vec![],
+            vec![],
+            proxy.get_clusters_config().clone(),
+        );
+        epoch_cache.set_synced(base_proxy);
+        epoch_cache.expire_epochs();
+
+        let sender = ProxyMetaRespSender::new(Arc::new(client_factory))
+            .with_epoch_cache(epoch_cache.clone());
+        assert!(sender.send_meta(proxy.clone()).await.is_ok());
+        assert_eq!(epoch_cache.get(proxy.get_address()), Some(7799));
+        assert!(epoch_cache
+            .get_synced_host(proxy.get_address(), 7799)
+            .is_some());
+    }
+
+    #[test]
+    fn test_epoch_cache_retain_hosts() {
+        let epoch_cache = ProxyEpochCache::default();
+        let proxy = gen_testing_proxy(Role::Master);
+        let address = proxy.get_address().to_string();
+        epoch_cache.set_synced(proxy);
+        epoch_cache.set("127.0.0.1:7001".to_string(), 7799);
+
+        let addresses: HashSet<String> = vec![address.clone()].into_iter().collect();
+        epoch_cache.retain_hosts(&addresses);
+        assert!(epoch_cache.get_synced_host(&address, 7799).is_some());
+        assert_eq!(epoch_cache.get("127.0.0.1:7001"), None);
+    }
+
     #[tokio::test]
     async fn test_meta_retriever() {
         let proxy_addr = "127.0.0.1:6000";
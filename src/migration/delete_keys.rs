@@ -1,7 +1,7 @@
 use super::task::{ScanResponse, SlotRangeArray};
 use crate::common::cluster::{DBName, SlotRange};
 use crate::common::config::AtomicMigrationConfig;
-use crate::common::db::ProxyDBMap;
+use crate::common::db::{ClusterConfigMap, ProxyDBMap};
 use crate::common::future_group::{new_auto_drop_future, FutureAutoStopHandle};
 use crate::common::resp_execution::keep_connecting_and_sending;
 use crate::migration::task::MigrationError;
@@ -45,8 +45,8 @@ impl DeleteKeysTaskMap {
     pub fn update_from_old_task_map<F: RedisClientFactory>(
         &self,
         local_db_map: &ProxyDBMap,
+        clusters_config: &ClusterConfigMap,
         left_slots_after_change: HashMap<DBName, HashMap<String, Vec<SlotRange>>>,
-        config: Arc<AtomicMigrationConfig>,
         client_factory: Arc<F>,
     ) -> (Self, Vec<Arc<DeleteKeysTask>>) {
         let mut new_task_map = HashMap::new();
@@ -72,6 +72,9 @@ impl DeleteKeysTaskMap {
 
         // Add new tasks
         for (dbname, nodes) in left_slots_after_change.into_iter() {
+            let config = Arc::new(AtomicMigrationConfig::from_config(
+                clusters_config.get(&dbname).migration_config,
+            ));
             for (address, slots) in nodes.into_iter() {
                 let db = new_task_map
                     .entry(dbname.clone())
@@ -242,3 +245,76 @@ impl DeleteKeysTask {
         self.slot_ranges.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::cluster::SlotRangeTag;
+    use crate::common::config::{ClusterConfig, MigrationConfig};
+    use crate::protocol::{Array, BinSafeStr, BulkStr, DummyRedisClientFactory, MockRedisClient};
+
+    const NODE: &str = "127.0.0.1:6379";
+
+    fn create_client_func() -> impl RedisClient {
+        let mut mock_client = MockRedisClient::new();
+        let cmd: Vec<BinSafeStr> = vec![
+            b"SCAN".to_vec(),
+            b"0".to_vec(),
+            b"COUNT".to_vec(),
+            b"7".to_vec(),
+        ];
+        mock_client
+            .expect_execute_single()
+            .withf(move |command: &Vec<BinSafeStr>| command.eq(&cmd))
+            .times(1)
+            .returning(|_| {
+                Box::pin(async {
+                    Ok(Resp::Arr(Array::Arr(vec![
+                        Resp::Bulk(BulkStr::Str(b"0".to_vec())),
+                        Resp::Arr(Array::Arr(vec![])),
+                    ])))
+                })
+            });
+        mock_client
+    }
+
+    #[tokio::test]
+    async fn test_delete_keys_with_db_config() {
+        let dbname = DBName::from("mydb").unwrap();
+        let slots = vec![SlotRange {
+            start: 0,
+            end: 100,
+            tag: SlotRangeTag::None,
+        }];
+        let mut nodes = HashMap::new();
+        nodes.insert(NODE.to_string(), slots);
+        let mut db_map = HashMap::new();
+        db_map.insert(dbname.clone(), nodes);
+        let local_db_map = ProxyDBMap::new(db_map.clone());
+
+        let config = ClusterConfig {
+            migration_config: MigrationConfig {
+                delete_count: 7,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut config_map = HashMap::new();
+        config_map.insert(dbname, config);
+        let clusters_config = ClusterConfigMap::new(config_map);
+
+        let client_factory = Arc::new(DummyRedisClientFactory::new(create_client_func));
+        let (_task_map, new_tasks) = DeleteKeysTaskMap::new().update_from_old_task_map(
+            &local_db_map,
+            &clusters_config,
+            db_map,
+            client_factory,
+        );
+        assert_eq!(new_tasks.len(), 1);
+        let fut = new_tasks[0]
+            .start()
+            .expect("test_delete_keys_with_db_config");
+        assert!(fut.await.is_ok());
+        assert!(new_tasks[0].is_finished());
+    }
+}
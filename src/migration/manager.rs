@@ -2,11 +2,12 @@ use super::scan_task::{RedisScanImportingTask, RedisScanMigratingTask};
 use super::task::{ImportingTask, MigratingTask, MigrationError, MigrationState, SwitchArg};
 use crate::common::cluster::{DBName, MigrationTaskMeta, Range, SlotRange, SlotRangeTag};
 use crate::common::config::AtomicMigrationConfig;
-use crate::common::db::ProxyDBMap;
+use crate::common::db::{ClusterConfigMap, ProxyDBMap};
 use crate::common::track::TrackedFutureRegistry;
 use crate::common::utils::{get_slot, ThreadSafe};
 use crate::migration::delete_keys::{DeleteKeysTask, DeleteKeysTaskMap};
 use crate::migration::task::MgrSubCmd;
+use crate::migration::verification::{VerificationReport, VerificationReportStore};
 use crate::protocol::RedisClientFactory;
 use crate::protocol::Resp;
 use crate::proxy::backend::{
@@ -18,10 +19,10 @@ use crate::proxy::service::ServerProxyConfig;
 use crate::proxy::slowlog::TaskEvent;
 use futures::TryFutureExt;
 use itertools::Either;
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
-type TaskRecord<T> = Either<Arc<dyn MigratingTask<Task = T>>, Arc<dyn ImportingTask<Task = T>>>;
+pub type TaskRecord<T> = Either<Arc<dyn MigratingTask<Task = T>>, Arc<dyn ImportingTask<Task = T>>>;
 type DBTask<T> = HashMap<MigrationTaskMeta, TaskRecord<T>>;
 type TaskMap<T> = HashMap<DBName, DBTask<T>>;
 type NewMigrationTuple<T> = (MigrationMap<T>, Vec<NewTask<T>>);
@@ -41,11 +42,13 @@ where
     CTF::Task: DBTag,
 {
     config: Arc<ServerProxyConfig>,
-    mgr_config: Arc<AtomicMigrationConfig>,
     client_factory: Arc<RCF>,
     sender_factory: Arc<TSF>,
     cmd_task_factory: Arc<CTF>,
     future_registry: Arc<TrackedFutureRegistry>,
+    verification_reports: Arc<VerificationReportStore>,
+    // The tasks removed from the migration map which have not stopped yet.
+    aborting_tasks: Arc<Mutex<HashSet<MigrationTaskMeta>>>,
 }
 
 impl<RCF: RedisClientFactory, TSF: CmdTaskSenderFactory + ThreadSafe, CTF>
@@ -57,7 +60,6 @@ where
 {
     pub fn new(
         config: Arc<ServerProxyConfig>,
-        mgr_config: Arc<AtomicMigrationConfig>,
         client_factory: Arc<RCF>,
         sender_factory: Arc<TSF>,
         cmd_task_factory: Arc<CTF>,
@@ -65,11 +67,12 @@ where
     ) -> Self {
         Self {
             config,
-            mgr_config,
             client_factory,
             sender_factory,
             cmd_task_factory,
             future_registry,
+            verification_reports: Arc::new(VerificationReportStore::default()),
+            aborting_tasks: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
@@ -78,20 +81,26 @@ where
         &self,
         old_migration_map: &MigrationMap<CTF::Task>,
         local_db_map: &ProxyDBMap,
+        clusters_config: &ClusterConfigMap,
         blocking_ctrl_factory: Arc<BCF>,
     ) -> NewMigrationTuple<CTF::Task> {
         old_migration_map.update_from_old_task_map(
             local_db_map,
+            clusters_config,
             self.config.clone(),
-            self.mgr_config.clone(),
             self.client_factory.clone(),
             self.sender_factory.clone(),
             self.cmd_task_factory.clone(),
             blocking_ctrl_factory,
             self.future_registry.clone(),
+            self.verification_reports.clone(),
         )
     }
 
+    pub fn get_verification_reports(&self) -> Vec<(MigrationTaskMeta, VerificationReport)> {
+        self.verification_reports.get_reports()
+    }
+
     pub fn run_tasks(&self, new_tasks: Vec<NewTask<CTF::Task>>) {
         if new_tasks.is_empty() {
             return;
@@ -157,16 +166,57 @@ where
         info!("spawn finished");
     }
 
+    pub fn abort_tasks(&self, tasks: Vec<(MigrationTaskMeta, TaskRecord<CTF::Task>)>) {
+        for (meta, task) in tasks.into_iter() {
+            self.aborting_tasks
+                .lock()
+                .expect("MigrationManager::abort_tasks")
+                .insert(meta.clone());
+            let aborting_tasks = self.aborting_tasks.clone();
+            let fut = async move {
+                let res = match task {
+                    Either::Left(migrating_task) => migrating_task.stop().await,
+                    Either::Right(importing_task) => importing_task.stop().await,
+                };
+                match res {
+                    Ok(()) | Err(MigrationError::AlreadyEnded) => (),
+                    Err(err) => error!("failed to abort migration task {:?}", err),
+                }
+                // Only report the task as aborted after it has completely stopped.
+                aborting_tasks
+                    .lock()
+                    .expect("MigrationManager::abort_tasks")
+                    .remove(&meta);
+            };
+            let fut = TrackedFutureRegistry::wrap(
+                self.future_registry.clone(),
+                fut,
+                "migration: abort task".to_string(),
+            );
+            tokio::spawn(fut);
+        }
+    }
+
+    pub fn get_aborting_tasks(&self) -> Vec<MigrationTaskMeta> {
+        self.aborting_tasks
+            .lock()
+            .expect("MigrationManager::get_aborting_tasks")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
     pub fn create_new_deleting_task_map(
         &self,
         old_deleting_task_map: &DeleteKeysTaskMap,
         local_db_map: &ProxyDBMap,
+        clusters_config: &ClusterConfigMap,
         left_slots_after_change: HashMap<DBName, HashMap<String, Vec<SlotRange>>>,
     ) -> (DeleteKeysTaskMap, Vec<Arc<DeleteKeysTask>>) {
         old_deleting_task_map.update_from_old_task_map(
             local_db_map,
+            clusters_config,
             left_slots_after_change,
-            self.mgr_config.clone(),
             self.client_factory.clone(),
         )
     }
@@ -223,7 +273,7 @@ where
             .iter()
             .map(|(db_name, tasks)| {
                 let mut lines = vec![format!("name: {}", db_name)];
-                for task_meta in tasks.keys() {
+                for (task_meta, task) in tasks.iter() {
                     if let Some(migration_meta) = task_meta.slot_range.tag.get_migration_meta() {
                         lines.push(format!(
                             "{}-{} {} -> {}",
@@ -232,6 +282,16 @@ where
                             migration_meta.src_node_address,
                             migration_meta.dst_node_address
                         ));
+                        if let Either::Left(migrating_task) = task {
+                            let stats = migrating_task.get_blocking_stats();
+                            lines.push(format!(
+                                "blocking: queued={} rejected={} time={}ms aborted={}",
+                                stats.queued_cmd_num,
+                                stats.rejected_cmd_num,
+                                stats.blocking_time.as_millis(),
+                                stats.aborted_num
+                            ));
+                        }
                     } else {
                         error!("invalid slot range migration meta");
                     }
@@ -299,6 +359,115 @@ where
         }
     }
 
+    // The tasks removed from the new metadata before finishing are canceled.
+    pub fn get_aborted_tasks(
+        &self,
+        new_migration_map: &Self,
+    ) -> Vec<(MigrationTaskMeta, TaskRecord<T>)> {
+        let mut aborted_tasks = vec![];
+        for (dbname, db) in self.task_map.iter() {
+            for (task_meta, record) in db.iter() {
+                if new_migration_map
+                    .task_map
+                    .get(dbname)
+                    .and_then(|db_task_map| db_task_map.get(task_meta))
+                    .is_some()
+                {
+                    continue;
+                }
+
+                let state = match record {
+                    Either::Left(migrating_task) => migrating_task.get_state(),
+                    Either::Right(importing_task) => importing_task.get_state(),
+                };
+                if state == MigrationState::SwitchCommitted {
+                    continue;
+                }
+                info!("abort migration task {:?}", task_meta);
+                aborted_tasks.push((task_meta.clone(), record.clone()));
+            }
+        }
+        aborted_tasks
+    }
+
+    // Once the migrating task has switched the slots to the destination,
+    // rolling it back would lose the data written to the destination.
+    // So it refuses to be canceled and keeps running until the migration is committed.
+    pub fn keep_switched_tasks(&mut self, old_migration_map: &Self, local_db_map: &ProxyDBMap) {
+        for (dbname, db) in old_migration_map.task_map.iter() {
+            for (task_meta, record) in db.iter() {
+                let migrating_task = match record {
+                    Either::Left(migrating_task) => migrating_task,
+                    Either::Right(_) => continue,
+                };
+                match migrating_task.get_state() {
+                    MigrationState::Scanning
+                    | MigrationState::FinalSwitch
+                    | MigrationState::SwitchCommitted => (),
+                    _ => continue,
+                }
+                if self
+                    .task_map
+                    .get(dbname)
+                    .map(|tasks| tasks.contains_key(task_meta))
+                    .unwrap_or(false)
+                {
+                    continue;
+                }
+                if !Self::is_rolled_back(task_meta, local_db_map) {
+                    continue;
+                }
+                warn!(
+                    "refuse to cancel migration task after switching {:?}",
+                    task_meta
+                );
+                self.task_map
+                    .entry(dbname.clone())
+                    .or_default()
+                    .insert(task_meta.clone(), record.clone());
+                self.empty = false;
+            }
+        }
+    }
+
+    // The source node owns the slot range again without the migrating tag.
+    fn is_rolled_back(task_meta: &MigrationTaskMeta, local_db_map: &ProxyDBMap) -> bool {
+        let src_node_address = match task_meta.slot_range.tag {
+            SlotRangeTag::Migrating(ref meta) => &meta.src_node_address,
+            _ => return false,
+        };
+        local_db_map
+            .get_map()
+            .get(&task_meta.db_name)
+            .and_then(|nodes| nodes.get(src_node_address))
+            .map(|slot_ranges| {
+                slot_ranges.iter().any(|slot_range| {
+                    slot_range.tag == SlotRangeTag::None
+                        && slot_range.start == task_meta.slot_range.start
+                        && slot_range.end == task_meta.slot_range.end
+                })
+            })
+            .unwrap_or(false)
+    }
+
+    // Copy the tasks of the databases not changed by the metadata delta.
+    pub fn keep_unchanged_dbs(&mut self, old_migration_map: &Self, changed_dbs: &HashSet<DBName>) {
+        for (dbname, db) in old_migration_map.task_map.iter() {
+            if changed_dbs.contains(dbname) {
+                continue;
+            }
+            self.task_map.insert(dbname.clone(), db.clone());
+        }
+        self.empty = self.task_map.is_empty();
+    }
+
+    pub fn get_task_metas(&self) -> Vec<MigrationTaskMeta> {
+        self.task_map
+            .values()
+            .flat_map(|tasks| tasks.keys().cloned())
+            .collect()
+    }
+
     pub fn get_left_slots_after_change(
         &self,
         new_migration_map: &Self,
@@ -345,13 +514,14 @@ where
     pub fn update_from_old_task_map<RCF, CTF, BCF, TSF>(
         &self,
         local_db_map: &ProxyDBMap,
+        clusters_config: &ClusterConfigMap,
         config: Arc<ServerProxyConfig>,
-        mgr_config: Arc<AtomicMigrationConfig>,
         client_factory: Arc<RCF>,
         sender_factory: Arc<TSF>,
         cmd_task_factory: Arc<CTF>,
         blocking_ctrl_map: Arc<BCF>,
         future_registry: Arc<TrackedFutureRegistry>,
+        verification_reports: Arc<VerificationReportStore>,
     ) -> (Self, Vec<NewTask<T>>)
     where
         RCF: RedisClientFactory,
@@ -434,15 +604,19 @@ where
                             }
 
                             let ctrl = blocking_ctrl_map.create(meta.src_node_address.clone());
+                            let mgr_config = Arc::new(AtomicMigrationConfig::from_config(
+                                clusters_config.get(db_name).migration_config,
+                            ));
                             let task = Arc::new(RedisScanMigratingTask::new(
                                 config.clone(),
-                                mgr_config.clone(),
+                                mgr_config,
                                 db_name.clone(),
                                 (slot_range.start, slot_range.end),
                                 meta.clone(),
                                 client_factory.clone(),
                                 ctrl,
                                 future_registry.clone(),
+                                verification_reports.clone(),
                             ));
                             new_tasks.push(NewTask {
                                 db_name: db_name.clone(),
@@ -475,9 +649,12 @@ where
                                 continue;
                             }
 
+                            let mgr_config = Arc::new(AtomicMigrationConfig::from_config(
+                                clusters_config.get(db_name).migration_config,
+                            ));
                             let task = Arc::new(RedisScanImportingTask::new(
                                 config.clone(),
-                                mgr_config.clone(),
+                                mgr_config,
                                 db_name.clone(),
                                 meta.clone(),
                                 client_factory.clone(),
@@ -3,3 +3,4 @@ pub mod manager;
 pub mod scan_migration;
 mod scan_task;
 pub mod task;
+pub mod verification;
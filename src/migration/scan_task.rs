@@ -3,8 +3,9 @@ use super::task::{
     AtomicMigrationState, ImportingTask, MgrSubCmd, MigratingTask, MigrationError, MigrationState,
     SwitchArg,
 };
+use super::verification::{verify_slot_range, VerificationReportStore};
 use crate::common::cluster::{DBName, MigrationMeta, MigrationTaskMeta, SlotRange, SlotRangeTag};
-use crate::common::config::AtomicMigrationConfig;
+use crate::common::config::{AtomicMigrationConfig, VerificationMode};
 use crate::common::resp_execution::keep_connecting_and_sending_cmd;
 use crate::common::track::TrackedFutureRegistry;
 use crate::common::utils::{pretty_print_bytes, ThreadSafe, NOT_READY_FOR_SWITCHING_REPLY};
@@ -14,7 +15,9 @@ use crate::protocol::{RedisClientError, RedisClientFactory, Resp};
 use crate::proxy::backend::{
     CmdTask, CmdTaskFactory, CmdTaskSender, CmdTaskSenderFactory, RedirectionSenderFactory, ReqTask,
 };
-use crate::proxy::blocking::{BlockingHandle, BlockingHintTask, TaskBlockingController};
+use crate::proxy::blocking::{
+    BlockingHandle, BlockingHintTask, BlockingStats, TaskBlockingController,
+};
 use crate::proxy::database::DBSendError;
 use crate::proxy::migration_backend::RestoreDataCmdTaskHandler;
 use crate::proxy::service::ServerProxyConfig;
@@ -22,10 +25,14 @@ use atomic_option::AtomicOption;
 use futures::channel::oneshot;
 use futures::{future, select, Future, FutureExt, TryFutureExt};
 use futures_timer::Delay;
+use std::cmp::min;
 use std::pin::Pin;
-use std::sync::atomic::Ordering;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const BLOCKING_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_BLOCKING_RETRY_TIMES: usize = 16;
 
 pub struct RedisScanMigratingTask<RCF, T, BC>
 where
@@ -45,6 +52,9 @@ where
     task: ScanMigrationTask,
     blocking_ctrl: Arc<BC>,
     future_registry: Arc<TrackedFutureRegistry>,
+    verification_reports: Arc<VerificationReportStore>,
+    blocking_stats: Mutex<BlockingStats>,
+    stopped: Arc<AtomicBool>,
 }
 
 impl<RCF, T, BC> RedisScanMigratingTask<RCF, T, BC>
@@ -63,6 +73,7 @@ where
         client_factory: Arc<RCF>,
         blocking_ctrl: Arc<BC>,
         future_registry: Arc<TrackedFutureRegistry>,
+        verification_reports: Arc<VerificationReportStore>,
     ) -> Self {
         let (stop_signal_sender, stop_signal_receiver) = oneshot::channel();
         let task = ScanMigrationTask::new(
@@ -86,6 +97,9 @@ where
             task,
             blocking_ctrl,
             future_registry,
+            verification_reports,
+            blocking_stats: Mutex::new(BlockingStats::default()),
+            stopped: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -100,18 +114,22 @@ where
         }
     }
 
+    fn gen_task_meta(&self) -> MigrationTaskMeta {
+        MigrationTaskMeta {
+            db_name: self.db_name.clone(),
+            slot_range: SlotRange {
+                start: self.slot_range.0,
+                end: self.slot_range.1,
+                tag: SlotRangeTag::Migrating(self.meta.clone()),
+            },
+        }
+    }
+
     fn gen_switch_arg(&self, sub_cmd: &str) -> Vec<String> {
         let mut cmd = vec!["UMCTL".to_string(), sub_cmd.to_string()];
         let arg = SwitchArg {
             version: UNDERMOON_MIGRATION_VERSION.to_string(),
-            meta: MigrationTaskMeta {
-                db_name: self.db_name.clone(),
-                slot_range: SlotRange {
-                    start: self.slot_range.0,
-                    end: self.slot_range.1,
-                    tag: SlotRangeTag::Migrating(self.meta.clone()),
-                },
-            },
+            meta: self.gen_task_meta(),
         }
         .into_strings();
         cmd.extend(arg.into_iter());
@@ -163,17 +181,22 @@ where
         info!("pre_check done");
     }
 
-    async fn pre_block(&self) -> BlockingHandle<BC::Sender> {
+    async fn pre_block(
+        &self,
+        blocking_handle: &BlockingHandle<BC::Sender>,
+    ) -> Result<(), MigrationError> {
         let state = self.state.clone();
 
         let ctrl = self.blocking_ctrl.clone();
-        let blocking_handle = ctrl.start_blocking();
         while !ctrl.blocking_done() {
+            if blocking_handle.has_rejected_cmd() {
+                return Err(MigrationError::BlockingQueueFull);
+            }
             Delay::new(Duration::from_millis(1)).await;
         }
         state.set_state(MigrationState::PreSwitch);
         info!("pre_block done");
-        blocking_handle
+        Ok(())
     }
 
     async fn pre_switch(&self) {
@@ -252,6 +275,56 @@ where
         }
     }
 
+    // Compare the keys in the source and the destination before the final switch,
+    // after which the source will delete its keys once the migration is committed.
+    // The destination has been serving the slots since the PRESWITCH,
+    // so the keys written during the migration can also be mismatched.
+    // Thus the report is only recorded for inspection and never blocks the final switch,
+    // which is the only way to get out of the `Scanning` state.
+    async fn verify(&self, deadline: Instant) {
+        if self.mgr_config.get_verification() == VerificationMode::Disabled {
+            return;
+        }
+
+        let verify_fut = verify_slot_range(
+            self.client_factory.clone(),
+            self.meta.src_node_address.clone(),
+            self.meta.dst_node_address.clone(),
+            self.slot_range,
+            self.mgr_config.clone(),
+            self.stopped.clone(),
+        );
+        let mut timeout_fut = Delay::new(deadline.saturating_duration_since(Instant::now())).fuse();
+        let res = select! {
+            res = verify_fut.fuse() => res,
+            () = timeout_fut => {
+                error!("verification not finished before the deadline {:?}", self.meta);
+                return;
+            }
+        };
+        let report = match res {
+            Ok(report) => report,
+            Err(err) => {
+                error!("failed to verify migrated keys: {:?} {:?}", err, self.meta);
+                return;
+            }
+        };
+
+        let mismatch_num = report.get_mismatch_num();
+        if mismatch_num == 0 {
+            info!(
+                "verification done: {} keys checked {:?}",
+                report.checked, self.meta
+            );
+        } else {
+            error!(
+                "verification found {} mismatched keys in {} keys: {:?} {:?}",
+                mismatch_num, report.checked, report.mismatched_keys, self.meta
+            );
+        }
+        self.verification_reports.add(self.gen_task_meta(), report);
+    }
+
     async fn final_switch(&self) {
         let state = self.state.clone();
         let meta = self.meta.clone();
@@ -295,47 +368,106 @@ where
     }
 
     async fn run(&self) -> Result<(), MigrationError> {
-        let final_switch = self.final_switch();
-
         let timeout = Duration::from_secs(self.mgr_config.get_max_migration_time());
-        let mut timeout_fut = Delay::new(timeout).fuse();
-        select! {
-            () = timeout_fut => error!("migration timeout after {:?}, force to commit migration", timeout),
-            res = self.run_migration().fuse() => res?,
-        };
-        final_switch.await;
+        let deadline = Instant::now() + timeout;
+        self.pre_check_and_switch(deadline).await?;
+
+        // The destination is serving the slots now, so there's no way back.
+        // The final switch is only sent after all the keys are migrated.
+        self.scan_migrate().await?;
+        self.verify(deadline).await;
+        self.final_switch().await;
 
         Ok(())
     }
 
-    async fn run_migration(&self) -> Result<(), MigrationError> {
-        let pre_check = self.pre_check();
-        let pre_block = self.pre_block();
-        let pre_switch = self.pre_switch();
-        let scan_migrate = self.scan_migrate();
+    // The migration can only give up before the PRESWITCH is accepted.
+    // It then stops in the `PreCheck` state where the source still owns the slots,
+    // so that the broker can roll it back by canceling the migration.
+    async fn pre_check_and_switch(&self, deadline: Instant) -> Result<(), MigrationError> {
+        let mut retry_times = 0;
+        loop {
+            let mut timeout_fut =
+                Delay::new(deadline.saturating_duration_since(Instant::now())).fuse();
+            select! {
+                () = self.pre_check().fuse() => (),
+                () = timeout_fut => {
+                    error!("migration timeout before switching {:?}", self.meta);
+                    self.state.set_state(MigrationState::PreCheck);
+                    return Err(MigrationError::Timeout);
+                }
+            };
 
-        pre_check.await;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let err = match self.block_and_pre_switch(remaining).await {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
 
-        let blocking = async move {
-            let blocking_handle = pre_block.await;
-            pre_switch.await;
-            blocking_handle.stop();
-        };
+            retry_times += 1;
+            if retry_times >= MAX_BLOCKING_RETRY_TIMES
+                || Instant::now() + BLOCKING_RETRY_INTERVAL >= deadline
+            {
+                error!(
+                    "give up migration after {} blocking attempts: {:?} {:?}",
+                    retry_times, err, self.meta
+                );
+                return Err(err);
+            }
+            warn!(
+                "blocking aborted {:?}, retry after {:?}: {:?}",
+                err, BLOCKING_RETRY_INTERVAL, self.meta
+            );
+            Delay::new(BLOCKING_RETRY_INTERVAL).await;
+        }
+    }
 
+    // Commands are blocked until the destination accepts the PRESWITCH.
+    // If the blocking budget is exceeded before the PRESWITCH is accepted,
+    // the blocking will be aborted and the slots will be served by the source again.
+    async fn block_and_pre_switch(&self, remaining: Duration) -> Result<(), MigrationError> {
         let max_blocking_time = self.mgr_config.get_max_blocking_time();
-        let max_blocking_time = Duration::from_millis(max_blocking_time);
-        let blocking_timeout = Delay::new(max_blocking_time);
+        let max_blocking_time = min(Duration::from_millis(max_blocking_time), remaining);
+        let mut blocking_timeout = Delay::new(max_blocking_time).fuse();
+        let max_queue_size = self.mgr_config.get_max_blocking_queue_size() as usize;
 
+        let blocking_handle = self.blocking_ctrl.start_blocking(max_queue_size);
         let res = select! {
-            () = blocking.fuse() => Ok(()),
-            () = blocking_timeout.fuse() => Err(MigrationError::Timeout),
+            res = self.pre_block(&blocking_handle).fuse() => res,
+            () = blocking_timeout => Err(MigrationError::Timeout),
+        };
+        let res = match res {
+            Ok(()) => select! {
+                () = self.pre_switch().fuse() => Ok(()),
+                () = blocking_timeout => Err(MigrationError::Timeout),
+            },
+            Err(err) => Err(err),
         };
 
         if let Err(err) = res {
-            error!("Migration failed {:?}. Force to go ahead.", err);
+            // The state needs to be reset before releasing the blocked commands
+            // so that they will be sent to the source.
+            self.state.set_state(MigrationState::PreCheck);
+            self.record_blocking_stats(blocking_handle.get_stats(), true);
+            drop(blocking_handle);
+            return Err(err);
         }
 
-        scan_migrate.await
+        self.record_blocking_stats(blocking_handle.get_stats(), false);
+        blocking_handle.stop();
+        Ok(())
+    }
+
+    fn record_blocking_stats(&self, stats: BlockingStats, aborted: bool) {
+        info!(
+            "migration blocking stats {:?} aborted: {} {:?}",
+            stats, aborted, self.meta
+        );
+        let mut blocking_stats = self
+            .blocking_stats
+            .lock()
+            .expect("RedisScanMigratingTask::record_blocking_stats");
+        blocking_stats.add_attempt(&stats, aborted);
     }
 }
 
@@ -379,6 +511,7 @@ where
     }
 
     fn stop<'s>(&'s self) -> Pin<Box<dyn Future<Output = Result<(), MigrationError>> + Send + 's>> {
+        self.stopped.store(true, Ordering::SeqCst);
         self.task.stop();
         let r = self.send_stop_signal();
         Box::pin(async { r })
@@ -411,6 +544,13 @@ where
     fn get_state(&self) -> MigrationState {
         self.state.get_state()
     }
+
+    fn get_blocking_stats(&self) -> BlockingStats {
+        self.blocking_stats
+            .lock()
+            .expect("RedisScanMigratingTask::get_blocking_stats")
+            .clone()
+    }
 }
 
 impl<RCF, T, BC> Drop for RedisScanMigratingTask<RCF, T, BC>
@@ -583,3 +723,198 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::config::MigrationConfig;
+    use crate::protocol::{
+        Array, BinSafeStr, BulkStr, DummyRedisClientFactory, MockRedisClient, OptionalMulti,
+        RedisClient,
+    };
+    use crate::proxy::backend::BackendError;
+    use crate::proxy::blocking::{BlockingCmdTaskSender, CounterTask, TaskBlockingQueue};
+    use crate::proxy::session::CmdCtx;
+    use std::num::NonZeroUsize;
+    use std::sync::atomic::{AtomicI64, AtomicUsize};
+
+    struct DummyInnerSender;
+
+    impl CmdTaskSender for DummyInnerSender {
+        type Task = CounterTask<CmdCtx>;
+
+        fn send(&self, cmd_task: Self::Task) -> Result<(), BackendError> {
+            cmd_task.set_resp_result(Ok(Resp::Simple(b"OK".to_vec())));
+            Ok(())
+        }
+    }
+
+    struct DummyBlockingTaskSender;
+
+    impl CmdTaskSender for DummyBlockingTaskSender {
+        type Task = CmdCtx;
+
+        fn send(&self, cmd_task: Self::Task) -> Result<(), BackendError> {
+            cmd_task.set_resp_result(Ok(Resp::Simple(b"OK".to_vec())));
+            Ok(())
+        }
+    }
+
+    impl BlockingCmdTaskSender for DummyBlockingTaskSender {}
+
+    type TestMigratingTask<F> = RedisScanMigratingTask<
+        F,
+        CmdCtx,
+        TaskBlockingQueue<DummyInnerSender, DummyBlockingTaskSender>,
+    >;
+
+    fn gen_proxy_config() -> Arc<ServerProxyConfig> {
+        let one = NonZeroUsize::new(1).expect("gen_proxy_config");
+        Arc::new(ServerProxyConfig {
+            address: "127.0.0.1:5299".to_string(),
+            announce_address: "127.0.0.1:5299".to_string(),
+            auto_select_db: false,
+            slowlog_len: one,
+            slowlog_log_slower_than: AtomicI64::new(0),
+            thread_number: one,
+            session_channel_size: 1,
+            backend_channel_size: 1,
+            backend_conn_num: one,
+            backend_batch_min_time: 0,
+            backend_batch_max_time: 0,
+            backend_batch_buf: one,
+            session_batch_min_time: 0,
+            session_batch_max_time: 0,
+            session_batch_buf: one,
+        })
+    }
+
+    fn gen_task<F: RedisClientFactory>(
+        client_factory: F,
+        config: MigrationConfig,
+    ) -> TestMigratingTask<F> {
+        let meta = MigrationMeta {
+            epoch: 233,
+            src_proxy_address: "127.0.0.1:7000".to_string(),
+            src_node_address: "127.0.0.1:7001".to_string(),
+            dst_proxy_address: "127.0.0.2:7000".to_string(),
+            dst_node_address: "127.0.0.2:7001".to_string(),
+        };
+        let blocking_ctrl =
+            TaskBlockingQueue::new(DummyInnerSender, Arc::new(DummyBlockingTaskSender));
+        RedisScanMigratingTask::new(
+            gen_proxy_config(),
+            Arc::new(AtomicMigrationConfig::from_config(config)),
+            DBName::from("mydb").expect("gen_task"),
+            (0, 16383),
+            meta,
+            Arc::new(client_factory),
+            Arc::new(blocking_ctrl),
+            Arc::new(TrackedFutureRegistry::default()),
+            Arc::new(VerificationReportStore::default()),
+        )
+    }
+
+    fn gen_digest_client(value: &'static str, scan: bool) -> MockRedisClient {
+        let mut mock_client = MockRedisClient::new();
+        if scan {
+            mock_client.expect_execute_single().returning(|_| {
+                Box::pin(async {
+                    Ok(Resp::Arr(Array::Arr(vec![
+                        Resp::Bulk(BulkStr::Str(b"0".to_vec())),
+                        Resp::Arr(Array::Arr(vec![Resp::Bulk(BulkStr::Str(b"key".to_vec()))])),
+                    ])))
+                })
+            });
+        }
+        mock_client.expect_execute_multi().returning(move |_| {
+            Box::pin(async move {
+                Ok(vec![
+                    Resp::Simple(b"string".to_vec()),
+                    Resp::Bulk(BulkStr::Str(value.as_bytes().to_vec())),
+                ])
+            })
+        });
+        mock_client
+    }
+
+    #[tokio::test]
+    async fn test_verify_key_written_during_migration() {
+        let created = AtomicUsize::new(0);
+        // The first client is for the source and the second one is for the destination,
+        // where the key was overwritten after the PRESWITCH.
+        let client_factory = DummyRedisClientFactory::new(move || {
+            if created.fetch_add(1, Ordering::SeqCst) == 0 {
+                gen_digest_client("old_value", true)
+            } else {
+                gen_digest_client("new_value", false)
+            }
+        });
+        let config = MigrationConfig {
+            verification: VerificationMode::Full,
+            ..Default::default()
+        };
+        let task = gen_task(client_factory, config);
+
+        task.verify(Instant::now() + Duration::from_secs(10)).await;
+        let reports = task.verification_reports.get_reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].1.checked, 1);
+        assert_eq!(reports[0].1.digest_mismatch, 1);
+    }
+
+    fn gen_switch_reply(command: &OptionalMulti<Vec<BinSafeStr>>, precheck_ok: bool) -> RespVec {
+        let sub_cmd = match command {
+            OptionalMulti::Single(cmd) => cmd.get(1).cloned().unwrap_or_default(),
+            OptionalMulti::Multi(_) => vec![],
+        };
+        match sub_cmd.as_slice() {
+            b"PRECHECK" if precheck_ok => Resp::Simple(b"OK".to_vec()),
+            b"FINALSWITCH" => panic!("unexpected FINALSWITCH"),
+            _ => Resp::Error(NOT_READY_FOR_SWITCHING_REPLY.to_string().into_bytes()),
+        }
+    }
+
+    fn gen_switch_client(precheck_ok: bool) -> impl RedisClient {
+        let mut mock_client = MockRedisClient::new();
+        mock_client.expect_execute().returning(move |command| {
+            let reply = gen_switch_reply(&command, precheck_ok);
+            Box::pin(async move { Ok(OptionalMulti::Single(reply)) })
+        });
+        mock_client
+    }
+
+    #[tokio::test]
+    async fn test_timeout_before_switch() {
+        let client_factory = DummyRedisClientFactory::new(|| gen_switch_client(false));
+        let config = MigrationConfig {
+            max_migration_time: 1,
+            ..Default::default()
+        };
+        let task = gen_task(client_factory, config);
+
+        match task.run().await {
+            Err(MigrationError::Timeout) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(task.get_state(), MigrationState::PreCheck);
+    }
+
+    #[tokio::test]
+    async fn test_give_up_blocking() {
+        let client_factory = DummyRedisClientFactory::new(|| gen_switch_client(true));
+        let config = MigrationConfig {
+            max_migration_time: 1,
+            max_blocking_time: 10,
+            ..Default::default()
+        };
+        let task = gen_task(client_factory, config);
+
+        match task.run().await {
+            Err(MigrationError::Timeout) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(task.get_state(), MigrationState::PreCheck);
+        assert_eq!(task.get_blocking_stats().aborted_num, 1);
+    }
+}
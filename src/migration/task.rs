@@ -2,7 +2,7 @@ use crate::common::cluster::MigrationTaskMeta;
 use crate::common::utils::{get_resp_bytes, get_resp_strings, get_slot, ThreadSafe};
 use crate::protocol::{Array, BinSafeStr, BulkStr, RedisClientError, Resp, RespSlice, RespVec};
 use crate::proxy::backend::CmdTask;
-use crate::proxy::blocking::BlockingHintTask;
+use crate::proxy::blocking::{BlockingHintTask, BlockingStats};
 use crate::proxy::database::DBSendError;
 use crate::replication::replicator::ReplicatorError;
 use futures::Future;
@@ -77,6 +77,7 @@ pub trait MigratingTask: ThreadSafe {
     fn stop<'s>(&'s self) -> Pin<Box<dyn Future<Output = Result<(), MigrationError>> + Send + 's>>;
     fn send(&self, cmd_task: Self::Task) -> Result<(), DBSendError<BlockingHintTask<Self::Task>>>;
     fn get_state(&self) -> MigrationState;
+    fn get_blocking_stats(&self) -> BlockingStats;
 }
 
 pub trait ImportingTask: ThreadSafe {
@@ -137,6 +138,7 @@ pub enum MigrationError {
     RedisClient(RedisClientError),
     Io(io::Error),
     Timeout,
+    BlockingQueueFull,
 }
 
 impl fmt::Display for MigrationError {
@@ -0,0 +1,342 @@
+use super::task::{ScanResponse, SlotRangeArray};
+use crate::common::cluster::MigrationTaskMeta;
+use crate::common::config::{AtomicMigrationConfig, VerificationMode};
+use crate::common::utils::pretty_print_bytes;
+use crate::protocol::{BulkStr, RedisClient, RedisClientError, RedisClientFactory, Resp, RespVec};
+use crc64::crc64;
+use futures_timer::Delay;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const KEY_NOT_FOUND_TYPE: &[u8] = b"none";
+const MAX_MISMATCHED_KEYS: usize = 16;
+const MAX_REPORT_NUM: usize = 64;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum KeyMismatch {
+    Missing,
+    TypeMismatch,
+    DigestMismatch,
+}
+
+impl KeyMismatch {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Missing => "missing",
+            Self::TypeMismatch => "type_mismatch",
+            Self::DigestMismatch => "digest_mismatch",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyDigest {
+    key_type: Vec<u8>,
+    // The checksum of the DUMP payload.
+    digest: Option<u64>,
+}
+
+impl KeyDigest {
+    fn from_resps(type_resp: RespVec, dump_resp: RespVec) -> Result<Self, RedisClientError> {
+        let key_type = match type_resp {
+            Resp::Simple(key_type) | Resp::Bulk(BulkStr::Str(key_type)) => key_type,
+            others => {
+                error!("failed to get key type: {:?}", others);
+                return Err(RedisClientError::InvalidReply);
+            }
+        };
+        let digest = match dump_resp {
+            Resp::Bulk(BulkStr::Str(raw_data)) => Some(crc64(0, raw_data.as_slice())),
+            Resp::Bulk(BulkStr::Nil) => None,
+            others => {
+                error!("failed to dump data: {:?}", others);
+                return Err(RedisClientError::InvalidReply);
+            }
+        };
+        Ok(Self { key_type, digest })
+    }
+
+    fn exists(&self) -> bool {
+        self.key_type.as_slice() != KEY_NOT_FOUND_TYPE && self.digest.is_some()
+    }
+}
+
+pub fn compare_key(src: &KeyDigest, dst: &KeyDigest) -> Option<KeyMismatch> {
+    // The key has been deleted or expired in the source after scanning.
+    if !src.exists() {
+        return None;
+    }
+    if !dst.exists() {
+        Some(KeyMismatch::Missing)
+    } else if src.key_type != dst.key_type {
+        Some(KeyMismatch::TypeMismatch)
+    } else if src.digest != dst.digest {
+        Some(KeyMismatch::DigestMismatch)
+    } else {
+        None
+    }
+}
+
+// Note that the keys written to the destination after being migrated
+// will also be reported as mismatched,
+// so the report only indicates the keys that need a closer look.
+#[derive(Debug, Clone, Default)]
+pub struct VerificationReport {
+    pub checked: u64,
+    pub missing: u64,
+    pub type_mismatch: u64,
+    pub digest_mismatch: u64,
+    pub mismatched_keys: Vec<(Vec<u8>, KeyMismatch)>,
+}
+
+impl VerificationReport {
+    fn record(&mut self, key: Vec<u8>, mismatch: Option<KeyMismatch>) {
+        self.checked += 1;
+        let mismatch = match mismatch {
+            Some(mismatch) => mismatch,
+            None => return,
+        };
+        match mismatch {
+            KeyMismatch::Missing => self.missing += 1,
+            KeyMismatch::TypeMismatch => self.type_mismatch += 1,
+            KeyMismatch::DigestMismatch => self.digest_mismatch += 1,
+        }
+        if self.mismatched_keys.len() < MAX_MISMATCHED_KEYS {
+            self.mismatched_keys.push((key, mismatch));
+        }
+    }
+
+    pub fn get_mismatch_num(&self) -> u64 {
+        self.missing + self.type_mismatch + self.digest_mismatch
+    }
+
+    pub fn into_strings(self) -> Vec<String> {
+        let mut strs = vec![
+            "checked".to_string(),
+            self.checked.to_string(),
+            "missing".to_string(),
+            self.missing.to_string(),
+            "type_mismatch".to_string(),
+            self.type_mismatch.to_string(),
+            "digest_mismatch".to_string(),
+            self.digest_mismatch.to_string(),
+        ];
+        for (key, mismatch) in self.mismatched_keys.into_iter() {
+            strs.push(pretty_print_bytes(key.as_slice()));
+            strs.push(mismatch.as_str().to_string());
+        }
+        strs
+    }
+}
+
+// Keeps the latest reports so that they can still be retrieved
+// after the migration is committed and the task is removed.
+#[derive(Default)]
+pub struct VerificationReportStore {
+    reports: Mutex<VecDeque<(MigrationTaskMeta, VerificationReport)>>,
+}
+
+impl VerificationReportStore {
+    pub fn add(&self, meta: MigrationTaskMeta, report: VerificationReport) {
+        let mut reports = self.reports.lock().expect("VerificationReportStore::add");
+        reports.retain(|(m, _)| m != &meta);
+        if reports.len() >= MAX_REPORT_NUM {
+            reports.pop_front();
+        }
+        reports.push_back((meta, report));
+    }
+
+    pub fn get_reports(&self) -> Vec<(MigrationTaskMeta, VerificationReport)> {
+        self.reports
+            .lock()
+            .expect("VerificationReportStore::get_reports")
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+pub async fn verify_slot_range<F: RedisClientFactory>(
+    client_factory: Arc<F>,
+    src_address: String,
+    dst_address: String,
+    slot_range: (usize, usize),
+    config: Arc<AtomicMigrationConfig>,
+    stopped: Arc<AtomicBool>,
+) -> Result<VerificationReport, RedisClientError> {
+    let mode = config.get_verification();
+    let sample_count = config.get_verification_sample_count();
+    let scan_count = config.get_scan_count();
+    let interval = min(
+        Duration::from_micros(config.get_scan_interval()),
+        Duration::from_millis(10),
+    );
+    let slot_ranges = SlotRangeArray {
+        ranges: vec![slot_range],
+    };
+
+    let mut src_client = client_factory.create_client(src_address).await?;
+    let mut dst_client = client_factory.create_client(dst_address).await?;
+
+    let mut report = VerificationReport::default();
+    let mut index = 0;
+    loop {
+        if stopped.load(Ordering::SeqCst) {
+            return Err(RedisClientError::Canceled);
+        }
+        let scan_cmd = vec![
+            "SCAN".to_string(),
+            index.to_string(),
+            "COUNT".to_string(),
+            scan_count.to_string(),
+        ];
+        let byte_cmd = scan_cmd.into_iter().map(|s| s.into_bytes()).collect();
+        let resp = src_client.execute_single(byte_cmd).await?;
+        let ScanResponse { next_index, keys } =
+            ScanResponse::parse_scan(resp).ok_or(RedisClientError::InvalidReply)?;
+
+        let mut keys: Vec<_> = keys
+            .into_iter()
+            .filter(|key| slot_ranges.is_key_inside(key.as_slice()))
+            .collect();
+        if mode == VerificationMode::Sample {
+            keys.truncate(sample_count.saturating_sub(report.checked) as usize);
+        }
+
+        if !keys.is_empty() {
+            let src_digests = get_key_digests(&mut src_client, &keys).await?;
+            let dst_digests = get_key_digests(&mut dst_client, &keys).await?;
+            for ((key, src), dst) in keys.into_iter().zip(src_digests).zip(dst_digests) {
+                let mismatch = compare_key(&src, &dst);
+                if src.exists() {
+                    report.record(key, mismatch);
+                }
+            }
+        }
+
+        if next_index == 0 {
+            break;
+        }
+        if mode == VerificationMode::Sample && report.checked >= sample_count {
+            break;
+        }
+        index = next_index;
+        Delay::new(interval).await;
+    }
+
+    Ok(report)
+}
+
+async fn get_key_digests<C: RedisClient>(
+    client: &mut C,
+    keys: &[Vec<u8>],
+) -> Result<Vec<KeyDigest>, RedisClientError> {
+    let mut commands = vec![];
+    for key in keys {
+        commands.push(vec!["TYPE".to_string().into_bytes(), key.clone()]);
+        commands.push(vec!["DUMP".to_string().into_bytes(), key.clone()]);
+    }
+
+    let resps = client.execute_multi(commands).await?;
+    if resps.len() != 2 * keys.len() {
+        error!(
+            "mismatch batch result number, expected {}, found {}",
+            2 * keys.len(),
+            resps.len()
+        );
+        return Err(RedisClientError::InvalidReply);
+    }
+
+    let mut digests = Vec::with_capacity(keys.len());
+    let mut resp_iter = resps.into_iter();
+    while let (Some(type_resp), Some(dump_resp)) = (resp_iter.next(), resp_iter.next()) {
+        digests.push(KeyDigest::from_resps(type_resp, dump_resp)?);
+    }
+    Ok(digests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{DummyRedisClientFactory, MockRedisClient};
+
+    fn gen_digest(key_type: &str, dump: Option<&str>) -> KeyDigest {
+        let dump_resp = match dump {
+            Some(data) => Resp::Bulk(BulkStr::Str(data.as_bytes().to_vec())),
+            None => Resp::Bulk(BulkStr::Nil),
+        };
+        KeyDigest::from_resps(Resp::Simple(key_type.as_bytes().to_vec()), dump_resp)
+            .expect("gen_digest")
+    }
+
+    #[test]
+    fn test_compare_key() {
+        let src = gen_digest("string", Some("value"));
+        assert_eq!(compare_key(&src, &src.clone()), None);
+        assert_eq!(
+            compare_key(&src, &gen_digest("none", None)),
+            Some(KeyMismatch::Missing)
+        );
+        assert_eq!(
+            compare_key(&src, &gen_digest("list", Some("value"))),
+            Some(KeyMismatch::TypeMismatch)
+        );
+        assert_eq!(
+            compare_key(&src, &gen_digest("string", Some("other_value"))),
+            Some(KeyMismatch::DigestMismatch)
+        );
+        assert_eq!(
+            compare_key(
+                &gen_digest("none", None),
+                &gen_digest("string", Some("value"))
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let mut report = VerificationReport::default();
+        report.record(b"a".to_vec(), None);
+        report.record(b"b".to_vec(), Some(KeyMismatch::Missing));
+        for _ in 0..(MAX_MISMATCHED_KEYS + 1) {
+            report.record(b"c".to_vec(), Some(KeyMismatch::DigestMismatch));
+        }
+        assert_eq!(report.checked, MAX_MISMATCHED_KEYS as u64 + 3);
+        assert_eq!(report.missing, 1);
+        assert_eq!(report.digest_mismatch, MAX_MISMATCHED_KEYS as u64 + 1);
+        assert_eq!(report.get_mismatch_num(), MAX_MISMATCHED_KEYS as u64 + 2);
+        assert_eq!(report.mismatched_keys.len(), MAX_MISMATCHED_KEYS);
+
+        let strs = report.into_strings();
+        assert_eq!(&strs[..4], &["checked", "19", "missing", "1"]);
+        assert_eq!(&strs[8..10], &["b", "missing"]);
+    }
+
+    fn create_unused_client_func() -> impl RedisClient {
+        let mut mock_client = MockRedisClient::new();
+        mock_client.expect_execute_single().times(0);
+        mock_client
+    }
+
+    #[tokio::test]
+    async fn test_verify_stopped() {
+        let client_factory = DummyRedisClientFactory::new(create_unused_client_func);
+        let res = verify_slot_range(
+            Arc::new(client_factory),
+            "127.0.0.1:6379".to_string(),
+            "127.0.0.1:6380".to_string(),
+            (0, 100),
+            Arc::new(AtomicMigrationConfig::default()),
+            Arc::new(AtomicBool::new(true)),
+        )
+        .await;
+        match res {
+            Err(RedisClientError::Canceled) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
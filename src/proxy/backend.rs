@@ -1,4 +1,5 @@
 use super::command::{CommandError, CommandResult};
+use super::health::BackendHealthMap;
 use super::service::ServerProxyConfig;
 use super::slowlog::TaskEvent;
 use crate::common::batch::TryChunksTimeoutStreamExt;
@@ -189,6 +190,7 @@ pub trait CmdTaskSenderFactory {
 pub struct RecoverableBackendNode<F: CmdTaskResultHandlerFactory> {
     address: String,
     node: BackendNode<<F as CmdTaskResultHandlerFactory>::Handler>,
+    health_map: Arc<BackendHealthMap>,
 }
 
 pub struct RecoverableBackendNodeFactory<F: CmdTaskResultHandlerFactory, CF: ConnFactory>
@@ -200,6 +202,7 @@ where
     handler_factory: Arc<F>,
     conn_factory: Arc<CF>,
     future_registry: Arc<TrackedFutureRegistry>,
+    health_map: Arc<BackendHealthMap>,
 }
 
 impl<F: CmdTaskResultHandlerFactory, CF: ConnFactory> RecoverableBackendNodeFactory<F, CF>
@@ -212,12 +215,14 @@ where
         handler_factory: Arc<F>,
         conn_factory: Arc<CF>,
         future_registry: Arc<TrackedFutureRegistry>,
+        health_map: Arc<BackendHealthMap>,
     ) -> Self {
         Self {
             config,
             handler_factory,
             conn_factory,
             future_registry,
+            health_map,
         }
     }
 }
@@ -236,11 +241,16 @@ where
             Arc::new(self.handler_factory.create()),
             self.config.clone(),
             self.conn_factory.clone(),
+            self.health_map.clone(),
         );
         let desc = format!("backend::RecoverableBackendNode: address={}", address);
         let fut = TrackedFutureRegistry::wrap(self.future_registry.clone(), fut, desc);
         tokio::spawn(fut);
-        Self::Sender { address, node }
+        Self::Sender {
+            address,
+            node,
+            health_map: self.health_map.clone(),
+        }
     }
 }
 
@@ -254,6 +264,9 @@ impl<F: CmdTaskResultHandlerFactory> CmdTaskSender for RecoverableBackendNode<F>
                 format!("backend connection failed: {}", self.address).into_bytes(),
             )));
             error!("backend node is closed");
+            if self.node.is_closed() {
+                self.health_map.report_closed(&self.address);
+            }
             BackendError::Canceled
         })
     }
@@ -279,6 +292,7 @@ impl<H: CmdTaskResultHandler> BackendNode<H> {
         handler: Arc<H>,
         config: Arc<ServerProxyConfig>,
         conn_factory: Arc<CF>,
+        health_map: Arc<BackendHealthMap>,
     ) -> (
         BackendNode<H>,
         impl Future<Output = Result<(), BackendError>> + Send,
@@ -299,6 +313,7 @@ impl<H: CmdTaskResultHandler> BackendNode<H> {
             config.backend_batch_max_time,
             config.backend_batch_buf,
             conn_factory,
+            health_map,
         );
         (Self { tx, conn_failed }, handle_backend_fut)
     }
@@ -402,6 +417,7 @@ pub async fn handle_backend<H, F>(
     backend_batch_max_time: usize,
     backend_batch_buf: NonZeroUsize,
     conn_factory: Arc<F>,
+    health_map: Arc<BackendHealthMap>,
 ) -> Result<(), BackendError>
 where
     H: CmdTaskResultHandler,
@@ -430,6 +446,7 @@ where
             Ok(conn) => conn,
             Err(err) => {
                 error!("failed to connect: {:?}", err);
+                health_map.report_conn_failure(&address);
                 retry_state.take();
 
                 let mut timeout_fut = Delay::new(Duration::from_secs(1)).fuse();
@@ -453,6 +470,7 @@ where
             }
         };
         conn_failed.store(false, Ordering::SeqCst);
+        health_map.report_connected(&address);
 
         let res = handle_conn(
             writer,
@@ -809,6 +827,7 @@ pub fn gen_sender_factory<F: CmdTaskResultHandlerFactory, CF: ConnFactory>(
     reply_handler_factory: Arc<F>,
     conn_factory: Arc<CF>,
     future_registry: Arc<TrackedFutureRegistry>,
+    health_map: Arc<BackendHealthMap>,
 ) -> BackendSenderFactory<F, CF>
 where
     <F::Handler as CmdTaskResultHandler>::Task: CmdTask<Pkt = CF::Pkt>,
@@ -821,6 +840,7 @@ where
             reply_handler_factory,
             conn_factory,
             future_registry,
+            health_map,
         ),
     ))
 }
@@ -834,6 +854,7 @@ pub fn gen_migration_sender_factory<F: CmdTaskResultHandlerFactory, CF: ConnFact
     reply_handler_factory: Arc<F>,
     conn_factory: Arc<CF>,
     future_registry: Arc<TrackedFutureRegistry>,
+    health_map: Arc<BackendHealthMap>,
 ) -> MigrationBackendSenderFactory<F, CF>
 where
     <F::Handler as CmdTaskResultHandler>::Task: CmdTask<Pkt = CF::Pkt>,
@@ -846,6 +867,7 @@ where
             reply_handler_factory,
             conn_factory,
             future_registry,
+            health_map,
         )),
     ))
 }
@@ -5,6 +5,7 @@ use super::backend::{
 };
 use super::command::{CommandError, CommandResult};
 use super::database::DBTag;
+use super::health::BackendHealthMap;
 use super::service::ServerProxyConfig;
 use super::slowlog::TaskEvent;
 use crate::common::cluster::DBName;
@@ -14,18 +15,40 @@ use crate::protocol::{Resp, RespVec};
 use crossbeam_channel;
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Clients should retry the commands rejected with this error.
+pub const BLOCKING_QUEUE_FULL_REPLY: &str = "TRYAGAIN blocking queue is full during migration";
 
 pub trait TaskBlockingController: ThreadSafe {
     type Sender: BlockingCmdTaskSender;
 
     fn blocking_done(&self) -> bool;
     fn is_blocking(&self) -> bool;
-    fn start_blocking(&self) -> BlockingHandle<Self::Sender>;
+    fn start_blocking(&self, max_queue_size: usize) -> BlockingHandle<Self::Sender>;
     fn stop_blocking(&self);
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct BlockingStats {
+    pub queued_cmd_num: u64,
+    pub rejected_cmd_num: u64,
+    pub blocking_time: Duration,
+    pub aborted_num: u64,
+}
+
+impl BlockingStats {
+    // Accumulate the stats of another blocking attempt.
+    pub fn add_attempt(&mut self, stats: &BlockingStats, aborted: bool) {
+        self.queued_cmd_num += stats.queued_cmd_num;
+        self.rejected_cmd_num += stats.rejected_cmd_num;
+        self.blocking_time += stats.blocking_time;
+        self.aborted_num += aborted as u64;
+    }
+}
+
 pub trait TaskBlockingControllerFactory {
     type Ctrl: TaskBlockingController;
 
@@ -44,6 +67,7 @@ pub fn gen_basic_blocking_sender_factory<F: CmdTaskResultHandlerFactory, CF: Con
     reply_handler_factory: Arc<F>,
     conn_factory: Arc<CF>,
     future_registry: Arc<TrackedFutureRegistry>,
+    health_map: Arc<BackendHealthMap>,
 ) -> BasicBlockingSenderFactory<F, CF>
 where
     <F::Handler as CmdTaskResultHandler>::Task: CmdTask<Pkt = CF::Pkt>,
@@ -56,6 +80,7 @@ where
             reply_handler_factory,
             conn_factory,
             future_registry,
+            health_map,
         ),
     )
 }
@@ -131,13 +156,40 @@ where
 
 pub struct BlockingHandle<BS: BlockingCmdTaskSender> {
     inner: Arc<BlockingHandleInner<BS>>,
+    start_time: Instant,
+    // The counters of the queue when this handle is created.
+    base_queued_cmd_num: u64,
+    base_rejected_cmd_num: u64,
 }
 
 impl<BS: BlockingCmdTaskSender> BlockingHandle<BS> {
-    fn new(inner: Arc<BlockingHandleInner<BS>>) -> Self {
+    fn new(inner: Arc<BlockingHandleInner<BS>>, max_queue_size: usize) -> Self {
+        inner.max_queue_size.store(max_queue_size, Ordering::SeqCst);
         inner.blocking.fetch_add(1, Ordering::SeqCst);
         info!("migration start blocking");
-        Self { inner }
+        let base_queued_cmd_num = inner.queued_cmd_num.load(Ordering::SeqCst);
+        let base_rejected_cmd_num = inner.rejected_cmd_num.load(Ordering::SeqCst);
+        Self {
+            inner,
+            start_time: Instant::now(),
+            base_queued_cmd_num,
+            base_rejected_cmd_num,
+        }
+    }
+
+    pub fn get_stats(&self) -> BlockingStats {
+        let queued_cmd_num = self.inner.queued_cmd_num.load(Ordering::SeqCst);
+        let rejected_cmd_num = self.inner.rejected_cmd_num.load(Ordering::SeqCst);
+        BlockingStats {
+            queued_cmd_num: queued_cmd_num.saturating_sub(self.base_queued_cmd_num),
+            rejected_cmd_num: rejected_cmd_num.saturating_sub(self.base_rejected_cmd_num),
+            blocking_time: self.start_time.elapsed(),
+            aborted_num: 0,
+        }
+    }
+
+    pub fn has_rejected_cmd(&self) -> bool {
+        self.inner.rejected_cmd_num.load(Ordering::SeqCst) > self.base_rejected_cmd_num
     }
 
     pub fn stop(self) {}
@@ -158,6 +210,9 @@ struct BlockingHandleInner<BS: BlockingCmdTaskSender> {
     blocking: AtomicUsize,
     queue_receiver: crossbeam_channel::Receiver<BS::Task>,
     blocking_task_sender: Arc<BS>,
+    max_queue_size: AtomicUsize,
+    queued_cmd_num: AtomicU64,
+    rejected_cmd_num: AtomicU64,
 }
 
 impl<BS: BlockingCmdTaskSender> BlockingHandleInner<BS> {
@@ -208,6 +263,9 @@ where
             blocking: AtomicUsize::new(0),
             queue_receiver,
             blocking_task_sender,
+            max_queue_size: AtomicUsize::new(0),
+            queued_cmd_num: AtomicU64::new(0),
+            rejected_cmd_num: AtomicU64::new(0),
         });
         Self {
             queue_sender,
@@ -241,6 +299,15 @@ where
         }
         drop(counter);
 
+        let inner = &self.blocking_handle_inner;
+        if self.queue_sender.len() >= inner.max_queue_size.load(Ordering::SeqCst) {
+            inner.rejected_cmd_num.fetch_add(1, Ordering::SeqCst);
+            cmd_task.set_resp_result(Ok(Resp::Error(
+                BLOCKING_QUEUE_FULL_REPLY.to_string().into_bytes(),
+            )));
+            return Ok(());
+        }
+
         if let Err(err) = self.queue_sender.send(cmd_task) {
             let cmd_task = err.into_inner();
             cmd_task.set_resp_result(Ok(Resp::Error(
@@ -249,6 +316,7 @@ where
             error!("failed to send to blocking queue");
             return Err(BackendError::Canceled);
         }
+        inner.queued_cmd_num.fetch_add(1, Ordering::SeqCst);
 
         if !self.is_blocking() {
             self.blocking_handle_inner.release_all();
@@ -272,8 +340,8 @@ where
         self.blocking_handle_inner.blocking.load(Ordering::SeqCst) > 0
     }
 
-    fn start_blocking(&self) -> BlockingHandle<Self::Sender> {
-        BlockingHandle::new(self.blocking_handle_inner.clone())
+    fn start_blocking(&self, max_queue_size: usize) -> BlockingHandle<Self::Sender> {
+        BlockingHandle::new(self.blocking_handle_inner.clone(), max_queue_size)
     }
 
     fn stop_blocking(&self) {
@@ -487,3 +555,118 @@ impl<T: CmdTask + DBTag> DBTag for BlockingHintTask<T> {
         self.inner.set_db_name(db)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::command::{new_command_pair, CmdReplyReceiver, Command};
+    use super::super::session::CmdCtx;
+    use super::*;
+    use crate::protocol::{Array, BulkStr, RespPacket};
+    use std::sync::RwLock;
+    use tokio;
+
+    struct DummyInnerSender;
+
+    impl CmdTaskSender for DummyInnerSender {
+        type Task = CounterTask<CmdCtx>;
+
+        fn send(&self, cmd_task: Self::Task) -> Result<(), BackendError> {
+            cmd_task.set_resp_result(Ok(Resp::Simple(b"OK".to_vec())));
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct DummyBlockingTaskSender {
+        released: AtomicUsize,
+    }
+
+    impl CmdTaskSender for DummyBlockingTaskSender {
+        type Task = CmdCtx;
+
+        fn send(&self, cmd_task: Self::Task) -> Result<(), BackendError> {
+            self.released.fetch_add(1, Ordering::SeqCst);
+            cmd_task.set_resp_result(Ok(Resp::Simple(b"OK".to_vec())));
+            Ok(())
+        }
+    }
+
+    impl BlockingCmdTaskSender for DummyBlockingTaskSender {}
+
+    fn gen_test_cmd_ctx() -> (CmdCtx, CmdReplyReceiver) {
+        let resp = Resp::Arr(Array::Arr(vec![
+            Resp::Bulk(BulkStr::Str(b"GET".to_vec())),
+            Resp::Bulk(BulkStr::Str(b"somekey".to_vec())),
+        ]));
+        let db = Arc::new(RwLock::new(DBName::from("mydb").unwrap()));
+        let cmd = Command::new(Box::new(RespPacket::from_resp_vec(resp)));
+        let (reply_sender, reply_receiver) = new_command_pair();
+        (CmdCtx::new(db, cmd, reply_sender, 0), reply_receiver)
+    }
+
+    async fn get_reply(reply_receiver: CmdReplyReceiver) -> Vec<u8> {
+        let task_reply = reply_receiver.await.expect("get_reply");
+        let (_, packet, _) = task_reply.into_inner();
+        match packet.to_resp_slice() {
+            Resp::Simple(s) | Resp::Error(s) => s.to_vec(),
+            others => format!("invalid_reply {:?}", others).into_bytes(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocking_queue_limit() {
+        let blocking_task_sender = Arc::new(DummyBlockingTaskSender::default());
+        let queue = TaskBlockingQueue::new(DummyInnerSender, blocking_task_sender.clone());
+
+        let handle = queue.start_blocking(2);
+        assert!(queue.is_blocking());
+
+        let mut receivers = vec![];
+        for _ in 0..3 {
+            let (cmd_ctx, reply_receiver) = gen_test_cmd_ctx();
+            queue
+                .send(BlockingHintTask::new(cmd_ctx, true))
+                .expect("test_blocking_queue_limit");
+            receivers.push(reply_receiver);
+        }
+
+        let rejected = receivers.pop().expect("test_blocking_queue_limit");
+        assert_eq!(
+            get_reply(rejected).await,
+            BLOCKING_QUEUE_FULL_REPLY.as_bytes().to_vec()
+        );
+        assert!(handle.has_rejected_cmd());
+        let stats = handle.get_stats();
+        assert_eq!(stats.queued_cmd_num, 2);
+        assert_eq!(stats.rejected_cmd_num, 1);
+        assert_eq!(blocking_task_sender.released.load(Ordering::SeqCst), 0);
+
+        drop(handle);
+        assert!(!queue.is_blocking());
+        assert_eq!(blocking_task_sender.released.load(Ordering::SeqCst), 2);
+        for reply_receiver in receivers.into_iter() {
+            assert_eq!(get_reply(reply_receiver).await, b"OK".to_vec());
+        }
+
+        let handle = queue.start_blocking(2);
+        assert!(!handle.has_rejected_cmd());
+        assert_eq!(handle.get_stats().queued_cmd_num, 0);
+    }
+
+    #[test]
+    fn test_add_blocking_attempt() {
+        let mut stats = BlockingStats::default();
+        let attempt = BlockingStats {
+            queued_cmd_num: 3,
+            rejected_cmd_num: 1,
+            blocking_time: Duration::from_millis(10),
+            aborted_num: 0,
+        };
+        stats.add_attempt(&attempt, true);
+        stats.add_attempt(&attempt, false);
+        assert_eq!(stats.queued_cmd_num, 6);
+        assert_eq!(stats.rejected_cmd_num, 2);
+        assert_eq!(stats.blocking_time, Duration::from_millis(20));
+        assert_eq!(stats.aborted_num, 1);
+    }
+}
@@ -8,10 +8,11 @@ use crate::common::utils::{gen_moved, get_slot};
 use crate::migration::task::MigrationState;
 use crate::protocol::{Array, BulkStr, Resp, RespVec};
 use crc64::crc64;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
 use std::iter::Iterator;
+use std::sync::Arc;
 
 pub const DEFAULT_DB: &str = "admin";
 
@@ -19,6 +20,8 @@ pub const DEFAULT_DB: &str = "admin";
 pub enum DBError {
     OldEpoch,
     TryAgain,
+    // The base epoch of `UMCTL SETDBDELTA` is not the current one.
+    BaseEpochMismatch,
 }
 
 impl fmt::Display for DBError {
@@ -46,8 +49,9 @@ pub struct DatabaseMap<S: CmdTaskSender>
 where
     <S as CmdTaskSender>::Task: DBTag,
 {
-    local_dbs: HashMap<DBName, Database<S>>,
-    remote_dbs: HashMap<DBName, RemoteDB>,
+    // Shared with the later maps updated by the metadata delta.
+    local_dbs: HashMap<DBName, Arc<Database<S>>>,
+    remote_dbs: HashMap<DBName, Arc<RemoteDB>>,
 }
 
 impl<S: CmdTaskSender> Default for DatabaseMap<S>
@@ -70,10 +74,55 @@ where
         db_meta: &ProxyDBMeta,
         sender_factory: &F,
     ) -> Self {
-        let epoch = db_meta.get_epoch();
+        let mut db_map = Self::default();
+        db_map.insert_dbs(
+            db_meta,
+            db_meta.get_local().get_map().keys(),
+            sender_factory,
+        );
+        db_map.insert_remote_dbs(db_meta, db_meta.get_peer().get_map().keys());
+        db_map
+    }
+
+    // Only rebuild the changed databases and share the others with the current map.
+    pub fn update_dbs<F: CmdTaskSenderFactory<Sender = S>>(
+        &self,
+        db_meta: &ProxyDBMeta,
+        changed_dbs: &HashSet<DBName>,
+        sender_factory: &F,
+    ) -> Self {
+        let mut db_map = Self {
+            local_dbs: self
+                .local_dbs
+                .iter()
+                .filter(|(db_name, _)| !changed_dbs.contains(*db_name))
+                .map(|(db_name, db)| (db_name.clone(), db.clone()))
+                .collect(),
+            remote_dbs: self
+                .remote_dbs
+                .iter()
+                .filter(|(db_name, _)| !changed_dbs.contains(*db_name))
+                .map(|(db_name, db)| (db_name.clone(), db.clone()))
+                .collect(),
+        };
+        db_map.insert_dbs(db_meta, changed_dbs.iter(), sender_factory);
+        db_map.insert_remote_dbs(db_meta, changed_dbs.iter());
+        db_map
+    }
 
-        let mut local_dbs = HashMap::new();
-        for (db_name, slot_ranges) in db_meta.get_local().get_map().iter() {
+    fn insert_dbs<'a, F: CmdTaskSenderFactory<Sender = S>>(
+        &mut self,
+        db_meta: &ProxyDBMeta,
+        db_names: impl Iterator<Item = &'a DBName>,
+        sender_factory: &F,
+    ) {
+        let epoch = db_meta.get_epoch();
+        let local = db_meta.get_local().get_map();
+        for db_name in db_names {
+            let slot_ranges = match local.get(db_name) {
+                Some(slot_ranges) => slot_ranges,
+                None => continue,
+            };
             let config = db_meta.get_configs().get(db_name);
             let db = Database::from_slot_map(
                 sender_factory,
@@ -82,17 +131,24 @@ where
                 slot_ranges.clone(),
                 config,
             );
-            local_dbs.insert(db_name.clone(), db);
+            self.local_dbs.insert(db_name.clone(), Arc::new(db));
         }
+    }
 
-        let mut remote_dbs = HashMap::new();
-        for (db_name, slot_ranges) in db_meta.get_peer().get_map().iter() {
+    fn insert_remote_dbs<'a>(
+        &mut self,
+        db_meta: &ProxyDBMeta,
+        db_names: impl Iterator<Item = &'a DBName>,
+    ) {
+        let epoch = db_meta.get_epoch();
+        let peer = db_meta.get_peer().get_map();
+        for db_name in db_names {
+            let slot_ranges = match peer.get(db_name) {
+                Some(slot_ranges) => slot_ranges,
+                None => continue,
+            };
             let remote_db = RemoteDB::from_slot_map(db_name.clone(), epoch, slot_ranges.clone());
-            remote_dbs.insert(db_name.clone(), remote_db);
-        }
-        Self {
-            local_dbs,
-            remote_dbs,
+            self.remote_dbs.insert(db_name.clone(), Arc::new(remote_db));
         }
     }
 
@@ -148,6 +204,13 @@ where
         self.local_dbs.keys().cloned().collect()
     }
 
+    pub fn get_local_node_addresses(&self) -> Vec<String> {
+        self.local_dbs
+            .values()
+            .flat_map(|db| db.slot_ranges.keys().cloned())
+            .collect()
+    }
+
     pub fn gen_cluster_nodes(
         &self,
         dbname: DBName,
@@ -566,7 +629,9 @@ fn gen_cluster_slots_helper(
 mod tests {
     use super::*;
     use crate::common::cluster::MigrationMeta;
+    use crate::common::db::{ClusterConfigMap, DBMapFlags, ProxyDBMap};
     use crate::protocol::{Array, BulkStr};
+    use crate::proxy::session::CmdCtx;
     use std::iter::repeat;
 
     fn gen_testing_slot_ranges(address: &str) -> HashMap<String, Vec<SlotRange>> {
@@ -775,4 +840,70 @@ mod tests {
     fn test_default_db_length() {
         DBName::from(DEFAULT_DB).unwrap();
     }
+
+    struct DummySender;
+
+    impl CmdTaskSender for DummySender {
+        type Task = CmdCtx;
+
+        fn send(&self, _cmd_task: Self::Task) -> Result<(), BackendError> {
+            Ok(())
+        }
+    }
+
+    struct DummySenderFactory;
+
+    impl CmdTaskSenderFactory for DummySenderFactory {
+        type Sender = DummySender;
+
+        fn create(&self, _address: String) -> Self::Sender {
+            DummySender
+        }
+    }
+
+    fn gen_db_meta(epoch: u64, local: Vec<(&str, &str)>) -> ProxyDBMeta {
+        let local = local
+            .into_iter()
+            .map(|(db_name, address)| {
+                (
+                    DBName::from(db_name).unwrap(),
+                    gen_testing_slot_ranges(address),
+                )
+            })
+            .collect();
+        ProxyDBMeta::new(
+            epoch,
+            DBMapFlags { force: false },
+            ProxyDBMap::new(local),
+            ProxyDBMap::new(HashMap::new()),
+            ClusterConfigMap::new(HashMap::new()),
+        )
+    }
+
+    #[test]
+    fn test_update_dbs() {
+        let db1 = DBName::from("db1").unwrap();
+        let db2 = DBName::from("db2").unwrap();
+        let db3 = DBName::from("db3").unwrap();
+        let meta = gen_db_meta(
+            1,
+            vec![("db1", "127.0.0.1:6000"), ("db2", "127.0.0.1:6001")],
+        );
+        let db_map = DatabaseMap::from_db_map(&meta, &DummySenderFactory);
+
+        let new_meta = gen_db_meta(
+            2,
+            vec![("db1", "127.0.0.1:6000"), ("db3", "127.0.0.1:6002")],
+        );
+        let changed_dbs = vec![db2.clone(), db3.clone()].into_iter().collect();
+        let new_db_map = db_map.update_dbs(&new_meta, &changed_dbs, &DummySenderFactory);
+
+        assert!(Arc::ptr_eq(
+            &db_map.local_dbs[&db1],
+            &new_db_map.local_dbs[&db1]
+        ));
+        assert!(!new_db_map.local_dbs.contains_key(&db2));
+        assert_eq!(new_db_map.local_dbs[&db3].epoch, 2);
+        assert_eq!(new_db_map.get_local_node_addresses().len(), 2);
+    }
 }
@@ -2,16 +2,17 @@ use super::backend::{CmdTask, CmdTaskFactory};
 use super::command::{CmdReplyReceiver, CmdType, DataCmdType, TaskResult};
 use super::compress::{CmdCompressor, CompressionError};
 use super::database::{DBError, DBTag};
+use super::health::BACKEND_HEALTH_CHECK_INTERVAL;
 use super::manager::{MetaManager, SharedMetaMap};
 use super::service::ServerProxyConfig;
 use super::session::{CmdCtx, CmdCtxFactory, CmdCtxHandler, CmdReplyFuture};
 use super::slowlog::{slowlogs_to_resp, SlowRequestLogger};
 use crate::common::cluster::DBName;
-use crate::common::db::ProxyDBMeta;
+use crate::common::db::{ParseExtendedMetaError, ProxyDBMeta, ProxyDBMetaDelta};
 use crate::common::track::TrackedFutureRegistry;
 use crate::common::utils::{
-    str_ascii_case_insensitive_eq, NOT_READY_FOR_SWITCHING_REPLY, OK_REPLY, OLD_EPOCH_REPLY,
-    TRY_AGAIN_REPLY,
+    bytes_ascii_case_insensitive_eq, str_ascii_case_insensitive_eq, BASE_EPOCH_MISMATCH_REPLY,
+    NOT_READY_FOR_SWITCHING_REPLY, OK_REPLY, OLD_EPOCH_REPLY, TRY_AGAIN_REPLY,
 };
 use crate::common::version::UNDERMOON_VERSION;
 use crate::migration::manager::SwitchError;
@@ -21,7 +22,8 @@ use crate::protocol::{Array, BulkStr, RedisClientFactory, Resp, RespVec};
 use crate::replication::replicator::ReplicatorMeta;
 use atoi::atoi;
 use btoi::btou;
-use futures::future;
+use futures::{future, Future};
+use futures_timer::Delay;
 use std::str;
 use std::sync::{self, Arc};
 
@@ -57,6 +59,19 @@ impl<F: RedisClientFactory> SharedForwardHandler<F> {
     }
 }
 
+impl<F: RedisClientFactory> SharedForwardHandler<F> {
+    // Needs to be spawned inside the runtime.
+    pub fn run_backend_health_check(&self) -> impl Future<Output = ()> + Send + 'static {
+        let handler = self.handler.clone();
+        async move {
+            loop {
+                handler.manager.check_backend_health().await;
+                Delay::new(BACKEND_HEALTH_CHECK_INTERVAL).await;
+            }
+        }
+    }
+}
+
 impl<F: RedisClientFactory> CmdCtxHandler for SharedForwardHandler<F> {
     fn handle_cmd_ctx(&self, cmd_ctx: CmdCtx, reply_receiver: CmdReplyReceiver) -> CmdReplyFuture {
         self.handler.handle_cmd_ctx(cmd_ctx, reply_receiver)
@@ -182,14 +197,23 @@ impl<F: RedisClientFactory> ForwardHandler<F> {
                 .map(|db| Resp::Bulk(BulkStr::Str(db.to_string().into_bytes())))
                 .collect();
             cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(resps))));
+        } else if sub_cmd.eq("GETEPOCH") {
+            let epoch = self.manager.get_epoch();
+            cmd_ctx.set_resp_result(Ok(Resp::Integer(epoch.to_string().into_bytes())));
         } else if sub_cmd.eq("SETDB") {
             self.handle_umctl_setdb(cmd_ctx);
+        } else if sub_cmd.eq("SETDBDELTA") {
+            self.handle_umctl_setdb_delta(cmd_ctx);
         } else if sub_cmd.eq("SETREPL") {
             self.handle_umctl_setrepl(cmd_ctx);
         } else if sub_cmd.eq("INFOREPL") {
             self.handle_umctl_info_repl(cmd_ctx);
+        } else if sub_cmd.eq("HEALTH") {
+            self.handle_umctl_health(cmd_ctx);
         } else if sub_cmd.eq("INFOMGR") {
             self.handle_umctl_info_migration(cmd_ctx);
+        } else if sub_cmd.eq("LISTMGR") {
+            self.handle_umctl_list_migration(cmd_ctx);
         } else if sub_cmd.eq(MgrSubCmd::PreCheck.as_str()) {
             self.handle_umctl_mgr_cmd(cmd_ctx, MgrSubCmd::PreCheck);
         } else if sub_cmd.eq(MgrSubCmd::PreSwitch.as_str()) {
@@ -219,7 +243,32 @@ impl<F: RedisClientFactory> ForwardHandler<F> {
                 }
             };
 
-        match self.manager.set_meta(db_meta) {
+        let res = self.manager.set_meta(db_meta);
+        Self::set_meta_resp(cmd_ctx, res, extended_res);
+    }
+
+    fn handle_umctl_setdb_delta(&self, cmd_ctx: CmdCtx) {
+        let (delta, extended_res) =
+            match ProxyDBMetaDelta::from_resp(&cmd_ctx.get_cmd().get_resp_slice()) {
+                Ok(r) => r,
+                Err(_) => {
+                    cmd_ctx.set_resp_result(Ok(Resp::Error(
+                        String::from("Invalid arguments").into_bytes(),
+                    )));
+                    return;
+                }
+            };
+
+        let res = self.manager.set_meta_delta(delta);
+        Self::set_meta_resp(cmd_ctx, res, extended_res);
+    }
+
+    fn set_meta_resp(
+        cmd_ctx: CmdCtx,
+        res: Result<(), DBError>,
+        extended_res: Result<(), ParseExtendedMetaError>,
+    ) {
+        match res {
             Ok(()) => match extended_res {
                 Ok(()) => {
                     debug!("Successfully update local meta data");
@@ -231,15 +280,19 @@ impl<F: RedisClientFactory> ForwardHandler<F> {
                     )));
                 }
             },
-            Err(err) => match err {
-                DBError::OldEpoch => cmd_ctx
-                    .set_resp_result(Ok(Resp::Error(OLD_EPOCH_REPLY.to_string().into_bytes()))),
-                DBError::TryAgain => cmd_ctx
-                    .set_resp_result(Ok(Resp::Error(TRY_AGAIN_REPLY.to_string().into_bytes()))),
-            },
+            Err(err) => cmd_ctx.set_resp_result(Ok(Self::db_error_resp(err))),
         }
     }
 
+    fn db_error_resp(err: DBError) -> RespVec {
+        let reply = match err {
+            DBError::OldEpoch => OLD_EPOCH_REPLY,
+            DBError::TryAgain => TRY_AGAIN_REPLY,
+            DBError::BaseEpochMismatch => BASE_EPOCH_MISMATCH_REPLY,
+        };
+        Resp::Error(reply.to_string().into_bytes())
+    }
+
     fn handle_umctl_setrepl(&self, cmd_ctx: CmdCtx) {
         let meta = match ReplicatorMeta::from_resp(&cmd_ctx.get_cmd().get_resp_slice()) {
             Ok(m) => m,
@@ -258,12 +311,7 @@ impl<F: RedisClientFactory> ForwardHandler<F> {
             }
             Err(e) => {
                 //                debug!("Failed to update replicator meta data {:?}", e);
-                match e {
-                    DBError::OldEpoch => cmd_ctx
-                        .set_resp_result(Ok(Resp::Error(OLD_EPOCH_REPLY.to_string().into_bytes()))),
-                    DBError::TryAgain => cmd_ctx
-                        .set_resp_result(Ok(Resp::Error(TRY_AGAIN_REPLY.to_string().into_bytes()))),
-                }
+                cmd_ctx.set_resp_result(Ok(Self::db_error_resp(e)))
             }
         }
     }
@@ -273,6 +321,11 @@ impl<F: RedisClientFactory> ForwardHandler<F> {
         cmd_ctx.set_resp_result(Ok(Resp::Bulk(BulkStr::Str(report.into_bytes()))));
     }
 
+    fn handle_umctl_health(&self, cmd_ctx: CmdCtx) {
+        let report = self.manager.get_backend_health_report();
+        cmd_ctx.set_resp_result(Ok(Resp::Bulk(BulkStr::Str(report.into_bytes()))));
+    }
+
     fn handle_umctl_mgr_cmd(&self, cmd_ctx: CmdCtx, sub_cmd: MgrSubCmd) {
         let switch_arg = match parse_switch_command(&cmd_ctx.get_cmd().get_resp_slice()) {
             Some(switch_meta) => switch_meta,
@@ -303,6 +356,15 @@ impl<F: RedisClientFactory> ForwardHandler<F> {
     }
 
     fn handle_umctl_info_migration(&self, cmd_ctx: CmdCtx) {
+        // The other arguments are ignored as before.
+        let verification = cmd_ctx
+            .get_cmd()
+            .get_command_element(2)
+            .map(|element| bytes_ascii_case_insensitive_eq(element, b"VERIFICATION"));
+        if verification == Some(true) {
+            return self.handle_umctl_info_verification(cmd_ctx);
+        }
+
         let finished_tasks = self.manager.get_finished_migration_tasks();
         let packet: Vec<RespVec> = finished_tasks
             .into_iter()
@@ -312,6 +374,30 @@ impl<F: RedisClientFactory> ForwardHandler<F> {
         cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(packet))))
     }
 
+    fn handle_umctl_list_migration(&self, cmd_ctx: CmdCtx) {
+        let tasks = self.manager.get_active_migration_tasks();
+        let packet: Vec<RespVec> = tasks
+            .into_iter()
+            .map(|task| task.into_strings().join(" "))
+            .map(|s| Resp::Bulk(BulkStr::Str(s.into_bytes())))
+            .collect();
+        cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(packet))))
+    }
+
+    fn handle_umctl_info_verification(&self, cmd_ctx: CmdCtx) {
+        let reports = self.manager.get_migration_verification_reports();
+        let packet: Vec<RespVec> = reports
+            .into_iter()
+            .map(|(meta, report)| {
+                let mut strs = meta.into_strings();
+                strs.extend(report.into_strings());
+                strs.join(" ")
+            })
+            .map(|s| Resp::Bulk(BulkStr::Str(s.into_bytes())))
+            .collect();
+        cmd_ctx.set_resp_result(Ok(Resp::Arr(Array::Arr(packet))))
+    }
+
     fn handle_umctl_slowlog(&self, cmd_ctx: CmdCtx) {
         let (cmd_ctx, sub_cmd) = match Self::get_sub_command(cmd_ctx, 2) {
             Some((cmd_ctx, sub_cmd)) => (cmd_ctx, sub_cmd),
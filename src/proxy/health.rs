@@ -0,0 +1,134 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const BACKEND_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// The backend is treated as unhealthy after this number of consecutive failures.
+const MAX_CONSECUTIVE_FAILURES: usize = 3;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackendHealth {
+    conn_failures: usize,
+    // The backend task exited and will never reconnect.
+    closed: bool,
+    ping_failures: usize,
+    ping_latency: Option<Duration>,
+}
+
+impl BackendHealth {
+    pub fn is_healthy(&self) -> bool {
+        !self.closed
+            && self.conn_failures < MAX_CONSECUTIVE_FAILURES
+            && self.ping_failures < MAX_CONSECUTIVE_FAILURES
+    }
+}
+
+// Shared by all the connections to the same backend.
+#[derive(Default)]
+pub struct BackendHealthMap {
+    health: Mutex<HashMap<String, BackendHealth>>,
+}
+
+impl BackendHealthMap {
+    fn update<F: FnOnce(&mut BackendHealth)>(&self, address: &str, f: F) {
+        let mut health = self.health.lock().expect("BackendHealthMap::update");
+        f(health.entry(address.to_string()).or_default())
+    }
+
+    pub fn report_connected(&self, address: &str) {
+        self.update(address, |health| {
+            health.conn_failures = 0;
+            health.closed = false;
+        })
+    }
+
+    pub fn report_conn_failure(&self, address: &str) {
+        self.update(address, |health| health.conn_failures += 1)
+    }
+
+    pub fn report_closed(&self, address: &str) {
+        self.update(address, |health| health.closed = true)
+    }
+
+    // `latency` is None if the PING failed.
+    pub fn report_ping(&self, address: &str, latency: Option<Duration>) {
+        self.update(address, |health| {
+            match latency {
+                Some(_) => health.ping_failures = 0,
+                None => health.ping_failures += 1,
+            }
+            health.ping_latency = latency;
+        })
+    }
+
+    // Remove the backends no longer in the metadata.
+    pub fn retain(&self, addresses: &HashSet<String>) {
+        self.health
+            .lock()
+            .expect("BackendHealthMap::retain")
+            .retain(|address, _| addresses.contains(address))
+    }
+
+    pub fn get_health(&self, address: &str) -> Option<BackendHealth> {
+        self.health
+            .lock()
+            .expect("BackendHealthMap::get_health")
+            .get(address)
+            .cloned()
+    }
+
+    pub fn gen_report(&self) -> String {
+        let health = self.health.lock().expect("BackendHealthMap::gen_report");
+        let mut addresses: Vec<&String> = health.keys().collect();
+        addresses.sort();
+
+        let mut report = String::new();
+        for address in addresses.into_iter() {
+            let h = &health[address];
+            let latency = h
+                .ping_latency
+                .map(|latency| latency.as_micros() as i64)
+                .unwrap_or(-1);
+            report.push_str(&format!(
+                "node_address:{}\nhealthy:{}\nconn_failures:{}\nclosed:{}\nping_failures:{}\nping_latency_us:{}\n\n",
+                address,
+                h.is_healthy(),
+                h.conn_failures,
+                h.closed,
+                h.ping_failures,
+                latency
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_health() {
+        let health_map = BackendHealthMap::default();
+        let address = "127.0.0.1:7000";
+        health_map.report_ping(address, Some(Duration::from_micros(100)));
+        assert!(health_map.get_health(address).unwrap().is_healthy());
+
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            health_map.report_conn_failure(address);
+        }
+        assert!(!health_map.get_health(address).unwrap().is_healthy());
+        health_map.report_connected(address);
+        assert!(health_map.get_health(address).unwrap().is_healthy());
+
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            health_map.report_ping(address, None);
+        }
+        let report = health_map.gen_report();
+        assert!(report.contains("node_address:127.0.0.1:7000\nhealthy:false\n"));
+        assert!(report.contains("ping_latency_us:-1\n"));
+
+        health_map.retain(&HashSet::new());
+        assert!(health_map.get_health(address).is_none());
+    }
+}
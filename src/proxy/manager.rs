@@ -7,25 +7,29 @@ use super::blocking::{
     BlockingBackendSenderFactory, BlockingCmdTaskSender, BlockingMap, CounterTask,
 };
 use super::database::{DBError, DBSendError, DBTag, DatabaseMap, DEFAULT_DB};
+use super::health::BackendHealthMap;
 use super::reply::{DecompressCommitHandlerFactory, ReplyCommitHandlerFactory};
 use super::service::ServerProxyConfig;
 use super::session::{CmdCtx, CmdCtxFactory};
 use super::slowlog::TaskEvent;
 use crate::common::cluster::{DBName, MigrationTaskMeta, SlotRangeTag};
-use crate::common::config::AtomicMigrationConfig;
-use crate::common::db::ProxyDBMeta;
+use crate::common::db::{ProxyDBMeta, ProxyDBMetaDelta};
 use crate::common::track::TrackedFutureRegistry;
 use crate::migration::delete_keys::DeleteKeysTaskMap;
 use crate::migration::manager::{MigrationManager, MigrationMap, SwitchError};
 use crate::migration::task::MgrSubCmd;
 use crate::migration::task::SwitchArg;
-use crate::protocol::{RedisClientFactory, RespPacket, RespVec};
+use crate::migration::verification::VerificationReport;
+use crate::protocol::{RedisClient, RedisClientFactory, Resp, RespPacket, RespVec};
 use crate::proxy::backend::{CmdTask, DefaultConnFactory};
 use crate::replication::manager::ReplicatorManager;
 use crate::replication::replicator::ReplicatorMeta;
 use arc_swap::ArcSwap;
+use futures::future;
+use std::collections::HashSet;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 pub struct MetaMap<S: CmdTaskSender, T>
 where
@@ -80,11 +84,15 @@ pub struct MetaManager<F: RedisClientFactory> {
     // inside meta_map.
     meta_map: SharedMetaMap,
     epoch: AtomicU64,
-    lock: Mutex<()>, // This is the write lock for `epoch`, `db`, and `task`.
+    // This is the write lock for `epoch`, `db`, and `task`.
+    // It also keeps the latest metadata as the base of `UMCTL SETDBDELTA`.
+    lock: Mutex<Option<ProxyDBMeta>>,
     replicator_manager: ReplicatorManager<F>,
     migration_manager: MigrationManager<F, MigrationSenderFactory, CmdCtxFactory>,
     sender_factory: SenderFactory,
     blocking_map: Arc<BlockingMap<BasicSenderFactory, BlockingTaskRetrySender>>,
+    client_factory: Arc<F>,
+    health_map: Arc<BackendHealthMap>,
 }
 
 impl<F: RedisClientFactory> MetaManager<F> {
@@ -97,11 +105,13 @@ impl<F: RedisClientFactory> MetaManager<F> {
         let reply_handler_factory = Arc::new(DecompressCommitHandlerFactory::new(meta_map.clone()));
         let conn_factory = Arc::new(DefaultConnFactory::default());
         let blocking_task_sender = Arc::new(BlockingTaskRetrySender::new(meta_map.clone()));
+        let health_map = Arc::new(BackendHealthMap::default());
         let basic_sender_factory = gen_basic_blocking_sender_factory(
             config.clone(),
             reply_handler_factory,
             conn_factory.clone(),
             future_registry.clone(),
+            health_map.clone(),
         );
         let blocking_map = Arc::new(BlockingMap::new(basic_sender_factory, blocking_task_sender));
         let sender_factory = gen_blocking_sender_factory(blocking_map.clone());
@@ -110,29 +120,32 @@ impl<F: RedisClientFactory> MetaManager<F> {
             Arc::new(ReplyCommitHandlerFactory::default()),
             conn_factory,
             future_registry.clone(),
+            // The migration targets are the nodes of other proxies
+            // and should not show up in the health report of this proxy.
+            Arc::new(BackendHealthMap::default()),
         ));
         let cmd_ctx_factory = Arc::new(CmdCtxFactory::default());
-        let migration_config = Arc::new(AtomicMigrationConfig::default());
         let config_clone = config.clone();
         Self {
             config,
             meta_map,
             epoch: AtomicU64::new(0),
-            lock: Mutex::new(()),
+            lock: Mutex::new(None),
             replicator_manager: ReplicatorManager::new(
                 client_factory.clone(),
                 future_registry.clone(),
             ),
             migration_manager: MigrationManager::new(
                 config_clone,
-                migration_config,
-                client_factory,
+                client_factory.clone(),
                 migration_sender_factory,
                 cmd_ctx_factory,
                 future_registry,
             ),
             sender_factory,
             blocking_map,
+            client_factory,
+            health_map,
         }
     }
 
@@ -156,27 +169,79 @@ impl<F: RedisClientFactory> MetaManager<F> {
         )
     }
 
+    // The smaller one of the epochs applied by `UMCTL SETDB` and `UMCTL SETREPL`
+    // so that the coordinator will resend both of them if any one fails.
+    pub fn get_epoch(&self) -> u64 {
+        std::cmp::min(
+            self.epoch.load(Ordering::SeqCst),
+            self.replicator_manager.get_epoch(),
+        )
+    }
+
     pub fn get_dbs(&self) -> Vec<DBName> {
         self.meta_map.load().db_map.get_dbs()
     }
 
     pub fn set_meta(&self, db_meta: ProxyDBMeta) -> Result<(), DBError> {
+        let mut last_meta = self.lock.lock().expect("MetaManager::set_meta");
+        self.set_meta_with_lock(&mut last_meta, db_meta, None)
+    }
+
+    // The unaffected backend connections are kept by the `BlockingMap`
+    // even though the `DatabaseMap` is rebuilt.
+    pub fn set_meta_delta(&self, delta: ProxyDBMetaDelta) -> Result<(), DBError> {
+        let mut last_meta = self.lock.lock().expect("MetaManager::set_meta_delta");
+        let db_meta = match last_meta.as_ref() {
+            Some(base) if base.get_epoch() == delta.get_base_epoch() => delta.apply(base),
+            _ => return Err(DBError::BaseEpochMismatch),
+        };
+        self.set_meta_with_lock(&mut last_meta, db_meta, Some(&delta))
+    }
+
+    fn set_meta_with_lock(
+        &self,
+        last_meta: &mut Option<ProxyDBMeta>,
+        db_meta: ProxyDBMeta,
+        delta: Option<&ProxyDBMetaDelta>,
+    ) -> Result<(), DBError> {
         let sender_factory = &self.sender_factory;
         let migration_manager = &self.migration_manager;
 
-        let _guard = self.lock.lock().expect("MetaManager::set_meta");
-
         if db_meta.get_epoch() <= self.epoch.load(Ordering::SeqCst) && !db_meta.get_flags().force {
             return Err(DBError::OldEpoch);
         }
 
         let old_meta_map = self.meta_map.load();
-        let db_map = DatabaseMap::from_db_map(&db_meta, sender_factory);
-        let (migration_map, new_tasks) = migration_manager.create_new_migration_map(
-            &old_meta_map.migration_map,
-            db_meta.get_local(),
-            self.blocking_map.clone(),
-        );
+        let (db_map, mut migration_map, new_tasks) = match delta {
+            Some(delta) => {
+                let changed_dbs: HashSet<DBName> =
+                    delta.get_changed_dbs().iter().cloned().collect();
+                let db_map = old_meta_map
+                    .db_map
+                    .update_dbs(&db_meta, &changed_dbs, sender_factory);
+                // The local map of the delta only contains the changed databases.
+                let (mut migration_map, new_tasks) = migration_manager.create_new_migration_map(
+                    &old_meta_map.migration_map,
+                    delta.get_meta().get_local(),
+                    db_meta.get_configs(),
+                    self.blocking_map.clone(),
+                );
+                migration_map.keep_unchanged_dbs(&old_meta_map.migration_map, &changed_dbs);
+                (db_map, migration_map, new_tasks)
+            }
+            None => {
+                let db_map = DatabaseMap::from_db_map(&db_meta, sender_factory);
+                let (migration_map, new_tasks) = migration_manager.create_new_migration_map(
+                    &old_meta_map.migration_map,
+                    db_meta.get_local(),
+                    db_meta.get_configs(),
+                    self.blocking_map.clone(),
+                );
+                (db_map, migration_map, new_tasks)
+            }
+        };
+        migration_map.keep_switched_tasks(&old_meta_map.migration_map, db_meta.get_local());
+        let aborted_tasks = old_meta_map.migration_map.get_aborted_tasks(&migration_map);
         let left_slots_after_change = old_meta_map
             .migration_map
             .get_left_slots_after_change(&migration_map, db_meta.get_local());
@@ -184,6 +249,7 @@ impl<F: RedisClientFactory> MetaManager<F> {
             .create_new_deleting_task_map(
                 &old_meta_map.deleting_task_map,
                 db_meta.get_local(),
+                db_meta.get_configs(),
                 left_slots_after_change,
             );
         self.meta_map.store(Arc::new(MetaMap {
@@ -193,9 +259,11 @@ impl<F: RedisClientFactory> MetaManager<F> {
         }));
         self.epoch.store(db_meta.get_epoch(), Ordering::SeqCst);
 
+        self.migration_manager.abort_tasks(aborted_tasks);
         self.migration_manager.run_tasks(new_tasks);
         self.migration_manager
             .run_deleting_tasks(new_deleting_tasks);
+        *last_meta = Some(db_meta);
         debug!("Successfully update db meta data");
 
         Ok(())
@@ -209,6 +277,68 @@ impl<F: RedisClientFactory> MetaManager<F> {
         self.replicator_manager.get_metadata_report()
     }
 
+    // Includes both the masters with slots and the nodes in the replication metadata.
+    pub fn get_backend_addresses(&self) -> HashSet<String> {
+        let mut addresses: HashSet<String> = self
+            .meta_map
+            .load()
+            .db_map
+            .get_local_node_addresses()
+            .into_iter()
+            .collect();
+        let (master_metadata, replica_metadata) = self.replicator_manager.get_metadata();
+        for meta in master_metadata.into_iter() {
+            addresses.insert(meta.master_node_address);
+        }
+        for meta in replica_metadata.into_iter() {
+            addresses.insert(meta.replica_node_address);
+        }
+        addresses
+    }
+
+    pub async fn check_backend_health(&self) {
+        let addresses = self.get_backend_addresses();
+        self.health_map.retain(&addresses);
+        let futs: Vec<_> = addresses
+            .into_iter()
+            .map(|address| self.ping_backend(address))
+            .collect();
+        future::join_all(futs).await;
+    }
+
+    async fn ping_backend(&self, address: String) {
+        let start = Instant::now();
+        let mut client = match self.client_factory.create_client(address.clone()).await {
+            Ok(client) => client,
+            Err(err) => {
+                warn!("failed to connect to backend {}: {:?}", address, err);
+                self.health_map.report_ping(&address, None);
+                return;
+            }
+        };
+        let latency = match client.execute_single(vec![b"PING".to_vec()]).await {
+            // Replies like LOADING also mean the backend is not available.
+            Ok(Resp::Error(err)) => {
+                warn!(
+                    "backend {} replied error to PING: {:?}",
+                    address,
+                    String::from_utf8_lossy(&err)
+                );
+                None
+            }
+            Ok(_) => Some(start.elapsed()),
+            Err(err) => {
+                warn!("failed to send PING to backend {}: {:?}", address, err);
+                None
+            }
+        };
+        self.health_map.report_ping(&address, latency);
+    }
+
+    pub fn get_backend_health_report(&self) -> String {
+        self.health_map.gen_report()
+    }
+
     pub fn info(&self) -> String {
         let meta_map = self.meta_map.load();
         let db_info = meta_map.db_map.info();
@@ -256,6 +386,19 @@ impl<F: RedisClientFactory> MetaManager<F> {
         self.meta_map.load().migration_map.get_finished_tasks()
     }
 
+    // Includes both the running tasks and the aborted tasks which have not stopped yet.
+    pub fn get_active_migration_tasks(&self) -> Vec<MigrationTaskMeta> {
+        let mut tasks = self.meta_map.load().migration_map.get_task_metas();
+        tasks.extend(self.migration_manager.get_aborting_tasks());
+        tasks
+    }
+
+    pub fn get_migration_verification_reports(
+        &self,
+    ) -> Vec<(MigrationTaskMeta, VerificationReport)> {
+        self.migration_manager.get_verification_reports()
+    }
+
     pub fn send(&self, cmd_ctx: CmdCtx) {
         send_cmd_ctx(&self.meta_map, cmd_ctx);
     }
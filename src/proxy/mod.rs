@@ -4,6 +4,7 @@ mod command;
 mod compress;
 pub mod database;
 pub mod executor;
+pub mod health;
 pub mod manager;
 pub mod migration_backend;
 pub mod reply;
@@ -185,6 +185,13 @@ impl<F: RedisClientFactory> ReplicatorManager<F> {
         Ok(())
     }
 
+    pub fn get_epoch(&self) -> u64 {
+        self.replicators
+            .read()
+            .expect("ReplicatorManager::get_epoch")
+            .0
+    }
+
     pub fn get_metadata(&self) -> (Vec<MasterMeta>, Vec<ReplicaMeta>) {
         let mut master_metadata = Vec::new();
         let mut replica_metadata = Vec::new();
@@ -209,8 +216,46 @@ impl<F: RedisClientFactory> ReplicatorManager<F> {
         (master_metadata, replica_metadata)
     }
 
+    // node address => replication offset
+    pub fn get_repl_offsets(&self) -> HashMap<String, i64> {
+        let replicators = self
+            .replicators
+            .read()
+            .expect("ReplicatorManager::get_repl_offsets");
+        let mut offsets = HashMap::new();
+        for ((_db_name, node_address), replicator) in replicators.1.iter() {
+            let offset = match replicator {
+                Either::Left(master) => master.get_repl_offset(),
+                Either::Right(replica) => replica.get_repl_offset(),
+            };
+            if let Some(offset) = offset {
+                offsets.insert(node_address.clone(), offset);
+            }
+        }
+        offsets
+    }
+
+    // replica node address => replication lag
+    pub fn get_repl_lags(&self) -> HashMap<String, i64> {
+        let replicators = self
+            .replicators
+            .read()
+            .expect("ReplicatorManager::get_repl_lags");
+        let mut lags = HashMap::new();
+        for ((_db_name, node_address), replicator) in replicators.1.iter() {
+            if let Either::Right(replica) = replicator {
+                if let Some(lag) = replica.get_repl_lag() {
+                    lags.insert(node_address.clone(), lag);
+                }
+            }
+        }
+        lags
+    }
+
     pub fn get_metadata_report(&self) -> String {
         let (master_metadata, replica_metadata) = self.get_metadata();
+        let offsets = self.get_repl_offsets();
+        let lags = self.get_repl_lags();
 
         let mut report = String::new();
 
@@ -223,6 +268,9 @@ impl<F: RedisClientFactory> ReplicatorManager<F> {
             report.push_str(&format!("db:{}\n", db_name));
             report.push_str("role:master\n");
             report.push_str(&format!("node_address:{}\n", master_node_address));
+            if let Some(offset) = offsets.get(&master_node_address) {
+                report.push_str(&format!("repl_offset:{}\n", offset));
+            }
             for replica in replicas.into_iter() {
                 report.push_str(&format!(
                     "replica:{}@{}\n",
@@ -240,6 +288,12 @@ impl<F: RedisClientFactory> ReplicatorManager<F> {
             report.push_str(&format!("db:{}\n", db_name));
             report.push_str("role:replica\n");
             report.push_str(&format!("node_address:{}\n", replica_node_address));
+            if let Some(offset) = offsets.get(&replica_node_address) {
+                report.push_str(&format!("repl_offset:{}\n", offset));
+            }
+            if let Some(lag) = lags.get(&replica_node_address) {
+                report.push_str(&format!("repl_lag:{}\n", lag));
+            }
             for master in masters.into_iter() {
                 report.push_str(&format!(
                     "master:{}@{}\n",
@@ -2,19 +2,69 @@ use super::replicator::{
     MasterMeta, MasterReplicator, ReplicaMeta, ReplicaReplicator, ReplicatorError, ReplicatorResult,
 };
 use crate::common::resp_execution::{retry_handle_func, I64Retriever};
-use crate::common::utils::resolve_first_address;
-use crate::protocol::{OptionalMulti, RedisClientError, RedisClientFactory, RespVec};
+use crate::common::utils::{resolve_first_address, strip_prefix};
+use crate::protocol::{
+    BulkStr, OptionalMulti, RedisClientError, RedisClientFactory, Resp, RespVec,
+};
 use futures::{future, Future};
 use futures::{FutureExt, TryFutureExt};
+use std::cmp;
 use std::pin::Pin;
+use std::str;
 use std::sync::atomic::AtomicI64;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
+const REPL_OFFSET_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+const UNKNOWN_REPL_OFFSET: i64 = -1;
+
+fn gen_offset_sync<F: RedisClientFactory>(
+    address: String,
+    client_factory: Arc<F>,
+) -> I64Retriever<F> {
+    let cmd = vec!["INFO".to_string(), "replication".to_string()];
+    I64Retriever::new(
+        UNKNOWN_REPL_OFFSET,
+        client_factory,
+        address,
+        cmd,
+        REPL_OFFSET_SYNC_INTERVAL,
+    )
+}
+
+fn handle_offset_result(resp: RespVec, data: &Arc<AtomicI64>) -> Result<(), RedisClientError> {
+    match parse_repl_offset(&resp) {
+        Some(offset) => data.store(offset, Ordering::SeqCst),
+        None => error!("failed to get replication offset from {:?}", resp),
+    }
+    Ok(())
+}
+
+// Both master and replica report their own processed offset as `master_repl_offset`.
+pub(crate) fn parse_repl_offset(resp: &RespVec) -> Option<i64> {
+    let info = match resp {
+        Resp::Bulk(BulkStr::Str(info)) => str::from_utf8(info).ok()?,
+        _ => return None,
+    };
+    info.lines()
+        .find_map(|line| strip_prefix(line.trim(), "master_repl_offset:"))
+        .and_then(|offset| offset.parse::<i64>().ok())
+}
+
+fn get_offset<F: RedisClientFactory>(offset_sync: &I64Retriever<F>) -> Option<i64> {
+    let offset = offset_sync.get_data();
+    if offset == UNKNOWN_REPL_OFFSET {
+        None
+    } else {
+        Some(offset)
+    }
+}
+
 pub struct RedisMasterReplicator<F: RedisClientFactory> {
     meta: MasterMeta,
     role_sync: I64Retriever<F>,
+    offset_sync: I64Retriever<F>,
 }
 
 impl<F: RedisClientFactory> RedisMasterReplicator<F> {
@@ -25,11 +75,13 @@ impl<F: RedisClientFactory> RedisMasterReplicator<F> {
 
         Self {
             meta,
-            role_sync: I64Retriever::new(0, client_factory, address, cmd, interval),
+            role_sync: I64Retriever::new(0, client_factory.clone(), address.clone(), cmd, interval),
+            offset_sync: gen_offset_sync(address, client_factory),
         }
     }
 
     fn send_stop_signal(&self) -> Result<(), ReplicatorError> {
+        self.offset_sync.stop();
         if self.role_sync.stop() {
             Ok(())
         } else {
@@ -53,14 +105,15 @@ impl<F: RedisClientFactory> RedisMasterReplicator<F> {
 impl<F: RedisClientFactory> MasterReplicator for RedisMasterReplicator<F> {
     fn start<'s>(&'s self) -> Option<Pin<Box<dyn Future<Output = ReplicatorResult> + Send + 's>>> {
         let meta = self.meta.clone();
-        self.role_sync.start(Self::handle_result).map(|f| {
-            let fut: Pin<Box<dyn Future<Output = Result<(), ReplicatorError>> + Send + 's>> =
-                Box::pin(f.map_err(ReplicatorError::RedisError).then(move |r| {
-                    warn!("RedisMasterReplicator {:?} stopped {:?}", meta, r);
-                    future::ok(())
-                }));
-            fut
-        })
+        let role_sync = self.role_sync.start(Self::handle_result)?;
+        let offset_sync = self.offset_sync.start(handle_offset_result)?;
+        let f = future::try_join(role_sync, offset_sync).map_ok(|_| ());
+        let fut: Pin<Box<dyn Future<Output = Result<(), ReplicatorError>> + Send + 's>> =
+            Box::pin(f.map_err(ReplicatorError::RedisError).then(move |r| {
+                warn!("RedisMasterReplicator {:?} stopped {:?}", meta, r);
+                future::ok(())
+            }));
+        Some(fut)
     }
 
     fn stop(&self) -> Result<(), ReplicatorError> {
@@ -70,11 +123,18 @@ impl<F: RedisClientFactory> MasterReplicator for RedisMasterReplicator<F> {
     fn get_meta(&self) -> &MasterMeta {
         &self.meta
     }
+
+    fn get_repl_offset(&self) -> Option<i64> {
+        get_offset(&self.offset_sync)
+    }
 }
 
 pub struct RedisReplicaReplicator<F: RedisClientFactory> {
     meta: ReplicaMeta,
     role_sync: I64Retriever<F>,
+    offset_sync: I64Retriever<F>,
+    // Retrieves the offset of the master to calculate the lag.
+    master_offset_sync: Option<I64Retriever<F>>,
 }
 
 impl<F: RedisClientFactory> RedisReplicaReplicator<F> {
@@ -89,10 +149,16 @@ impl<F: RedisClientFactory> RedisReplicaReplicator<F> {
         };
         let address = meta.replica_node_address.clone();
         let interval = Duration::new(5, 0);
+        let master_offset_sync = meta
+            .masters
+            .first()
+            .map(|master| gen_offset_sync(master.node_address.clone(), client_factory.clone()));
 
         Self {
             meta,
-            role_sync: I64Retriever::new(0, client_factory, address, cmd, interval),
+            role_sync: I64Retriever::new(0, client_factory.clone(), address.clone(), cmd, interval),
+            offset_sync: gen_offset_sync(address, client_factory),
+            master_offset_sync,
         }
     }
 
@@ -116,6 +182,10 @@ impl<F: RedisClientFactory> RedisReplicaReplicator<F> {
     }
 
     fn send_stop_signal(&self) -> Result<(), ReplicatorError> {
+        self.offset_sync.stop();
+        if let Some(master_offset_sync) = self.master_offset_sync.as_ref() {
+            master_offset_sync.stop();
+        }
         if self.role_sync.stop() {
             Ok(())
         } else {
@@ -131,14 +201,20 @@ impl<F: RedisClientFactory> RedisReplicaReplicator<F> {
 impl<F: RedisClientFactory> ReplicaReplicator for RedisReplicaReplicator<F> {
     fn start<'s>(&'s self) -> Option<Pin<Box<dyn Future<Output = ReplicatorResult> + Send + 's>>> {
         let meta = self.meta.clone();
-        self.role_sync.start(Self::handle_result).map(|f| {
-            let fut: Pin<Box<dyn Future<Output = Result<(), ReplicatorError>> + Send + 's>> =
-                Box::pin(f.map_err(ReplicatorError::RedisError).then(move |r| {
-                    warn!("RedisReplicaReplicator {:?} stopped {:?}", meta, r);
-                    future::ok(())
-                }));
-            fut
-        })
+        let role_sync = self.role_sync.start(Self::handle_result)?;
+        let offset_sync = self.offset_sync.start(handle_offset_result)?;
+        let master_offset_sync: Pin<Box<dyn Future<Output = _> + Send>> =
+            match self.master_offset_sync.as_ref() {
+                Some(master_offset_sync) => master_offset_sync.start(handle_offset_result)?,
+                None => Box::pin(future::ok(())),
+            };
+        let f = future::try_join3(role_sync, offset_sync, master_offset_sync).map_ok(|_| ());
+        let fut: Pin<Box<dyn Future<Output = Result<(), ReplicatorError>> + Send + 's>> =
+            Box::pin(f.map_err(ReplicatorError::RedisError).then(move |r| {
+                warn!("RedisReplicaReplicator {:?} stopped {:?}", meta, r);
+                future::ok(())
+            }));
+        Some(fut)
     }
 
     fn stop(&self) -> Result<(), ReplicatorError> {
@@ -148,4 +224,43 @@ impl<F: RedisClientFactory> ReplicaReplicator for RedisReplicaReplicator<F> {
     fn get_meta(&self) -> &ReplicaMeta {
         &self.meta
     }
+
+    fn get_repl_offset(&self) -> Option<i64> {
+        get_offset(&self.offset_sync)
+    }
+
+    fn get_repl_lag(&self) -> Option<i64> {
+        let master_offset = get_offset(self.master_offset_sync.as_ref()?)?;
+        let replica_offset = get_offset(&self.offset_sync)?;
+        Some(calculate_repl_lag(master_offset, replica_offset))
+    }
+}
+
+// The offsets are retrieved at different time,
+// so the replica could look ahead of the master.
+fn calculate_repl_lag(master_offset: i64, replica_offset: i64) -> i64 {
+    cmp::max(master_offset - replica_offset, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_repl_offset() {
+        let info =
+            "# Replication\r\nrole:slave\r\nslave_repl_offset:233\r\nmaster_repl_offset:233\r\n";
+        let resp = Resp::Bulk(BulkStr::Str(info.as_bytes().to_vec()));
+        assert_eq!(parse_repl_offset(&resp), Some(233));
+
+        let resp = Resp::Bulk(BulkStr::Str(b"# Replication\r\nrole:master\r\n".to_vec()));
+        assert_eq!(parse_repl_offset(&resp), None);
+        assert_eq!(parse_repl_offset(&Resp::Error(b"ERR".to_vec())), None);
+    }
+
+    #[test]
+    fn test_calculate_repl_lag() {
+        assert_eq!(calculate_repl_lag(300, 233), 67);
+        assert_eq!(calculate_repl_lag(233, 300), 0);
+    }
 }
@@ -17,12 +17,19 @@ pub trait MasterReplicator: ThreadSafe {
     fn start<'s>(&'s self) -> Option<Pin<Box<dyn Future<Output = ReplicatorResult> + Send + 's>>>;
     fn stop(&self) -> Result<(), ReplicatorError>;
     fn get_meta(&self) -> &MasterMeta;
+    // None if the offset has not been retrieved yet.
+    fn get_repl_offset(&self) -> Option<i64>;
 }
 
 pub trait ReplicaReplicator: ThreadSafe {
     fn start<'s>(&'s self) -> Option<Pin<Box<dyn Future<Output = ReplicatorResult> + Send + 's>>>;
     fn stop(&self) -> Result<(), ReplicatorError>;
     fn get_meta(&self) -> &ReplicaMeta;
+    // None if the offset has not been retrieved yet.
+    fn get_repl_offset(&self) -> Option<i64>;
+    // The bytes the replica falls behind the master.
+    // Keeps the last known value if the master could not be reached.
+    fn get_repl_lag(&self) -> Option<i64>;
 }
 
 #[derive(Debug, Clone)]